@@ -0,0 +1,861 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fs::{self, File},
+    io::{self, ErrorKind, Read},
+    path::{Component, Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tar::{Archive, Builder, Entry, EntryType, Header, HeaderMode};
+use tracing::{debug, info};
+use walkdir::WalkDir;
+
+use crate::{
+    InstallingError,
+    archive::errors::{ManifestError, UnpackError},
+    compression::{Encoder, decoder},
+    encryption::{BoxedIdentity, Encryption, decryptor},
+    progress::{ByteCounter, Progress, ProgressEvent},
+    report::Recorder,
+    volume::{self, Output},
+};
+
+pub mod errors;
+#[cfg(test)]
+mod test;
+
+/// Name of the manifest at the root of the archive
+pub(crate) const MANIFEST_FILE: &str = "manifest.json";
+/// Name of the first entry of a delta archive, describing the archive it depends on
+pub(crate) const BASELINE_FILE: &str = "baseline.json";
+
+/// Description of every file stored in the archive, used to check its integrity once unpacked
+#[derive(Debug, Deserialize, Serialize)]
+pub(crate) struct Manifest {
+    /// Version of the tool that generated the archive
+    pub(crate) version: String,
+    /// Creation time of the archive in seconds since the UNIX epoch
+    pub(crate) created_at: u64,
+    /// Every file once unpacked, including the ones of a delta archive unchanged since its baseline
+    pub(crate) entries: Vec<ManifestEntry>,
+    /// Files of the baseline archive that a delta archive no longer contains, removed when installing it
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub(crate) removed: Vec<String>,
+    /// Directories updated in place once unpacked (e.g., git mirrors fetching a bundle), whose files are not listed
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub(crate) unlisted_dirs: Vec<String>,
+    /// Archive that must already be unpacked before this one, if it only contains changes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) baseline: Option<Baseline>,
+    /// Reference name to commit hash, for every git mirror
+    #[serde(default)]
+    pub(crate) git_refs: BTreeMap<String, BTreeMap<String, String>>,
+    /// Git mirrors changed since the baseline, only shipped as a bundle of the objects missing from it, if any
+    #[serde(default)]
+    pub(crate) git_bundles: Vec<String>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub(crate) struct Baseline {
+    /// Hex-encoded SHA-256 digest of the manifest of the baseline archive
+    pub(crate) manifest_sha256: String,
+    /// Creation time of the baseline archive in seconds since the UNIX epoch
+    pub(crate) created_at: u64,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub(crate) struct ManifestEntry {
+    /// Path relative to the root of the archive, with '/' separators
+    pub(crate) path: String,
+    pub(crate) size: u64,
+    /// Hex-encoded SHA-256 digest of the file content
+    pub(crate) sha256: String,
+    /// Unchanged since the baseline archive, so only unpacked by the installation of the baseline
+    #[serde(default, skip_serializing_if = "<&bool as std::ops::Not>::not")]
+    pub(crate) unchanged: bool,
+}
+
+/// Seconds since the UNIX epoch
+pub(crate) fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default()
+}
+
+impl Default for Manifest {
+    fn default() -> Self {
+        Self {
+            version: env!("CARGO_PKG_VERSION").to_owned(),
+            created_at: now(),
+            entries: Vec::new(),
+            removed: Vec::new(),
+            unlisted_dirs: Vec::new(),
+            baseline: None,
+            git_refs: BTreeMap::new(),
+            git_bundles: Vec::new(),
+        }
+    }
+}
+
+impl Manifest {
+    /// Read the manifest at the root of an unpacked archive
+    pub(crate) fn load(root: &Path) -> Result<Self, ManifestError> {
+        let manifest_path = root.join(MANIFEST_FILE);
+        let manifest_file = File::open(&manifest_path)
+            .map_err(|e| ManifestError::Read(manifest_path.clone(), e))?;
+        serde_json::from_reader(manifest_file).map_err(|e| ManifestError::Parse(manifest_path, e))
+    }
+
+    /// Read the manifest of a previous archive, decrypted with one of `identities`,
+    /// or the manifest file itself if `path` is a JSON file.
+    /// The manifest is returned along with its description as a baseline for a delta archive.
+    pub(crate) fn load_baseline(
+        path: &Path,
+        identities: &[BoxedIdentity],
+    ) -> Result<(Self, Baseline), ManifestError> {
+        let content = if path
+            .extension()
+            .is_some_and(|extension| extension == "json")
+        {
+            fs::read(path).map_err(|e| ManifestError::Read(path.to_path_buf(), e))?
+        } else {
+            read_entry(path, MANIFEST_FILE, identities)
+                .map_err(|e| ManifestError::Read(path.to_path_buf(), e))?
+                .ok_or(ManifestError::NotInArchive(path.to_path_buf()))?
+        };
+        let manifest: Self = serde_json::from_slice(&content)
+            .map_err(|e| ManifestError::Parse(path.to_path_buf(), e))?;
+        let (_, manifest_sha256) = hash_reader(content.as_slice())
+            .map_err(|e| ManifestError::Read(path.to_path_buf(), e))?;
+        let baseline = Baseline {
+            manifest_sha256,
+            created_at: manifest.created_at,
+        };
+        Ok((manifest, baseline))
+    }
+
+    /// Check every listed file under `root` and return the ones that are missing or altered,
+    /// along with the files of `unpacked` (paths of the files unpacked from the archive) that are not listed
+    /// and the removed files still there, recording a warning for each of them with `recorder`.
+    /// Other files under `root`, e.g., left by previous installations, are not checked.
+    ///
+    /// The files unchanged since the baseline archive are only checked if `with_unchanged`,
+    /// i.e., when the baseline archive was unpacked into `root` too.
+    pub(crate) fn verify(
+        &self,
+        root: &Path,
+        unpacked: &[String],
+        with_unchanged: bool,
+        recorder: &Recorder,
+    ) -> Vec<PathBuf> {
+        let mut mismatches = Vec::new();
+        for entry in self
+            .entries
+            .iter()
+            .filter(|entry| with_unchanged || !entry.unchanged)
+        {
+            let path = root.join(&entry.path);
+            match File::open(&path).and_then(hash_reader) {
+                Ok((size, sha256)) if size == entry.size && sha256 == entry.sha256 => {}
+                Ok(_) => {
+                    recorder.warning(format!("Checksum mismatch for {}", path.display()));
+                    mismatches.push(PathBuf::from(&entry.path));
+                }
+                Err(e) => {
+                    recorder.warning(format!(
+                        "Cannot read {} to verify its checksum: {e}",
+                        path.display()
+                    ));
+                    mismatches.push(PathBuf::from(&entry.path));
+                }
+            }
+        }
+        mismatches.extend(self.unexpected_files(root, unpacked, recorder));
+        mismatches
+    }
+
+    /// Files of `unpacked` that are not listed, and removed files still under `root`,
+    /// recording a warning for each of them with `recorder`
+    fn unexpected_files(
+        &self,
+        root: &Path,
+        unpacked: &[String],
+        recorder: &Recorder,
+    ) -> Vec<PathBuf> {
+        let listed: HashSet<&str> = self
+            .entries
+            .iter()
+            .map(|entry| entry.path.as_str())
+            .chain([MANIFEST_FILE])
+            .collect();
+        let mut unexpected = Vec::new();
+        for path in unpacked
+            .iter()
+            .filter(|path| !listed.contains(path.as_str()))
+        {
+            recorder.warning(format!("{path} is in the archive but not in its manifest"));
+            unexpected.push(PathBuf::from(path));
+        }
+        for path in self
+            .removed
+            .iter()
+            .filter(|path| root.join(path).symlink_metadata().is_ok())
+        {
+            recorder.warning(format!("{path} should have been removed"));
+            unexpected.push(PathBuf::from(path));
+        }
+        unexpected
+    }
+
+    /// Remove from `root` the files of the baseline archive that this one no longer contains,
+    /// along with the directories they leave empty
+    pub(crate) fn remove_deleted(&self, root: &Path) -> Result<(), UnpackError> {
+        for path in &self.removed {
+            let path = Path::new(path);
+            check_relative(path)?;
+            if let Some(link) = path.ancestors().skip(1).find(|ancestor| {
+                !ancestor.as_os_str().is_empty()
+                    && root
+                        .join(ancestor)
+                        .symlink_metadata()
+                        .is_ok_and(|metadata| metadata.file_type().is_symlink())
+            }) {
+                return Err(UnpackError::ThroughLink {
+                    path: path.to_path_buf(),
+                    link: link.to_path_buf(),
+                });
+            }
+            let removed = root.join(path);
+            debug!("Removing {}", removed.display());
+            match fs::remove_file(&removed) {
+                Err(e) if e.kind() == ErrorKind::NotFound => {}
+                res => res.map_err(|e| UnpackError::Remove(removed, e))?,
+            }
+            // Stops at the first directory that is not empty
+            for parent in path.ancestors().skip(1) {
+                if parent.as_os_str().is_empty() || fs::remove_dir(root.join(parent)).is_err() {
+                    break;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Create an empty file under `root` for every file shipped in the archive,
+    /// e.g., to plan an installation without unpacking the archive
+    pub(crate) fn lay_out(&self, root: &Path) -> Result<(), UnpackError> {
+        for entry in self.entries.iter().filter(|entry| !entry.unchanged) {
+            let path = Path::new(&entry.path);
+            check_relative(path)?;
+            let path = root.join(path);
+            path.parent()
+                .map_or(Ok(()), fs::create_dir_all)
+                .and_then(|()| File::create(&path))
+                .map_err(|e| UnpackError::LayOut(path, e))?;
+        }
+        Ok(())
+    }
+}
+
+/// Compressed and possibly encrypted stream written to the archive file or to its volumes
+pub(crate) type ArchiveWriter = Encoder<Encryption<Output>>;
+
+/// A compressed tar builder keeping track of the size and the digest of every appended file
+pub struct ArchiveBuilder {
+    tar: Builder<ArchiveWriter>,
+    pub(crate) manifest: Manifest,
+    /// Files of the baseline archive by path, when building a delta archive
+    baseline_files: HashMap<String, ManifestEntry>,
+    /// Git references of the baseline archive, when building a delta archive
+    baseline_git_refs: BTreeMap<String, BTreeMap<String, String>>,
+    /// Only record the directories that would be archived, without reading them
+    dry_run: bool,
+    /// Timestamp of every entry, when building a reproducible archive
+    reproducible_mtime: Option<u64>,
+    progress: Progress,
+    recorder: Recorder,
+    archived: ByteCounter,
+}
+
+impl ArchiveBuilder {
+    pub(crate) fn new(encoder: ArchiveWriter) -> Self {
+        Self {
+            tar: Builder::new(encoder),
+            manifest: Manifest::default(),
+            baseline_files: HashMap::new(),
+            baseline_git_refs: BTreeMap::new(),
+            dry_run: false,
+            reproducible_mtime: None,
+            progress: Progress::default(),
+            recorder: Recorder::default(),
+            archived: ByteCounter::default(),
+        }
+    }
+
+    /// Report the bytes of every appended file to `progress`
+    pub(crate) fn with_progress(mut self, progress: Progress) -> Self {
+        self.progress = progress;
+        self
+    }
+
+    /// Record the archived directories and the warnings of the sections being archived with `recorder`
+    pub(crate) fn with_recorder(mut self, recorder: Recorder) -> Self {
+        self.recorder = recorder;
+        self
+    }
+
+    /// Recorder of the packaging run
+    pub(crate) fn recorder(&self) -> &Recorder {
+        &self.recorder
+    }
+
+    /// Build the same archive from the same inputs: every entry gets the timestamp `mtime`,
+    /// no ownership and normalized permissions
+    pub(crate) fn reproducible(mut self, mtime: u64) -> Self {
+        self.reproducible_mtime = Some(mtime);
+        self.manifest.created_at = mtime;
+        self
+    }
+
+    /// Plan the archive instead of building it: appended paths are recorded but never read
+    pub(crate) fn dry_run(mut self) -> Self {
+        self.dry_run = true;
+        self
+    }
+
+    /// Whether the appended paths are only recorded, without being read
+    #[must_use]
+    pub fn is_dry_run(&self) -> bool {
+        self.dry_run
+    }
+
+    /// Only archive the files that are new or changed since `baseline_manifest`.
+    ///
+    /// Must be called before appending anything.
+    pub(crate) fn with_baseline(
+        mut self,
+        baseline_manifest: Manifest,
+        baseline: Baseline,
+    ) -> io::Result<Self> {
+        // Written first so that the installation can check the baseline before unpacking anything
+        let content = serde_json::to_vec_pretty(&baseline).map_err(io::Error::other)?;
+        self.append_generated(BASELINE_FILE, &content)?;
+
+        self.baseline_files = baseline_manifest
+            .entries
+            .into_iter()
+            .map(|entry| (entry.path.clone(), entry))
+            .collect();
+        self.baseline_git_refs = baseline_manifest.git_refs;
+        self.manifest.unlisted_dirs = baseline_manifest.unlisted_dirs;
+        self.manifest.baseline = Some(baseline);
+        Ok(self)
+    }
+
+    /// Git references of `mirror` in the baseline archive, if this is a delta archive containing it
+    pub(crate) fn baseline_git_refs(&self, mirror: &str) -> Option<&BTreeMap<String, String>> {
+        self.baseline_git_refs.get(mirror)
+    }
+
+    /// Keep track of the git references of `mirror` so that later archives can be built against this one
+    pub(crate) fn record_git_refs(&mut self, mirror: String, refs: BTreeMap<String, String>) {
+        self.manifest.git_refs.insert(mirror, refs);
+    }
+
+    /// Mark `mirror` as changed since the baseline, its bundle, if any, to apply on top of the baseline mirror
+    pub(crate) fn record_git_bundle(&mut self, mirror: String) {
+        self.manifest.git_bundles.push(mirror);
+    }
+
+    /// Keep the files of the baseline archive under `path`, unchanged and not appended again
+    pub(crate) fn keep_baseline_dir<P: AsRef<Path>>(&mut self, path: P) {
+        let dir = archive_path(path.as_ref());
+        let mut kept: Vec<_> = self
+            .baseline_files
+            .values()
+            .filter(|entry| is_within(&entry.path, &dir))
+            .map(|entry| ManifestEntry {
+                unchanged: true,
+                ..entry.clone()
+            })
+            .collect();
+        kept.sort_unstable_by(|a, b| a.path.cmp(&b.path));
+        self.manifest.entries.extend(kept);
+    }
+
+    /// Stop listing the files under `path`, updated in place once unpacked (e.g., a git mirror fetching a bundle)
+    pub(crate) fn unlist_dir<P: AsRef<Path>>(&mut self, path: P) {
+        let dir = archive_path(path.as_ref());
+        if !self.manifest.unlisted_dirs.contains(&dir) {
+            self.manifest.unlisted_dirs.push(dir);
+        }
+    }
+
+    /// Recursively append the content of `src_path` under `path` in the archive
+    ///
+    /// # Errors
+    ///
+    /// `src_path` cannot be read or the archive cannot be written
+    pub fn append_dir_all<P: AsRef<Path>, Q: AsRef<Path>>(
+        &mut self,
+        path: P,
+        src_path: Q,
+    ) -> io::Result<()> {
+        let path = path.as_ref();
+        let src_path = src_path.as_ref();
+        if self.dry_run {
+            info!("Would archive {} as {}", src_path.display(), path.display());
+            self.recorder
+                .record_archived_directory(archive_path(path), 0);
+            return Ok(());
+        }
+        let mut size: u64 = 0;
+        // Sorted to keep the same order whatever the file system
+        for entry in WalkDir::new(src_path)
+            .follow_links(true)
+            .sort_by_file_name()
+        {
+            let entry = entry?;
+            let relative_path = entry
+                .path()
+                .strip_prefix(src_path)
+                .map_err(io::Error::other)?;
+            let dst_path = path.join(relative_path);
+            if entry.file_type().is_dir() {
+                if dst_path != Path::new("") {
+                    if let Some(mtime) = self.reproducible_mtime {
+                        let mut header = Header::new_gnu();
+                        header.set_metadata_in_mode(&entry.metadata()?, HeaderMode::Deterministic);
+                        header.set_mtime(mtime);
+                        self.tar.append_data(&mut header, &dst_path, io::empty())?;
+                    } else {
+                        self.tar.append_dir(&dst_path, entry.path())?;
+                    }
+                }
+            } else {
+                size = size.saturating_add(entry.metadata()?.len());
+                self.append_path_with_name(entry.path(), &dst_path)?;
+            }
+        }
+        self.recorder
+            .record_archived_directory(archive_path(path), size);
+        Ok(())
+    }
+
+    /// Append the file at `src_path` under the name `path` in the archive, unless it is unchanged since the baseline
+    ///
+    /// # Errors
+    ///
+    /// `src_path` cannot be read or the archive cannot be written
+    pub fn append_path_with_name<P: AsRef<Path>, N: AsRef<Path>>(
+        &mut self,
+        src_path: P,
+        path: N,
+    ) -> io::Result<()> {
+        let archive_path = archive_path(path.as_ref());
+        if !self.dry_run
+            && let Some(baseline_entry) = self.baseline_files.get(&archive_path)
+        {
+            let (_, sha256) = hash_reader(File::open(&src_path)?)?;
+            if baseline_entry.sha256 == sha256 {
+                debug!("Skipping {archive_path}, unchanged since the baseline");
+                self.manifest.entries.push(ManifestEntry {
+                    unchanged: true,
+                    ..baseline_entry.clone()
+                });
+                return Ok(());
+            }
+        }
+        self.append_path_always(src_path, path)
+    }
+
+    /// Append the file at `src_path` under the name `path` in the archive, even if it is unchanged since the baseline
+    pub(crate) fn append_path_always<P: AsRef<Path>, N: AsRef<Path>>(
+        &mut self,
+        src_path: P,
+        path: N,
+    ) -> io::Result<()> {
+        let archive_path = archive_path(path.as_ref());
+        if self.dry_run {
+            debug!("Would archive {archive_path}");
+            return Ok(());
+        }
+
+        let file = File::open(src_path)?;
+        let mut header = Header::new_gnu();
+        if let Some(mtime) = self.reproducible_mtime {
+            header.set_metadata_in_mode(&file.metadata()?, HeaderMode::Deterministic);
+            header.set_mtime(mtime);
+        } else {
+            header.set_metadata_in_mode(&file.metadata()?, HeaderMode::Complete);
+        }
+
+        let mut reader = HashingReader::new(file);
+        self.tar
+            .append_data(&mut header, path.as_ref(), &mut reader)?;
+        let (size, sha256) = reader.finalize();
+        self.manifest.entries.push(ManifestEntry {
+            path: archive_path,
+            size,
+            sha256,
+            unchanged: false,
+        });
+        self.progress.emit(ProgressEvent::BytesArchived {
+            total: self.archived.add(size),
+        });
+        Ok(())
+    }
+
+    /// Write the manifest at the root of the archive and flush the compressed stream.
+    ///
+    /// Return the path of the archive, or of the index of its volumes.
+    pub(crate) fn finish(mut self) -> io::Result<PathBuf> {
+        if self.manifest.baseline.is_some() {
+            // Whatever was neither appended nor kept is gone, unless its directory is updated in place
+            let listed: HashSet<&str> = self
+                .manifest
+                .entries
+                .iter()
+                .map(|entry| entry.path.as_str())
+                .collect();
+            let mut removed: Vec<_> = self
+                .baseline_files
+                .keys()
+                .filter(|path| {
+                    !listed.contains(path.as_str())
+                        && !self
+                            .manifest
+                            .unlisted_dirs
+                            .iter()
+                            .any(|dir| is_within(path, dir))
+                })
+                .cloned()
+                .collect();
+            removed.sort_unstable();
+            self.manifest.removed = removed;
+        }
+        debug!("Archive manifest: {:#?}", self.manifest);
+        let manifest = serde_json::to_vec_pretty(&self.manifest).map_err(io::Error::other)?;
+        self.append_generated(MANIFEST_FILE, &manifest)?;
+        self.tar.into_inner()?.finish()?.finish()?.finish()
+    }
+
+    /// Append a file generated by this tool, that is not listed in the manifest
+    fn append_generated(&mut self, path: &str, content: &[u8]) -> io::Result<()> {
+        let mut header = Header::new_gnu();
+        header.set_size(content.len() as u64);
+        header.set_mode(0o644);
+        header.set_uid(0);
+        header.set_gid(0);
+        header.set_mtime(self.manifest.created_at);
+        self.tar.append_data(&mut header, path, content)
+    }
+}
+
+/// Open the archive at `path` for reading, whatever its compression format.
+///
+/// `path` can also be the index or the first volume of an archive split into volumes.
+/// An encrypted archive is decrypted with one of `identities`.
+pub(crate) fn open(
+    path: &Path,
+    identities: &[BoxedIdentity],
+) -> io::Result<Archive<Box<dyn Read>>> {
+    read(volume::open(path)?, identities)
+}
+
+/// Read the archive from `reader`, decrypted with one of `identities`
+fn read(reader: Box<dyn Read>, identities: &[BoxedIdentity]) -> io::Result<Archive<Box<dyn Read>>> {
+    let reader = decryptor(reader, identities).map_err(io::Error::other)?;
+    Ok(Archive::new(decoder(reader)?))
+}
+
+/// Read the content of the file at `entry_path` in the archive at `path`, decrypted with one of `identities`.
+///
+/// The volumes of a split archive are not checked against their digests: the content must be checked
+/// by other means, e.g. when the archive is unpacked afterwards.
+pub(crate) fn read_entry(
+    path: &Path,
+    entry_path: &str,
+    identities: &[BoxedIdentity],
+) -> io::Result<Option<Vec<u8>>> {
+    let mut archive = read(volume::open_unchecked(path)?, identities)?;
+    Ok(find_entries(&mut archive, &[entry_path])?.remove(entry_path))
+}
+
+/// Read the content of the files at `entry_paths` found in the archive at `path`, by archive path,
+/// decrypted with one of `identities`. The archive is read until every file is found.
+pub(crate) fn read_entries(
+    path: &Path,
+    entry_paths: &[&str],
+    identities: &[BoxedIdentity],
+) -> io::Result<BTreeMap<String, Vec<u8>>> {
+    find_entries(&mut open(path, identities)?, entry_paths)
+}
+
+/// Read the content of the files at `entry_paths` found in `archive`, by archive path
+fn find_entries(
+    archive: &mut Archive<Box<dyn Read>>,
+    entry_paths: &[&str],
+) -> io::Result<BTreeMap<String, Vec<u8>>> {
+    let mut contents = BTreeMap::new();
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let entry_path = archive_path(&entry.path()?);
+        if entry_paths.contains(&entry_path.as_str()) {
+            let mut content = Vec::new();
+            entry.read_to_end(&mut content)?;
+            contents.insert(entry_path, content);
+            if contents.len() == entry_paths.len() {
+                break;
+            }
+        }
+    }
+    Ok(contents)
+}
+
+/// Unpack the archive at `path` into `dst`.
+///
+/// Every entry is checked before being written: it must stay within `dst` without going through a symbolic link,
+/// be a regular file, a directory or a link to a path within `dst`, and the total size of the files cannot exceed
+/// `max_size` bytes if set.
+/// A delta archive is only unpacked if its baseline archive was previously unpacked into `dst`.
+/// The bytes unpacked so far are reported to `progress`.
+///
+/// Returns the archive paths of the files that were unpacked.
+pub(crate) fn unpack(
+    path: &Path,
+    dst: &Path,
+    identities: &[BoxedIdentity],
+    max_size: Option<u64>,
+    progress: &Progress,
+) -> Result<Vec<String>, InstallingError> {
+    let mut archive = open(path, identities).map_err(InstallingError::ArchiveUncompress)?;
+    let mut unpacked_size: u64 = 0;
+    let mut unpacked = Vec::new();
+    for entry in archive
+        .entries()
+        .map_err(InstallingError::ArchiveUncompress)?
+    {
+        let mut entry = entry.map_err(InstallingError::ArchiveUncompress)?;
+        let raw_path = entry
+            .path()
+            .map_err(InstallingError::ArchiveUncompress)?
+            .into_owned();
+        check_entry(&entry, &raw_path)?;
+        check_link_parents(&entry, &raw_path, dst)?;
+        unpacked_size = unpacked_size.saturating_add(entry.header().size().unwrap_or_default());
+        if let Some(max_size) = max_size
+            && unpacked_size > max_size
+        {
+            return Err(UnpackError::SizeLimit(max_size).into());
+        }
+
+        let entry_path = archive_path(&raw_path);
+        if entry_path == BASELINE_FILE {
+            let baseline: Baseline = serde_json::from_reader(&mut entry)
+                .map_err(|e| ManifestError::Parse(PathBuf::from(BASELINE_FILE), e))?;
+            check_baseline(&baseline, dst)?;
+            info!(
+                "Merging changes into the archive unpacked at {}",
+                dst.display()
+            );
+            continue;
+        }
+        let is_dir = entry.header().entry_type().is_dir();
+        entry
+            .unpack_in(dst)
+            .map_err(InstallingError::ArchiveUncompress)?;
+        if !is_dir {
+            unpacked.push(entry_path);
+        }
+        progress.emit(ProgressEvent::BytesUnpacked {
+            total: unpacked_size,
+        });
+    }
+    Ok(unpacked)
+}
+
+/// Reject any path that would be outside of the unpack directory
+fn check_relative(path: &Path) -> Result<(), UnpackError> {
+    for component in path.components() {
+        match component {
+            Component::ParentDir => return Err(UnpackError::ParentDirectory(path.to_path_buf())),
+            Component::RootDir | Component::Prefix(_) => {
+                return Err(UnpackError::AbsolutePath(path.to_path_buf()));
+            }
+            Component::Normal(_) | Component::CurDir => {}
+        }
+    }
+    Ok(())
+}
+
+/// Whether the archive path `path` is within the archive directory `dir`
+fn is_within(path: &str, dir: &str) -> bool {
+    path.strip_prefix(dir)
+        .is_some_and(|rest| rest.starts_with('/'))
+}
+
+/// Reject any entry that would be written or point outside of the unpack directory, and special files
+fn check_entry<R: Read>(entry: &Entry<'_, R>, path: &Path) -> Result<(), UnpackError> {
+    check_relative(path)?;
+    let entry_type = entry.header().entry_type();
+    match entry_type {
+        EntryType::Regular
+        | EntryType::Continuous
+        | EntryType::GNUSparse
+        | EntryType::Directory => {}
+        EntryType::Symlink | EntryType::Link => {
+            let target = entry
+                .link_name()
+                .ok()
+                .flatten()
+                .unwrap_or_default()
+                .into_owned();
+            // Symbolic links are relative to their parent directory, hard links to the root of the archive
+            let base = if entry_type == EntryType::Symlink {
+                path.parent().unwrap_or(Path::new(""))
+            } else {
+                Path::new("")
+            };
+            if target.has_root() || !stays_within(&target, base) {
+                return Err(UnpackError::LinkEscape {
+                    path: path.to_path_buf(),
+                    target,
+                });
+            }
+        }
+        _ => {
+            return Err(UnpackError::SpecialFile(
+                path.to_path_buf(),
+                format!("{entry_type:?}"),
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Reject any entry whose parent directory in `dst` is a symbolic link, e.g., `a/b -> ..` after `a -> .`:
+/// each link is checked against its own path in the archive, not where the links unpacked before resolve it.
+/// Hard links cannot target a symbolic link or a path through one either.
+fn check_link_parents<R: Read>(
+    entry: &Entry<'_, R>,
+    path: &Path,
+    dst: &Path,
+) -> Result<(), UnpackError> {
+    let is_link = |ancestor: &Path| {
+        !ancestor.as_os_str().is_empty()
+            && dst
+                .join(ancestor)
+                .symlink_metadata()
+                .is_ok_and(|metadata| metadata.file_type().is_symlink())
+    };
+    if let Some(link) = path.ancestors().skip(1).find(|ancestor| is_link(ancestor)) {
+        return Err(UnpackError::ThroughLink {
+            path: path.to_path_buf(),
+            link: link.to_path_buf(),
+        });
+    }
+    if entry.header().entry_type() == EntryType::Link {
+        let target = entry
+            .link_name()
+            .ok()
+            .flatten()
+            .unwrap_or_default()
+            .into_owned();
+        if let Some(link) = target.ancestors().find(|ancestor| is_link(ancestor)) {
+            return Err(UnpackError::ThroughLink {
+                path: path.to_path_buf(),
+                link: link.to_path_buf(),
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Whether the relative path `target`, resolved from the directory `base`, stays within the unpack directory
+fn stays_within(target: &Path, base: &Path) -> bool {
+    let mut depth = base.components().count();
+    for component in target.components() {
+        match component {
+            Component::Normal(_) => depth = depth.saturating_add(1),
+            Component::CurDir => {}
+            Component::ParentDir => match depth.checked_sub(1) {
+                Some(parent_depth) => depth = parent_depth,
+                None => return false,
+            },
+            Component::RootDir | Component::Prefix(_) => return false,
+        }
+    }
+    true
+}
+
+/// Check that the manifest of the archive unpacked at `dst` is the one `baseline` refers to
+pub(crate) fn check_baseline(baseline: &Baseline, dst: &Path) -> Result<(), InstallingError> {
+    let installed_sha256 = File::open(dst.join(MANIFEST_FILE))
+        .and_then(hash_reader)
+        .map(|(_, sha256)| sha256)
+        .ok();
+    if installed_sha256.as_ref() != Some(&baseline.manifest_sha256) {
+        return Err(InstallingError::MissingBaseline {
+            dst: dst.to_path_buf(),
+            manifest_sha256: baseline.manifest_sha256.clone(),
+        });
+    }
+    Ok(())
+}
+
+/// Convert a path within the archive to a platform-independent string
+pub(crate) fn archive_path(path: &Path) -> String {
+    path.components()
+        .filter_map(|component| match component {
+            Component::Normal(part) => Some(part.to_string_lossy()),
+            _ => None,
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Return the size and the hex-encoded SHA-256 digest of everything read from `reader`
+pub(crate) fn hash_reader<R: Read>(reader: R) -> io::Result<(u64, String)> {
+    let mut reader = HashingReader::new(reader);
+    io::copy(&mut reader, &mut io::sink())?;
+    Ok(reader.finalize())
+}
+
+/// A reader computing the SHA-256 digest of the data going through it
+pub(crate) struct HashingReader<R> {
+    inner: R,
+    hasher: Sha256,
+    size: u64,
+}
+
+impl<R: Read> HashingReader<R> {
+    pub(crate) fn new(inner: R) -> Self {
+        Self {
+            inner,
+            hasher: Sha256::new(),
+            size: 0,
+        }
+    }
+
+    /// Return the size and the hex-encoded SHA-256 digest of the data read so far
+    pub(crate) fn finalize(self) -> (u64, String) {
+        (self.size, hex::encode(self.hasher.finalize()))
+    }
+}
+
+impl<R: Read> Read for HashingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        #[expect(
+            clippy::indexing_slicing,
+            reason = "read() never exceeds the buffer length"
+        )]
+        self.hasher.update(&buf[..read]);
+        self.size = self.size.saturating_add(read as u64);
+        Ok(read)
+    }
+}
@@ -0,0 +1,33 @@
+use std::path::PathBuf;
+
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum ManifestError {
+    #[error("Cannot read archive manifest at '{0}': {1}")]
+    Read(PathBuf, #[source] std::io::Error),
+    #[error("Invalid archive manifest at '{0}': {1}")]
+    Parse(PathBuf, #[source] serde_json::Error),
+    #[error("No manifest in the archive '{0}'")]
+    NotInArchive(PathBuf),
+}
+
+#[derive(Error, Debug)]
+pub enum UnpackError {
+    #[error("Entry '{0}' has a '..' component")]
+    ParentDirectory(PathBuf),
+    #[error("Entry '{0}' has an absolute path")]
+    AbsolutePath(PathBuf),
+    #[error("Link '{path}' points outside of the unpack directory: '{target}'")]
+    LinkEscape { path: PathBuf, target: PathBuf },
+    #[error("Entry '{path}' goes through the symbolic link '{link}'")]
+    ThroughLink { path: PathBuf, link: PathBuf },
+    #[error("Entry '{0}' is a special file ({1})")]
+    SpecialFile(PathBuf, String),
+    #[error("Unpacked content exceeds the limit of {0} bytes")]
+    SizeLimit(u64),
+    #[error("Cannot remove '{0}', removed since the baseline archive: {1}")]
+    Remove(PathBuf, #[source] std::io::Error),
+    #[error("Cannot create '{0}' to plan the installation: {1}")]
+    LayOut(PathBuf, #[source] std::io::Error),
+}
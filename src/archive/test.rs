@@ -0,0 +1,664 @@
+use std::fs::{self, File, create_dir_all};
+use std::io::Write;
+use std::path::PathBuf;
+
+use rstest::rstest;
+use tar::{EntryType, Header};
+use tempfile::tempdir;
+
+use crate::archive::errors::UnpackError;
+use crate::archive::{ArchiveBuilder, MANIFEST_FILE, Manifest, hash_reader, open, unpack};
+use crate::compression::{CompressionFormat, CompressionSettings, Encoder};
+use crate::encryption::Encryption;
+use crate::progress::Progress;
+use crate::report::Recorder;
+use crate::state::STATE_FILE;
+use crate::test::archive_at;
+use crate::volume::Output;
+use crate::{InstallOptions, InstallingError, PackagingError, plan_install};
+
+#[rstest]
+#[test_log::test]
+fn manifest_lists_every_file() {
+    let in_folder = tempdir().unwrap();
+    let sub_folder = in_folder.path().join("sub");
+    create_dir_all(&sub_folder).unwrap();
+    write!(File::create(sub_folder.join("a")).unwrap(), "content a").unwrap();
+    write!(
+        File::create(in_folder.path().join("b")).unwrap(),
+        "content b"
+    )
+    .unwrap();
+
+    let out_folder = tempdir().unwrap();
+    let archive_path = out_folder.path().join("archive.tar.gz");
+    let mut archive = archive_at(&archive_path);
+    archive.append_dir_all("dir", in_folder.path()).unwrap();
+    archive
+        .append_path_with_name(in_folder.path().join("b"), "renamed_b")
+        .unwrap();
+    archive.finish().expect("Shouldn't fail to build archive");
+
+    let unpacked = tempdir().unwrap();
+    open(&archive_path, &[])
+        .unwrap()
+        .unpack(unpacked.path())
+        .unwrap();
+    assert!(
+        unpacked.path().join(MANIFEST_FILE).is_file(),
+        "The manifest should be at the root of the archive"
+    );
+
+    let manifest = Manifest::load(unpacked.path()).expect("Manifest should be readable");
+    let mut paths: Vec<_> = manifest.entries.iter().map(|e| e.path.as_str()).collect();
+    paths.sort_unstable();
+    assert_eq!(
+        paths,
+        ["dir/b", "dir/sub/a", "renamed_b"],
+        "Unexpected entries"
+    );
+    assert_eq!(
+        manifest.entries[0].size, 9,
+        "Size should match the content length"
+    );
+    assert!(
+        manifest
+            .verify(unpacked.path(), &[], true, &Recorder::default())
+            .is_empty(),
+        "Untouched files should pass the integrity check"
+    );
+}
+
+#[rstest]
+#[test_log::test]
+fn verify_reports_altered_missing_and_unlisted_files() {
+    let root = tempdir().unwrap();
+    write!(File::create(root.path().join("intact")).unwrap(), "intact").unwrap();
+    write!(
+        File::create(root.path().join("altered")).unwrap(),
+        "altered"
+    )
+    .unwrap();
+    write!(
+        File::create(root.path().join("missing")).unwrap(),
+        "missing"
+    )
+    .unwrap();
+
+    // Build the manifest from the original files
+    let out_folder = tempdir().unwrap();
+    let archive_path = out_folder.path().join("archive.tar.gz");
+    let mut archive = archive_at(&archive_path);
+    archive.append_dir_all("", root.path()).unwrap();
+    let manifest = serde_json::to_value(&archive.manifest).unwrap();
+    let mut manifest: Manifest = serde_json::from_value(manifest).unwrap();
+    manifest.removed = vec!["stale".to_string(), "deleted".to_string()];
+
+    // Corrupt the unpacked content
+    write!(
+        File::create(root.path().join("altered")).unwrap(),
+        "altered!"
+    )
+    .unwrap();
+    fs::remove_file(root.path().join("missing")).unwrap();
+    create_dir_all(root.path().join("extra")).unwrap();
+    fs::write(root.path().join("extra").join("unlisted"), "unlisted").unwrap();
+    fs::write(root.path().join(MANIFEST_FILE), "{}").unwrap();
+    fs::write(root.path().join("stale"), "stale").unwrap();
+    // Left by previous installations
+    fs::write(root.path().join(STATE_FILE), "{}").unwrap();
+    create_dir_all(root.path().join("previous")).unwrap();
+    fs::write(root.path().join("previous").join("left"), "left").unwrap();
+
+    let unpacked = [
+        "altered",
+        "extra/unlisted",
+        "intact",
+        "missing",
+        MANIFEST_FILE,
+    ]
+    .map(String::from);
+    let mut mismatches = manifest.verify(root.path(), &unpacked, true, &Recorder::default());
+    mismatches.sort_unstable();
+    assert_eq!(
+        mismatches,
+        [
+            PathBuf::from("altered"),
+            PathBuf::from("extra/unlisted"),
+            PathBuf::from("missing"),
+            PathBuf::from("stale")
+        ],
+        "Only altered, missing, unlisted and leftover removed files should be reported"
+    );
+}
+
+/// Build an archive at `archive_path` from the content of `in_folder`, against `baseline` if any
+fn build_archive(
+    in_folder: &std::path::Path,
+    archive_path: &std::path::Path,
+    baseline: Option<&std::path::Path>,
+) {
+    let output = Output::new(archive_path, None).unwrap();
+    let enc = Encoder::new(Encryption::None(output), &CompressionSettings::default()).unwrap();
+    let mut archive = match baseline {
+        Some(baseline) => {
+            let (manifest, baseline) = Manifest::load_baseline(baseline, &[]).unwrap();
+            ArchiveBuilder::new(enc)
+                .with_baseline(manifest, baseline)
+                .unwrap()
+        }
+        None => ArchiveBuilder::new(enc),
+    };
+    archive.append_dir_all("", in_folder).unwrap();
+    archive.finish().unwrap();
+}
+
+#[rstest]
+#[test_log::test]
+fn delta_only_contains_changes() {
+    let in_folder = tempdir().unwrap();
+    write!(
+        File::create(in_folder.path().join("unchanged")).unwrap(),
+        "same"
+    )
+    .unwrap();
+    write!(
+        File::create(in_folder.path().join("changed")).unwrap(),
+        "before"
+    )
+    .unwrap();
+
+    let out_folder = tempdir().unwrap();
+    let full_path = out_folder.path().join("full.tar.gz");
+    build_archive(in_folder.path(), &full_path, None);
+
+    write!(
+        File::create(in_folder.path().join("changed")).unwrap(),
+        "after"
+    )
+    .unwrap();
+    write!(File::create(in_folder.path().join("new")).unwrap(), "new").unwrap();
+    let delta_path = out_folder.path().join("delta.tar.gz");
+    build_archive(in_folder.path(), &delta_path, Some(&full_path));
+
+    // Install the full archive, then the delta on top of it
+    let unpacked = tempdir().unwrap();
+    unpack(&full_path, unpacked.path(), &[], None, &Progress::default())
+        .expect("Full archive should unpack");
+    let full_manifest_sha256 = File::open(unpacked.path().join(MANIFEST_FILE))
+        .and_then(hash_reader)
+        .unwrap()
+        .1;
+    unpack(
+        &delta_path,
+        unpacked.path(),
+        &[],
+        None,
+        &Progress::default(),
+    )
+    .expect("Delta should unpack over its baseline");
+
+    let manifest = Manifest::load(unpacked.path()).unwrap();
+    let mut paths: Vec<_> = manifest
+        .entries
+        .iter()
+        .filter(|e| !e.unchanged)
+        .map(|e| e.path.as_str())
+        .collect();
+    paths.sort_unstable();
+    assert_eq!(paths, ["changed", "new"], "Only changes should be packaged");
+    assert_eq!(
+        manifest.entries.len(),
+        3,
+        "Unchanged files should still be listed, got {:?}",
+        manifest.entries
+    );
+    assert_eq!(
+        manifest.baseline.map(|baseline| baseline.manifest_sha256),
+        Some(full_manifest_sha256),
+        "The delta should refer to the full archive"
+    );
+    assert_eq!(
+        fs::read_to_string(unpacked.path().join("changed")).unwrap(),
+        "after",
+        "Changed files should be overwritten"
+    );
+    assert_eq!(
+        fs::read_to_string(unpacked.path().join("unchanged")).unwrap(),
+        "same",
+        "Unchanged files should be kept"
+    );
+}
+
+/// Unpack the archive at `archive_path` into `dst` as an installation does, removing the deleted files
+fn install_archive(archive_path: &std::path::Path, dst: &std::path::Path) -> Manifest {
+    unpack(archive_path, dst, &[], None, &Progress::default()).unwrap();
+    let manifest = Manifest::load(dst).unwrap();
+    manifest
+        .remove_deleted(dst)
+        .expect("Deleted files should be removed");
+    manifest
+}
+
+#[rstest]
+#[test_log::test]
+fn delta_of_delta_removes_deleted_files() {
+    let in_folder = tempdir().unwrap();
+    create_dir_all(in_folder.path().join("sub")).unwrap();
+    fs::write(in_folder.path().join("kept"), "same").unwrap();
+    fs::write(in_folder.path().join("sub").join("gone"), "gone").unwrap();
+    let out_folder = tempdir().unwrap();
+    let full_path = out_folder.path().join("full.tar.gz");
+    build_archive(in_folder.path(), &full_path, None);
+    fs::remove_dir_all(in_folder.path().join("sub")).unwrap();
+    let delta_path = out_folder.path().join("delta.tar.gz");
+    build_archive(in_folder.path(), &delta_path, Some(&full_path));
+    fs::write(in_folder.path().join("new"), "new").unwrap();
+    let next_delta_path = out_folder.path().join("next_delta.tar.gz");
+    build_archive(in_folder.path(), &next_delta_path, Some(&delta_path));
+
+    let unpacked = tempdir().unwrap();
+    install_archive(&full_path, unpacked.path());
+    let manifest = install_archive(&delta_path, unpacked.path());
+    assert_eq!(
+        manifest.removed,
+        ["sub/gone"],
+        "The delta should list the deleted files"
+    );
+    assert!(
+        !unpacked.path().join("sub").exists(),
+        "Deleted files should be removed along with their emptied directory"
+    );
+    let manifest = install_archive(&next_delta_path, unpacked.path());
+
+    let shipped: Vec<_> = manifest
+        .entries
+        .iter()
+        .filter(|entry| !entry.unchanged)
+        .map(|entry| entry.path.as_str())
+        .collect();
+    assert_eq!(
+        shipped,
+        ["new"],
+        "A delta of a delta should only contain the changes since the previous delta"
+    );
+    assert!(
+        manifest.removed.is_empty(),
+        "Files removed by the previous delta shouldn't be removed again"
+    );
+    assert!(
+        manifest
+            .verify(unpacked.path(), &[], true, &Recorder::default())
+            .is_empty(),
+        "Every listed file should be installed"
+    );
+}
+
+#[rstest]
+#[test_log::test]
+fn delta_requires_baseline() {
+    let in_folder = tempdir().unwrap();
+    write!(
+        File::create(in_folder.path().join("file")).unwrap(),
+        "before"
+    )
+    .unwrap();
+
+    let out_folder = tempdir().unwrap();
+    let full_path = out_folder.path().join("full.tar.gz");
+    build_archive(in_folder.path(), &full_path, None);
+    write!(
+        File::create(in_folder.path().join("file")).unwrap(),
+        "after"
+    )
+    .unwrap();
+    let delta_path = out_folder.path().join("delta.tar.gz");
+    build_archive(in_folder.path(), &delta_path, Some(&full_path));
+
+    let unpacked = tempdir().unwrap();
+    let res = unpack(
+        &delta_path,
+        unpacked.path(),
+        &[],
+        None,
+        &Progress::default(),
+    );
+    assert!(
+        matches!(res, Err(InstallingError::MissingBaseline { .. })),
+        "Delta shouldn't unpack without its baseline: {res:?}"
+    );
+    assert!(
+        fs::read_dir(unpacked.path()).unwrap().next().is_none(),
+        "Nothing should be unpacked"
+    );
+}
+
+#[rstest]
+#[test_log::test]
+fn plan_delta_over_baseline() {
+    let in_folder = tempdir().unwrap();
+    write!(
+        File::create(in_folder.path().join("file")).unwrap(),
+        "before"
+    )
+    .unwrap();
+    let out_folder = tempdir().unwrap();
+    let full_path = out_folder.path().join("full.tar.gz");
+    build_archive(in_folder.path(), &full_path, None);
+    write!(
+        File::create(in_folder.path().join("file")).unwrap(),
+        "after"
+    )
+    .unwrap();
+    let delta_path = out_folder.path().join("delta.tar.gz");
+    build_archive(in_folder.path(), &delta_path, Some(&full_path));
+    let unpacked = tempdir().unwrap();
+    unpack(&full_path, unpacked.path(), &[], None, &Progress::default()).unwrap();
+
+    let res = plan_install(
+        &delta_path,
+        &InstallOptions {
+            unpack_dir: Some(unpacked.path().to_path_buf()),
+            ..InstallOptions::default()
+        },
+    );
+
+    assert!(
+        res.is_ok(),
+        "The dry run should accept a delta over its unpacked baseline, got {res:?}"
+    );
+    assert_eq!(
+        fs::read_to_string(unpacked.path().join("file")).unwrap(),
+        "before",
+        "The dry run shouldn't change the unpacked baseline"
+    );
+}
+
+#[rstest]
+#[test_log::test]
+fn unpack_every_compression_format(
+    #[values(
+        CompressionFormat::Gzip,
+        CompressionFormat::Zstd,
+        CompressionFormat::Xz,
+        CompressionFormat::None
+    )]
+    format: CompressionFormat,
+) {
+    let in_folder = tempdir().unwrap();
+    write!(
+        File::create(in_folder.path().join("file")).unwrap(),
+        "content"
+    )
+    .unwrap();
+
+    // No file extension: the format must be detected from the content
+    let out_folder = tempdir().unwrap();
+    let archive_path = out_folder.path().join("archive");
+    let settings = CompressionSettings {
+        format,
+        // No level can be set without compression
+        level: (format != CompressionFormat::None).then_some(3),
+    };
+    let enc = Encoder::new(
+        Encryption::None(Output::new(&archive_path, None).unwrap()),
+        &settings,
+    )
+    .unwrap();
+    let mut archive = ArchiveBuilder::new(enc);
+    archive.append_dir_all("", in_folder.path()).unwrap();
+    archive.finish().unwrap();
+
+    let unpacked = tempdir().unwrap();
+    unpack(
+        &archive_path,
+        unpacked.path(),
+        &[],
+        None,
+        &Progress::default(),
+    )
+    .expect("Archive should unpack");
+    assert_eq!(
+        fs::read_to_string(unpacked.path().join("file")).unwrap(),
+        "content",
+        "Content should survive the compression"
+    );
+}
+
+#[rstest]
+#[case::gzip(CompressionFormat::Gzip, 10)]
+#[case::zstd(CompressionFormat::Zstd, 0)]
+#[case::xz(CompressionFormat::Xz, 42)]
+#[case::none(CompressionFormat::None, 0)]
+#[test_log::test]
+fn invalid_compression_level(#[case] format: CompressionFormat, #[case] level: u32) {
+    let settings = CompressionSettings {
+        format,
+        level: Some(level),
+    };
+    let res = Encoder::new(tempfile::tempfile().unwrap(), &settings);
+    assert!(
+        matches!(res, Err(PackagingError::CompressionLevel(f, l)) if f == format && l == level),
+        "Level {level} should be rejected for {format}"
+    );
+}
+
+/// Write an uncompressed archive with a single entry, without any of the checks of [`Header::set_path`]
+fn write_raw_entry(
+    archive_path: &std::path::Path,
+    path: &str,
+    entry_type: EntryType,
+    link_name: Option<&str>,
+) {
+    write_raw_entries(archive_path, &[(path, entry_type, link_name)]);
+}
+
+/// Write an uncompressed archive with the entries `(path, type, link name)`, in order
+fn write_raw_entries(archive_path: &std::path::Path, entries: &[(&str, EntryType, Option<&str>)]) {
+    let mut builder = tar::Builder::new(File::create(archive_path).unwrap());
+    for &(path, entry_type, link_name) in entries {
+        let mut header = Header::new_gnu();
+        header.as_old_mut().name[..path.len()].copy_from_slice(path.as_bytes());
+        header.set_entry_type(entry_type);
+        header.set_mode(0o644);
+        header.set_size(if entry_type == EntryType::Regular {
+            7
+        } else {
+            0
+        });
+        if let Some(link_name) = link_name {
+            header.set_link_name_literal(link_name).unwrap();
+        }
+        header.set_cksum();
+        let content: &[u8] = if entry_type == EntryType::Regular {
+            b"content"
+        } else {
+            b""
+        };
+        builder.append(&header, content).unwrap();
+    }
+    builder.finish().unwrap();
+}
+
+#[rstest]
+#[case::parent_directory("sub/../../escape", EntryType::Regular, None, None)]
+#[case::absolute_path("/tmp/escape", EntryType::Regular, None, None)]
+#[case::symlink_escape("sub/link", EntryType::Symlink, Some("../../escape"), None)]
+#[case::absolute_symlink("link", EntryType::Symlink, Some("/etc/passwd"), None)]
+#[case::hardlink_escape("link", EntryType::Link, Some("../escape"), None)]
+#[case::device("device", EntryType::Char, None, None)]
+#[case::fifo("fifo", EntryType::Fifo, None, None)]
+// Each link stays within the unpack directory from its own path, but `a/b` resolves to its parent
+#[case::symlink_chain("a/b", EntryType::Symlink, Some(".."), Some(("a", ".")))]
+#[case::hardlink_to_symlink("link", EntryType::Link, Some("sub/up"), Some(("sub/up", "..")))]
+#[test_log::test]
+fn unpack_rejects_unsafe_entry(
+    #[case] path: &str,
+    #[case] entry_type: EntryType,
+    #[case] link_name: Option<&str>,
+    #[case] preceding_symlink: Option<(&str, &str)>,
+) {
+    let out_folder = tempdir().unwrap();
+    let archive_path = out_folder.path().join("archive.tar");
+    let mut entries = Vec::new();
+    if let Some((symlink, target)) = preceding_symlink {
+        if let Some((parent, _)) = symlink.rsplit_once('/') {
+            entries.push((parent, EntryType::Directory, None));
+        }
+        entries.push((symlink, EntryType::Symlink, Some(target)));
+    }
+    entries.push((path, entry_type, link_name));
+    write_raw_entries(&archive_path, &entries);
+
+    let unpacked = tempdir().unwrap();
+    let res = unpack(
+        &archive_path,
+        unpacked.path(),
+        &[],
+        None,
+        &Progress::default(),
+    );
+    let expected = match (entry_type, path) {
+        _ if preceding_symlink.is_some() => matches!(
+            res,
+            Err(InstallingError::Unpack(UnpackError::ThroughLink { .. }))
+        ),
+        (EntryType::Regular, "/tmp/escape") => matches!(
+            res,
+            Err(InstallingError::Unpack(UnpackError::AbsolutePath(_)))
+        ),
+        (EntryType::Regular, _) => matches!(
+            res,
+            Err(InstallingError::Unpack(UnpackError::ParentDirectory(_)))
+        ),
+        (EntryType::Symlink | EntryType::Link, _) => matches!(
+            res,
+            Err(InstallingError::Unpack(UnpackError::LinkEscape { .. }))
+        ),
+        _ => matches!(
+            res,
+            Err(InstallingError::Unpack(UnpackError::SpecialFile(..)))
+        ),
+    };
+    assert!(expected, "Entry '{path}' should be rejected, got {res:?}");
+    assert_eq!(
+        fs::read_dir(unpacked.path()).unwrap().count(),
+        usize::from(preceding_symlink.is_some()),
+        "Nothing should be unpacked but the preceding symbolic link"
+    );
+}
+
+#[rstest]
+#[test_log::test]
+fn unpack_accepts_inner_symlink() {
+    let out_folder = tempdir().unwrap();
+    let archive_path = out_folder.path().join("archive.tar");
+    write_raw_entry(
+        &archive_path,
+        "sub/link",
+        EntryType::Symlink,
+        Some("../file"),
+    );
+
+    let unpacked = tempdir().unwrap();
+    unpack(
+        &archive_path,
+        unpacked.path(),
+        &[],
+        None,
+        &Progress::default(),
+    )
+    .expect("Link within the root should unpack");
+}
+
+#[rstest]
+#[case::under_limit(7, true)]
+#[case::over_limit(6, false)]
+#[test_log::test]
+fn unpack_size_limit(#[case] max_size: u64, #[case] accepted: bool) {
+    let out_folder = tempdir().unwrap();
+    let archive_path = out_folder.path().join("archive.tar");
+    write_raw_entry(&archive_path, "file", EntryType::Regular, None);
+
+    let unpacked = tempdir().unwrap();
+    let res = unpack(
+        &archive_path,
+        unpacked.path(),
+        &[],
+        Some(max_size),
+        &Progress::default(),
+    );
+    assert_eq!(
+        res.is_ok(),
+        accepted,
+        "Unpacking 7 bytes with a limit of {max_size} bytes, got {res:?}"
+    );
+    if !accepted {
+        assert!(
+            matches!(res, Err(InstallingError::Unpack(UnpackError::SizeLimit(limit))) if limit == max_size),
+            "Size limit should be reported"
+        );
+    }
+}
+
+#[rstest]
+#[test_log::test]
+fn reproducible_archive() {
+    let in_folder = tempdir().unwrap();
+    for name in ["b", "a", "c"] {
+        create_dir_all(in_folder.path().join(name)).unwrap();
+        fs::write(in_folder.path().join(name).join("file"), name).unwrap();
+    }
+    let out_folder = tempdir().unwrap();
+    let build = |name: &str| {
+        let archive_path = out_folder.path().join(name);
+        let mut archive = archive_at(&archive_path).reproducible(42);
+        archive.append_dir_all("dir", in_folder.path()).unwrap();
+        archive.finish().unwrap();
+        archive_path
+    };
+
+    let first = build("first.tar.gz");
+    // Touching the files between both runs shouldn't change anything
+    for name in ["a", "b", "c"] {
+        File::options()
+            .write(true)
+            .open(in_folder.path().join(name).join("file"))
+            .unwrap()
+            .set_modified(std::time::UNIX_EPOCH)
+            .unwrap();
+    }
+    let second = build("second.tar.gz");
+
+    assert_eq!(
+        fs::read(&first).unwrap(),
+        fs::read(&second).unwrap(),
+        "Both archives should be byte-identical"
+    );
+    let mut archive = open(&first, &[]).unwrap();
+    let entries: Vec<_> = archive
+        .entries()
+        .unwrap()
+        .map(|entry| {
+            let entry = entry.unwrap();
+            let header = entry.header();
+            assert_eq!(header.mtime().unwrap(), 42, "Timestamp should be fixed");
+            assert_eq!(header.uid().unwrap(), 0, "Ownership should be dropped");
+            entry.path().unwrap().display().to_string()
+        })
+        .collect();
+    assert_eq!(
+        entries,
+        [
+            "dir/",
+            "dir/a",
+            "dir/a/file",
+            "dir/b",
+            "dir/b/file",
+            "dir/c",
+            "dir/c/file",
+            MANIFEST_FILE
+        ],
+        "Entries should be sorted"
+    );
+}
@@ -1,7 +1,10 @@
-use std::path::PathBuf;
+use std::{path::PathBuf, sync::Arc};
 
-use clap::Parser;
-use offline_vendoring::{InstallSkip, InstallingError, PythonConfigLevel, install};
+use clap::{Parser, ValueEnum};
+use offline_vendoring::{
+    InstallOptions, InstallReport, InstallSkip, InstallingError, ProgressDisplay,
+    PythonConfigLevel, init_logging, inspect, install, parse_size, plan_install, uninstall,
+};
 use thiserror::Error;
 use tracing::debug;
 
@@ -17,7 +20,8 @@ enum Cargo {
 /// Install packaged external resources in offline environment
 struct Cli {
     /// Path to the archive containing external resources
-    archive: PathBuf,
+    #[clap(required_unless_present = "uninstall")]
+    archive: Option<PathBuf>,
 
     /// Set the configuration level of python configuration
     #[clap(long, short, value_enum, default_value = "user")]
@@ -30,6 +34,60 @@ struct Cli {
     /// Skip one or more install steps
     #[clap(long, short, value_enum)]
     skip: Vec<InstallSkip>,
+
+    /// Minisign public key the archive must be signed with (e.g., "minisign.pub").
+    /// Unsigned archives are rejected unless "--allow-unsigned" is set.
+    #[clap(long)]
+    trusted_key: Option<PathBuf>,
+    /// Accept an archive without signature even if a trusted key is set.
+    /// An archive with an invalid signature is still rejected.
+    #[clap(long, requires = "trusted_key")]
+    allow_unsigned: bool,
+
+    /// Age identity file (e.g., generated with "age-keygen") to decrypt an encrypted archive
+    #[clap(long, short)]
+    identity: Option<PathBuf>,
+
+    /// Refuse to unpack an archive whose content exceeds that size (e.g., "20G")
+    #[clap(long, value_parser = parse_size)]
+    max_unpacked_size: Option<u64>,
+
+    /// Merge the vendored crates and python packages with the ones of other archives installed with the same directory,
+    /// and point cargo and pip to it. Different versions of a crate or package already merged are kept and reported.
+    #[clap(long)]
+    merge_into: Option<PathBuf>,
+    /// Unpack the archive into that directory instead of a directory named after the archive settings
+    #[clap(long)]
+    unpack_dir: Option<PathBuf>,
+
+    /// Only check the archive integrity and list its content, without installing anything
+    #[clap(long, alias = "verify")]
+    inspect: bool,
+    /// Output format of the archive content listed by "--inspect"
+    #[clap(long, value_enum, default_value = "human", requires = "inspect")]
+    format: OutputFormat,
+
+    /// Write a JSON report of the run at that path: commands run, skipped steps, warnings and outcome of every step
+    #[clap(long, conflicts_with = "inspect")]
+    report: Option<PathBuf>,
+    /// Only print the commands that would be run and the config files that would be changed, with a diff.
+    /// The signature of the archive is still checked, but only its settings and manifest are read.
+    #[clap(long, conflicts_with_all = ["inspect", "report"])]
+    dry_run: bool,
+
+    /// Undo the configuration changes of previous installations, using the state file they saved
+    /// (e.g., "<unpacked directory>/install-state.json"): restore the previous cargo and pip configuration
+    /// and remove the installed cargo tools
+    #[clap(long, conflicts_with_all = ["archive", "inspect", "dry_run", "report"])]
+    uninstall: Option<PathBuf>,
+}
+
+#[derive(ValueEnum, Clone)]
+enum OutputFormat {
+    /// Human-readable listing
+    Human,
+    /// JSON document
+    Json,
 }
 
 #[derive(Error, Debug)]
@@ -39,25 +97,84 @@ enum CliError {
     InstallingError(#[from] Box<InstallingError>),
     #[error("Invalid archive path: {0}")]
     InvalidArchivePath(String),
+    #[error("Cannot serialize archive content: {0}")]
+    Serialize(#[from] serde_json::Error),
+    #[error("{0} install steps failed")]
+    FailedSteps(usize),
 }
 
 fn main() -> Result<(), CliError> {
-    // Install global subscriber configured based on RUST_LOG environment variable
-    tracing_subscriber::fmt::init();
+    // Phases and steps on the standard error, updated in place on a terminal, with the logs kept above them
+    let display = Arc::new(ProgressDisplay::default());
+    init_logging(Arc::clone(&display));
 
     let Cargo::OfflineInstall(cli) = Cargo::parse();
-    debug!("Archive file: {}", cli.archive.display());
-    if !cli.archive.is_file() {
-        return Err(CliError::InvalidArchivePath(
-            cli.archive.display().to_string(),
-        ));
+    if let Some(state_path) = &cli.uninstall {
+        let report = uninstall(state_path)?;
+        return print_report(&report);
+    }
+    let archive = cli.archive.unwrap_or_default();
+    debug!("Archive file: {}", archive.display());
+    if !archive.is_file() {
+        return Err(CliError::InvalidArchivePath(archive.display().to_string()));
     }
 
-    install(
-        cli.archive.as_path(),
-        &cli.python_config_level,
-        cli.rust_config_for.as_ref(),
-        &cli.skip,
-    )?;
-    Ok(())
+    if cli.inspect {
+        let summary = inspect(archive.as_path(), cli.identity.as_deref())?;
+        #[expect(
+            clippy::print_stdout,
+            reason = "the listing is the output of the command"
+        )]
+        match cli.format {
+            OutputFormat::Human => print!("{summary}"),
+            OutputFormat::Json => println!("{}", serde_json::to_string_pretty(&summary)?),
+        }
+        return Ok(());
+    }
+
+    let options = InstallOptions {
+        python_config_level: cli.python_config_level,
+        rust_config_for: cli.rust_config_for,
+        skip: cli.skip,
+        trusted_key: cli.trusted_key,
+        allow_unsigned: cli.allow_unsigned,
+        identity: cli.identity,
+        max_unpacked_size: cli.max_unpacked_size,
+        merge_into: cli.merge_into,
+        unpack_dir: cli.unpack_dir,
+        report: cli.report,
+        progress: Some(display),
+        ..Default::default()
+    };
+    if cli.dry_run {
+        let plan = plan_install(archive.as_path(), &options)?;
+        #[expect(clippy::print_stdout, reason = "the plan is the output of the command")]
+        {
+            print!("{plan}");
+        }
+        return Ok(());
+    }
+    let report = install(archive.as_path(), &options)?;
+    print_report(&report)
+}
+
+/// Print the outcome of every step, failing if any of them failed
+fn print_report(report: &InstallReport) -> Result<(), CliError> {
+    #[expect(
+        clippy::print_stdout,
+        reason = "the summary is the output of the command"
+    )]
+    {
+        print!("{report}");
+        if let Some(state_path) = &report.state_path {
+            println!(
+                "Undo the configuration changes with: cargo offline-install --uninstall {}",
+                state_path.display()
+            );
+        }
+    }
+    match report.failures().count() {
+        0 => Ok(()),
+        failures => Err(CliError::FailedSteps(failures)),
+    }
 }
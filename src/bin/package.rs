@@ -1,9 +1,12 @@
-use std::path::PathBuf;
+use std::{num::NonZeroUsize, path::PathBuf, sync::Arc};
 
 use clap::Parser;
-use offline_vendoring::{DownloadSkip, PackagingError, Settings, package};
+use offline_vendoring::{
+    DownloadSkip, PackageOptions, PackagingError, ProgressDisplay, check_settings, init_logging,
+    load_settings, package, parse_size, plan_package, settings_schema,
+};
 use thiserror::Error;
-use tracing::debug;
+use tracing::{debug, error, info};
 
 #[derive(Parser)]
 #[clap(name = "cargo")]
@@ -18,38 +21,151 @@ enum Cargo {
 struct Cli {
     /// Path to config without the file extension (supported formats: JSON, TOML, YAML, INI, RON)
     /// E.g., "config/settings" instead of "config/settings.json"
-    config: PathBuf,
+    #[clap(required_unless_present = "print_schema")]
+    config: Option<PathBuf>,
+    /// Merge this profile, among the "profiles" of the settings, on top of the settings
+    #[clap(long)]
+    profile: Option<String>,
     /// Skip one or more downloading steps
     #[clap(long, short, value_enum)]
     skip_download: Vec<DownloadSkip>,
+    /// Only package what changed since a previous archive (or its extracted "manifest.json").
+    /// The generated archive can only be installed on top of that previous archive.
+    #[clap(long)]
+    since: Option<PathBuf>,
+    /// Age identity file (e.g., generated with "age-keygen") to decrypt the previous archive given to --since
+    #[clap(long, requires = "since")]
+    identity: Option<PathBuf>,
+    /// Split the archive into volumes of at most that size (e.g., "4G", "700M", "4096").
+    /// Volumes are listed with their checksum in a ".volumes.json" index file.
+    #[clap(long, value_parser = parse_size)]
+    volume_size: Option<u64>,
+    /// Sign the archive (or its volume index) with this minisign secret key (e.g., "minisign.key").
+    /// The signature is written next to it with a ".minisig" extension.
+    #[clap(long)]
+    signing_key: Option<PathBuf>,
+    /// Encrypt the archive to this age public key (e.g., "age1..."), can be repeated.
+    /// Any of the matching identities can decrypt it.
+    #[clap(long, short)]
+    recipient: Vec<String>,
+
+    /// Build a byte-identical archive from the same inputs: entries are sorted, owned by root, dated from
+    /// SOURCE_DATE_EPOCH (or a fixed date if unset) and only keep their executable permission
+    #[clap(long, conflicts_with = "recipient")]
+    reproducible: bool,
+
+    /// Run up to that number of downloads at the same time: git clones, pip downloads of each requirement file
+    /// and the rust and python sections against each other. The archive content stays in the same order.
+    #[clap(long, short, default_value = "1")]
+    jobs: NonZeroUsize,
+
+    /// Write the archive into that directory instead of the current directory
+    #[clap(long)]
+    output_dir: Option<PathBuf>,
+    /// Download the resources into that directory instead of a directory named after the settings
+    #[clap(long)]
+    staging_dir: Option<PathBuf>,
+    /// Keep the downloads in that directory between runs: existing git mirrors are updated instead of cloned again,
+    /// pip reuses the packages already there and the vendored crates are re-synced
+    #[clap(long, conflicts_with = "staging_dir")]
+    cache_dir: Option<PathBuf>,
+    /// Skip the downloads that a previous run interrupted by a failure already finished
+    #[clap(long)]
+    resume: bool,
+    /// Name of the archive without its extension (e.g., "{name}-{version}-{date}").
+    /// Placeholders: {name} and {version} from the settings, {date} of creation and {git_sha} of the current directory
+    #[clap(long)]
+    name_template: Option<String>,
+
+    /// Write a JSON report of the run at that path: commands run, archived directories, skipped steps, warnings and outcome
+    #[clap(long)]
+    report: Option<PathBuf>,
+    /// Only print the commands that would be run and the directories that would be archived,
+    /// without downloading or writing anything
+    #[clap(long, conflicts_with = "report")]
+    dry_run: bool,
+    /// Only check the settings without downloading anything: paths exist, mirrors have distinct names,
+    /// install commands can be parsed, etc.
+    #[clap(long, conflicts_with_all = ["dry_run", "report"])]
+    check: bool,
+    /// Print the JSON Schema of the settings, e.g., for editors to complete settings files
+    #[clap(long, exclusive = true)]
+    print_schema: bool,
 }
 
 #[derive(Error, Debug)]
 /// Errors exposed to the CLI user
 enum CliError {
-    #[error("Invalid config: {0}")]
-    Config(#[from] config::ConfigError),
-    #[error("Cannot deserialize config: {0}")]
-    DeserializeConfig(String),
     #[error(transparent)]
     PackagingError(#[from] Box<PackagingError>),
+    #[error("{0} mistakes found in the settings")]
+    InvalidSettings(usize),
+    #[error("Cannot serialize the settings schema: {0}")]
+    Serialize(#[from] serde_json::Error),
 }
 
 fn main() -> Result<(), CliError> {
-    // Install global subscriber configured based on RUST_LOG environment variable
-    tracing_subscriber::fmt::init();
+    // Phases and steps on the standard error, updated in place on a terminal, with the logs kept above them
+    let display = Arc::new(ProgressDisplay::default());
+    init_logging(Arc::clone(&display));
 
     let Cargo::OfflinePackage(cli) = Cargo::parse();
-    debug!("Config file: {}", cli.config.display());
-
-    let settings = config::Config::builder()
-        .add_source(config::File::with_name(&cli.config.display().to_string()).required(false))
-        .build()?;
-    let settings: Settings = settings
-        .try_deserialize()
-        .map_err(|e| CliError::DeserializeConfig(e.to_string()))?;
-    debug!("Got the following settings: {settings:#?}");
+    if cli.print_schema {
+        #[expect(
+            clippy::print_stdout,
+            reason = "the schema is the output of the command"
+        )]
+        {
+            println!("{}", serde_json::to_string_pretty(&settings_schema())?);
+        }
+        return Ok(());
+    }
+    let config = cli.config.unwrap_or_default();
+    debug!("Config file: {}", config.display());
 
-    package(&settings, &cli.skip_download)?;
+    let settings = load_settings(&config, cli.profile.as_deref())?;
+    debug!("Got the following settings: {settings:#?}");
+    let options = PackageOptions {
+        skip: cli.skip_download,
+        since: cli.since,
+        volume_size: cli.volume_size,
+        signing_key: cli.signing_key,
+        recipients: cli.recipient,
+        identity: cli.identity,
+        reproducible: cli.reproducible,
+        jobs: Some(cli.jobs),
+        output_dir: cli.output_dir,
+        staging_dir: cli.staging_dir,
+        cache_dir: cli.cache_dir,
+        resume: cli.resume,
+        name_template: cli.name_template,
+        report: cli.report,
+        progress: Some(display),
+        ..Default::default()
+    };
+    if cli.check {
+        return match check_settings(&settings, &options).map_err(|e| *e) {
+            Err(PackagingError::InvalidSettings(errors)) => {
+                for e in &errors {
+                    error!("{e}");
+                }
+                Err(CliError::InvalidSettings(errors.len()))
+            }
+            res => {
+                res.map_err(Box::new)?;
+                info!("Settings are valid");
+                Ok(())
+            }
+        };
+    }
+    if cli.dry_run {
+        let plan = plan_package(&settings, &options)?;
+        #[expect(clippy::print_stdout, reason = "the plan is the output of the command")]
+        {
+            print!("{plan}");
+        }
+        return Ok(());
+    }
+    package(&settings, &options)?;
     Ok(())
 }
@@ -0,0 +1,479 @@
+use std::{
+    collections::BTreeMap,
+    fmt::{self, Display},
+    fs::{self, create_dir_all, remove_dir_all},
+    io::{self, ErrorKind},
+    path::{self, Path, PathBuf},
+    process::{Command, ExitStatus},
+    sync::{Mutex, PoisonError},
+    time::SystemTime,
+};
+
+use serde::{Deserialize, Serialize};
+use tracing::info;
+use walkdir::WalkDir;
+
+use crate::{
+    cassette::errors::CassetteError,
+    cmd::{CommandFailedError, CommandOutput, CommandRunner, CommandSpec, LocalCommandRunner},
+};
+
+pub mod errors;
+#[cfg(test)]
+mod test;
+
+/// Description of the recorded commands, in the cassette directory
+const CASSETTE_FILE: &str = "cassette.json";
+/// Files written by the recorded commands, in the cassette directory: `files/<index of the command>/<path>`
+const FILES_DIR: &str = "files";
+/// Stands for the root directory in the recorded commands, so that they can be replayed in another one
+const ROOT_PLACEHOLDER: &str = "{root}";
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+struct Cassette {
+    interactions: Vec<Interaction>,
+}
+
+/// What identifies a command to replay, with the root directory replaced by [`ROOT_PLACEHOLDER`]
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+struct Request {
+    program: String,
+    args: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    cwd: Option<String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    env: BTreeMap<String, String>,
+}
+
+impl Display for Request {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.program, self.args.join(" "))
+    }
+}
+
+/// A recorded command, with its outcome and what it wrote under the root directory
+#[derive(Clone, Debug, Deserialize, Serialize)]
+struct Interaction {
+    #[serde(flatten)]
+    request: Request,
+    outcome: Outcome,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    stdout: String,
+    /// Only the last lines for failed commands
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    stderr: String,
+    /// Directories created by the command, relative to the root directory
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    directories: Vec<String>,
+    /// Files created or modified by the command, relative to the root directory
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    files: Vec<String>,
+    /// Directories and files removed by the command, relative to the root directory.
+    /// The content of a removed directory is not listed.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    removed: Vec<String>,
+}
+
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+enum Outcome {
+    Success,
+    /// 1 if the command was killed by a signal
+    Failed {
+        exit_code: i32,
+    },
+    TimedOut,
+    NotStarted {
+        error: String,
+    },
+}
+
+impl Interaction {
+    fn new(
+        request: Request,
+        res: &Result<CommandOutput, Box<CommandFailedError>>,
+        root: &Root,
+    ) -> Self {
+        let (outcome, stdout, stderr) = match res.as_ref().map_err(|e| &**e) {
+            Ok(output) => (Outcome::Success, &*output.stdout, &*output.stderr),
+            Err(CommandFailedError::CommandStart(_, e)) => (
+                Outcome::NotStarted {
+                    error: e.to_string(),
+                },
+                "",
+                "",
+            ),
+            Err(CommandFailedError::CommandFailed(_, status, tail)) => (
+                Outcome::Failed {
+                    exit_code: status.code().unwrap_or(1),
+                },
+                "",
+                tail.as_str(),
+            ),
+            Err(CommandFailedError::TimedOut(_, _, tail)) => (Outcome::TimedOut, "", tail.as_str()),
+        };
+        Self {
+            request,
+            outcome,
+            stdout: root.hide(stdout),
+            stderr: root.hide(stderr),
+            directories: Vec::new(),
+            files: Vec::new(),
+            removed: Vec::new(),
+        }
+    }
+}
+
+/// Directory the recorded commands work in, absolute
+struct Root {
+    path: PathBuf,
+    display: String,
+}
+
+impl Root {
+    fn new(path: &Path) -> Result<Self, CassetteError> {
+        let path = path::absolute(path).map_err(|e| CassetteError::Root(path.to_path_buf(), e))?;
+        Ok(Self {
+            display: path.display().to_string(),
+            path,
+        })
+    }
+
+    fn hide(&self, text: &str) -> String {
+        text.replace(&self.display, ROOT_PLACEHOLDER)
+    }
+
+    fn show(&self, text: &str) -> String {
+        text.replace(ROOT_PLACEHOLDER, &self.display)
+    }
+
+    fn request(&self, command: &CommandSpec) -> Request {
+        Request {
+            program: command.program.clone(),
+            args: command.args.iter().map(|arg| self.hide(arg)).collect(),
+            cwd: command
+                .cwd
+                .as_ref()
+                .map(|cwd| self.hide(&cwd.display().to_string())),
+            env: command
+                .env
+                .iter()
+                .map(|(key, value)| (key.clone(), self.hide(value)))
+                .collect(),
+        }
+    }
+}
+
+/// Entry of the root directory, to find what a command changed
+#[derive(Eq, PartialEq)]
+enum Entry {
+    Directory,
+    File {
+        len: u64,
+        modified: Option<SystemTime>,
+    },
+}
+
+/// Directories and files under `root`, by path relative to it. Symbolic links are ignored.
+fn snapshot(root: &Path) -> io::Result<BTreeMap<PathBuf, Entry>> {
+    let mut entries = BTreeMap::new();
+    if !root.is_dir() {
+        return Ok(entries);
+    }
+    for entry in WalkDir::new(root).min_depth(1) {
+        let entry = entry?;
+        let path = entry
+            .path()
+            .strip_prefix(root)
+            .map_err(io::Error::other)?
+            .to_path_buf();
+        let metadata = entry.metadata()?;
+        if metadata.is_dir() {
+            entries.insert(path, Entry::Directory);
+        } else if metadata.is_file() {
+            entries.insert(
+                path,
+                Entry::File {
+                    len: metadata.len(),
+                    modified: metadata.modified().ok(),
+                },
+            );
+        }
+    }
+    Ok(entries)
+}
+
+/// `path` with `/` separators, whatever the platform
+fn portable(path: &Path) -> String {
+    path.components()
+        .map(|component| component.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// The command to report in errors
+fn std_command(spec: &CommandSpec) -> Command {
+    let mut command = Command::new(&spec.program);
+    command.args(&spec.args);
+    if let Some(cwd) = &spec.cwd {
+        command.current_dir(cwd);
+    }
+    command
+}
+
+#[cfg(unix)]
+fn exit_status(code: i32) -> ExitStatus {
+    use std::os::unix::process::ExitStatusExt;
+    // The exit code is the second byte of the wait status
+    ExitStatus::from_raw(code.saturating_mul(0x100))
+}
+
+#[cfg(windows)]
+fn exit_status(code: i32) -> ExitStatus {
+    use std::os::windows::process::ExitStatusExt;
+    ExitStatus::from_raw(code.cast_unsigned())
+}
+
+/// Runner recording the commands run by another runner into a cassette, with the files they write or remove
+/// under a root directory, e.g., a temporary directory holding the staging and unpack directories.
+///
+/// Commands run one at a time while recording, so that every file is recorded with the command writing it.
+/// Paths under the root directory are recorded relative to it, so that the cassette can be replayed
+/// by a [`CassettePlayer`] in another directory.
+pub struct CassetteRecorder<R: CommandRunner = LocalCommandRunner> {
+    runner: R,
+    root: Root,
+    /// Commands recorded so far, with the content of the files they wrote
+    recorded: Mutex<Vec<(Interaction, Vec<Vec<u8>>)>>,
+}
+
+impl<R: CommandRunner> CassetteRecorder<R> {
+    /// Record the commands run by `runner` and the files they write under `root`
+    ///
+    /// # Errors
+    ///
+    /// `root` cannot be made absolute
+    pub fn new(runner: R, root: &Path) -> Result<Self, CassetteError> {
+        Ok(Self {
+            runner,
+            root: Root::new(root)?,
+            recorded: Mutex::new(Vec::new()),
+        })
+    }
+
+    /// Write the commands recorded so far into the cassette directory `cassette_dir`, replacing its content
+    ///
+    /// # Errors
+    ///
+    /// The cassette cannot be written
+    pub fn save(&self, cassette_dir: &Path) -> Result<(), CassetteError> {
+        let recorded = self.recorded.lock().unwrap_or_else(PoisonError::into_inner);
+        let files_dir = cassette_dir.join(FILES_DIR);
+        if files_dir.is_dir() {
+            remove_dir_all(&files_dir).map_err(|e| CassetteError::Write(files_dir.clone(), e))?;
+        }
+        for (index, (interaction, contents)) in recorded.iter().enumerate() {
+            let interaction_dir = files_dir.join(index.to_string());
+            for (file, content) in interaction.files.iter().zip(contents) {
+                let path = interaction_dir.join(file);
+                path.parent()
+                    .map_or(Ok(()), create_dir_all)
+                    .and_then(|()| fs::write(&path, content))
+                    .map_err(|e| CassetteError::Write(path, e))?;
+            }
+        }
+        let cassette = Cassette {
+            interactions: recorded
+                .iter()
+                .map(|(interaction, _)| interaction.clone())
+                .collect(),
+        };
+        let path = cassette_dir.join(CASSETTE_FILE);
+        create_dir_all(cassette_dir)
+            .and_then(|()| serde_json::to_string_pretty(&cassette).map_err(io::Error::from))
+            .and_then(|json| fs::write(&path, json + "\n"))
+            .map_err(|e| CassetteError::Write(path, e))
+    }
+
+    /// Record `command`, run with the outcome `res`, and the files it wrote or removed since `before`
+    fn record(
+        &self,
+        command: &CommandSpec,
+        res: &Result<CommandOutput, Box<CommandFailedError>>,
+        before: &BTreeMap<PathBuf, Entry>,
+    ) -> io::Result<(Interaction, Vec<Vec<u8>>)> {
+        let mut interaction = Interaction::new(self.root.request(command), res, &self.root);
+        let mut contents = Vec::new();
+        let after = snapshot(&self.root.path)?;
+        // Paths are sorted, so a removed directory comes right before its removed content
+        let mut removed: Vec<&PathBuf> = Vec::new();
+        for path in before.keys().filter(|path| !after.contains_key(*path)) {
+            if !removed
+                .last()
+                .is_some_and(|parent| path.starts_with(parent))
+            {
+                removed.push(path);
+            }
+        }
+        interaction.removed = removed.into_iter().map(|path| portable(path)).collect();
+        for (path, entry) in after {
+            if before.get(&path) == Some(&entry) {
+                continue;
+            }
+            match entry {
+                Entry::Directory => interaction.directories.push(portable(&path)),
+                Entry::File { .. } => {
+                    contents.push(fs::read(self.root.path.join(&path))?);
+                    interaction.files.push(portable(&path));
+                }
+            }
+        }
+        Ok((interaction, contents))
+    }
+}
+
+impl<R: CommandRunner> CommandRunner for CassetteRecorder<R> {
+    fn run(&self, command: &CommandSpec) -> Result<CommandOutput, Box<CommandFailedError>> {
+        let mut recorded = self.recorded.lock().unwrap_or_else(PoisonError::into_inner);
+        let recording_error =
+            |e: io::Error| Box::new(CommandFailedError::CommandStart(std_command(command), e));
+        let before = snapshot(&self.root.path).map_err(recording_error)?;
+        let res = self.runner.run(command);
+        recorded.push(
+            self.record(command, &res, &before)
+                .map_err(recording_error)?,
+        );
+        res
+    }
+}
+
+/// Runner replaying the commands of a cassette recorded by a [`CassetteRecorder`], in another root directory.
+///
+/// Nothing is run: every command is matched with a recorded one by its program, arguments, working directory
+/// and environment, the files it removed are removed again, the files it wrote are written again,
+/// and its outcome is returned.
+/// Identical commands are replayed in the order they were recorded, and commands missing from the cassette
+/// fail to start.
+pub struct CassettePlayer {
+    cassette_dir: PathBuf,
+    root: Root,
+    interactions: Vec<Interaction>,
+    /// Whether each interaction was replayed
+    played: Mutex<Vec<bool>>,
+}
+
+impl CassettePlayer {
+    /// Replay the cassette directory `cassette_dir` with `root` as root directory
+    ///
+    /// # Errors
+    ///
+    /// The cassette cannot be read or parsed, or `root` cannot be made absolute
+    pub fn load(cassette_dir: &Path, root: &Path) -> Result<Self, CassetteError> {
+        let path = cassette_dir.join(CASSETTE_FILE);
+        let content =
+            fs::read_to_string(&path).map_err(|e| CassetteError::Read(path.clone(), e))?;
+        let cassette: Cassette =
+            serde_json::from_str(&content).map_err(|e| CassetteError::Parse(path, e))?;
+        Ok(Self {
+            cassette_dir: cassette_dir.to_path_buf(),
+            root: Root::new(root)?,
+            played: Mutex::new(vec![false; cassette.interactions.len()]),
+            interactions: cassette.interactions,
+        })
+    }
+
+    /// Recorded commands that were not replayed yet, e.g., to check that a run replayed the whole cassette
+    #[must_use]
+    pub fn unplayed(&self) -> Vec<String> {
+        let played = self.played.lock().unwrap_or_else(PoisonError::into_inner);
+        self.interactions
+            .iter()
+            .zip(played.iter())
+            .filter(|(_, played)| !**played)
+            .map(|(interaction, _)| interaction.request.to_string())
+            .collect()
+    }
+
+    /// Remove again the directories and files removed by the interaction at `index`,
+    /// and write again the ones it wrote
+    fn restore(&self, index: usize, interaction: &Interaction) -> io::Result<()> {
+        for removed in &interaction.removed {
+            let path = self.root.path.join(removed);
+            let res = if path.is_dir() {
+                remove_dir_all(&path)
+            } else {
+                fs::remove_file(&path)
+            };
+            match res {
+                Err(e) if e.kind() == ErrorKind::NotFound => {}
+                res => res?,
+            }
+        }
+        for directory in &interaction.directories {
+            create_dir_all(self.root.path.join(directory))?;
+        }
+        let files_dir = self.cassette_dir.join(FILES_DIR).join(index.to_string());
+        for file in &interaction.files {
+            let path = self.root.path.join(file);
+            if let Some(parent) = path.parent() {
+                create_dir_all(parent)?;
+            }
+            fs::copy(files_dir.join(file), path)?;
+        }
+        Ok(())
+    }
+}
+
+impl CommandRunner for CassettePlayer {
+    fn run(&self, command: &CommandSpec) -> Result<CommandOutput, Box<CommandFailedError>> {
+        let request = self.root.request(command);
+        let mut played = self.played.lock().unwrap_or_else(PoisonError::into_inner);
+        let Some((index, interaction)) = self
+            .interactions
+            .iter()
+            .enumerate()
+            .zip(played.iter_mut())
+            .find(|((_, interaction), played)| !**played && interaction.request == request)
+            .map(|(found, played)| {
+                *played = true;
+                found
+            })
+        else {
+            return Err(Box::new(CommandFailedError::CommandStart(
+                std_command(command),
+                io::Error::new(
+                    ErrorKind::NotFound,
+                    format!(
+                        "'{request}' is not in the cassette {}",
+                        self.cassette_dir.display()
+                    ),
+                ),
+            )));
+        };
+        drop(played);
+        info!("Replaying '{request}'");
+        self.restore(index, interaction)
+            .map_err(|e| Box::new(CommandFailedError::CommandStart(std_command(command), e)))?;
+        let output = CommandOutput {
+            stdout: self.root.show(&interaction.stdout),
+            stderr: self.root.show(&interaction.stderr),
+        };
+        match &interaction.outcome {
+            Outcome::Success => Ok(output),
+            Outcome::Failed { exit_code } => Err(Box::new(CommandFailedError::CommandFailed(
+                std_command(command),
+                exit_status(*exit_code),
+                output.tail(),
+            ))),
+            Outcome::TimedOut => Err(Box::new(CommandFailedError::TimedOut(
+                std_command(command),
+                command.timeout.unwrap_or_default(),
+                output.tail(),
+            ))),
+            Outcome::NotStarted { error } => Err(Box::new(CommandFailedError::CommandStart(
+                std_command(command),
+                io::Error::other(error.clone()),
+            ))),
+        }
+    }
+}
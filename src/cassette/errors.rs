@@ -0,0 +1,15 @@
+use std::path::PathBuf;
+
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum CassetteError {
+    #[error("Cannot resolve the root directory '{0}': {1}")]
+    Root(PathBuf, #[source] std::io::Error),
+    #[error("Cannot read the cassette at '{0}': {1}")]
+    Read(PathBuf, #[source] std::io::Error),
+    #[error("Cannot parse the cassette at '{0}': {1}")]
+    Parse(PathBuf, #[source] serde_json::Error),
+    #[error("Cannot write the cassette at '{0}': {1}")]
+    Write(PathBuf, #[source] std::io::Error),
+}
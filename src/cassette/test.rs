@@ -0,0 +1,157 @@
+use std::fs::{self, create_dir_all};
+use std::io;
+use std::path::Path;
+use std::process::Command;
+use std::time::Duration;
+
+use rstest::rstest;
+use tempfile::tempdir;
+
+use crate::cassette::exit_status;
+use crate::cmd::{
+    CommandFailedError, CommandOutput, CommandRunner, CommandSpec, MockCommandRunner,
+};
+use crate::{CassettePlayer, CassetteRecorder};
+
+/// Build command run in `root`, writing into `root/out`
+fn build_command(root: &Path) -> CommandSpec {
+    CommandSpec::new(
+        "tool",
+        ["build".to_owned(), root.join("out").display().to_string()],
+    )
+    .cwd(root)
+    .env("TOOL_CACHE", root.join("cache").display().to_string())
+}
+
+#[rstest]
+#[test_log::test]
+fn replay_in_another_root() {
+    let recorded_root = tempdir().unwrap();
+    let replayed_root = tempdir().unwrap();
+    let cassette_dir = tempdir().unwrap();
+    let mut runner = MockCommandRunner::new();
+    runner.expect_run().times(1).returning(|command| {
+        let cwd = command.cwd.as_ref().unwrap();
+        create_dir_all(cwd.join("out").join("empty")).unwrap();
+        fs::write(cwd.join("out").join("data.txt"), "built").unwrap();
+        Ok(CommandOutput {
+            stdout: format!("built into {}", command.args[1]),
+            stderr: String::new(),
+        })
+    });
+    let recorder = CassetteRecorder::new(runner, recorded_root.path()).unwrap();
+    recorder
+        .run(&build_command(recorded_root.path()))
+        .expect("The command should be recorded");
+    recorder
+        .save(cassette_dir.path())
+        .expect("Saving should work");
+
+    let player = CassettePlayer::load(cassette_dir.path(), replayed_root.path()).unwrap();
+    let output = player
+        .run(&build_command(replayed_root.path()))
+        .expect("The command should be replayed");
+
+    let out = replayed_root.path().join("out");
+    assert_eq!(
+        output.stdout,
+        format!("built into {}", out.display()),
+        "The recorded root should be replaced by the replayed one in the output"
+    );
+    assert_eq!(
+        fs::read_to_string(out.join("data.txt")).ok().as_deref(),
+        Some("built"),
+        "Written files should be written again"
+    );
+    assert!(
+        out.join("empty").is_dir(),
+        "Created directories should be created again"
+    );
+    assert!(
+        player.unplayed().is_empty(),
+        "The whole cassette should be replayed"
+    );
+    let res = player.run(&build_command(replayed_root.path()));
+    assert!(
+        matches!(res.as_ref().map_err(|e| &**e), Err(CommandFailedError::CommandStart(_, e)) if e.kind() == io::ErrorKind::NotFound),
+        "Commands are only replayed as many times as they were recorded, got {res:?}"
+    );
+}
+
+#[rstest]
+#[test_log::test]
+fn replay_removals() {
+    let root = tempdir().unwrap();
+    let cassette_dir = tempdir().unwrap();
+    let out = root.path().join("out");
+    create_dir_all(out.join("stale")).unwrap();
+    fs::write(out.join("stale").join("old.txt"), "old").unwrap();
+    fs::write(out.join("old.txt"), "old").unwrap();
+    let mut runner = MockCommandRunner::new();
+    runner.expect_run().times(1).returning(|command| {
+        let out = Path::new(&command.args[1]);
+        fs::remove_dir_all(out.join("stale")).unwrap();
+        fs::remove_file(out.join("old.txt")).unwrap();
+        Ok(CommandOutput::default())
+    });
+    let recorder = CassetteRecorder::new(runner, root.path()).unwrap();
+    recorder.run(&build_command(root.path())).unwrap();
+    recorder.save(cassette_dir.path()).unwrap();
+    create_dir_all(out.join("stale")).unwrap();
+    fs::write(out.join("stale").join("old.txt"), "old").unwrap();
+    fs::write(out.join("old.txt"), "old").unwrap();
+
+    let player = CassettePlayer::load(cassette_dir.path(), root.path()).unwrap();
+    player
+        .run(&build_command(root.path()))
+        .expect("The command should be replayed");
+
+    assert!(
+        !out.join("stale").exists(),
+        "Removed directories should be removed again"
+    );
+    assert!(
+        !out.join("old.txt").exists(),
+        "Removed files should be removed again"
+    );
+}
+
+#[rstest]
+#[case::failed(
+    CommandFailedError::CommandFailed(Command::new("tool"), exit_status(3), "no space left".to_owned()),
+    |e: &CommandFailedError| matches!(e, CommandFailedError::CommandFailed(_, status, tail) if status.code() == Some(3) && tail == "no space left")
+)]
+#[case::timed_out(
+    CommandFailedError::TimedOut(Command::new("tool"), Duration::from_secs(5), "still building".to_owned()),
+    |e: &CommandFailedError| matches!(e, CommandFailedError::TimedOut(_, timeout, tail) if *timeout == Duration::from_secs(5) && tail == "still building")
+)]
+#[case::not_started(
+    CommandFailedError::CommandStart(Command::new("tool"), io::Error::other("not found")),
+    |e: &CommandFailedError| matches!(e, CommandFailedError::CommandStart(_, e) if e.to_string() == "not found")
+)]
+#[test_log::test]
+fn replay_failures(
+    #[case] error: CommandFailedError,
+    #[case] expected: fn(&CommandFailedError) -> bool,
+) {
+    let root = tempdir().unwrap();
+    let cassette_dir = tempdir().unwrap();
+    let mut runner = MockCommandRunner::new();
+    let mut error = Some(error);
+    runner
+        .expect_run()
+        .times(1)
+        .returning(move |_| Err(Box::new(error.take().unwrap())));
+    let recorder = CassetteRecorder::new(runner, root.path()).unwrap();
+    let command = CommandSpec::new("tool", ["build"]).timeout(Duration::from_secs(5));
+    let _ = recorder.run(&command);
+    recorder.save(cassette_dir.path()).unwrap();
+
+    let player = CassettePlayer::load(cassette_dir.path(), root.path()).unwrap();
+    let res = player.run(&command);
+
+    assert!(
+        res.as_ref().is_err_and(|e| expected(e)),
+        "The recorded failure should be replayed, got {res:?}"
+    );
+}
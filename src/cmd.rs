@@ -1,48 +1,425 @@
-use std::{path::PathBuf, process::Command};
+use std::{
+    collections::BTreeMap,
+    io::{self, Read},
+    path::PathBuf,
+    process::{Child, Command, ExitStatus, Stdio},
+    sync::{Arc, Mutex, PoisonError},
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
 
 use thiserror::Error;
-use tracing::info;
+use tracing::{info, warn};
+
+use crate::report::Recorder;
+
+#[cfg(test)]
+mod test;
+
+/// Lines of output kept in the errors of failed commands
+const OUTPUT_TAIL_LINES: usize = 20;
+/// How often a command with a timeout is checked for completion
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+/// Output of git, cargo (through curl) and pip when they fail because of the network, in lowercase
+const NETWORK_FAILURES: &[&str] = &[
+    "could not resolve host",
+    "couldn't resolve host",
+    "temporary failure in name resolution",
+    "name or service not known",
+    "connection refused",
+    "connection reset",
+    "connection timed out",
+    "timeout was reached",
+    "network is unreachable",
+    "failed to connect",
+    "the remote end hung up unexpectedly",
+    "early eof",
+    "spurious network error",
+    "max retries exceeded",
+    "read timed out",
+];
 
 #[derive(Error, Debug)]
 pub enum CommandFailedError {
     #[error("Failed to start command '{0:#?}': {1}")]
     CommandStart(Command, #[source] std::io::Error),
-    #[error("Command '{0:#?}' exited with error code {1}")]
-    CommandFailed(Command, std::process::ExitStatus),
+    #[error("Command '{0:#?}' exited with error code {1}{tail}", tail = format_tail(.2))]
+    CommandFailed(Command, ExitStatus, String),
+    #[error("Command '{0:#?}' was killed after {1:?}{tail}", tail = format_tail(.2))]
+    TimedOut(Command, Duration, String),
+}
+
+impl CommandFailedError {
+    /// Whether running the command again might succeed: it timed out,
+    /// or the last lines of its output contain one of the `transient_failures` (case-insensitive)
+    #[must_use]
+    pub fn is_transient(&self, transient_failures: &[&str]) -> bool {
+        match self {
+            Self::TimedOut(..) => true,
+            Self::CommandFailed(_, _, tail) => {
+                let tail = tail.to_lowercase();
+                transient_failures
+                    .iter()
+                    .any(|failure| tail.contains(&failure.to_lowercase()))
+            }
+            Self::CommandStart(..) => false,
+        }
+    }
+}
+
+fn format_tail(tail: &str) -> String {
+    if tail.is_empty() {
+        String::new()
+    } else {
+        format!(", last output:\n{tail}")
+    }
+}
+
+/// How many times a failing command is run, how long to wait between two runs, and which failures are retried
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct RetryPolicy {
+    /// Number of runs, including the first one
+    pub attempts: u32,
+    /// Wait before the second run, doubled before every following one
+    pub backoff: Duration,
+    /// Failures worth running the command again for, found in the last lines of its output (case-insensitive).
+    /// Timed out runs are always run again.
+    pub transient_failures: &'static [&'static str],
+}
+
+impl RetryPolicy {
+    /// Run the command once
+    pub const NONE: Self = Self {
+        attempts: 1,
+        backoff: Duration::ZERO,
+        transient_failures: &[],
+    };
+    /// Downloads that may fail because of the network: 3 runs, 2 then 4 seconds apart,
+    /// only if they timed out or their output tells of a network failure (e.g., "Could not resolve host")
+    pub const NETWORK: Self = Self {
+        attempts: 3,
+        backoff: Duration::from_secs(2),
+        transient_failures: NETWORK_FAILURES,
+    };
+
+    /// Wait after the failed run `attempt` (from 1)
+    fn delay(&self, attempt: u32) -> Duration {
+        self.backoff
+            .saturating_mul(2_u32.saturating_pow(attempt.saturating_sub(1)))
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self::NONE
+    }
+}
+
+/// A command to run: its program, arguments, working directory, environment, timeout and retry policy
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+#[must_use]
+pub struct CommandSpec {
+    pub program: String,
+    pub args: Vec<String>,
+    /// Run in the current directory if unset
+    pub cwd: Option<PathBuf>,
+    /// Variables set on top of the inherited environment
+    pub env: BTreeMap<String, String>,
+    /// Kill the command if it runs for longer, never if unset
+    pub timeout: Option<Duration>,
+    pub retry: RetryPolicy,
+    /// Only reads (e.g., the current commit), so it is run even when planning a run
+    pub read_only: bool,
+}
+
+impl CommandSpec {
+    pub fn new<P: Into<String>, I: IntoIterator<Item = A>, A: Into<String>>(
+        program: P,
+        args: I,
+    ) -> Self {
+        Self {
+            program: program.into(),
+            args: args.into_iter().map(Into::into).collect(),
+            ..Self::default()
+        }
+    }
+
+    pub fn cwd<P: Into<PathBuf>>(mut self, cwd: P) -> Self {
+        self.cwd = Some(cwd.into());
+        self
+    }
+
+    pub fn env<K: Into<String>, V: Into<String>>(mut self, key: K, value: V) -> Self {
+        self.env.insert(key.into(), value.into());
+        self
+    }
+
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    pub fn retry(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    pub fn read_only(mut self) -> Self {
+        self.read_only = true;
+        self
+    }
 }
 
-/// This trait's purpose is mainly to be able to capture its implementation in mockall
+/// Captured output of a command
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct CommandOutput {
+    pub stdout: String,
+    pub stderr: String,
+}
+
+impl CommandOutput {
+    /// Last lines of the output, to explain a failure: the ones of stderr, or of stdout if stderr is empty
+    #[must_use]
+    pub fn tail(&self) -> String {
+        let output = if self.stderr.trim().is_empty() {
+            &self.stdout
+        } else {
+            &self.stderr
+        };
+        let lines: Vec<_> = output.trim_end().lines().collect();
+        lines
+            .get(lines.len().saturating_sub(OUTPUT_TAIL_LINES)..)
+            .unwrap_or_default()
+            .join("\n")
+    }
+}
+
+/// Runs the commands of packaging and installation steps, e.g., to audit them or run them in a sandbox.
+///
+/// Commands are shared between the downloads running at the same time, hence `Send + Sync`.
+/// Failed commands are retried by the packaging and the installation according to their [`RetryPolicy`],
+/// runners only run them once.
 #[cfg_attr(test, mockall::automock)]
-pub(crate) trait CommandRunner {
-    fn run_cmd(
-        cmd: &str,
-        args: &[String],
-        cwd: Option<PathBuf>,
-    ) -> Result<(), Box<CommandFailedError>>;
+pub trait CommandRunner: Send + Sync {
+    /// Run `command` and return its captured output
+    ///
+    /// # Errors
+    ///
+    /// The command cannot be started, fails or times out
+    fn run(&self, command: &CommandSpec) -> Result<CommandOutput, Box<CommandFailedError>>;
 }
 
 /// A structure running requested command on the local machine
-pub(crate) struct LocalCommandRunner;
+#[derive(Clone, Copy, Debug, Default)]
+pub struct LocalCommandRunner;
+
+impl LocalCommandRunner {
+    fn command(spec: &CommandSpec) -> Command {
+        info!("Running '{} {}'", spec.program, spec.args.join(" "));
+        let mut command = Command::new(&spec.program);
+        command
+            .args(&spec.args)
+            .envs(&spec.env)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+        if let Some(cwd) = &spec.cwd {
+            command.current_dir(cwd);
+        }
+        command
+    }
+
+    /// Wait for `child` to exit, killing it after `timeout` if set. Return `None` if it was killed.
+    fn wait(child: &mut Child, timeout: Option<Duration>) -> io::Result<Option<ExitStatus>> {
+        let Some(timeout) = timeout else {
+            return child.wait().map(Some);
+        };
+        let start = Instant::now();
+        loop {
+            if let Some(status) = child.try_wait()? {
+                return Ok(Some(status));
+            }
+            if start.elapsed() >= timeout {
+                child.kill()?;
+                child.wait()?;
+                return Ok(None);
+            }
+            thread::sleep(POLL_INTERVAL);
+        }
+    }
+}
 
 impl CommandRunner for LocalCommandRunner {
-    fn run_cmd(
-        cmd: &str,
-        args: &[String],
-        cwd: Option<PathBuf>,
-    ) -> Result<(), Box<CommandFailedError>> {
-        info!("Running '{cmd} {}'", args.join(" "));
-        let mut cmd = Command::new(cmd);
-        cmd.args(args);
-        if let Some(cwd) = cwd {
-            cmd.current_dir(cwd);
-        }
-        let status = match cmd.status() {
-            Ok(status) => status,
-            Err(e) => return Err(Box::new(CommandFailedError::CommandStart(cmd, e))),
+    fn run(&self, spec: &CommandSpec) -> Result<CommandOutput, Box<CommandFailedError>> {
+        let mut command = Self::command(spec);
+        let mut child = match command.spawn() {
+            Ok(child) => child,
+            Err(e) => return Err(Box::new(CommandFailedError::CommandStart(command, e))),
+        };
+        // Read both outputs while the command runs, so that it never blocks on a full pipe
+        let stdout = Capture::start(child.stdout.take(), &spec.program);
+        let stderr = Capture::start(child.stderr.take(), &spec.program);
+        let status = Self::wait(&mut child, spec.timeout);
+        // Processes started by a killed command may keep its output open, don't wait for them
+        let finished = matches!(status, Ok(Some(_)));
+        let output = CommandOutput {
+            stdout: stdout.finish(finished),
+            stderr: stderr.finish(finished),
         };
-        if !status.success() {
-            return Err(Box::new(CommandFailedError::CommandFailed(cmd, status)));
+        match status {
+            Err(e) => Err(Box::new(CommandFailedError::CommandStart(command, e))),
+            Ok(None) => Err(Box::new(CommandFailedError::TimedOut(
+                command,
+                spec.timeout.unwrap_or_default(),
+                output.tail(),
+            ))),
+            Ok(Some(status)) if !status.success() => Err(Box::new(
+                CommandFailedError::CommandFailed(command, status, output.tail()),
+            )),
+            Ok(Some(_)) => Ok(output),
+        }
+    }
+}
+
+/// Output stream of a command, read into a buffer by a thread of its own.
+///
+/// Every line is logged as soon as it is read, so that long commands show their output while they run.
+struct Capture {
+    buffer: Arc<Mutex<Vec<u8>>>,
+    reader: Option<JoinHandle<()>>,
+}
+
+impl Capture {
+    /// Start reading `stream`, the output of `program`
+    fn start<R: Read + Send + 'static>(stream: Option<R>, program: &str) -> Self {
+        let buffer = Arc::new(Mutex::new(Vec::new()));
+        let reader = stream.map(|mut stream| {
+            let buffer = Arc::clone(&buffer);
+            let program = program.to_owned();
+            thread::spawn(move || {
+                let mut chunk = [0; 8192];
+                // Start of the line being read
+                let mut line_start = 0;
+                while let Ok(read @ 1..) = stream.read(&mut chunk) {
+                    let mut buffer = buffer.lock().unwrap_or_else(PoisonError::into_inner);
+                    buffer.extend(chunk.get(..read).unwrap_or_default());
+                    while let Some(line_end) = buffer
+                        .get(line_start..)
+                        .and_then(|rest| rest.iter().position(|&byte| byte == b'\n'))
+                        .map(|end| line_start.saturating_add(end))
+                    {
+                        log_line(&program, buffer.get(line_start..line_end));
+                        line_start = line_end.saturating_add(1);
+                    }
+                }
+                let buffer = buffer.lock().unwrap_or_else(PoisonError::into_inner);
+                if line_start < buffer.len() {
+                    log_line(&program, buffer.get(line_start..));
+                }
+            })
+        });
+        Self { buffer, reader }
+    }
+
+    /// What was read, once the stream is closed if `wait` is set or so far otherwise
+    fn finish(self, wait: bool) -> String {
+        if wait && let Some(reader) = self.reader {
+            // The reader never panics
+            let _ = reader.join();
+        }
+        let buffer = self.buffer.lock().unwrap_or_else(PoisonError::into_inner);
+        String::from_utf8_lossy(&buffer).into_owned()
+    }
+}
+
+/// Log a line of the output of `program`
+fn log_line(program: &str, line: Option<&[u8]>) {
+    info!(
+        "[{program}] {}",
+        String::from_utf8_lossy(line.unwrap_or_default()).trim_end()
+    );
+}
+
+/// A structure only logging requested commands, to plan a run without changing anything
+pub(crate) struct DryRunCommandRunner;
+
+impl CommandRunner for DryRunCommandRunner {
+    /// Only read-only commands are run, the output of the others is always empty
+    fn run(&self, command: &CommandSpec) -> Result<CommandOutput, Box<CommandFailedError>> {
+        if command.read_only {
+            return LocalCommandRunner.run(command);
+        }
+        info!("Would run '{} {}'", command.program, command.args.join(" "));
+        Ok(CommandOutput::default())
+    }
+}
+
+/// Runner recording every command run by `runner` in the report of its run, whatever the runner,
+/// and running failed commands again according to their [`RetryPolicy`]
+pub(crate) struct RecordingRunner<'r> {
+    runner: &'r dyn CommandRunner,
+    /// Commands are only planned, so they have no exit status
+    dry_run: bool,
+    pub(crate) recorder: Recorder,
+}
+
+impl<'r> RecordingRunner<'r> {
+    pub(crate) fn new(runner: &'r dyn CommandRunner, dry_run: bool, recorder: Recorder) -> Self {
+        Self {
+            runner,
+            dry_run,
+            recorder,
+        }
+    }
+
+    /// Run `command` once and record it
+    fn record(&self, command: &CommandSpec) -> Result<CommandOutput, Box<CommandFailedError>> {
+        let start = Instant::now();
+        let res = self.runner.run(command);
+        let status = match &res {
+            _ if self.dry_run => None,
+            Ok(_) => Some(ExitStatus::default()),
+            Err(e) => match **e {
+                CommandFailedError::CommandFailed(_, status, _) => Some(status),
+                CommandFailedError::CommandStart(..) | CommandFailedError::TimedOut(..) => None,
+            },
+        };
+        let duration = if self.dry_run {
+            Duration::ZERO
+        } else {
+            start.elapsed()
+        };
+        self.recorder.record_command(
+            &command.program,
+            &command.args,
+            command.cwd.clone(),
+            duration,
+            status,
+        );
+        res
+    }
+}
+
+impl CommandRunner for RecordingRunner<'_> {
+    fn run(&self, command: &CommandSpec) -> Result<CommandOutput, Box<CommandFailedError>> {
+        let mut attempt = 1;
+        loop {
+            match self.record(command) {
+                Err(e)
+                    if attempt < command.retry.attempts
+                        && e.is_transient(command.retry.transient_failures) =>
+                {
+                    let delay = command.retry.delay(attempt);
+                    warn!(
+                        "'{} {}' failed (attempt {attempt}/{}), retrying in {delay:?}: {e}",
+                        command.program,
+                        command.args.join(" "),
+                        command.retry.attempts
+                    );
+                    thread::sleep(delay);
+                    attempt = attempt.saturating_add(1);
+                }
+                res => return res,
+            }
         }
-        Ok(())
     }
 }
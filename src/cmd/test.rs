@@ -0,0 +1,257 @@
+use std::fs::{self, create_dir_all};
+use std::os::unix::process::ExitStatusExt;
+use std::path::PathBuf;
+use std::process::{Command, ExitStatus};
+use std::time::{Duration, Instant};
+
+use mockall::predicate::{eq, function};
+use rstest::rstest;
+use tempfile::tempdir;
+use url::Url;
+
+use crate::cmd::{
+    CommandFailedError, CommandOutput, CommandRunner, CommandSpec, DryRunCommandRunner,
+    LocalCommandRunner, MockCommandRunner, RecordingRunner, RetryPolicy,
+};
+use crate::report::Recorder;
+use crate::test::command;
+use crate::{
+    GitMirrors, InstallOptions, InstallSkip, PackageOptions, Settings, install_with_runner,
+    package_with_runner,
+};
+
+#[rstest]
+#[test_log::test]
+fn package_and_install_through_runner() {
+    let folder = tempdir().unwrap();
+    let settings = Settings {
+        git_mirrors: GitMirrors::builder()
+            .mirror(
+                Url::parse("https://github.com/rustsec/advisory-db").unwrap(),
+                Url::parse("https://private.domain/global/advisory-db").unwrap(),
+            )
+            .build(),
+        ..Settings::default()
+    };
+    let mut runner = MockCommandRunner::new();
+    runner
+        .expect_run()
+        .with(command(
+            eq("git"),
+            function(|args: &[String]| args[0] == "clone"),
+            function(|_: &Option<PathBuf>| true),
+        ))
+        .times(1)
+        .returning(|command| {
+            create_dir_all(command.cwd.as_ref().unwrap().join(&command.args[3])).unwrap();
+            Ok(CommandOutput::default())
+        });
+    runner
+        .expect_run()
+        .with(command(
+            eq("git"),
+            function(|args: &[String]| args[0] == "for-each-ref"),
+            function(|_: &Option<PathBuf>| true),
+        ))
+        .times(1)
+        .returning(|_| Ok(CommandOutput::default()));
+    let unpack_dir = folder.path().join("unpacked");
+    runner
+        .expect_run()
+        .with(command(
+            eq("git"),
+            eq([
+                "push".to_owned(),
+                "--mirror".to_owned(),
+                "https://private.domain/global/advisory-db".to_owned(),
+            ]),
+            function(|cwd: &Option<PathBuf>| {
+                cwd.as_ref()
+                    .is_some_and(|cwd| cwd.ends_with("mirrors/advisory-db"))
+            }),
+        ))
+        .times(1)
+        .returning(|_| Ok(CommandOutput::default()));
+
+    let report_path = folder.path().join("report.json");
+    let package_options = PackageOptions {
+        staging_dir: Some(folder.path().join("staging")),
+        output_dir: Some(folder.path().join("out")),
+        report: Some(report_path.clone()),
+        ..PackageOptions::default()
+    };
+    package_with_runner(&settings, &package_options, &runner)
+        .expect("Packaging should run its commands through the runner");
+    let install_options = InstallOptions {
+        skip: vec![
+            InstallSkip::RustConfig,
+            InstallSkip::RustTools,
+            InstallSkip::PythonConfig,
+        ],
+        unpack_dir: Some(unpack_dir),
+        ..InstallOptions::default()
+    };
+    let install_report = install_with_runner(
+        &folder.path().join("out").join("offline-vendoring.tar.gz"),
+        &install_options,
+        &runner,
+    )
+    .expect("Installing should run its commands through the runner");
+
+    assert!(
+        install_report.is_success(),
+        "Every step should succeed: {install_report}"
+    );
+    let report: serde_json::Value =
+        serde_json::from_slice(&fs::read(&report_path).unwrap()).unwrap();
+    assert!(
+        report["commands"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .any(|command| command["command"] == "git"
+                && command["args"][0] == "clone"
+                && command["success"] == true),
+        "Commands of any runner should be reported, got {report}"
+    );
+}
+
+#[rstest]
+#[test_log::test]
+fn capture_output_with_env() {
+    let output = LocalCommandRunner
+        .run(
+            &CommandSpec::new("sh", ["-c", "echo $GREETING; echo warning >&2"])
+                .env("GREETING", "hello"),
+        )
+        .expect("Command should succeed");
+
+    assert_eq!(
+        output,
+        CommandOutput {
+            stdout: "hello\n".to_owned(),
+            stderr: "warning\n".to_owned(),
+        },
+        "Both outputs should be captured, with the variables set"
+    );
+}
+
+#[rstest]
+#[case::read_only(CommandSpec::new("sh", ["-c", "echo read"]).read_only(), "read\n")]
+#[case::changing(CommandSpec::new("sh", ["-c", "echo changed"]), "")]
+#[test_log::test]
+fn only_run_read_only_commands_on_dry_run(#[case] command: CommandSpec, #[case] stdout: &str) {
+    let output = DryRunCommandRunner
+        .run(&command)
+        .expect("Planning should succeed");
+
+    assert_eq!(
+        output.stdout, stdout,
+        "Only read-only commands should be run when planning"
+    );
+}
+
+#[rstest]
+#[test_log::test]
+fn report_output_tail() {
+    let res = LocalCommandRunner.run(&CommandSpec::new(
+        "sh",
+        [
+            "-c",
+            "for i in $(seq 1 30); do echo line $i >&2; done; exit 3",
+        ],
+    ));
+
+    let Err(e) = res else {
+        panic!("Command should fail, got {res:?}");
+    };
+    let CommandFailedError::CommandFailed(_, status, tail) = *e else {
+        panic!("Command should exit with an error, got {e:?}");
+    };
+    assert_eq!(status.code(), Some(3), "Exit code should be kept");
+    assert_eq!(
+        tail.lines().collect::<Vec<_>>(),
+        (11..=30).map(|i| format!("line {i}")).collect::<Vec<_>>(),
+        "Only the last lines of stderr should be kept"
+    );
+}
+
+#[rstest]
+#[test_log::test]
+fn kill_after_timeout() {
+    let start = Instant::now();
+
+    let res = LocalCommandRunner.run(
+        &CommandSpec::new("sh", ["-c", "echo started; sleep 10"])
+            .timeout(Duration::from_millis(200)),
+    );
+
+    assert!(
+        start.elapsed() < Duration::from_secs(5),
+        "Command should be killed at its timeout"
+    );
+    assert!(
+        matches!(res.as_ref().map_err(|e| &**e), Err(CommandFailedError::TimedOut(_, _, tail)) if tail == "started"),
+        "Timeout should be reported with the output so far, got {res:?}"
+    );
+}
+
+#[rstest]
+#[case::network(
+    || CommandFailedError::CommandFailed(
+        Command::new("git"),
+        ExitStatus::from_raw(128 << 8),
+        "fatal: unable to access 'https://github.com/rustsec/advisory-db/': Could not resolve host: github.com".to_owned()
+    ),
+    3,
+    true
+)]
+#[case::timed_out(
+    || CommandFailedError::TimedOut(Command::new("git"), Duration::from_secs(1), String::new()),
+    3,
+    true
+)]
+#[case::other_failure(
+    || CommandFailedError::CommandFailed(
+        Command::new("git"),
+        ExitStatus::from_raw(128 << 8),
+        "fatal: repository 'https://github.com/rustsec/advisory-db/' not found".to_owned()
+    ),
+    1,
+    false
+)]
+#[case::missing_program(
+    || CommandFailedError::CommandStart(Command::new("git"), std::io::Error::other("not found")),
+    1,
+    false
+)]
+#[test_log::test]
+fn retry_transient_failures(
+    #[case] error: fn() -> CommandFailedError,
+    #[case] expected_runs: usize,
+    #[case] success: bool,
+) {
+    let mut runner = MockCommandRunner::new();
+    runner
+        .expect_run()
+        .times(expected_runs.min(2))
+        .returning(move |_| Err(Box::new(error())));
+    runner
+        .expect_run()
+        .times(expected_runs.saturating_sub(2))
+        .returning(|_| Ok(CommandOutput::default()));
+    let retry = RetryPolicy {
+        backoff: Duration::from_millis(1),
+        ..RetryPolicy::NETWORK
+    };
+
+    let res = RecordingRunner::new(&runner, false, Recorder::default()).run(
+        &CommandSpec::new("git", ["clone", "https://github.com/rustsec/advisory-db"]).retry(retry),
+    );
+
+    assert_eq!(
+        res.is_ok(),
+        success,
+        "Only transient failures should be retried, got {res:?}"
+    );
+}
@@ -0,0 +1,210 @@
+use std::{
+    collections::BTreeSet,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use serde_json::{Map, Value};
+
+use crate::compose::errors::ComposeError;
+
+pub mod errors;
+#[cfg(test)]
+mod test;
+
+/// Key listing the settings files merged below the one declaring it
+const INCLUDE_KEY: &str = "include";
+/// Key of the named variants of the settings, merged on top of them when selected
+const PROFILES_KEY: &str = "profiles";
+/// Fields holding paths or URLs, the only ones where `${VAR}` is replaced:
+/// `[]` stands for every item of a list and `{}` for every key and value of a map
+const INTERPOLATED_FIELDS: &[&[&str]] = &[
+    &["rust", "manifests", "[]"],
+    &["python", "requirement_files", "[]"],
+    &["git_mirrors", "mirrors", "[]", "src"],
+    &["git_mirrors", "mirrors", "[]", "dst"],
+    &["custom", "tasks", "[]", "paths_to_package", "{}"],
+];
+
+/// Settings at `path` as a single document: included files merged below it, then `profile` merged on top,
+/// and `${VAR}` replaced by the value of `lookup(VAR)` in the paths and URLs, `$${` being kept as a literal `${`
+pub(crate) fn compose<F: Fn(&str) -> Option<String>>(
+    path: &Path,
+    profile: Option<&str>,
+    lookup: F,
+) -> Result<Value, ComposeError> {
+    let mut settings = read_with_includes(path, &mut Vec::new(), &mut BTreeSet::new())?;
+    let profiles = match &mut settings {
+        Value::Object(map) => map.remove(PROFILES_KEY),
+        _ => None,
+    };
+    if let Some(profile) = profile {
+        let mut profiles = match profiles {
+            Some(Value::Object(profiles)) => profiles,
+            _ => Map::new(),
+        };
+        let Some(overlay) = profiles.remove(profile) else {
+            return Err(ComposeError::UnknownProfile {
+                name: profile.to_owned(),
+                available: profiles.keys().cloned().collect(),
+            });
+        };
+        merge(&mut settings, overlay);
+    }
+    for fields in INTERPOLATED_FIELDS {
+        interpolate(&mut settings, fields, "", &lookup)?;
+    }
+    Ok(settings)
+}
+
+/// Content of the settings file at `path` with the files it includes (relative to its directory) merged below it.
+/// `stack` holds the files being read, to detect include cycles, and `included` the files already read:
+/// a file included through several others (e.g., a common base) is only merged the first time.
+fn read_with_includes(
+    path: &Path,
+    stack: &mut Vec<PathBuf>,
+    included: &mut BTreeSet<PathBuf>,
+) -> Result<Value, ComposeError> {
+    let identity = file_identity(path);
+    if stack.contains(&identity) {
+        return Err(ComposeError::IncludeCycle(path.to_owned()));
+    }
+    if !included.insert(identity.clone()) {
+        return Ok(Value::Object(Map::new()));
+    }
+    let mut settings: Value = config::Config::builder()
+        .add_source(config::File::with_name(&path.display().to_string()))
+        .build()
+        .and_then(config::Config::try_deserialize)
+        .map_err(|e| ComposeError::Read(path.to_owned(), Box::new(e)))?;
+    let includes = match &mut settings {
+        Value::Object(map) => map.remove(INCLUDE_KEY),
+        _ => None,
+    };
+    let includes = match includes {
+        None => Vec::new(),
+        Some(Value::Array(includes)) => includes,
+        Some(include) => vec![include],
+    };
+
+    stack.push(identity);
+    let directory = path.parent().unwrap_or_else(|| Path::new(""));
+    let mut composed = Value::Object(Map::new());
+    for include in includes {
+        let Value::String(include) = include else {
+            return Err(ComposeError::InvalidInclude(path.to_owned(), include));
+        };
+        merge(
+            &mut composed,
+            read_with_includes(&directory.join(include), stack, included)?,
+        );
+    }
+    stack.pop();
+
+    merge(&mut composed, settings);
+    Ok(composed)
+}
+
+/// Path identifying the file at `path` whether its extension is given or not
+fn file_identity(path: &Path) -> PathBuf {
+    if path.is_file() {
+        return fs::canonicalize(path)
+            .unwrap_or_else(|_| path.to_owned())
+            .with_extension("");
+    }
+    let directory = path
+        .parent()
+        .and_then(|directory| fs::canonicalize(directory).ok())
+        .unwrap_or_default();
+    path.file_name()
+        .map_or_else(|| path.to_owned(), |name| directory.join(name))
+}
+
+/// Merge `overlay` into `base`: maps are merged key by key, lists are appended and other values are replaced
+fn merge(base: &mut Value, overlay: Value) {
+    match (base, overlay) {
+        (Value::Object(base), Value::Object(overlay)) => {
+            for (key, value) in overlay {
+                match base.get_mut(&key) {
+                    Some(existing) => merge(existing, value),
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (Value::Array(base), Value::Array(overlay)) => base.extend(overlay),
+        (base, overlay) => *base = overlay,
+    }
+}
+
+/// Replace every `${VAR}` by `lookup(VAR)` in the strings of `value` (found at `field`) matching `fields`
+fn interpolate<F: Fn(&str) -> Option<String>>(
+    value: &mut Value,
+    fields: &[&str],
+    field: &str,
+    lookup: &F,
+) -> Result<(), ComposeError> {
+    let Some((first, rest)) = fields.split_first() else {
+        if let Value::String(text) = value {
+            *text = interpolate_str(text, field, lookup)?;
+        }
+        return Ok(());
+    };
+    match (*first, value) {
+        ("[]", Value::Array(values)) => {
+            for (index, value) in values.iter_mut().enumerate() {
+                interpolate(value, rest, &format!("{field}[{index}]"), lookup)?;
+            }
+        }
+        ("{}", Value::Object(map)) => {
+            for (key, mut value) in std::mem::take(map) {
+                let field = format!("{field}.{key}");
+                interpolate(&mut value, rest, &field, lookup)?;
+                map.insert(interpolate_str(&key, &field, lookup)?, value);
+            }
+        }
+        (key, Value::Object(map)) => {
+            if let Some(value) = map.get_mut(key) {
+                let field = if field.is_empty() {
+                    key.to_owned()
+                } else {
+                    format!("{field}.{key}")
+                };
+                interpolate(value, rest, &field, lookup)?;
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+fn interpolate_str<F: Fn(&str) -> Option<String>>(
+    text: &str,
+    field: &str,
+    lookup: &F,
+) -> Result<String, ComposeError> {
+    let mut interpolated = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some((before, after)) = rest.split_once("${") {
+        if let Some(before) = before.strip_suffix('$') {
+            // Escaped: `$${` is a literal `${`
+            interpolated.push_str(before);
+            interpolated.push_str("${");
+            rest = after;
+            continue;
+        }
+        interpolated.push_str(before);
+        let Some((variable, after)) = after.split_once('}') else {
+            return Err(ComposeError::UnterminatedVariable(field.to_owned()));
+        };
+        let value = lookup(variable).ok_or_else(|| ComposeError::UndefinedVariable {
+            field: field.to_owned(),
+            variable: variable.to_owned(),
+        })?;
+        interpolated.push_str(&value);
+        rest = after;
+    }
+    interpolated.push_str(rest);
+    Ok(interpolated)
+}
@@ -0,0 +1,23 @@
+use std::path::PathBuf;
+
+use serde_json::Value;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum ComposeError {
+    #[error("Cannot read the settings at '{0}': {1}")]
+    Read(PathBuf, #[source] Box<config::ConfigError>),
+    #[error("'{0}' includes itself, directly or through other files")]
+    IncludeCycle(PathBuf),
+    #[error("include in '{0}': expected the path of a settings file, got {1}")]
+    InvalidInclude(PathBuf, Value),
+    #[error("Unknown profile '{name}', the settings declare: {}", if .available.is_empty() { "none".to_owned() } else { .available.join(", ") })]
+    UnknownProfile {
+        name: String,
+        available: Vec<String>,
+    },
+    #[error("{field}: environment variable '{variable}' is not set")]
+    UndefinedVariable { field: String, variable: String },
+    #[error("{0}: '${{' without a closing '}}'")]
+    UnterminatedVariable(String),
+}
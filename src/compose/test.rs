@@ -0,0 +1,213 @@
+use std::fs;
+
+use rstest::rstest;
+use serde_json::json;
+use tempfile::tempdir;
+
+use crate::compose::{compose, errors::ComposeError};
+
+fn no_variable(_: &str) -> Option<String> {
+    None
+}
+
+#[rstest]
+#[test_log::test]
+fn merge_included_files() {
+    let folder = tempdir().unwrap();
+    fs::create_dir(folder.path().join("common")).unwrap();
+    fs::write(
+        folder.path().join("common/base.yaml"),
+        "
+name: base
+rust:
+    binaries:
+        - cargo-audit
+    use_binstall: false
+",
+    )
+    .unwrap();
+    fs::write(
+        folder.path().join("common/python.yaml"),
+        "
+python:
+    requirement_files:
+        - ./requirements.txt
+",
+    )
+    .unwrap();
+    fs::write(
+        folder.path().join("team.yaml"),
+        "
+include:
+    - common/base
+    - common/python.yaml
+name: team
+rust:
+    binaries:
+        - cargo-nextest
+",
+    )
+    .unwrap();
+
+    let settings = compose(&folder.path().join("team"), None, no_variable).unwrap();
+
+    assert_eq!(
+        settings,
+        json!({
+            "name": "team",
+            "rust": {"binaries": ["cargo-audit", "cargo-nextest"], "use_binstall": false},
+            "python": {"requirement_files": ["./requirements.txt"]},
+        }),
+        "Included files should be merged below the including one, with lists appended"
+    );
+}
+
+#[rstest]
+#[test_log::test]
+fn reject_include_cycle() {
+    let folder = tempdir().unwrap();
+    fs::write(folder.path().join("a.yaml"), "include: [b]").unwrap();
+    fs::write(folder.path().join("b.yaml"), "include: [a.yaml]").unwrap();
+
+    let res = compose(&folder.path().join("a"), None, no_variable);
+
+    assert!(
+        matches!(res, Err(ComposeError::IncludeCycle(_))),
+        "Include cycle should be reported, got {res:?}"
+    );
+}
+
+#[rstest]
+#[test_log::test]
+fn include_shared_file_once() {
+    let folder = tempdir().unwrap();
+    fs::write(
+        folder.path().join("base.yaml"),
+        "rust:\n    binaries: [cargo-audit]\n",
+    )
+    .unwrap();
+    fs::write(folder.path().join("rust.yaml"), "include: [base]").unwrap();
+    fs::write(folder.path().join("python.yaml"), "include: [base.yaml]").unwrap();
+    fs::write(folder.path().join("team.yaml"), "include: [rust, python]").unwrap();
+
+    let settings = compose(&folder.path().join("team"), None, no_variable).unwrap();
+
+    assert_eq!(
+        settings,
+        json!({"rust": {"binaries": ["cargo-audit"]}}),
+        "A file included through several others should only be merged once"
+    );
+}
+
+#[rstest]
+#[case::site_a(Some("site-a"), json!(["https://a.private.domain/advisory-db"]))]
+#[case::no_profile(None, json!([]))]
+#[test_log::test]
+fn select_profile(#[case] profile: Option<&str>, #[case] destinations: serde_json::Value) {
+    let folder = tempdir().unwrap();
+    fs::write(
+        folder.path().join("settings.yaml"),
+        "
+git_mirrors:
+    mirrors: []
+profiles:
+    site-a:
+        git_mirrors:
+            mirrors:
+                - src: https://github.com/rustsec/advisory-db
+                  dst: https://a.private.domain/advisory-db
+    site-b:
+        name: site-b
+",
+    )
+    .unwrap();
+
+    let settings = compose(&folder.path().join("settings"), profile, no_variable).unwrap();
+
+    assert_eq!(
+        settings["git_mirrors"]["mirrors"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|mirror| mirror["dst"].clone())
+            .collect::<serde_json::Value>(),
+        destinations,
+        "Only the selected profile should be merged"
+    );
+    assert!(
+        settings.get("profiles").is_none(),
+        "Profiles should not be left in the settings"
+    );
+}
+
+#[rstest]
+#[test_log::test]
+fn reject_unknown_profile() {
+    let folder = tempdir().unwrap();
+    fs::write(
+        folder.path().join("settings.yaml"),
+        "profiles:\n    site-a: {}\n    site-b: {}\n",
+    )
+    .unwrap();
+
+    let res = compose(&folder.path().join("settings"), Some("site-c"), no_variable);
+
+    assert!(
+        matches!(&res, Err(ComposeError::UnknownProfile { available, .. }) if available == &["site-a", "site-b"]),
+        "Unknown profile should be reported with the available ones, got {res:?}"
+    );
+}
+
+#[rstest]
+#[test_log::test]
+fn interpolate_variables() {
+    let folder = tempdir().unwrap();
+    fs::write(
+        folder.path().join("settings.yaml"),
+        "
+git_mirrors:
+    mirrors:
+        - src: https://github.com/rustsec/advisory-db
+          dst: https://${MIRROR_HOST}/global/${MIRROR_HOST}-advisory-db
+rust:
+    manifests:
+        - ./$${MIRROR_HOST}/Cargo.toml
+custom:
+    tasks:
+        - paths_to_package:
+            ${MIRROR_HOST}/data: data/${MIRROR_HOST}
+          install_command: sh -c 'cp x ${HOME}/y'
+",
+    )
+    .unwrap();
+
+    let settings = compose(&folder.path().join("settings"), None, |variable| {
+        (variable == "MIRROR_HOST").then(|| "git.site".to_owned())
+    })
+    .unwrap();
+    let res = compose(&folder.path().join("settings"), None, no_variable);
+
+    assert_eq!(
+        settings["git_mirrors"]["mirrors"][0]["dst"],
+        "https://git.site/global/git.site-advisory-db",
+        "Every variable should be replaced"
+    );
+    assert_eq!(
+        settings["custom"]["tasks"][0]["paths_to_package"],
+        json!({"git.site/data": "data/git.site"}),
+        "Variables should be replaced in the paths to package, including the local ones"
+    );
+    assert_eq!(
+        settings["rust"]["manifests"][0], "./${MIRROR_HOST}/Cargo.toml",
+        "An escaped variable should be kept as is"
+    );
+    assert_eq!(
+        settings["custom"]["tasks"][0]["install_command"], "sh -c 'cp x ${HOME}/y'",
+        "Commands should be left alone"
+    );
+    assert!(
+        matches!(&res, Err(ComposeError::UndefinedVariable { field, variable })
+            if field == "git_mirrors.mirrors[0].dst" && variable == "MIRROR_HOST"),
+        "Unset variable should be reported with its field, got {res:?}"
+    );
+}
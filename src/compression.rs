@@ -0,0 +1,150 @@
+use std::{
+    fmt::{self, Display},
+    io::{self, BufRead, BufReader, Read, Write},
+};
+
+use flate2::{Compression, read::GzDecoder, write::GzEncoder};
+use liblzma::{read::XzDecoder, write::XzEncoder};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::PackagingError;
+
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+const ZSTD_MAGIC: &[u8] = &[0x28, 0xb5, 0x2f, 0xfd];
+const XZ_MAGIC: &[u8] = &[0xfd, b'7', b'z', b'X', b'Z', 0x00];
+
+/// How the archive is compressed
+#[derive(Clone, Debug, Default, Deserialize, JsonSchema, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct CompressionSettings {
+    #[serde(default)]
+    pub format: CompressionFormat,
+    /// Compression level, the default level of the format is used if unset.
+    /// Gzip and Xz accept levels from 0 to 9 and Zstd from 1 to 22, no level can be set without compression.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub level: Option<u32>,
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, JsonSchema, PartialEq, Serialize)]
+pub enum CompressionFormat {
+    #[default]
+    Gzip,
+    /// Much faster to decompress and smaller than gzip at high levels
+    Zstd,
+    /// Smallest output but slowest compression
+    Xz,
+    /// No compression at all
+    None,
+}
+
+impl CompressionFormat {
+    /// File extension of archives compressed in that format
+    pub(crate) fn extension(self) -> &'static str {
+        match self {
+            Self::Gzip => "tar.gz",
+            Self::Zstd => "tar.zst",
+            Self::Xz => "tar.xz",
+            Self::None => "tar",
+        }
+    }
+
+    /// Whether `level` can be set for this format, none can be without compression
+    pub(crate) fn accepts_level(self, level: u32) -> bool {
+        match self {
+            Self::Gzip | Self::Xz => level <= 9,
+            Self::Zstd => (1..=22).contains(&level),
+            Self::None => false,
+        }
+    }
+}
+
+impl Display for CompressionFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{self:?}")
+    }
+}
+
+/// A writer compressing its input in the format chosen in the [`CompressionSettings`]
+pub(crate) enum Encoder<W: Write> {
+    Gzip(GzEncoder<W>),
+    Zstd(zstd::Encoder<'static, W>),
+    Xz(XzEncoder<W>),
+    None(W),
+}
+
+impl<W: Write> Encoder<W> {
+    pub(crate) fn new(writer: W, settings: &CompressionSettings) -> Result<Self, PackagingError> {
+        let format = settings.format;
+        if let Some(level) = settings.level
+            && !format.accepts_level(level)
+        {
+            return Err(PackagingError::CompressionLevel(format, level));
+        }
+        Ok(match format {
+            CompressionFormat::Gzip => Self::Gzip(GzEncoder::new(
+                writer,
+                settings
+                    .level
+                    .map_or_else(Compression::default, Compression::new),
+            )),
+            CompressionFormat::Zstd => {
+                let level = settings
+                    .level
+                    .and_then(|level| i32::try_from(level).ok())
+                    .unwrap_or(zstd::DEFAULT_COMPRESSION_LEVEL);
+                Self::Zstd(
+                    zstd::Encoder::new(writer, level).map_err(PackagingError::ArchiveCreation)?,
+                )
+            }
+            CompressionFormat::Xz => Self::Xz(XzEncoder::new(writer, settings.level.unwrap_or(6))),
+            CompressionFormat::None => Self::None(writer),
+        })
+    }
+
+    /// Flush the end of the compressed stream and return the underlying writer
+    pub(crate) fn finish(self) -> io::Result<W> {
+        match self {
+            Self::Gzip(encoder) => encoder.finish(),
+            Self::Zstd(encoder) => encoder.finish(),
+            Self::Xz(encoder) => encoder.finish(),
+            Self::None(writer) => Ok(writer),
+        }
+    }
+}
+
+impl<W: Write> Write for Encoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Self::Gzip(encoder) => encoder.write(buf),
+            Self::Zstd(encoder) => encoder.write(buf),
+            Self::Xz(encoder) => encoder.write(buf),
+            Self::None(writer) => writer.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Self::Gzip(encoder) => encoder.flush(),
+            Self::Zstd(encoder) => encoder.flush(),
+            Self::Xz(encoder) => encoder.flush(),
+            Self::None(writer) => writer.flush(),
+        }
+    }
+}
+
+/// Decompress `reader` based on the magic bytes at its start, not on any file extension
+pub(crate) fn decoder<'a, R: Read + 'a>(reader: R) -> io::Result<Box<dyn Read + 'a>> {
+    let mut reader = BufReader::new(reader);
+    let magic = reader.fill_buf()?;
+    Ok(if magic.starts_with(GZIP_MAGIC) {
+        Box::new(GzDecoder::new(reader))
+    } else if magic.starts_with(ZSTD_MAGIC) {
+        Box::new(zstd::Decoder::with_buffer(reader)?)
+    } else if magic.starts_with(XZ_MAGIC) {
+        Box::new(XzDecoder::new(reader))
+    } else {
+        // Assume an uncompressed tar archive
+        Box::new(reader)
+    })
+}
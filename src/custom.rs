@@ -1,38 +1,51 @@
 use std::{
-    collections::HashMap,
+    collections::BTreeMap,
     path::{Path, PathBuf},
 };
 
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
-use tracing::{debug, info, warn};
+use tracing::{debug, info};
 use walkdir::WalkDir;
 
-use crate::{ArchiveBuilder, cmd::CommandRunner, custom::errors::CustomError};
+use crate::{
+    ArchiveBuilder, PackagingError,
+    cmd::{CommandRunner, CommandSpec},
+    custom::errors::CustomError,
+    progress::Position,
+    provider::{InstallContext, PackageContext, ResourceProvider},
+    report::Recorder,
+    validation::{check_path_exists, errors::SettingsError},
+};
 
 pub mod errors;
 #[cfg(test)]
 mod test;
 
-#[derive(Debug, Default, Deserialize, Serialize)]
+#[derive(Debug, Default, Deserialize, JsonSchema, Serialize)]
+#[serde(default, deny_unknown_fields)]
 pub struct CustomTasks {
     tasks: Vec<CustomTask>,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Deserialize, JsonSchema, Serialize)]
+#[serde(deny_unknown_fields)]
 #[expect(clippy::doc_markdown, reason = "false positives")]
 pub struct CustomTask {
-    /// An hashmap mapping local paths (to files or directories) to their relative path within the archive
+    /// A map of local paths (to files or directories) to their relative path within the archive
     /// e.g., {"/path/to/vscode/code-spell-check.vsix": "vscode/code-spell-check.vsix", "/path/to/debian_packages": "debian_packages"}
-    paths_to_package: HashMap<PathBuf, PathBuf>,
+    #[serde(default)]
+    paths_to_package: BTreeMap<PathBuf, PathBuf>,
     /// An optional command to install the files on the offline machine.
     /// e.g., "code --install-extension" (with `install_counts` set to "EachFile")
     /// The working directory of the command will be the root of the extracted archive.
     install_command: Option<String>,
     /// How many times to run the install command (see [`CustomInstallInstallCount`])
+    #[serde(default)]
     install_counts: CustomInstallInstallCount,
 }
 
-#[derive(Debug, Default, Deserialize, Serialize)]
+#[derive(Debug, Default, Deserialize, JsonSchema, Serialize)]
 pub enum CustomInstallInstallCount {
     /// The custom command must be run once for each path, the last argument will be the path
     EachPath,
@@ -45,7 +58,112 @@ pub enum CustomInstallInstallCount {
 }
 
 impl CustomTasks {
-    pub(crate) fn package(&self, tar: &mut ArchiveBuilder) -> Result<(), CustomError> {
+    /// Start building custom tasks in code, without any task
+    pub fn builder() -> CustomTasksBuilder {
+        CustomTasksBuilder::default()
+    }
+}
+
+/// Builds [`CustomTasks`] in code instead of reading them from a settings file
+#[derive(Debug, Default)]
+#[must_use]
+pub struct CustomTasksBuilder {
+    settings: CustomTasks,
+}
+
+impl CustomTasksBuilder {
+    /// Package and install `task`, after the previous tasks
+    pub fn task(mut self, task: CustomTask) -> Self {
+        self.settings.tasks.push(task);
+        self
+    }
+
+    pub fn build(self) -> CustomTasks {
+        self.settings
+    }
+}
+
+impl CustomTask {
+    /// Start building a task in code, without any path nor install command, run once
+    pub fn builder() -> CustomTaskBuilder {
+        CustomTaskBuilder::default()
+    }
+}
+
+/// Builds a [`CustomTask`] in code instead of reading it from a settings file
+#[derive(Debug, Default)]
+#[must_use]
+pub struct CustomTaskBuilder {
+    paths_to_package: BTreeMap<PathBuf, PathBuf>,
+    install_command: Option<String>,
+    install_counts: CustomInstallInstallCount,
+}
+
+impl CustomTaskBuilder {
+    /// Package the file or directory at `local_path` as `package_path`, relative to the root of the archive
+    pub fn path<L: Into<PathBuf>, P: Into<PathBuf>>(
+        mut self,
+        local_path: L,
+        package_path: P,
+    ) -> Self {
+        self.paths_to_package
+            .insert(local_path.into(), package_path.into());
+        self
+    }
+
+    /// Run this command from the unpacked archive on the offline machine, e.g., "code --install-extension"
+    pub fn install_command<S: Into<String>>(mut self, install_command: S) -> Self {
+        self.install_command = Some(install_command.into());
+        self
+    }
+
+    /// How many times the install command is run, see [`CustomInstallInstallCount`]
+    pub fn install_counts(mut self, install_counts: CustomInstallInstallCount) -> Self {
+        self.install_counts = install_counts;
+        self
+    }
+
+    pub fn build(self) -> CustomTask {
+        CustomTask {
+            paths_to_package: self.paths_to_package,
+            install_command: self.install_command,
+            install_counts: self.install_counts,
+        }
+    }
+}
+
+impl CustomTasks {
+    /// Every packaged path that doesn't exist and every install command that cannot be parsed
+    pub(crate) fn validate(&self) -> Vec<SettingsError> {
+        let mut errors = Vec::new();
+        for (index, task) in self.tasks.iter().enumerate() {
+            errors.extend(task.paths_to_package.keys().filter_map(|local_path| {
+                check_path_exists(
+                    format!("custom.tasks[{index}].paths_to_package"),
+                    local_path,
+                )
+            }));
+            if let Some(install_command) = &task.install_command
+                && shlex::split(install_command).is_none()
+            {
+                errors.push(SettingsError::UnparsableCommand {
+                    field: format!("custom.tasks[{index}].install_command"),
+                    command: install_command.clone(),
+                });
+            }
+        }
+        errors
+    }
+
+    /// Relative paths within the archive of every packaged path
+    pub(crate) fn package_paths(&self) -> impl Iterator<Item = &PathBuf> {
+        self.tasks
+            .iter()
+            .flat_map(|task| task.paths_to_package.values())
+    }
+
+    /// Append every packaged path to `tar`, as they are without downloading anything
+    pub(crate) fn archive(&self, tar: &mut ArchiveBuilder) -> Result<(), CustomError> {
         info!("Packaging custom tasks");
         for task in &self.tasks {
             debug!("Processing {:#?}", task);
@@ -73,83 +191,134 @@ impl CustomTasks {
         Ok(())
     }
 
-    /// `in_folder` needs to be a canonicalized path
-    pub(crate) fn install<T: CommandRunner>(&self, in_folder: &Path) -> Result<(), CustomError> {
+    fn run_command_with_path(
+        runner: &dyn CommandRunner,
+        install_command: &[String],
+        extra_arg_path: &Path,
+        cwd: PathBuf,
+    ) -> Result<(), CustomError> {
+        let mut args_with_path = install_command.to_vec();
+        args_with_path.push(extra_arg_path.display().to_string());
+        #[expect(clippy::indexing_slicing, reason = "checked after shlex.split()")]
+        runner.run(&CommandSpec::new(&install_command[0], &args_with_path[1..]).cwd(cwd))?;
+
+        Ok(())
+    }
+}
+
+impl ResourceProvider for CustomTasks {
+    fn section(&self) -> &str {
+        "custom"
+    }
+
+    fn describe(&self) -> Vec<String> {
+        self.package_paths()
+            .map(|package_path| format!("custom path {}", package_path.display()))
+            .collect()
+    }
+
+    fn verify(&self) -> Vec<SettingsError> {
+        self.validate()
+    }
+
+    /// The packaged paths are local, nothing to download
+    fn package(&self, _context: &PackageContext<'_>) -> Result<(), PackagingError> {
+        Ok(())
+    }
+
+    fn archive(
+        &self,
+        _context: &PackageContext<'_>,
+        archive: &mut ArchiveBuilder,
+    ) -> Result<(), PackagingError> {
+        Ok(self.archive(archive)?)
+    }
+
+    /// Run the install command of every task from the unpacked archive, recording each task as a step
+    fn install(&self, context: &mut InstallContext<'_>) {
         info!("Installing through custom tasks");
-        for task in &self.tasks {
-            if task.install_command.is_none() {
-                continue;
-            }
-            #[expect(clippy::unwrap_used, reason = "already checked")]
-            let install_command = task.install_command.as_ref().unwrap();
-            let install_command = shlex::split(install_command)
-                .ok_or(CustomError::CommandUnparsable(install_command.clone()))?;
-            if install_command.is_empty() {
-                warn!("Empty string command in task '{task:#?}'");
+        let runner = context.runner();
+        let recorder = context.recorder();
+        let in_folder = context.unpacked_directory();
+        for (index, task) in self.tasks.iter().enumerate() {
+            let Some(install_command) = &task.install_command else {
                 continue;
+            };
+            context.step(
+                "custom task",
+                format!(
+                    "custom task #{} ({install_command})",
+                    index.saturating_add(1)
+                ),
+                Position::new(index.saturating_add(1), self.tasks.len()),
+                |_| task.install(runner, install_command, in_folder, recorder),
+            );
+        }
+    }
+}
+
+impl CustomTask {
+    fn install(
+        &self,
+        runner: &dyn CommandRunner,
+        install_command: &str,
+        in_folder: &Path,
+        recorder: &Recorder,
+    ) -> Result<(), CustomError> {
+        let install_command = shlex::split(install_command)
+            .ok_or(CustomError::CommandUnparsable(install_command.to_owned()))?;
+        if install_command.is_empty() {
+            recorder.warning(format!("Empty string command in task '{self:#?}'"));
+            return Ok(());
+        }
+
+        match self.install_counts {
+            CustomInstallInstallCount::EachPath => {
+                for package_path in self.paths_to_package.values() {
+                    let package_path = in_folder.join(package_path);
+                    CustomTasks::run_command_with_path(
+                        runner,
+                        &install_command,
+                        &package_path,
+                        in_folder.to_path_buf(),
+                    )?;
+                }
             }
+            CustomInstallInstallCount::EachFile => {
+                for package_path in self.paths_to_package.values() {
+                    let package_path = in_folder.join(package_path);
 
-            match task.install_counts {
-                CustomInstallInstallCount::EachPath => {
-                    for package_path in task.paths_to_package.values() {
-                        let package_path = in_folder.join(package_path);
-                        Self::run_command_with_path::<T>(
+                    // Recursively look over every file in listed folders
+                    if package_path.is_dir() {
+                        for entry in WalkDir::new(&package_path) {
+                            let entry = entry
+                                .map_err(|e| CustomError::WalkDirectory(package_path.clone(), e))?;
+                            if entry.path().is_file() {
+                                CustomTasks::run_command_with_path(
+                                    runner,
+                                    &install_command,
+                                    entry.path(),
+                                    in_folder.to_path_buf(),
+                                )?;
+                            }
+                        }
+                    } else {
+                        CustomTasks::run_command_with_path(
+                            runner,
                             &install_command,
                             &package_path,
                             in_folder.to_path_buf(),
                         )?;
                     }
                 }
-                CustomInstallInstallCount::EachFile => {
-                    for package_path in task.paths_to_package.values() {
-                        let package_path = in_folder.join(package_path);
-
-                        // Recursively look over every file in listed folders
-                        if package_path.is_dir() {
-                            for entry in WalkDir::new(&package_path) {
-                                let entry = entry.map_err(|e| {
-                                    CustomError::WalkDirectory(package_path.clone(), e)
-                                })?;
-                                if entry.path().is_file() {
-                                    Self::run_command_with_path::<T>(
-                                        &install_command,
-                                        entry.path(),
-                                        in_folder.to_path_buf(),
-                                    )?;
-                                }
-                            }
-                        } else {
-                            Self::run_command_with_path::<T>(
-                                &install_command,
-                                &package_path,
-                                in_folder.to_path_buf(),
-                            )?;
-                        }
-                    }
-                }
-                CustomInstallInstallCount::Once => {
-                    #[expect(clippy::indexing_slicing, reason = "checked after shlex.split()")]
-                    T::run_cmd(
-                        &install_command[0],
-                        &install_command[1..],
-                        Some(in_folder.to_path_buf()),
-                    )?;
-                }
+            }
+            CustomInstallInstallCount::Once => {
+                #[expect(clippy::indexing_slicing, reason = "checked after shlex.split()")]
+                runner.run(
+                    &CommandSpec::new(&install_command[0], &install_command[1..]).cwd(in_folder),
+                )?;
             }
         }
         Ok(())
     }
-
-    fn run_command_with_path<T: CommandRunner>(
-        install_command: &[String],
-        extra_arg_path: &Path,
-        cwd: PathBuf,
-    ) -> Result<(), CustomError> {
-        let mut args_with_path = install_command.to_vec();
-        args_with_path.push(extra_arg_path.display().to_string());
-        #[expect(clippy::indexing_slicing, reason = "checked after shlex.split()")]
-        T::run_cmd(&install_command[0], &args_with_path[1..], Some(cwd))?;
-
-        Ok(())
-    }
 }
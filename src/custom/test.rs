@@ -1,21 +1,15 @@
 use std::fs::{File, create_dir_all};
-use std::sync::Mutex;
 
 use mockall::predicate::{eq, function};
 use rstest::rstest;
 use tempfile::tempdir;
 
 use crate::custom::CustomTasks;
-use crate::test::archive;
-use crate::{ArchiveBuilder, cmd::MockCommandRunner};
-
-/// Required to lock this mutex in every test
-/// because of <https://docs.rs/mockall/latest/mockall/#static-methods>
-///
-/// The mutex might be poisoned if a test fails. But we don't
-/// care, because it doesn't hold any data. Whether it's poisoned or
-/// not, we'll still hold the `MutexGuard`.
-static MTX: Mutex<()> = Mutex::new(());
+use crate::test::{InstallFixture, archive, command};
+use crate::{
+    ArchiveBuilder, ResourceProvider,
+    cmd::{CommandOutput, MockCommandRunner},
+};
 
 #[rstest]
 #[test_log::test]
@@ -27,7 +21,7 @@ tasks: []
     )
     .unwrap();
     tasks
-        .package(&mut archive)
+        .archive(&mut archive)
         .expect("Shouldn't fail to custom files");
     archive.finish().expect("Shouldn't fail to build archive");
 }
@@ -61,14 +55,13 @@ fn package_regular(mut archive: ArchiveBuilder) {
     .unwrap();
 
     tasks
-        .package(&mut archive)
+        .archive(&mut archive)
         .expect("Shouldn't fail to package custom files");
     archive.finish().expect("Shouldn't fail to build archive");
 }
 
 #[test_log::test]
 fn install() {
-    let _m = MTX.lock();
     let in_folder = tempdir().unwrap();
 
     // Create a folder with multiple files and a separate file
@@ -114,72 +107,80 @@ fn install() {
     }))
     .unwrap();
 
-    let ctx = MockCommandRunner::run_cmd_context();
+    let mut runner = MockCommandRunner::new();
     let cwd = in_folder.path();
 
     // First task (for each file)
 
     let example_folder_sub_1 = sub_files[0].clone();
-    ctx.expect()
-        .with(
+    runner
+        .expect_run()
+        .with(command(
             eq("echo"),
             function(move |args: &[String]| args == [example_folder_sub_1.display().to_string()]),
             eq(Some(cwd.to_path_buf())),
-        )
+        ))
         .times(1)
-        .returning(|_, _, _| Ok(()));
+        .returning(|_| Ok(CommandOutput::default()));
     let example_folder_sub_2 = sub_files[1].clone();
-    ctx.expect()
-        .with(
+    runner
+        .expect_run()
+        .with(command(
             eq("echo"),
             function(move |args: &[String]| args == [example_folder_sub_2.display().to_string()]),
             eq(Some(cwd.to_path_buf())),
-        )
+        ))
         .times(1)
-        .returning(|_, _, _| Ok(()));
+        .returning(|_| Ok(CommandOutput::default()));
     let example_file_clone_2 = example_file.clone();
-    ctx.expect()
-        .with(
+    runner
+        .expect_run()
+        .with(command(
             eq("echo"),
             function(move |args: &[String]| args == [example_file_clone_2.display().to_string()]),
             eq(Some(cwd.to_path_buf())),
-        )
+        ))
         .times(1)
-        .returning(|_, _, _| Ok(()));
+        .returning(|_| Ok(CommandOutput::default()));
 
     // Second task (for each path)
 
     let example_folder_clone = example_folder.clone();
-    ctx.expect()
-        .with(
+    runner
+        .expect_run()
+        .with(command(
             eq("echo"),
             function(move |args: &[String]| args == [example_folder_clone.display().to_string()]),
             eq(Some(cwd.to_path_buf())),
-        )
+        ))
         .times(1)
-        .returning(|_, _, _| Ok(()));
+        .returning(|_| Ok(CommandOutput::default()));
     let example_file_clone = example_file.clone();
-    ctx.expect()
-        .with(
+    runner
+        .expect_run()
+        .with(command(
             eq("echo"),
             function(move |args: &[String]| args == [example_file_clone.display().to_string()]),
             eq(Some(cwd.to_path_buf())),
-        )
+        ))
         .times(1)
-        .returning(|_, _, _| Ok(()));
+        .returning(|_| Ok(CommandOutput::default()));
 
     // Third task (only once)
 
-    ctx.expect()
-        .with(
+    runner
+        .expect_run()
+        .with(command(
             eq("echo"),
             function(move |args: &[String]| args.is_empty()),
             eq(Some(cwd.to_path_buf())),
-        )
+        ))
         .times(1)
-        .returning(|_, _, _| Ok(()));
+        .returning(|_| Ok(CommandOutput::default()));
 
-    tasks
-        .install::<MockCommandRunner>(in_folder.path())
-        .expect("Shouldn't fail to run custom tasks");
+    let mut fixture = InstallFixture::default();
+    tasks.install(&mut fixture.context(&runner, in_folder.path()));
+    let report = fixture.report;
+    assert!(report.is_success(), "Shouldn't fail to run custom tasks");
+    assert_eq!(report.steps.len(), 3, "Every task should be reported");
 }
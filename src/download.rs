@@ -0,0 +1,169 @@
+use std::{
+    collections::BTreeMap,
+    fs,
+    io::ErrorKind,
+    num::NonZeroUsize,
+    path::{Path, PathBuf},
+    sync::{Mutex, MutexGuard, PoisonError},
+};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tracing::info;
+
+use crate::{
+    PackageOptions, Settings,
+    download::errors::CheckpointError,
+    jobs::Jobs,
+    progress::{Phase, Position, Progress},
+    report::Recorder,
+};
+
+pub mod errors;
+#[cfg(test)]
+mod test;
+
+/// Name of the file listing the download steps finished by an unfinished packaging run
+pub(crate) const CHECKPOINT_FILE: &str = ".checkpoint.json";
+
+/// Download steps finished by a packaging run, with what they downloaded from
+#[derive(Default, Deserialize, Serialize)]
+struct Checkpoint {
+    /// SHA-256 digest of the settings of the run
+    settings: String,
+    /// SHA-256 digest of the inputs of every finished step, by step name
+    steps: BTreeMap<String, String>,
+}
+
+/// How the resources of every section are downloaded
+#[derive(Default)]
+pub(crate) struct Downloads {
+    pub(crate) jobs: Jobs,
+    /// Update the downloads of previous runs (e.g., fetch into existing clones) instead of starting over
+    pub(crate) reuse: bool,
+    pub(crate) dry_run: bool,
+    /// Skip the steps that the checkpoint lists as finished
+    resume: bool,
+    /// Where the finished steps are written, unset to not keep any
+    checkpoint_path: Option<PathBuf>,
+    finished: Mutex<Checkpoint>,
+    progress: Progress,
+    recorder: Recorder,
+}
+
+impl Downloads {
+    /// Prepare the downloads of `settings` into `directory`, reading the checkpoint of the previous run if
+    /// `options.resume` is set and the settings didn't change since then
+    pub(crate) fn new(
+        directory: &Path,
+        settings: &Settings,
+        options: &PackageOptions,
+        dry_run: bool,
+    ) -> Result<Self, CheckpointError> {
+        let checkpoint_path = directory.join(CHECKPOINT_FILE);
+        let settings = serde_json::to_vec(settings)
+            .map(|content| hex::encode(Sha256::digest(content)))
+            .map_err(CheckpointError::SerializeSettings)?;
+        let mut finished = Checkpoint::default();
+        if options.resume {
+            match fs::read(&checkpoint_path) {
+                Ok(content) => {
+                    let previous: Checkpoint = serde_json::from_slice(&content)
+                        .map_err(|e| CheckpointError::Parse(checkpoint_path.clone(), e))?;
+                    if previous.settings == settings {
+                        finished = previous;
+                    } else {
+                        info!(
+                            "The settings changed since the previous run, downloading everything again"
+                        );
+                    }
+                }
+                Err(e) if e.kind() == ErrorKind::NotFound => {}
+                Err(e) => return Err(CheckpointError::Read(checkpoint_path, e)),
+            }
+        }
+        finished.settings = settings;
+        let downloads = Self {
+            jobs: Jobs::new(options.jobs.unwrap_or(NonZeroUsize::MIN)),
+            reuse: options.cache_dir.is_some(),
+            dry_run,
+            resume: options.resume,
+            checkpoint_path: (!dry_run).then_some(checkpoint_path),
+            finished: Mutex::new(finished),
+            progress: Progress::new(options.progress.clone()),
+            recorder: Recorder::default(),
+        };
+        if !options.resume {
+            // A checkpoint left by an older run doesn't describe the downloads of this one
+            downloads.clear_checkpoint()?;
+        }
+        Ok(downloads)
+    }
+
+    /// Record the skipped steps with `recorder`
+    pub(crate) fn with_recorder(mut self, recorder: Recorder) -> Self {
+        self.recorder = recorder;
+        self
+    }
+
+    /// Run the download step `name`, at `position` among the steps of its kind,
+    /// unless the run is resumed and a previous run already finished it with the same `inputs` files
+    pub(crate) fn step<E: From<CheckpointError>, F: FnOnce() -> Result<(), E>>(
+        &self,
+        name: &str,
+        inputs: &[&Path],
+        position: Position,
+        download: F,
+    ) -> Result<(), E> {
+        let digest = inputs_digest(inputs);
+        if self.resume
+            && digest.is_some()
+            && self.lock_finished().steps.get(name) == digest.as_ref()
+        {
+            info!("Skipping '{name}', finished by a previous run");
+            self.recorder.record_skipped_step(name);
+            return Ok(());
+        }
+        self.progress
+            .step(Phase::Download, name, position, download)?;
+        let mut finished = self.lock_finished();
+        match digest {
+            Some(digest) => finished.steps.insert(name.to_owned(), digest),
+            // Inputs that cannot be read now won't match the next run either
+            None => finished.steps.remove(name),
+        };
+        if let Some(checkpoint_path) = &self.checkpoint_path {
+            let content = serde_json::to_vec_pretty(&*finished)
+                .map_err(|e| CheckpointError::Serialize(checkpoint_path.clone(), e))?;
+            fs::write(checkpoint_path, content)
+                .map_err(|e| CheckpointError::Write(checkpoint_path.clone(), e))?;
+        }
+        Ok(())
+    }
+
+    /// Forget the finished steps, once the whole run succeeded
+    pub(crate) fn clear_checkpoint(&self) -> Result<(), CheckpointError> {
+        let Some(checkpoint_path) = &self.checkpoint_path else {
+            return Ok(());
+        };
+        match fs::remove_file(checkpoint_path) {
+            Err(e) if e.kind() != ErrorKind::NotFound => {
+                Err(CheckpointError::Write(checkpoint_path.clone(), e))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn lock_finished(&self) -> MutexGuard<'_, Checkpoint> {
+        self.finished.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// SHA-256 digest of the content of the `inputs` files, unset if one of them cannot be read
+fn inputs_digest(inputs: &[&Path]) -> Option<String> {
+    let mut hasher = Sha256::new();
+    for input in inputs {
+        hasher.update(fs::read(input).ok()?);
+    }
+    Some(hex::encode(hasher.finalize()))
+}
@@ -0,0 +1,17 @@
+use std::path::PathBuf;
+
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum CheckpointError {
+    #[error("Cannot read the download checkpoint at '{0}': {1}")]
+    Read(PathBuf, #[source] std::io::Error),
+    #[error("Invalid download checkpoint at '{0}': {1}")]
+    Parse(PathBuf, #[source] serde_json::Error),
+    #[error("Cannot serialize the download checkpoint at '{0}': {1}")]
+    Serialize(PathBuf, #[source] serde_json::Error),
+    #[error("Cannot serialize the settings to key the download checkpoint on: {0}")]
+    SerializeSettings(#[source] serde_json::Error),
+    #[error("Cannot write the download checkpoint at '{0}': {1}")]
+    Write(PathBuf, #[source] std::io::Error),
+}
@@ -0,0 +1,143 @@
+use std::cell::Cell;
+use std::fs;
+
+use rstest::rstest;
+use tempfile::tempdir;
+
+use crate::download::{CHECKPOINT_FILE, Checkpoint, Downloads, errors::CheckpointError};
+use crate::progress::Position;
+use crate::{PackageOptions, Settings};
+
+fn resume_options() -> PackageOptions {
+    PackageOptions {
+        resume: true,
+        ..PackageOptions::default()
+    }
+}
+
+#[rstest]
+#[test_log::test]
+fn resume_skips_finished_steps() {
+    let directory = tempdir().unwrap();
+    let settings = Settings::default();
+    let options = PackageOptions::default();
+    let downloads = Downloads::new(directory.path(), &settings, &options, false).unwrap();
+    downloads
+        .step::<CheckpointError, _>("git clone a", &[], Position::new(1, 2), || Ok(()))
+        .unwrap();
+    let res = downloads.step("git clone b", &[], Position::new(2, 2), || {
+        Err(CheckpointError::Write(
+            directory.path().to_path_buf(),
+            std::io::Error::other("interrupted"),
+        ))
+    });
+    assert!(res.is_err(), "The failure should be returned");
+
+    let resumed = Downloads::new(directory.path(), &settings, &resume_options(), false).unwrap();
+    let ran = Cell::new(Vec::new());
+    for step in ["git clone a", "git clone b"] {
+        resumed
+            .step::<CheckpointError, _>(step, &[], Position::SINGLE, || {
+                let mut steps = ran.take();
+                steps.push(step);
+                ran.set(steps);
+                Ok(())
+            })
+            .unwrap();
+    }
+    assert_eq!(
+        ran.take(),
+        ["git clone b"],
+        "Only the unfinished step should run again"
+    );
+
+    resumed.clear_checkpoint().unwrap();
+    assert!(
+        !directory.path().join(CHECKPOINT_FILE).exists(),
+        "The checkpoint should be removed once the run succeeded"
+    );
+}
+
+#[rstest]
+#[case::settings_changed(true, false)]
+#[case::input_changed(false, true)]
+#[test_log::test]
+fn resume_after_changes(#[case] change_settings: bool, #[case] change_input: bool) {
+    let directory = tempdir().unwrap();
+    let requirements = directory.path().join("requirements.txt");
+    fs::write(&requirements, "numpy").unwrap();
+    let settings = Settings::default();
+    let downloads = Downloads::new(
+        directory.path(),
+        &settings,
+        &PackageOptions::default(),
+        false,
+    )
+    .unwrap();
+    downloads
+        .step::<CheckpointError, _>(
+            "pip download",
+            &[&requirements],
+            Position::SINGLE,
+            || Ok(()),
+        )
+        .unwrap();
+
+    let settings = Settings {
+        name: if change_settings {
+            "other".to_owned()
+        } else {
+            settings.name
+        },
+        ..Settings::default()
+    };
+    if change_input {
+        fs::write(&requirements, "numpy\npandas").unwrap();
+    }
+    let resumed = Downloads::new(directory.path(), &settings, &resume_options(), false).unwrap();
+    let ran = Cell::new(false);
+    resumed
+        .step::<CheckpointError, _>("pip download", &[&requirements], Position::SINGLE, || {
+            ran.set(true);
+            Ok(())
+        })
+        .unwrap();
+
+    assert!(
+        ran.get(),
+        "A step finished with other settings or inputs should run again"
+    );
+}
+
+#[rstest]
+#[case::new_run(false, &["git clone b"])]
+#[case::dry_run(true, &["git clone a"])]
+#[test_log::test]
+fn checkpoint_of_another_run(#[case] dry_run: bool, #[case] expected: &[&str]) {
+    let directory = tempdir().unwrap();
+    let checkpoint_path = directory.path().join(CHECKPOINT_FILE);
+    fs::write(
+        &checkpoint_path,
+        r#"{"settings": "", "steps": {"git clone a": ""}}"#,
+    )
+    .unwrap();
+
+    let downloads = Downloads::new(
+        directory.path(),
+        &Settings::default(),
+        &PackageOptions::default(),
+        dry_run,
+    )
+    .unwrap();
+    downloads
+        .step::<CheckpointError, _>("git clone b", &[], Position::SINGLE, || Ok(()))
+        .unwrap();
+
+    let checkpoint: Checkpoint =
+        serde_json::from_slice(&fs::read(&checkpoint_path).unwrap()).unwrap();
+    assert_eq!(
+        checkpoint.steps.keys().collect::<Vec<_>>(),
+        expected,
+        "A new run should replace the checkpoint of the previous one, a dry run shouldn't touch it"
+    );
+}
@@ -0,0 +1,121 @@
+use std::{
+    io::{self, BufRead, BufReader, Read, Write},
+    path::Path,
+    str::FromStr,
+};
+
+use age::{Decryptor, Encryptor, Identity, IdentityFile, Recipient, stream::StreamWriter, x25519};
+use tracing::debug;
+
+use crate::encryption::errors::EncryptionError;
+
+pub mod errors;
+#[cfg(test)]
+mod test;
+
+/// Start of every age-encrypted file
+const AGE_MAGIC: &[u8] = b"age-encryption.org/v1\n";
+
+/// Private key able to decrypt an archive
+pub(crate) type BoxedIdentity = Box<dyn Identity + Send + Sync>;
+
+/// A writer encrypting its input to age recipients, if any
+pub(crate) enum Encryption<W: Write> {
+    None(W),
+    Age(StreamWriter<W>),
+}
+
+impl<W: Write> Encryption<W> {
+    /// Encrypt `writer` to every recipient in `recipients`, leave it in plain text if there are none
+    pub(crate) fn new(
+        writer: W,
+        recipients: &[x25519::Recipient],
+    ) -> Result<Self, EncryptionError> {
+        if recipients.is_empty() {
+            return Ok(Self::None(writer));
+        }
+        debug!("Encrypting the archive to {} recipients", recipients.len());
+        let encryptor = Encryptor::with_recipients(
+            recipients
+                .iter()
+                .map(|recipient| recipient as &dyn Recipient),
+        )
+        .map_err(EncryptionError::Encrypt)?;
+        let writer = encryptor
+            .wrap_output(writer)
+            .map_err(|e| EncryptionError::Encrypt(e.into()))?;
+        Ok(Self::Age(writer))
+    }
+
+    /// Write the last encrypted chunk and return the underlying writer
+    pub(crate) fn finish(self) -> io::Result<W> {
+        match self {
+            Self::None(writer) => Ok(writer),
+            Self::Age(writer) => writer.finish(),
+        }
+    }
+}
+
+impl<W: Write> Write for Encryption<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Self::None(writer) => writer.write(buf),
+            Self::Age(writer) => writer.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Self::None(writer) => writer.flush(),
+            Self::Age(writer) => writer.flush(),
+        }
+    }
+}
+
+/// Parse every X25519 recipient in `recipients` (e.g., "age1..."), before anything is written
+pub(crate) fn parse_recipients(
+    recipients: &[String],
+) -> Result<Vec<x25519::Recipient>, EncryptionError> {
+    recipients
+        .iter()
+        .map(|recipient| {
+            x25519::Recipient::from_str(recipient)
+                .map_err(|e| EncryptionError::InvalidRecipient(recipient.clone(), e))
+        })
+        .collect()
+}
+
+/// Read every identity (e.g., "AGE-SECRET-KEY-1...") of the identity file at `path`
+pub(crate) fn read_identities(path: &Path) -> Result<Vec<BoxedIdentity>, EncryptionError> {
+    IdentityFile::from_file(path.display().to_string())
+        .map_err(|e| EncryptionError::ReadIdentity(path.to_path_buf(), e))?
+        .into_identities()
+        .map_err(|e| EncryptionError::InvalidIdentity(path.to_path_buf(), e))
+}
+
+/// Decrypt `reader` with one of `identities` if it starts with an age header, pass it through otherwise
+pub(crate) fn decryptor<'a, R: Read + 'a>(
+    reader: R,
+    identities: &[BoxedIdentity],
+) -> Result<Box<dyn Read + 'a>, EncryptionError> {
+    let mut reader = BufReader::new(reader);
+    let magic = reader
+        .fill_buf()
+        .map_err(|e| EncryptionError::Decrypt(e.into()))?;
+    if !magic.starts_with(AGE_MAGIC) {
+        return Ok(Box::new(reader));
+    }
+    if identities.is_empty() {
+        return Err(EncryptionError::MissingIdentity);
+    }
+    let reader = Decryptor::new_buffered(reader)
+        .and_then(|decryptor| {
+            decryptor.decrypt(
+                identities
+                    .iter()
+                    .map(|identity| identity.as_ref() as &dyn Identity),
+            )
+        })
+        .map_err(EncryptionError::Decrypt)?;
+    Ok(Box::new(reader))
+}
@@ -0,0 +1,19 @@
+use std::path::PathBuf;
+
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum EncryptionError {
+    #[error("Invalid recipient '{0}': {1}")]
+    InvalidRecipient(String, &'static str),
+    #[error("Cannot encrypt the archive: {0}")]
+    Encrypt(#[source] age::EncryptError),
+    #[error("Cannot read the identity file '{0}': {1}")]
+    ReadIdentity(PathBuf, #[source] std::io::Error),
+    #[error("Invalid identity file '{0}': {1}")]
+    InvalidIdentity(PathBuf, #[source] age::DecryptError),
+    #[error("The archive is encrypted, an identity is required to read it")]
+    MissingIdentity,
+    #[error("Cannot decrypt the archive: {0}")]
+    Decrypt(#[source] age::DecryptError),
+}
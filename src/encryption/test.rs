@@ -0,0 +1,158 @@
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use age::secrecy::ExposeSecret;
+use age::x25519;
+use rstest::rstest;
+use tempfile::tempdir;
+
+use crate::archive::{ArchiveBuilder, Manifest, unpack};
+use crate::cmd::MockCommandRunner;
+use crate::compression::{CompressionFormat, CompressionSettings, Encoder};
+use crate::encryption::{Encryption, errors::EncryptionError, parse_recipients, read_identities};
+use crate::progress::Progress;
+use crate::volume::Output;
+use crate::{
+    DownloadSkip, InstallingError, PackageOptions, PackagingError, Settings, package_with_runner,
+};
+
+/// Generate an identity file in `folder`, return its path and its public key
+fn write_identity(folder: &Path, name: &str) -> (PathBuf, String) {
+    let identity = x25519::Identity::generate();
+    let identity_path = folder.join(name);
+    fs::write(&identity_path, identity.to_string().expose_secret()).unwrap();
+    (identity_path, identity.to_public().to_string())
+}
+
+/// Build a zstd archive encrypted to `recipient` in `folder`
+fn build_encrypted_archive(folder: &Path, recipient: String) -> PathBuf {
+    let in_folder = tempdir().unwrap();
+    write!(
+        File::create(in_folder.path().join("file")).unwrap(),
+        "secret"
+    )
+    .unwrap();
+
+    let archive_path = folder.join("archive.tar.zst");
+    let output = Output::new(&archive_path, None).unwrap();
+    let recipients = parse_recipients(&[recipient]).expect("Recipient should be valid");
+    let output = Encryption::new(output, &recipients).unwrap();
+    let settings = CompressionSettings {
+        format: CompressionFormat::Zstd,
+        level: None,
+    };
+    let mut archive = ArchiveBuilder::new(Encoder::new(output, &settings).unwrap());
+    archive.append_dir_all("", in_folder.path()).unwrap();
+    archive.finish().unwrap()
+}
+
+#[rstest]
+#[test_log::test]
+fn unpack_encrypted_archive() {
+    let folder = tempdir().unwrap();
+    let (identity_path, recipient) = write_identity(folder.path(), "key.txt");
+    let archive_path = build_encrypted_archive(folder.path(), recipient);
+    assert!(
+        !fs::read(&archive_path)
+            .unwrap()
+            .windows(b"secret".len())
+            .any(|window| window == b"secret"),
+        "Content should not be readable in the archive"
+    );
+
+    let identities = read_identities(&identity_path).unwrap();
+    let unpacked = tempdir().unwrap();
+    unpack(
+        &archive_path,
+        unpacked.path(),
+        &identities,
+        None,
+        &Progress::default(),
+    )
+    .expect("Archive should be decrypted");
+    assert_eq!(
+        fs::read_to_string(unpacked.path().join("file")).unwrap(),
+        "secret",
+        "Content should survive the encryption"
+    );
+}
+
+#[rstest]
+#[case::no_identity(false)]
+#[case::other_identity(true)]
+#[test_log::test]
+fn cannot_decrypt_without_identity(#[case] other_identity: bool) {
+    let folder = tempdir().unwrap();
+    let (_, recipient) = write_identity(folder.path(), "key.txt");
+    let archive_path = build_encrypted_archive(folder.path(), recipient);
+    let identities = if other_identity {
+        let (other_path, _) = write_identity(folder.path(), "other.txt");
+        read_identities(&other_path).unwrap()
+    } else {
+        Vec::new()
+    };
+
+    let unpacked = tempdir().unwrap();
+    let res = unpack(
+        &archive_path,
+        unpacked.path(),
+        &identities,
+        None,
+        &Progress::default(),
+    );
+    let expected = if other_identity {
+        "Cannot decrypt the archive"
+    } else {
+        "an identity is required"
+    };
+    assert!(
+        matches!(res, Err(InstallingError::ArchiveUncompress(ref e)) if e.to_string().contains(expected)),
+        "Decryption should fail with '{expected}', got {res:?}"
+    );
+}
+
+#[rstest]
+#[test_log::test]
+fn baseline_from_encrypted_archive() {
+    let folder = tempdir().unwrap();
+    let (identity_path, recipient) = write_identity(folder.path(), "key.txt");
+    let archive_path = build_encrypted_archive(folder.path(), recipient);
+
+    let res = Manifest::load_baseline(&archive_path, &read_identities(&identity_path).unwrap());
+
+    assert!(
+        res.is_ok_and(|(manifest, _)| manifest.entries.iter().any(|entry| entry.path == "file")),
+        "The manifest of an encrypted baseline should be read with the identity"
+    );
+}
+
+#[rstest]
+#[test_log::test]
+fn invalid_recipient() {
+    let folder = tempdir().unwrap();
+    let options = PackageOptions {
+        skip: vec![
+            DownloadSkip::Rust,
+            DownloadSkip::Python,
+            DownloadSkip::GitClone,
+        ],
+        staging_dir: Some(folder.path().join("staging")),
+        output_dir: Some(folder.path().join("out")),
+        recipients: vec!["age1invalid".to_owned()],
+        ..PackageOptions::default()
+    };
+
+    let res = package_with_runner(&Settings::default(), &options, &MockCommandRunner::new())
+        .map_err(|e| *e);
+
+    assert!(
+        matches!(res, Err(PackagingError::Encryption(EncryptionError::InvalidRecipient(ref recipient, _))) if recipient == "age1invalid"),
+        "Invalid recipient should be rejected, got {res:?}"
+    );
+    assert!(
+        fs::read_dir(folder.path().join("out"))
+            .map_or(true, |mut entries| entries.next().is_none()),
+        "No archive should be left behind"
+    );
+}
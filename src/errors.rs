@@ -3,8 +3,20 @@ use std::path::PathBuf;
 use thiserror::Error;
 
 use crate::{
-    custom::errors::CustomError, git::errors::GitError, python::errors::PythonError,
+    CompressionFormat,
+    archive::errors::{ManifestError, UnpackError},
+    compose::errors::ComposeError,
+    custom::errors::CustomError,
+    download::errors::CheckpointError,
+    encryption::errors::EncryptionError,
+    git::errors::GitError,
+    merge::errors::MergeError,
+    naming::errors::NamingError,
+    python::errors::PythonError,
     rust::errors::RustError,
+    signature::errors::SignatureError,
+    state::errors::StateError,
+    validation::errors::SettingsError,
 };
 
 #[derive(Error, Debug)]
@@ -17,6 +29,24 @@ pub enum PackagingError {
     CreateMainDirectory(PathBuf, #[source] std::io::Error),
     #[error("Cannot get the absolute path for the intermediate output directory '{0}': {1}")]
     GetCannonMainDirectory(PathBuf, #[source] std::io::Error),
+    #[error("Cannot create the archive output directory at '{0}': {1}")]
+    CreateOutputDirectory(PathBuf, #[source] std::io::Error),
+    #[error("SOURCE_DATE_EPOCH must be a number of seconds since the UNIX epoch, got '{0}'")]
+    SourceDateEpoch(String),
+    #[error("Invalid compression level {1} for {0}")]
+    CompressionLevel(CompressionFormat, u32),
+    #[error(transparent)]
+    Checkpoint(#[from] CheckpointError),
+    #[error("Baseline archive: {0}")]
+    Baseline(#[from] ManifestError),
+    #[error("Signature: {0}")]
+    Signature(#[from] SignatureError),
+    #[error("Encryption: {0}")]
+    Encryption(#[from] EncryptionError),
+    #[error("Archive name: {0}")]
+    Naming(#[from] NamingError),
+    #[error("Cannot write the report at '{0}': {1}")]
+    WriteReport(PathBuf, #[source] std::io::Error),
     #[error("Custom tasks: {0}")]
     Custom(#[from] CustomError),
     #[error("Git: {0}")]
@@ -25,6 +55,24 @@ pub enum PackagingError {
     Python(#[from] PythonError),
     #[error("Rust: {0}")]
     Rust(#[from] RustError),
+    #[error("{} downloads failed: {}", .0.len(), format_errors(.0))]
+    Downloads(Vec<PackagingError>),
+    #[error("Invalid settings: {}", format_errors(.0))]
+    InvalidSettings(Vec<SettingsError>),
+    #[error("Settings: {0}")]
+    Compose(#[from] ComposeError),
+    #[error("{section}: {source}")]
+    Provider {
+        /// Section of the resource provider that failed
+        section: String,
+        source: Box<dyn std::error::Error + Send + Sync>,
+    },
+    #[error("Cannot deserialize the settings, at '{path}': {message}")]
+    DeserializeSettings {
+        /// Path of the offending field, e.g., `rust.binaries[2]`
+        path: String,
+        message: String,
+    },
 }
 
 #[derive(Error, Debug)]
@@ -35,12 +83,45 @@ pub enum InstallingError {
     CreateMainDirectory(PathBuf, #[source] std::io::Error),
     #[error("Cannot get the absolute path for the output directory '{0}': {1}")]
     GetCannonMainDirectory(PathBuf, #[source] std::io::Error),
+    #[error("Cannot create a staging directory to unpack the archive: {0}")]
+    CreateStagingDirectory(#[source] std::io::Error),
     #[error("Invalid config: {0}")]
     Config(#[from] config::ConfigError),
     #[error("Cannot deserialize config: {0}")]
     DeserializeConfig(String),
     #[error("Open & uncompress archive failed: {0}")]
     ArchiveUncompress(#[source] std::io::Error),
+    #[error("Signature: {0}")]
+    Signature(#[from] SignatureError),
+    #[error("Encryption: {0}")]
+    Encryption(#[from] EncryptionError),
+    #[error("Cannot write the report at '{0}': {1}")]
+    WriteReport(PathBuf, #[source] std::io::Error),
+    #[error("Install state: {0}")]
+    State(#[from] StateError),
+    #[error("Merge: {0}")]
+    Merge(#[from] MergeError),
+    #[error("Archive name: {0}")]
+    Naming(#[from] NamingError),
+    #[error("The archive is not signed, no signature found at '{0}'")]
+    MissingSignature(PathBuf),
+    #[error("Invalid signature '{0}': {1}")]
+    InvalidSignature(PathBuf, #[source] minisign::PError),
+    #[error("Unsafe archive: {0}")]
+    Unpack(#[from] UnpackError),
+    #[error("Cannot find '{0}' in the archive")]
+    MissingArchiveEntry(&'static str),
+    #[error(transparent)]
+    Manifest(#[from] ManifestError),
+    #[error("Files missing, altered or not listed in the archive: {}", format_paths(.0))]
+    IntegrityCheck(Vec<PathBuf>),
+    #[error(
+        "This archive only contains changes since the archive with manifest digest {manifest_sha256}, which is not unpacked in '{dst}'"
+    )]
+    MissingBaseline {
+        dst: PathBuf,
+        manifest_sha256: String,
+    },
     #[error("Custom tasks: {0}")]
     Custom(#[from] CustomError),
     #[error("Git: {0}")]
@@ -49,4 +130,26 @@ pub enum InstallingError {
     Python(#[from] PythonError),
     #[error("Rust: {0}")]
     Rust(#[from] RustError),
+    #[error("{section}: {source}")]
+    Provider {
+        /// Section of the resource provider that failed
+        section: String,
+        source: Box<dyn std::error::Error + Send + Sync>,
+    },
+}
+
+pub(crate) fn format_errors<E: std::fmt::Display>(errors: &[E]) -> String {
+    errors
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>()
+        .join("; ")
+}
+
+pub(crate) fn format_paths(paths: &[PathBuf]) -> String {
+    paths
+        .iter()
+        .map(|path| path.display().to_string())
+        .collect::<Vec<_>>()
+        .join(", ")
 }
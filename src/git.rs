@@ -1,102 +1,399 @@
 use std::{
+    collections::{BTreeMap, BTreeSet},
     fs::{self, remove_dir_all},
     path::{Path, PathBuf},
 };
 
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use tracing::{debug, info};
 use url::Url;
 
-use crate::{ArchiveBuilder, MIRRORS_PATH, cmd::CommandRunner, git::errors::GitError};
+use crate::{
+    ArchiveBuilder, MIRRORS_PATH, PackagingError,
+    cmd::{CommandRunner, CommandSpec, RetryPolicy},
+    download::Downloads,
+    git::errors::GitError,
+    jobs,
+    progress::Position,
+    provider::{InstallContext, PackageContext, ResourceProvider},
+    validation::errors::SettingsError,
+};
 
 pub mod errors;
 #[cfg(test)]
 mod test;
 
-#[derive(Debug, Default, Deserialize, Serialize)]
+#[derive(Debug, Default, Deserialize, JsonSchema, Serialize)]
+#[serde(default, deny_unknown_fields)]
 pub struct GitMirrors {
     mirrors: Vec<GitMirror>,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Deserialize, JsonSchema, Serialize)]
+#[serde(deny_unknown_fields)]
 pub struct GitMirror {
     src: Url,
     dst: Url,
 }
 
 impl GitMirrors {
-    pub(crate) fn package<T: CommandRunner>(
+    /// Start building git mirrors in code, without any mirror
+    pub fn builder() -> GitMirrorsBuilder {
+        GitMirrorsBuilder::default()
+    }
+}
+
+/// Builds [`GitMirrors`] in code instead of reading them from a settings file
+#[derive(Debug, Default)]
+#[must_use]
+pub struct GitMirrorsBuilder {
+    settings: GitMirrors,
+}
+
+impl GitMirrorsBuilder {
+    /// Mirror the repository at `src`, pushed to `dst` on the offline machine
+    pub fn mirror(mut self, src: Url, dst: Url) -> Self {
+        self.settings.mirrors.push(GitMirror::new(src, dst));
+        self
+    }
+
+    pub fn build(self) -> GitMirrors {
+        self.settings
+    }
+}
+
+impl GitMirror {
+    /// Mirror of the repository at `src`, pushed to `dst` on the offline machine
+    #[must_use]
+    pub fn new(src: Url, dst: Url) -> Self {
+        Self { src, dst }
+    }
+
+    /// Name of the directory holding the mirror
+    fn basename(&self) -> Result<String, GitError> {
+        Ok(PathBuf::from(self.src.path())
+            .file_name()
+            .and_then(|file_name| file_name.to_str())
+            .ok_or(GitError::NonUtf8BaseName(self.src.clone()))?
+            .to_owned())
+    }
+}
+
+impl GitMirrors {
+    /// Every mirror without a base name to name its clone, and every clone shared by several mirrors
+    pub(crate) fn validate(&self) -> Vec<SettingsError> {
+        let mut errors = Vec::new();
+        let mut sources_by_basename: BTreeMap<String, Vec<Url>> = BTreeMap::new();
+        for (index, mirror) in self.mirrors.iter().enumerate() {
+            match mirror.basename() {
+                Ok(basename) if !basename.is_empty() => sources_by_basename
+                    .entry(basename)
+                    .or_default()
+                    .push(mirror.src.clone()),
+                _ => errors.push(SettingsError::MirrorBaseName {
+                    index,
+                    src: mirror.src.clone(),
+                }),
+            }
+        }
+        errors.extend(
+            sources_by_basename
+                .into_iter()
+                .filter(|(_, sources)| sources.len() > 1)
+                .map(|(basename, sources)| SettingsError::MirrorCollision { basename, sources }),
+        );
+        errors
+    }
+
+    /// Clone every mirror, several at a time if `jobs` allows it
+    pub(crate) fn download(
         &self,
+        runner: &dyn CommandRunner,
         out_folder: &Path,
-        tar: &mut ArchiveBuilder,
-        skip_download: bool,
+        downloads: &Downloads,
     ) -> Result<(), GitError> {
-        info!("Packaging git mirrors");
+        info!("Cloning git mirrors");
         if self.mirrors.is_empty() {
             debug!("No mirror to clone");
             return Ok(());
         }
         let out_folder = out_folder.join(MIRRORS_PATH);
-        fs::create_dir_all(&out_folder)
-            .map_err(|e| GitError::CreateMainDirectory(out_folder.clone(), e))?;
-
-        if !skip_download {
-            for mirror in &self.mirrors {
-                let mirror_basename = PathBuf::from(mirror.src.path())
-                    .file_name()
-                    .and_then(|file_name| file_name.to_str())
-                    .ok_or(GitError::NonUtf8BaseName(mirror.src.clone()))?
-                    .to_owned();
-
-                let mirror_clone_path = out_folder.join(&mirror_basename);
-                if mirror_clone_path.exists() && mirror_clone_path.is_dir() {
-                    remove_dir_all(&mirror_clone_path)
-                        .map_err(|e| GitError::CleanSubDirectory(out_folder.clone(), e))?;
+        if !downloads.dry_run {
+            fs::create_dir_all(&out_folder)
+                .map_err(|e| GitError::CreateMainDirectory(out_folder.clone(), e))?;
+        }
+        let mirrors: Vec<_> = self.mirrors.iter().enumerate().collect();
+        let errors = downloads
+            .jobs
+            .map(&mirrors, |&(index, mirror)| {
+                let position = Position::new(index.saturating_add(1), mirrors.len());
+                downloads.step(&format!("git clone {}", mirror.src), &[], position, || {
+                    mirror.clone_into(runner, &out_folder, downloads)
+                })
+            })
+            .into_iter()
+            .filter_map(Result::err)
+            .collect();
+        jobs::collect_errors(errors, GitError::Mirrors)
+    }
+
+    /// Append the clones, or only their changes since the baseline, to `tar`
+    pub(crate) fn archive(
+        &self,
+        runner: &dyn CommandRunner,
+        out_folder: &Path,
+        tar: &mut ArchiveBuilder,
+    ) -> Result<(), GitError> {
+        if self.mirrors.is_empty() {
+            return Ok(());
+        }
+        let out_folder = out_folder.join(MIRRORS_PATH);
+        for mirror in &self.mirrors {
+            let mirror_basename = mirror.basename()?;
+            let mirror_clone_path = out_folder.join(&mirror_basename);
+            if tar.is_dry_run() {
+                // Nothing was cloned, so the references to compare to the baseline are unknown
+                let dst = Path::new(MIRRORS_PATH).join(&mirror_basename);
+                tar.append_dir_all(&dst, &mirror_clone_path)
+                    .map_err(|e| GitError::Archive {
+                        src: mirror_clone_path,
+                        dst: dst.display().to_string(),
+                        source: e,
+                    })?;
+                continue;
+            }
+            if !mirror_clone_path.is_dir() {
+                tar.recorder()
+                    .warning(format!("No clone of mirror {mirror_basename} to package"));
+                continue;
+            }
+            let refs = read_refs(runner, &mirror_clone_path)?;
+            match tar.baseline_git_refs(&mirror_basename) {
+                Some(baseline_refs) if *baseline_refs == refs => {
+                    debug!("Mirror {mirror_basename} unchanged since the baseline");
+                    tar.keep_baseline_dir(Path::new(MIRRORS_PATH).join(&mirror_basename));
                 }
+                Some(baseline_refs) => {
+                    // Only ship the objects that the baseline mirror doesn't have, if any:
+                    // git refuses to create an empty bundle, e.g., when references were only deleted
+                    let baseline_commits: BTreeSet<&String> = baseline_refs.values().collect();
+                    if refs
+                        .values()
+                        .any(|commit| !baseline_commits.contains(commit))
+                    {
+                        let bundle_name = format!("{mirror_basename}.bundle");
+                        let bundle_path = out_folder.join(&bundle_name);
+                        let mut args = vec![
+                            "bundle".to_owned(),
+                            "create".to_owned(),
+                            bundle_path.display().to_string(),
+                            "--ignore-missing".to_owned(),
+                            "--all".to_owned(),
+                            "--not".to_owned(),
+                        ];
+                        args.extend(baseline_commits.into_iter().cloned());
+                        runner.run(&CommandSpec::new("git", &args).cwd(&mirror_clone_path))?;
 
-                T::run_cmd(
-                    "git",
-                    &[
-                        "clone".to_owned(),
-                        "--mirror".to_owned(),
-                        mirror.src.to_string(),
-                        mirror_basename,
-                    ],
-                    Some(out_folder.clone()),
-                )?;
+                        let dst = Path::new(MIRRORS_PATH).join(bundle_name);
+                        tar.append_path_with_name(&bundle_path, &dst).map_err(|e| {
+                            GitError::Archive {
+                                src: bundle_path,
+                                dst: dst.display().to_string(),
+                                source: e,
+                            }
+                        })?;
+                    }
+                    tar.record_git_bundle(mirror_basename.clone());
+                    // Fetching the bundle changes the baseline mirror, whose files can no longer be listed
+                    tar.unlist_dir(Path::new(MIRRORS_PATH).join(&mirror_basename));
+                }
+                None => {
+                    let dst = Path::new(MIRRORS_PATH).join(&mirror_basename);
+                    tar.append_dir_all(&dst, &mirror_clone_path).map_err(|e| {
+                        GitError::Archive {
+                            src: mirror_clone_path,
+                            dst: dst.display().to_string(),
+                            source: e,
+                        }
+                    })?;
+                }
             }
+            tar.record_git_refs(mirror_basename, refs);
         }
-        tar.append_dir_all(MIRRORS_PATH, &out_folder)
-            .map_err(|e| GitError::Archive {
-                src: out_folder,
-                dst: MIRRORS_PATH.to_owned(),
-                source: e,
-            })?;
         Ok(())
     }
 
+    /// Update the mirrors of the baseline archive changed by a delta archive:
+    /// fetch their bundle, if any, then set their references to the ones recorded in `git_refs`
+    ///
     /// `in_folder` needs to be a canonicalized path
-    pub(crate) fn install<T: CommandRunner>(&self, in_folder: &Path) -> Result<(), GitError> {
-        info!("Synching git mirrors");
+    pub(crate) fn apply_bundles(
+        runner: &dyn CommandRunner,
+        in_folder: &Path,
+        mirrors: &[String],
+        git_refs: &BTreeMap<String, BTreeMap<String, String>>,
+    ) -> Result<(), GitError> {
         let in_folder = in_folder.join(MIRRORS_PATH);
-        for mirror in &self.mirrors {
-            let mirror_basename = PathBuf::from(mirror.src.path())
-                .file_name()
-                .and_then(|file_name| file_name.to_str())
-                .ok_or(GitError::NonUtf8BaseName(mirror.src.clone()))?
-                .to_owned();
-            let in_folder = in_folder.join(mirror_basename);
-
-            T::run_cmd(
+        for mirror_basename in mirrors {
+            info!("Merging changes into git mirror {mirror_basename}");
+            let mirror_path = in_folder.join(mirror_basename);
+            let bundle_path = in_folder.join(format!("{mirror_basename}.bundle"));
+            if bundle_path.is_file() {
+                // The bundle only holds the references to new commits, so the others must not be pruned
+                runner.run(
+                    &CommandSpec::new(
+                        "git",
+                        &[
+                            "fetch".to_owned(),
+                            bundle_path.display().to_string(),
+                            "+refs/*:refs/*".to_owned(),
+                        ],
+                    )
+                    .cwd(&mirror_path),
+                )?;
+            }
+
+            let expected = git_refs.get(mirror_basename).cloned().unwrap_or_default();
+            let current = read_refs(runner, &mirror_path)?;
+            for name in current.keys().filter(|name| !expected.contains_key(*name)) {
+                runner.run(
+                    &CommandSpec::new("git", ["update-ref", "-d", name.as_str()]).cwd(&mirror_path),
+                )?;
+            }
+            for (name, commit) in expected
+                .iter()
+                .filter(|(name, commit)| current.get(*name) != Some(*commit))
+            {
+                runner.run(
+                    &CommandSpec::new("git", ["update-ref", name.as_str(), commit.as_str()])
+                        .cwd(&mirror_path),
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl ResourceProvider for GitMirrors {
+    fn section(&self) -> &str {
+        "git_mirrors"
+    }
+
+    fn describe(&self) -> Vec<String> {
+        self.mirrors
+            .iter()
+            .map(|mirror| format!("git mirror {} pushed to {}", mirror.src, mirror.dst))
+            .collect()
+    }
+
+    fn verify(&self) -> Vec<SettingsError> {
+        self.validate()
+    }
+
+    fn package(&self, context: &PackageContext<'_>) -> Result<(), PackagingError> {
+        Ok(self.download(context.runner(), context.directory(), context.downloads)?)
+    }
+
+    fn archive(
+        &self,
+        context: &PackageContext<'_>,
+        archive: &mut ArchiveBuilder,
+    ) -> Result<(), PackagingError> {
+        Ok(self.archive(context.runner(), context.directory(), archive)?)
+    }
+
+    /// Push every mirror of the unpacked archive to its destination, recording each push as a step
+    fn install(&self, context: &mut InstallContext<'_>) {
+        info!("Synching git mirrors");
+        let runner = context.runner();
+        let in_folder = context.unpacked_directory().join(MIRRORS_PATH);
+        for (index, mirror) in self.mirrors.iter().enumerate() {
+            context.step(
+                "git push",
+                format!("git push {}", mirror.dst),
+                Position::new(index.saturating_add(1), self.mirrors.len()),
+                |_| mirror.push(runner, &in_folder),
+            );
+        }
+    }
+}
+
+impl GitMirror {
+    /// Clone the mirror into `out_folder`, replacing any previous clone unless `downloads` reuses them
+    fn clone_into(
+        &self,
+        runner: &dyn CommandRunner,
+        out_folder: &Path,
+        downloads: &Downloads,
+    ) -> Result<(), GitError> {
+        let basename = self.basename()?;
+        let clone_path = out_folder.join(&basename);
+        if downloads.reuse && clone_path.is_dir() {
+            runner.run(
+                &CommandSpec::new(
+                    "git",
+                    &[
+                        "remote".to_owned(),
+                        "update".to_owned(),
+                        "--prune".to_owned(),
+                    ],
+                )
+                .cwd(clone_path)
+                .retry(RetryPolicy::NETWORK),
+            )?;
+            return Ok(());
+        }
+        if clone_path.is_dir() && !downloads.dry_run {
+            remove_dir_all(&clone_path)
+                .map_err(|e| GitError::CleanSubDirectory(out_folder.to_path_buf(), e))?;
+        }
+        runner.run(
+            &CommandSpec::new(
+                "git",
+                &[
+                    "clone".to_owned(),
+                    "--mirror".to_owned(),
+                    self.src.to_string(),
+                    basename,
+                ],
+            )
+            .cwd(out_folder)
+            .retry(RetryPolicy::NETWORK),
+        )?;
+        Ok(())
+    }
+
+    fn push(&self, runner: &dyn CommandRunner, mirrors_folder: &Path) -> Result<(), GitError> {
+        runner.run(
+            &CommandSpec::new(
                 "git",
                 &[
                     "push".to_owned(),
                     "--mirror".to_owned(),
-                    mirror.dst.to_string(),
+                    self.dst.to_string(),
                 ],
-                Some(in_folder),
-            )?;
-        }
+            )
+            .cwd(mirrors_folder.join(self.basename()?))
+            .retry(RetryPolicy::NETWORK),
+        )?;
         Ok(())
     }
 }
+
+/// Map every reference of the repository at `repo_path` to the object it points to
+fn read_refs(
+    runner: &dyn CommandRunner,
+    repo_path: &Path,
+) -> Result<BTreeMap<String, String>, GitError> {
+    let output = runner.run(
+        &CommandSpec::new("git", ["for-each-ref", "--format=%(objectname) %(refname)"])
+            .cwd(repo_path),
+    )?;
+    Ok(output
+        .stdout
+        .lines()
+        .filter_map(|line| line.split_once(' '))
+        .map(|(hash, name)| (name.to_owned(), hash.to_owned()))
+        .collect())
+}
@@ -3,7 +3,7 @@ use std::path::PathBuf;
 use thiserror::Error;
 use url::Url;
 
-use crate::cmd::CommandFailedError;
+use crate::{cmd::CommandFailedError, download::errors::CheckpointError, errors::format_errors};
 
 #[derive(Error, Debug)]
 pub enum GitError {
@@ -15,10 +15,14 @@ pub enum GitError {
     },
     #[error(transparent)]
     CommandFailed(#[from] Box<CommandFailedError>),
+    #[error(transparent)]
+    Checkpoint(#[from] CheckpointError),
     #[error("Cannot create git sub-directory at '{0}': {1}")]
     CreateMainDirectory(PathBuf, #[source] std::io::Error),
     #[error("Cannot clean git repo at '{0}' before a re-clone: {1}")]
     CleanSubDirectory(PathBuf, #[source] std::io::Error),
     #[error("Either no base name to url path or it contains non-UTF-8 characters: {0}")]
     NonUtf8BaseName(Url),
+    #[error("{} mirrors failed: {}", .0.len(), format_errors(.0))]
+    Mirrors(Vec<GitError>),
 }
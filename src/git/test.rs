@@ -1,22 +1,27 @@
-use std::sync::Mutex;
+use std::collections::BTreeMap;
+use std::fs::{File, create_dir_all};
+use std::io;
+use std::path::PathBuf;
+use std::process::Command;
 
 use mockall::predicate::{eq, function};
 use rstest::rstest;
-use tempfile::tempdir;
-
-use crate::MIRRORS_PATH;
-use crate::cmd::LocalCommandRunner;
-use crate::git::GitMirrors;
-use crate::test::archive;
-use crate::{ArchiveBuilder, cmd::MockCommandRunner};
-
-/// Required to lock this mutex in every test
-/// because of <https://docs.rs/mockall/latest/mockall/#static-methods>
-///
-/// The mutex might be poisoned if a test fails. But we don't
-/// care, because it doesn't hold any data. Whether it's poisoned or
-/// not, we'll still hold the `MutexGuard`.
-static MTX: Mutex<()> = Mutex::new(());
+use tempfile::{tempdir, tempfile};
+
+use crate::archive::{Baseline, Manifest, ManifestEntry};
+use crate::cmd::{CommandFailedError, LocalCommandRunner};
+use crate::compression::{CompressionSettings, Encoder};
+use crate::download::Downloads;
+use crate::encryption::Encryption;
+use crate::git::{GitMirrors, errors::GitError};
+use crate::report::StepStatus;
+use crate::test::{InstallFixture, archive, command, parallel_downloads};
+use crate::volume::Output;
+use crate::{
+    ArchiveBuilder, PackageOptions, ResourceProvider,
+    cmd::{CommandOutput, MockCommandRunner},
+};
+use crate::{MIRRORS_PATH, Settings};
 
 #[rstest]
 #[test_log::test]
@@ -29,8 +34,14 @@ mirrors: []
     .unwrap();
 
     let out_folder = tempdir().unwrap();
-    git.package::<LocalCommandRunner>(out_folder.path(), &mut archive, false)
-        .expect("Shouldn't fail to package crates");
+    git.download(
+        &LocalCommandRunner,
+        out_folder.path(),
+        &Downloads::default(),
+    )
+    .expect("Shouldn't fail to clone mirrors");
+    git.archive(&LocalCommandRunner, out_folder.path(), &mut archive)
+        .expect("Shouldn't fail to archive mirrors");
     archive.finish().expect("Shouldn't fail to build archive");
 }
 
@@ -49,8 +60,14 @@ mirrors:
     .unwrap();
 
     let out_folder = tempdir().unwrap();
-    git.package::<LocalCommandRunner>(out_folder.path(), &mut archive, false)
-        .expect("Shouldn't fail to install mirrors");
+    git.download(
+        &LocalCommandRunner,
+        out_folder.path(),
+        &Downloads::default(),
+    )
+    .expect("Shouldn't fail to clone mirrors");
+    git.archive(&LocalCommandRunner, out_folder.path(), &mut archive)
+        .expect("Shouldn't fail to archive mirrors");
     archive.finish().expect("Shouldn't fail to build archive");
 
     // Check the clones
@@ -61,8 +78,6 @@ mirrors:
 
 #[test_log::test]
 fn install() {
-    let _m = MTX.lock();
-
     let git: GitMirrors = serde_yaml::from_str(
         "
 mirrors:
@@ -74,13 +89,14 @@ mirrors:
     )
     .unwrap();
 
-    let ctx = MockCommandRunner::run_cmd_context();
+    let mut runner = MockCommandRunner::new();
     let in_folder = tempdir().unwrap();
     let mirrors_folder = in_folder.path().join(MIRRORS_PATH);
 
     let pre_commit_rust = mirrors_folder.join("pre-commit-rust");
-    ctx.expect()
-        .with(
+    runner
+        .expect_run()
+        .with(command(
             eq("git"),
             function(move |args: &[String]| {
                 args == [
@@ -90,12 +106,13 @@ mirrors:
                 ]
             }),
             eq(Some(pre_commit_rust)),
-        )
+        ))
         .times(1)
-        .returning(|_, _, _| Ok(()));
+        .returning(|_| Ok(CommandOutput::default()));
     let advisory_db = mirrors_folder.join("advisory-db");
-    ctx.expect()
-        .with(
+    runner
+        .expect_run()
+        .with(command(
             eq("git"),
             function(move |args: &[String]| {
                 args == [
@@ -105,10 +122,449 @@ mirrors:
                 ]
             }),
             eq(Some(advisory_db)),
-        )
+        ))
+        .times(1)
+        .returning(|_| Ok(CommandOutput::default()));
+
+    let mut fixture = InstallFixture::default();
+    git.install(&mut fixture.context(&runner, in_folder.path()));
+    let report = fixture.report;
+    assert!(report.is_success(), "Shouldn't fail to install mirrors");
+    assert_eq!(report.steps.len(), 2, "Every mirror should be reported");
+}
+
+#[test_log::test]
+fn package_delta() {
+    let git: GitMirrors = serde_yaml::from_str(
+        "
+mirrors:
+    - src: https://github.com/doublify/pre-commit-rust
+      dst: https://private.domain/global/pre-commit-rust
+    - src: https://github.com/rustsec/advisory-db
+      dst: https://private.domain/global/advisory-db
+",
+    )
+    .unwrap();
+
+    // The baseline has the same pre-commit-rust mirror but an older advisory-db
+    let mut baseline_manifest = Manifest::default();
+    baseline_manifest.git_refs.insert(
+        "pre-commit-rust".to_owned(),
+        BTreeMap::from([("refs/heads/master".to_owned(), "1111".to_owned())]),
+    );
+    baseline_manifest.git_refs.insert(
+        "advisory-db".to_owned(),
+        BTreeMap::from([("refs/heads/main".to_owned(), "2222".to_owned())]),
+    );
+    for mirror in ["pre-commit-rust", "advisory-db"] {
+        baseline_manifest.entries.push(ManifestEntry {
+            path: format!("mirrors/{mirror}/HEAD"),
+            size: 0,
+            sha256: String::new(),
+            unchanged: false,
+        });
+    }
+    let baseline = Baseline {
+        manifest_sha256: "0000".to_owned(),
+        created_at: 0,
+    };
+    let output = Output::Single {
+        path: PathBuf::new(),
+        file: tempfile().unwrap(),
+    };
+    let mut archive = ArchiveBuilder::new(
+        Encoder::new(Encryption::None(output), &CompressionSettings::default()).unwrap(),
+    )
+    .with_baseline(baseline_manifest, baseline)
+    .unwrap();
+
+    let mut runner = MockCommandRunner::new();
+    let out_folder = tempdir().unwrap();
+    let mirrors_folder = out_folder.path().join(MIRRORS_PATH);
+    runner
+        .expect_run()
+        .with(command(
+            eq("git"),
+            function(|args: &[String]| args[0] == "clone"),
+            eq(Some(mirrors_folder.clone())),
+        ))
+        .times(2)
+        .returning(|command| {
+            create_dir_all(command.cwd.as_ref().unwrap().join(&command.args[3])).unwrap();
+            Ok(CommandOutput::default())
+        });
+    // Fake clones with a single reference
+    runner
+        .expect_run()
+        .with(command(
+            eq("git"),
+            function(|args: &[String]| args[0] == "for-each-ref"),
+            function(|_: &Option<PathBuf>| true),
+        ))
+        .times(2)
+        .returning(|command| {
+            let stdout = if command.cwd.as_ref().unwrap().ends_with("advisory-db") {
+                "3333 refs/heads/main\n"
+            } else {
+                "1111 refs/heads/master\n"
+            };
+            Ok(CommandOutput {
+                stdout: stdout.to_owned(),
+                ..CommandOutput::default()
+            })
+        });
+    let bundle_path = mirrors_folder.join("advisory-db.bundle");
+    runner
+        .expect_run()
+        .with(command(
+            eq("git"),
+            eq([
+                "bundle".to_owned(),
+                "create".to_owned(),
+                bundle_path.display().to_string(),
+                "--ignore-missing".to_owned(),
+                "--all".to_owned(),
+                "--not".to_owned(),
+                "2222".to_owned(),
+            ]),
+            eq(Some(mirrors_folder.join("advisory-db"))),
+        ))
+        .times(1)
+        .returning(move |_| {
+            File::create(&bundle_path).unwrap();
+            Ok(CommandOutput::default())
+        });
+
+    git.download(&runner, out_folder.path(), &Downloads::default())
+        .expect("Shouldn't fail to clone mirrors");
+    git.archive(&runner, out_folder.path(), &mut archive)
+        .expect("Shouldn't fail to package mirrors");
+    assert_eq!(
+        archive.manifest.git_bundles,
+        ["advisory-db"],
+        "Only the changed mirror should be bundled"
+    );
+    assert_eq!(
+        archive.manifest.git_refs["advisory-db"]["refs/heads/main"], "3333",
+        "The new references should be recorded"
+    );
+    assert_eq!(
+        archive
+            .manifest
+            .entries
+            .iter()
+            .map(|entry| (entry.path.as_str(), entry.unchanged))
+            .collect::<Vec<_>>(),
+        [
+            ("mirrors/pre-commit-rust/HEAD", true),
+            ("mirrors/advisory-db.bundle", false)
+        ],
+        "Only the bundle should be archived, the unchanged mirror being kept"
+    );
+    assert_eq!(
+        archive.manifest.unlisted_dirs,
+        ["mirrors/advisory-db"],
+        "The mirror fetching the bundle should no longer be listed"
+    );
+    archive.finish().expect("Shouldn't fail to build archive");
+}
+
+#[test_log::test]
+fn package_delta_with_deleted_refs() {
+    let git: GitMirrors = serde_yaml::from_str(
+        "
+mirrors:
+    - src: https://github.com/rustsec/advisory-db
+      dst: https://private.domain/global/advisory-db
+",
+    )
+    .unwrap();
+    let mut baseline_manifest = Manifest::default();
+    baseline_manifest.git_refs.insert(
+        "advisory-db".to_owned(),
+        BTreeMap::from([
+            ("refs/heads/main".to_owned(), "2222".to_owned()),
+            ("refs/heads/old".to_owned(), "1111".to_owned()),
+        ]),
+    );
+    let output = Output::Single {
+        path: PathBuf::new(),
+        file: tempfile().unwrap(),
+    };
+    let mut archive = ArchiveBuilder::new(
+        Encoder::new(Encryption::None(output), &CompressionSettings::default()).unwrap(),
+    )
+    .with_baseline(
+        baseline_manifest,
+        Baseline {
+            manifest_sha256: "0000".to_owned(),
+            created_at: 0,
+        },
+    )
+    .unwrap();
+
+    // The old branch was deleted, so there is no new object to bundle
+    let out_folder = tempdir().unwrap();
+    create_dir_all(out_folder.path().join(MIRRORS_PATH).join("advisory-db")).unwrap();
+    let mut runner = MockCommandRunner::new();
+    runner
+        .expect_run()
+        .with(command(
+            eq("git"),
+            function(|args: &[String]| args[0] == "for-each-ref"),
+            function(|_: &Option<PathBuf>| true),
+        ))
+        .times(1)
+        .returning(|_| {
+            Ok(CommandOutput {
+                stdout: "2222 refs/heads/main\n".to_owned(),
+                ..CommandOutput::default()
+            })
+        });
+
+    git.archive(&runner, out_folder.path(), &mut archive)
+        .expect("Shouldn't try to create an empty bundle");
+    assert_eq!(
+        archive.manifest.git_bundles,
+        ["advisory-db"],
+        "The changed mirror should be updated at install"
+    );
+    assert!(
+        archive.manifest.entries.is_empty(),
+        "No bundle should be archived"
+    );
+    assert_eq!(
+        archive.manifest.git_refs["advisory-db"],
+        BTreeMap::from([("refs/heads/main".to_owned(), "2222".to_owned())]),
+        "The remaining references should be recorded"
+    );
+}
+
+#[test_log::test]
+fn apply_bundles_sets_recorded_refs() {
+    let in_folder = tempdir().unwrap();
+    let mirrors_folder = in_folder.path().join(MIRRORS_PATH);
+    create_dir_all(mirrors_folder.join("advisory-db")).unwrap();
+    File::create(mirrors_folder.join("advisory-db.bundle")).unwrap();
+    let git_refs = BTreeMap::from([(
+        "advisory-db".to_owned(),
+        BTreeMap::from([
+            ("refs/heads/main".to_owned(), "3333".to_owned()),
+            ("refs/tags/v1".to_owned(), "1111".to_owned()),
+        ]),
+    )]);
+
+    let mut runner = MockCommandRunner::new();
+    let mirror_path = Some(mirrors_folder.join("advisory-db"));
+    runner
+        .expect_run()
+        .with(command(
+            eq("git"),
+            function(|args: &[String]| args[0] == "fetch" && args.len() == 3),
+            eq(mirror_path.clone()),
+        ))
+        .times(1)
+        .returning(|_| Ok(CommandOutput::default()));
+    // The bundle updated main, but doesn't delete the old branch nor bring the tag of an old commit
+    runner
+        .expect_run()
+        .with(command(
+            eq("git"),
+            function(|args: &[String]| args[0] == "for-each-ref"),
+            eq(mirror_path.clone()),
+        ))
+        .times(1)
+        .returning(|_| {
+            Ok(CommandOutput {
+                stdout: "3333 refs/heads/main\n2222 refs/heads/old\n".to_owned(),
+                ..CommandOutput::default()
+            })
+        });
+    runner
+        .expect_run()
+        .with(command(
+            eq("git"),
+            eq(["update-ref", "-d", "refs/heads/old"].map(String::from)),
+            eq(mirror_path.clone()),
+        ))
+        .times(1)
+        .returning(|_| Ok(CommandOutput::default()));
+    runner
+        .expect_run()
+        .with(command(
+            eq("git"),
+            eq(["update-ref", "refs/tags/v1", "1111"].map(String::from)),
+            eq(mirror_path),
+        ))
+        .times(1)
+        .returning(|_| Ok(CommandOutput::default()));
+
+    GitMirrors::apply_bundles(
+        &runner,
+        in_folder.path(),
+        &["advisory-db".to_owned()],
+        &git_refs,
+    )
+    .expect("Shouldn't fail to update the mirror");
+}
+
+#[test_log::test]
+fn install_continues_after_failure() {
+    let git: GitMirrors = serde_yaml::from_str(
+        "
+mirrors:
+    - src: https://github.com/doublify/pre-commit-rust
+      dst: https://private.domain/global/pre-commit-rust
+    - src: https://github.com/rustsec/advisory-db
+      dst: https://private.domain/global/advisory-db
+",
+    )
+    .unwrap();
+
+    let mut runner = MockCommandRunner::new();
+    let in_folder = tempdir().unwrap();
+    runner
+        .expect_run()
+        .with(command(
+            eq("git"),
+            function(|args: &[String]| args.last().unwrap().ends_with("pre-commit-rust")),
+            function(|_: &Option<PathBuf>| true),
+        ))
+        .times(1)
+        .returning(|command| {
+            Err(Box::new(CommandFailedError::CommandStart(
+                Command::new(&command.program),
+                io::Error::other("unreachable remote"),
+            )))
+        });
+    runner
+        .expect_run()
+        .with(command(
+            eq("git"),
+            function(|args: &[String]| args.last().unwrap().ends_with("advisory-db")),
+            function(|_: &Option<PathBuf>| true),
+        ))
+        .times(1)
+        .returning(|_| Ok(CommandOutput::default()));
+
+    let mut fixture = InstallFixture::default();
+    git.install(&mut fixture.context(&runner, in_folder.path()));
+    let report = fixture.report;
+    let failures: Vec<_> = report.failures().map(|(name, _)| name).collect();
+    assert_eq!(
+        failures,
+        ["git push https://private.domain/global/pre-commit-rust"],
+        "Only the first push should fail"
+    );
+    assert!(
+        matches!(report.steps[1].status, StepStatus::Succeeded),
+        "The second push should still run"
+    );
+}
+
+#[test_log::test]
+fn download_in_parallel_collects_failures() {
+    let git: GitMirrors = serde_yaml::from_str(
+        "
+mirrors:
+    - src: https://github.com/doublify/pre-commit-rust
+      dst: https://private.domain/global/pre-commit-rust
+    - src: https://github.com/rustsec/advisory-db
+      dst: https://private.domain/global/advisory-db
+    - src: https://github.com/jadinm/rust-pre-commit-hooks
+      dst: https://private.domain/global/rust-pre-commit-hooks
+",
+    )
+    .unwrap();
+
+    let mut runner = MockCommandRunner::new();
+    let out_folder = tempdir().unwrap();
+    runner
+        .expect_run()
+        .with(command(
+            eq("git"),
+            function(|args: &[String]| args[0] == "clone"),
+            eq(Some(out_folder.path().join(MIRRORS_PATH))),
+        ))
+        .times(3)
+        .returning(|command| {
+            if command.args[3] == "advisory-db" {
+                create_dir_all(command.cwd.as_ref().unwrap().join(&command.args[3])).unwrap();
+                return Ok(CommandOutput::default());
+            }
+            Err(Box::new(CommandFailedError::CommandStart(
+                Command::new(&command.program),
+                io::Error::other("unreachable remote"),
+            )))
+        });
+
+    let res = git.download(
+        &runner,
+        out_folder.path(),
+        &parallel_downloads(out_folder.path()),
+    );
+
+    assert!(
+        matches!(&res, Err(GitError::Mirrors(errors)) if errors.len() == 2),
+        "Both failed clones should be reported, got {res:?}"
+    );
+    assert!(
+        out_folder
+            .path()
+            .join(MIRRORS_PATH)
+            .join("advisory-db")
+            .is_dir(),
+        "The other clone should still run"
+    );
+}
+
+#[test_log::test]
+fn download_updates_cached_clones() {
+    let git: GitMirrors = serde_yaml::from_str(
+        "
+mirrors:
+    - src: https://github.com/doublify/pre-commit-rust
+      dst: https://private.domain/global/pre-commit-rust
+    - src: https://github.com/rustsec/advisory-db
+      dst: https://private.domain/global/advisory-db
+",
+    )
+    .unwrap();
+
+    let mut runner = MockCommandRunner::new();
+    let cache_folder = tempdir().unwrap();
+    let mirrors_folder = cache_folder.path().join(MIRRORS_PATH);
+    // advisory-db was cloned by a previous run
+    create_dir_all(mirrors_folder.join("advisory-db")).unwrap();
+    runner
+        .expect_run()
+        .with(command(
+            eq("git"),
+            eq([
+                "remote".to_owned(),
+                "update".to_owned(),
+                "--prune".to_owned(),
+            ]),
+            eq(Some(mirrors_folder.join("advisory-db"))),
+        ))
+        .times(1)
+        .returning(|_| Ok(CommandOutput::default()));
+    runner
+        .expect_run()
+        .with(command(
+            eq("git"),
+            function(|args: &[String]| args[0] == "clone" && args[3] == "pre-commit-rust"),
+            eq(Some(mirrors_folder.clone())),
+        ))
         .times(1)
-        .returning(|_, _, _| Ok(()));
+        .returning(|_| Ok(CommandOutput::default()));
 
-    git.install::<MockCommandRunner>(in_folder.path())
-        .expect("Shouldn't fail to install mirrors");
+    let options = PackageOptions {
+        cache_dir: Some(cache_folder.path().to_path_buf()),
+        ..PackageOptions::default()
+    };
+    let downloads =
+        Downloads::new(cache_folder.path(), &Settings::default(), &options, false).unwrap();
+    git.download(&runner, cache_folder.path(), &downloads)
+        .expect("Shouldn't fail to update mirrors");
 }
@@ -0,0 +1,226 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt::{self, Display},
+    io::Read,
+    path::{Path, PathBuf},
+};
+
+use serde::Serialize;
+use tar::EntryType;
+use tracing::debug;
+
+use crate::{
+    CARGO_TOOLS_PATH, CARGO_VENDOR_PATH, InstallingError, MIRRORS_PATH, PIP_DOWNLOAD_DIR,
+    SETTINGS_FILE, Settings,
+    archive::{
+        self, BASELINE_FILE, MANIFEST_FILE, Manifest, archive_path, errors::ManifestError,
+        hash_reader,
+    },
+    embedded_settings,
+    encryption::read_identities,
+};
+
+#[cfg(test)]
+mod test;
+
+/// Content of an archive, as read without unpacking it
+#[derive(Debug, Serialize)]
+pub struct ArchiveSummary {
+    /// Settings embedded in the archive
+    pub settings: Settings,
+    /// Vendored crates, with their version
+    pub crates: Vec<String>,
+    /// Python wheels and source distributions
+    pub wheels: Vec<String>,
+    /// Git mirrors
+    pub mirrors: Vec<String>,
+    /// Cargo tool binaries
+    pub tools: Vec<String>,
+    /// Paths packaged by custom tasks
+    pub custom_paths: Vec<CustomPathSummary>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CustomPathSummary {
+    /// Path relative to the root of the archive
+    pub path: String,
+    /// Total size in bytes of the files under that path
+    pub size: u64,
+}
+
+/// Read the archive at `archive_path` without writing anything to disk,
+/// check its content against its manifest and summarize what it contains.
+///
+/// An encrypted archive is decrypted with the age identity file at `identity`.
+///
+/// # Errors
+///
+/// Check [`InstallingError`]
+pub fn inspect(
+    archive_path: &Path,
+    identity: Option<&Path>,
+) -> Result<ArchiveSummary, Box<InstallingError>> {
+    inspect_inner(archive_path, identity).map_err(Box::new)
+}
+
+fn inspect_inner(
+    archive_path: &Path,
+    identity: Option<&Path>,
+) -> Result<ArchiveSummary, InstallingError> {
+    let identities = identity
+        .map(read_identities)
+        .transpose()?
+        .unwrap_or_default();
+    let mut archive =
+        archive::open(archive_path, &identities).map_err(InstallingError::ArchiveUncompress)?;
+
+    let mut settings: Option<Settings> = None;
+    let mut manifest: Option<Manifest> = None;
+    // Size and digest of every regular file read from the archive
+    let mut files = BTreeMap::new();
+    for entry in archive
+        .entries()
+        .map_err(InstallingError::ArchiveUncompress)?
+    {
+        let mut entry = entry.map_err(InstallingError::ArchiveUncompress)?;
+        if entry.header().entry_type() != EntryType::Regular {
+            continue;
+        }
+        let path =
+            archive::archive_path(&entry.path().map_err(InstallingError::ArchiveUncompress)?);
+        debug!("Reading {path}");
+        if path == SETTINGS_FILE {
+            let mut content = Vec::new();
+            entry
+                .read_to_end(&mut content)
+                .map_err(InstallingError::ArchiveUncompress)?;
+            let value = serde_yaml::from_slice(&content)
+                .map_err(|e| InstallingError::DeserializeConfig(e.to_string()))?;
+            settings = Some(embedded_settings(value)?);
+            files.insert(path, hash_reader(content.as_slice()));
+        } else if path == MANIFEST_FILE {
+            manifest = Some(
+                serde_json::from_reader(&mut entry)
+                    .map_err(|e| ManifestError::Parse(PathBuf::from(MANIFEST_FILE), e))?,
+            );
+        } else {
+            files.insert(path, hash_reader(&mut entry));
+        }
+    }
+
+    let manifest = manifest.ok_or(InstallingError::MissingArchiveEntry(MANIFEST_FILE))?;
+    let settings = settings.ok_or(InstallingError::MissingArchiveEntry(SETTINGS_FILE))?;
+
+    let mut corrupted_files = Vec::new();
+    let mut sizes = Vec::new();
+    // A delta archive lists the files of its baseline too
+    for entry in manifest.entries.iter().filter(|entry| !entry.unchanged) {
+        match files.get(&entry.path) {
+            Some(Ok((size, sha256))) if *size == entry.size && *sha256 == entry.sha256 => {
+                sizes.push((entry.path.as_str(), entry.size));
+            }
+            _ => corrupted_files.push(PathBuf::from(&entry.path)),
+        }
+    }
+    // Installing rejects the files that the manifest doesn't list
+    let listed: BTreeSet<&str> = manifest
+        .entries
+        .iter()
+        .map(|entry| entry.path.as_str())
+        .chain([BASELINE_FILE])
+        .collect();
+    corrupted_files.extend(
+        files
+            .keys()
+            .filter(|path| !listed.contains(path.as_str()))
+            .map(PathBuf::from),
+    );
+    if !corrupted_files.is_empty() {
+        return Err(InstallingError::IntegrityCheck(corrupted_files));
+    }
+
+    Ok(ArchiveSummary::new(settings, &sizes))
+}
+
+impl ArchiveSummary {
+    /// `files` lists the path and the size of every file in the archive
+    fn new(settings: Settings, files: &[(&str, u64)]) -> Self {
+        let mut crates = BTreeSet::new();
+        let mut wheels = BTreeSet::new();
+        let mut mirrors = BTreeSet::new();
+        let mut tools = BTreeSet::new();
+        for (path, _) in files {
+            let components: Vec<&str> = path.split('/').collect();
+            match components.as_slice() {
+                [CARGO_VENDOR_PATH, crate_dir, _, ..] => {
+                    crates.insert((*crate_dir).to_owned());
+                }
+                [PIP_DOWNLOAD_DIR, wheel] => {
+                    wheels.insert((*wheel).to_owned());
+                }
+                [MIRRORS_PATH, mirror, _, ..] => {
+                    mirrors.insert((*mirror).to_owned());
+                }
+                // A delta archive ships the changes of a mirror as a bundle
+                [MIRRORS_PATH, bundle] => {
+                    if let Some(mirror) = bundle.strip_suffix(".bundle") {
+                        mirrors.insert(mirror.to_owned());
+                    }
+                }
+                [CARGO_TOOLS_PATH, "bin", tool] => {
+                    tools.insert((*tool).to_owned());
+                }
+                _ => {}
+            }
+        }
+
+        let custom_paths = settings
+            .custom
+            .package_paths()
+            .map(|package_path| {
+                let package_path = archive_path(package_path);
+                let prefix = format!("{package_path}/");
+                let size = files
+                    .iter()
+                    .filter(|(path, _)| *path == package_path || path.starts_with(&prefix))
+                    .map(|(_, size)| size)
+                    .sum();
+                CustomPathSummary {
+                    path: package_path,
+                    size,
+                }
+            })
+            .collect();
+
+        Self {
+            settings,
+            crates: crates.into_iter().collect(),
+            wheels: wheels.into_iter().collect(),
+            mirrors: mirrors.into_iter().collect(),
+            tools: tools.into_iter().collect(),
+            custom_paths,
+        }
+    }
+}
+
+impl Display for ArchiveSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Archive '{}'", self.settings.name)?;
+        for (title, items) in [
+            ("Rust crates", &self.crates),
+            ("Python packages", &self.wheels),
+            ("Git mirrors", &self.mirrors),
+            ("Cargo tools", &self.tools),
+        ] {
+            writeln!(f, "{title} ({}):", items.len())?;
+            for item in items {
+                writeln!(f, "  - {item}")?;
+            }
+        }
+        writeln!(f, "Custom task paths ({}):", self.custom_paths.len())?;
+        for custom_path in &self.custom_paths {
+            writeln!(f, "  - {} ({} bytes)", custom_path.path, custom_path.size)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,198 @@
+use std::fs::{File, create_dir_all};
+use std::io::Write;
+use std::path::PathBuf;
+
+use flate2::{Compression, read::GzDecoder, write::GzEncoder};
+use rstest::rstest;
+use tempfile::tempdir;
+
+use crate::inspect::inspect_inner;
+use crate::provider::ResourceProvider;
+use crate::test::archive_at;
+use crate::{
+    CARGO_TOOLS_PATH, CARGO_VENDOR_PATH, InstallingError, MIRRORS_PATH, PIP_DOWNLOAD_DIR,
+    SETTINGS_FILE,
+};
+
+const SETTINGS: &str = "
+name: inspected
+rust:
+    manifests: []
+    binaries: []
+    use_binstall: true
+python:
+    requirement_files: []
+git_mirrors:
+    mirrors: []
+custom:
+    tasks:
+        - paths_to_package:
+            /somewhere/vsix: vsix/
+          install_command: code --install-extension
+          install_counts: EachFile
+";
+
+/// Create files in `folder` at each of the given relative paths with `content`
+fn create_files(folder: &std::path::Path, paths: &[&str], content: &str) {
+    for path in paths {
+        let path = folder.join(path);
+        create_dir_all(path.parent().unwrap()).unwrap();
+        write!(File::create(path).unwrap(), "{content}").unwrap();
+    }
+}
+
+#[rstest]
+#[test_log::test]
+fn inspect_lists_content() {
+    let in_folder = tempdir().unwrap();
+    create_files(
+        in_folder.path(),
+        &[
+            &format!("{CARGO_VENDOR_PATH}/serde-1.0.0/Cargo.toml"),
+            &format!("{CARGO_VENDOR_PATH}/serde-1.0.0/src/lib.rs"),
+            &format!("{CARGO_VENDOR_PATH}/tar-0.4.45/Cargo.toml"),
+            &format!("{PIP_DOWNLOAD_DIR}/pre_commit-4.0.0-py2.py3-none-any.whl"),
+            &format!("{MIRRORS_PATH}/advisory-db/HEAD"),
+            &format!("{MIRRORS_PATH}/cargo.bundle"),
+            &format!("{CARGO_TOOLS_PATH}/bin/cargo-audit"),
+            "vsix/one.vsix",
+            "vsix/two.vsix",
+        ],
+        "12345",
+    );
+    create_files(in_folder.path(), &[SETTINGS_FILE], SETTINGS);
+
+    let out_folder = tempdir().unwrap();
+    let archive_path = out_folder.path().join("inspected.tar.gz");
+    let mut archive = archive_at(&archive_path);
+    archive.append_dir_all("", in_folder.path()).unwrap();
+    archive.finish().unwrap();
+
+    let summary = inspect_inner(&archive_path, None).expect("Inspection should succeed");
+    assert_eq!(
+        summary.settings.name, "inspected",
+        "Wrong embedded settings"
+    );
+    assert_eq!(
+        summary.crates,
+        ["serde-1.0.0", "tar-0.4.45"],
+        "Wrong crates"
+    );
+    assert_eq!(
+        summary.wheels,
+        ["pre_commit-4.0.0-py2.py3-none-any.whl"],
+        "Wrong wheels"
+    );
+    assert_eq!(
+        summary.mirrors,
+        ["advisory-db", "cargo"],
+        "Wrong mirrors, including the ones shipped as a bundle"
+    );
+    assert_eq!(summary.tools, ["cargo-audit"], "Wrong tools");
+    assert_eq!(summary.custom_paths.len(), 1, "Wrong custom paths");
+    assert_eq!(summary.custom_paths[0].path, "vsix", "Wrong custom path");
+    assert_eq!(summary.custom_paths[0].size, 10, "Wrong custom path size");
+}
+
+#[rstest]
+#[test_log::test]
+fn inspect_rejects_unlisted_files() {
+    let in_folder = tempdir().unwrap();
+    create_files(in_folder.path(), &["file"], "12345");
+    create_files(in_folder.path(), &[SETTINGS_FILE], SETTINGS);
+
+    let out_folder = tempdir().unwrap();
+    let listed_path = out_folder.path().join("listed.tar.gz");
+    let mut archive = archive_at(&listed_path);
+    archive.append_dir_all("", in_folder.path()).unwrap();
+    archive.finish().unwrap();
+
+    // Same archive, with a file that its manifest doesn't list
+    let archive_path = out_folder.path().join("unlisted.tar.gz");
+    let mut builder = tar::Builder::new(GzEncoder::new(
+        File::create(&archive_path).unwrap(),
+        Compression::default(),
+    ));
+    let mut listed = tar::Archive::new(GzDecoder::new(File::open(&listed_path).unwrap()));
+    for entry in listed.entries().unwrap() {
+        let mut entry = entry.unwrap();
+        let header = entry.header().clone();
+        builder.append(&header, &mut entry).unwrap();
+    }
+    let mut header = tar::Header::new_gnu();
+    header.set_size(8);
+    header.set_mode(0o644);
+    builder
+        .append_data(&mut header, "unlisted", b"unlisted".as_slice())
+        .unwrap();
+    builder.into_inner().unwrap().finish().unwrap();
+
+    assert!(
+        inspect_inner(&listed_path, None).is_ok(),
+        "The original archive should be valid"
+    );
+    let res = inspect_inner(&archive_path, None);
+    assert!(
+        matches!(res, Err(InstallingError::IntegrityCheck(ref files)) if *files == [PathBuf::from("unlisted")]),
+        "Files missing from the manifest should be reported, got {res:?}"
+    );
+}
+
+#[rstest]
+#[test_log::test]
+fn inspect_requires_settings() {
+    let in_folder = tempdir().unwrap();
+    create_files(in_folder.path(), &["file"], "12345");
+
+    let out_folder = tempdir().unwrap();
+    let archive_path = out_folder.path().join("no-settings.tar.gz");
+    let mut archive = archive_at(&archive_path);
+    archive.append_dir_all("", in_folder.path()).unwrap();
+    archive.finish().unwrap();
+
+    let res = inspect_inner(&archive_path, None);
+    assert!(
+        matches!(
+            res,
+            Err(InstallingError::MissingArchiveEntry(SETTINGS_FILE))
+        ),
+        "Inspection should fail without settings: {res:?}"
+    );
+}
+
+#[rstest]
+#[test_log::test]
+fn inspect_ignores_unknown_settings() {
+    let in_folder = tempdir().unwrap();
+    create_files(
+        in_folder.path(),
+        &[SETTINGS_FILE],
+        "
+name: newer
+signing: {}
+rust:
+    binaries: [cargo-audit]
+    offline_only: true
+git_mirrors:
+    mirrors:
+        - src: https://github.com/rustsec/advisory-db
+          dst: https://private.domain/global/advisory-db
+          shallow: true
+",
+    );
+
+    let out_folder = tempdir().unwrap();
+    let archive_path = out_folder.path().join("newer.tar.gz");
+    let mut archive = archive_at(&archive_path);
+    archive.append_dir_all("", in_folder.path()).unwrap();
+    archive.finish().unwrap();
+
+    let res = inspect_inner(&archive_path, None);
+    assert!(
+        res.as_ref()
+            .is_ok_and(|summary| summary.settings.name == "newer"
+                && summary.settings.rust.describe() == ["cargo tool cargo-audit"]
+                && summary.settings.git_mirrors.describe().len() == 1),
+        "Fields unknown to this version should be ignored in embedded settings, got {res:?}"
+    );
+}
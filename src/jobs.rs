@@ -0,0 +1,132 @@
+use std::{
+    num::NonZeroUsize,
+    panic,
+    sync::{Condvar, Mutex, PoisonError},
+    thread::{self, ScopedJoinHandle},
+};
+
+#[cfg(test)]
+mod test;
+
+/// Limit on the number of downloads running at the same time
+pub(crate) struct Jobs {
+    max: usize,
+    running: Mutex<usize>,
+    released: Condvar,
+}
+
+/// Slot taken by a running job, given back when dropped
+struct Slot<'a>(&'a Jobs);
+
+impl Default for Jobs {
+    fn default() -> Self {
+        Self::new(NonZeroUsize::MIN)
+    }
+}
+
+impl Jobs {
+    pub(crate) fn new(max: NonZeroUsize) -> Self {
+        Self {
+            max: max.get(),
+            running: Mutex::new(0),
+            released: Condvar::new(),
+        }
+    }
+
+    /// Whether more than one job can run at the same time
+    pub(crate) fn is_parallel(&self) -> bool {
+        self.max > 1
+    }
+
+    /// Run `job` once fewer than the maximum number of jobs are running
+    pub(crate) fn run<R, F: FnOnce() -> R>(&self, job: F) -> R {
+        let _slot = self.acquire();
+        job()
+    }
+
+    /// Run `job` on every item, on as many threads as allowed, and return the results in the order of `items`.
+    ///
+    /// Each thread takes the next item left once its job is done, so no more threads are started than allowed jobs.
+    pub(crate) fn map<I: Sync, R: Send, F: Fn(&I) -> R + Sync>(
+        &self,
+        items: &[I],
+        job: F,
+    ) -> Vec<R> {
+        self.map_nested(items, |item| self.run(|| job(item)))
+    }
+
+    /// Run `job` on every item like [`Self::map`], but without taking a slot for it.
+    ///
+    /// Meant for jobs running other jobs (e.g., the downloads of a section), which take their own slots:
+    /// taking one for the outer job too could leave the inner ones waiting forever.
+    pub(crate) fn map_nested<I: Sync, R: Send, F: Fn(&I) -> R + Sync>(
+        &self,
+        items: &[I],
+        job: F,
+    ) -> Vec<R> {
+        if !self.is_parallel() {
+            return items.iter().map(job).collect();
+        }
+        let next = Mutex::new(items.iter().enumerate());
+        let work = || {
+            let mut results = Vec::new();
+            loop {
+                let Some((index, item)) =
+                    next.lock().unwrap_or_else(PoisonError::into_inner).next()
+                else {
+                    return results;
+                };
+                results.push((index, job(item)));
+            }
+        };
+        let mut results: Vec<_> = thread::scope(|scope| {
+            let handles: Vec<_> = (0..self.max.min(items.len()))
+                .map(|_| scope.spawn(work))
+                .collect();
+            handles.into_iter().flat_map(join).collect()
+        });
+        results.sort_unstable_by_key(|(index, _)| *index);
+        results.into_iter().map(|(_, result)| result).collect()
+    }
+
+    fn acquire(&self) -> Slot<'_> {
+        let running = self.running.lock().unwrap_or_else(PoisonError::into_inner);
+        let mut running = self
+            .released
+            .wait_while(running, |running| *running >= self.max)
+            .unwrap_or_else(PoisonError::into_inner);
+        *running = running.saturating_add(1);
+        Slot(self)
+    }
+}
+
+impl Drop for Slot<'_> {
+    fn drop(&mut self) {
+        let mut running = self
+            .0
+            .running
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        *running = running.saturating_sub(1);
+        self.0.released.notify_one();
+    }
+}
+
+/// Wait for the thread of `handle`, forwarding its panic if it panicked
+fn join<R>(handle: ScopedJoinHandle<'_, R>) -> R {
+    handle
+        .join()
+        .unwrap_or_else(|payload| panic::resume_unwind(payload))
+}
+
+/// Fail with the only error of `errors`, or with all of them gathered by `several`
+pub(crate) fn collect_errors<E, F: FnOnce(Vec<E>) -> E>(
+    mut errors: Vec<E>,
+    several: F,
+) -> Result<(), E> {
+    match errors.len() {
+        0 => Ok(()),
+        1 => Err(errors.remove(0)),
+        _ => Err(several(errors)),
+    }
+}
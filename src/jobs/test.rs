@@ -0,0 +1,89 @@
+use std::collections::HashSet;
+use std::num::NonZeroUsize;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::Duration;
+
+use rstest::rstest;
+
+use crate::jobs::{Jobs, collect_errors};
+
+#[rstest]
+#[case::sequential(1)]
+#[case::parallel(3)]
+#[test_log::test]
+fn map_in_order_within_the_limit(#[case] max: usize) {
+    let jobs = Jobs::new(NonZeroUsize::new(max).unwrap());
+    let running = AtomicUsize::new(0);
+    let peak = AtomicUsize::new(0);
+    let threads = Mutex::new(HashSet::new());
+    let items: Vec<usize> = (0..8).collect();
+
+    let results = jobs.map(&items, |item| {
+        threads.lock().unwrap().insert(thread::current().id());
+        let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+        peak.fetch_max(now, Ordering::SeqCst);
+        // The first items take longer, so they finish last
+        thread::sleep(Duration::from_millis(u64::try_from(8 - item).unwrap() * 5));
+        running.fetch_sub(1, Ordering::SeqCst);
+        item * 2
+    });
+
+    assert_eq!(
+        results,
+        [0, 2, 4, 6, 8, 10, 12, 14],
+        "Results should follow the order of the items"
+    );
+    assert!(
+        peak.load(Ordering::SeqCst) <= max,
+        "No more than {max} jobs should run at the same time"
+    );
+    assert!(
+        threads.lock().unwrap().len() <= max,
+        "No more than {max} threads should run the jobs"
+    );
+}
+
+#[rstest]
+#[case::sequential(1)]
+#[case::parallel(2)]
+#[test_log::test]
+fn map_nested_leaves_the_slots_to_inner_jobs(#[case] max: usize) {
+    let jobs = Jobs::new(NonZeroUsize::new(max).unwrap());
+    let outer: Vec<usize> = (0..4).collect();
+    let inner: Vec<usize> = (0..3).collect();
+
+    let results = jobs.map_nested(&outer, |item| jobs.map(&inner, |other| item * 10 + other));
+
+    assert_eq!(
+        results,
+        [
+            vec![0, 1, 2],
+            vec![10, 11, 12],
+            vec![20, 21, 22],
+            vec![30, 31, 32]
+        ],
+        "Inner jobs should all run and keep the order of the items"
+    );
+}
+
+#[rstest]
+#[test_log::test]
+fn collect_every_error() {
+    assert!(
+        collect_errors(Vec::<String>::new(), |_| unreachable!()).is_ok(),
+        "No error should succeed"
+    );
+    assert_eq!(
+        collect_errors(vec!["one".to_owned()], |_| unreachable!()),
+        Err("one".to_owned()),
+        "A single error should be returned as is"
+    );
+    assert_eq!(
+        collect_errors(vec!["one".to_owned(), "two".to_owned()], |errors| errors
+            .join(", ")),
+        Err("one, two".to_owned()),
+        "Several errors should be gathered"
+    );
+}
@@ -1,46 +1,176 @@
 use std::{
-    fs::{File, create_dir_all},
+    collections::{BTreeMap, BTreeSet},
+    env,
+    fs::{self, File, create_dir_all},
+    num::NonZeroUsize,
     path::{Path, PathBuf},
+    sync::Arc,
 };
 
 use clap::ValueEnum;
-use flate2::{Compression, read::GzDecoder, write::GzEncoder};
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
-use tar::{Archive, Builder};
-use tracing::{debug, error, info};
+use serde_path_to_error::Segment;
+use tracing::{debug, info, warn};
 
 use crate::{
-    cmd::{CommandRunner, LocalCommandRunner},
-    custom::CustomTasks,
-    git::GitMirrors,
-    python::PythonSettings,
-    rust::RustSettings,
+    archive::{BASELINE_FILE, Baseline, MANIFEST_FILE, Manifest, errors::ManifestError},
+    cmd::{DryRunCommandRunner, RecordingRunner},
+    compression::Encoder,
+    download::Downloads,
+    encryption::{BoxedIdentity, Encryption, parse_recipients, read_identities},
+    naming::{DEFAULT_NAME_TEMPLATE, archive_file_name, archive_name, read_archive_name},
+    progress::Progress,
+    provider::ProviderRegistry,
+    report::{Outcome, Recorder},
+    state::{InstallState, STATE_FILE},
+    volume::Output,
 };
 
+mod archive;
+#[cfg(feature = "testing")]
+mod cassette;
 mod cmd;
+mod compose;
+mod compression;
 mod custom;
+mod download;
+mod encryption;
 mod errors;
 mod git;
+mod inspect;
+mod jobs;
+mod merge;
+mod naming;
+mod progress;
+mod provider;
 mod python;
+mod report;
 mod rust;
+mod signature;
+mod state;
+mod validation;
+mod volume;
 
+pub use archive::ArchiveBuilder;
+#[cfg(feature = "testing")]
+pub use cassette::{CassettePlayer, CassetteRecorder, errors::CassetteError};
+pub use cmd::{
+    CommandFailedError, CommandOutput, CommandRunner, CommandSpec, LocalCommandRunner, RetryPolicy,
+};
+pub use compression::{CompressionFormat, CompressionSettings};
+pub use custom::{
+    CustomInstallInstallCount, CustomTask, CustomTaskBuilder, CustomTasks, CustomTasksBuilder,
+};
 pub use errors::InstallingError;
 pub use errors::PackagingError;
-pub use python::PythonConfigLevel;
+pub use git::{GitMirror, GitMirrors, GitMirrorsBuilder};
+pub use inspect::{ArchiveSummary, CustomPathSummary, inspect};
+pub use progress::{
+    Phase, Position, ProgressDisplay, ProgressEvent, ProgressSubscriber, init_logging,
+};
+pub use provider::{InstallContext, PackageContext, ResourceProvider};
+pub use python::{PythonConfigLevel, PythonSettings, PythonSettingsBuilder};
+pub use report::{ConfigChange, InstallReport, Plan, PlannedCommand, StepReport, StepStatus};
+pub use rust::{RustSettings, RustSettingsBuilder};
+pub use validation::errors::SettingsError;
 
 const CARGO_TOOLS_PATH: &str = "cargo-tools";
 const CARGO_VENDOR_PATH: &str = "cargo-vendor";
 const PIP_DOWNLOAD_DIR: &str = "pip";
 const MIRRORS_PATH: &str = "mirrors";
+const SETTINGS_FILE: &str = "settings.yaml";
 
-#[derive(Debug, Default, Deserialize, Serialize)]
+/// Every section and list is optional and empty by default
+#[derive(Debug, Deserialize, JsonSchema, Serialize)]
+#[serde(default)]
 pub struct Settings {
     #[serde(default = "default_name")]
     pub name: String,
+    /// Version of the packaged resources, available as `{version}` in the archive name template
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
     pub rust: RustSettings,
     pub python: PythonSettings,
     pub git_mirrors: GitMirrors,
     pub custom: CustomTasks,
+    pub compression: CompressionSettings,
+    /// Sections of the downstream providers, by section key, embedded in the archive like the built-in ones
+    #[serde(flatten)]
+    pub sections: BTreeMap<String, serde_json::Value>,
+}
+
+/// Options changing how the archive is built
+#[derive(Default)]
+pub struct PackageOptions {
+    /// Skip one or more downloading steps
+    pub skip: Vec<DownloadSkip>,
+    /// Only package what changed since this previous archive (or its manifest)
+    pub since: Option<PathBuf>,
+    /// Split the archive into volumes of at most this size in bytes
+    pub volume_size: Option<u64>,
+    /// Sign the archive with this minisign secret key
+    pub signing_key: Option<PathBuf>,
+    /// Encrypt the archive to these age X25519 public keys (e.g., "age1...")
+    pub recipients: Vec<String>,
+    /// Decrypt the `since` archive with an identity of this age identity file
+    pub identity: Option<PathBuf>,
+    /// Build the same archive from the same inputs, with every entry dated from `SOURCE_DATE_EPOCH` if set
+    pub reproducible: bool,
+    /// Run up to this number of downloads at the same time, one at a time if unset
+    pub jobs: Option<NonZeroUsize>,
+    /// Keep the downloads in this directory between runs and update them instead of downloading them again.
+    /// Takes precedence over `staging_dir`.
+    pub cache_dir: Option<PathBuf>,
+    /// Skip the downloads that the checkpoint of a previous unfinished run lists as finished
+    pub resume: bool,
+    /// Write the archive into this directory instead of the current directory
+    pub output_dir: Option<PathBuf>,
+    /// Download the resources into this directory instead of a directory named after the settings
+    pub staging_dir: Option<PathBuf>,
+    /// Name of the archive without its extension, with `{name}`, `{date}`, `{version}` and `{git_sha}` placeholders
+    pub name_template: Option<String>,
+    /// Write a JSON report of the run at this path
+    pub report: Option<PathBuf>,
+    /// Skip downloading the resources of the providers of these sections, e.g., downstream ones
+    pub skip_sections: Vec<String>,
+    /// Extra resource providers, after the built-in ones or in place of the built-in one of the same section
+    pub providers: Vec<Arc<dyn ResourceProvider>>,
+    /// Receive the phases and steps of the run as they start and finish, e.g., to display them
+    pub progress: Option<Arc<dyn ProgressSubscriber>>,
+}
+
+/// Options changing how the archive is installed
+#[derive(Default)]
+pub struct InstallOptions {
+    /// Configuration level of the python configuration
+    pub python_config_level: PythonConfigLevel,
+    /// Limit the rust configuration to this path instead of the user-level config
+    pub rust_config_for: Option<PathBuf>,
+    /// Skip one or more install steps
+    pub skip: Vec<InstallSkip>,
+    /// Only install archives signed with this minisign public key
+    pub trusted_key: Option<PathBuf>,
+    /// Accept archives without any signature even if `trusted_key` is set
+    pub allow_unsigned: bool,
+    /// Decrypt the archive with an identity of this age identity file
+    pub identity: Option<PathBuf>,
+    /// Refuse to unpack more than this size in bytes
+    pub max_unpacked_size: Option<u64>,
+    /// Merge the crates and python packages with the ones of the archives previously installed with the same
+    /// directory, and point cargo and pip to this directory instead of the unpacked archive
+    pub merge_into: Option<PathBuf>,
+    /// Unpack the archive into this directory instead of a directory named after the archive
+    pub unpack_dir: Option<PathBuf>,
+    /// Write a JSON report of the run at this path
+    pub report: Option<PathBuf>,
+    /// Skip the providers of these sections (e.g., downstream ones) or these kinds of steps (e.g., "git push")
+    pub skip_sections: Vec<String>,
+    /// Extra resource providers, after the built-in ones or in place of the built-in one of the same section.
+    /// They should be the same as when packaging.
+    pub providers: Vec<Arc<dyn ResourceProvider>>,
+    /// Receive the phases and steps of the run as they start and finish, e.g., to display them
+    pub progress: Option<Arc<dyn ProgressSubscriber>>,
 }
 
 #[derive(ValueEnum, Clone, Eq, Hash, PartialEq)]
@@ -67,94 +197,603 @@ pub enum InstallSkip {
     Custom,
 }
 
-type ArchiveBuilder = Builder<GzEncoder<File>>;
+impl DownloadSkip {
+    /// Section of the skipped provider
+    fn section(&self) -> &'static str {
+        match self {
+            Self::Rust => "rust",
+            Self::Python => "python",
+            Self::GitClone => "git_mirrors",
+        }
+    }
+}
+
+impl InstallSkip {
+    /// Kind of the skipped install steps
+    fn step_kind(&self) -> &'static str {
+        match self {
+            Self::RustTools => "rust tools",
+            Self::RustConfig => "rust config",
+            Self::PythonConfig => "python config",
+            Self::GitPush => "git push",
+            Self::Custom => "custom task",
+        }
+    }
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            name: default_name(),
+            version: None,
+            rust: RustSettings::default(),
+            python: PythonSettings::default(),
+            git_mirrors: GitMirrors::default(),
+            custom: CustomTasks::default(),
+            compression: CompressionSettings::default(),
+            sections: BTreeMap::new(),
+        }
+    }
+}
 
 fn default_name() -> String {
     "offline-vendoring".to_string()
 }
 
+/// Parse a size in bytes with an optional binary unit suffix (K, M, G or T), e.g., "4G"
+///
+/// # Errors
+///
+/// The size is not a positive integer followed by an optional unit
+pub fn parse_size(size: &str) -> Result<u64, String> {
+    let size = size.trim();
+    let (number, multiplier) = match size.chars().last().map(|unit| unit.to_ascii_uppercase()) {
+        Some('K') => (size.get(..size.len().saturating_sub(1)), 1 << 10),
+        Some('M') => (size.get(..size.len().saturating_sub(1)), 1 << 20),
+        Some('G') => (size.get(..size.len().saturating_sub(1)), 1 << 30),
+        Some('T') => (size.get(..size.len().saturating_sub(1)), 1 << 40),
+        _ => (Some(size), 1),
+    };
+    number
+        .and_then(|number| number.trim().parse::<u64>().ok())
+        .and_then(|number| number.checked_mul(multiplier))
+        .filter(|size| *size > 0)
+        .ok_or(format!("'{size}' is not a valid non-zero size"))
+}
+
+/// Timestamp of the entries of a reproducible archive: `SOURCE_DATE_EPOCH` if set, an arbitrary fixed date otherwise
+fn source_date_epoch() -> Result<u64, PackagingError> {
+    match std::env::var("SOURCE_DATE_EPOCH") {
+        Ok(epoch) if !epoch.trim().is_empty() => epoch
+            .trim()
+            .parse()
+            .map_err(|_| PackagingError::SourceDateEpoch(epoch)),
+        _ => Ok(tar::DETERMINISTIC_TIMESTAMP),
+    }
+}
+
+/// Read the [`Settings`] at `path`, the extension being optional, with every missing section or field
+/// taking its default value.
+///
+/// The files listed in its `include` are merged below it, maps key by key and lists appended.
+/// The `profile` among its `profiles` is then merged on top, and `${VAR}` is replaced by the environment
+/// variable `VAR` in every value.
+///
+/// # Errors
+///
+/// - [`PackagingError::Compose`] if a file cannot be found or parsed, the profile doesn't exist or a variable isn't set
+/// - [`PackagingError::DeserializeSettings`] with the path of the first field that is unknown or has a wrong type
+pub fn load_settings(path: &Path, profile: Option<&str>) -> Result<Settings, Box<PackagingError>> {
+    let settings = compose::compose(path, profile, |variable| env::var(variable).ok())
+        .map_err(PackagingError::Compose)?;
+    serde_path_to_error::deserialize(settings).map_err(|e| {
+        Box::new(PackagingError::DeserializeSettings {
+            path: e.path().to_string(),
+            message: e.into_inner().to_string(),
+        })
+    })
+}
+
+/// Deserialize the [`Settings`] embedded in an archive. Unlike settings files, fields unknown to this version
+/// (e.g., of an archive packaged by a newer one) are ignored with a warning, so that the archive can still be installed.
+pub(crate) fn embedded_settings(
+    mut settings: serde_yaml::Value,
+) -> Result<Settings, InstallingError> {
+    loop {
+        let e = match serde_path_to_error::deserialize(settings.clone()) {
+            Ok(settings) => return Ok(settings),
+            Err(e) => e,
+        };
+        let message = e.inner().to_string();
+        let unknown_field = message
+            .strip_prefix("unknown field `")
+            .and_then(|rest| rest.split_once('`'))
+            .map(|(field, _)| field);
+        match unknown_field {
+            Some(field) if remove_field(&mut settings, e.path(), field) => {
+                warn!(
+                    "Ignoring the unknown field '{field}' of the embedded settings, at '{}'",
+                    e.path()
+                );
+            }
+            _ => return Err(InstallingError::DeserializeConfig(e.to_string())),
+        }
+    }
+}
+
+/// Remove the unknown `field` at `path` from `value`, and return whether it was there
+fn remove_field(
+    value: &mut serde_yaml::Value,
+    path: &serde_path_to_error::Path,
+    field: &str,
+) -> bool {
+    let segments: Vec<_> = path.iter().collect();
+    // The path ends with the unknown field, which is removed from the mapping holding it
+    let Some((Segment::Map { key }, parents)) = segments.split_last() else {
+        return false;
+    };
+    if key != field {
+        return false;
+    }
+    let mut mapping = Some(value);
+    for segment in parents {
+        mapping = mapping.and_then(|value| match segment {
+            Segment::Map { key } => value.get_mut(key.as_str()),
+            Segment::Seq { index } => value.get_mut(*index),
+            Segment::Enum { .. } | Segment::Unknown => None,
+        });
+    }
+    mapping
+        .and_then(serde_yaml::Value::as_mapping_mut)
+        .and_then(|mapping| mapping.remove(field))
+        .is_some()
+}
+
+/// Check the [`Settings`] to package with `options` without downloading anything: paths exist,
+/// mirrors have distinct clone names, install commands can be parsed, etc. Every mistake is reported at once.
+///
+/// # Errors
+///
+/// [`PackagingError::InvalidSettings`] with every mistake found
+pub fn check_settings(
+    settings: &Settings,
+    options: &PackageOptions,
+) -> Result<(), Box<PackagingError>> {
+    let providers = ProviderRegistry::new(settings, &options.providers);
+    validation::check(settings, &providers, name_is_directory(options)).map_err(Box::new)
+}
+
+/// Whether the packaging directory is named after the settings, without a staging or cache directory
+fn name_is_directory(options: &PackageOptions) -> bool {
+    options.staging_dir.is_none() && options.cache_dir.is_none()
+}
+
+/// JSON Schema of the [`Settings`], e.g., for editors to complete settings files
+#[must_use]
+pub fn settings_schema() -> serde_json::Value {
+    let mut schema = schemars::schema_for!(Settings).to_value();
+    // Only settings files have these keys, resolved by `load_settings`
+    if let Some(properties) = schema
+        .get_mut("properties")
+        .and_then(serde_json::Value::as_object_mut)
+    {
+        properties.insert(
+            "include".to_owned(),
+            serde_json::json!({
+                "description": "Settings files merged below this one, relative to its directory",
+                "type": "array",
+                "items": {"type": "string"},
+            }),
+        );
+        properties.insert(
+            "profiles".to_owned(),
+            serde_json::json!({
+                "description": "Named settings merged on top of these ones when selected with `--profile`",
+                "type": "object",
+                "additionalProperties": {"$ref": "#"},
+            }),
+        );
+    }
+    schema
+}
+
 /// Download and package external resources listed in the [`Settings`]
 ///
 /// # Errors
 ///
 /// Check [`PackagingError`]
-pub fn package(settings: &Settings, skip: &[DownloadSkip]) -> Result<(), Box<PackagingError>> {
-    package_inner::<LocalCommandRunner>(settings, skip).map_err(Box::new)
+pub fn package(settings: &Settings, options: &PackageOptions) -> Result<(), Box<PackagingError>> {
+    package_with_runner(settings, options, &LocalCommandRunner)
 }
 
-fn package_inner<T: CommandRunner>(
+/// Same as [`package`], running every command through `runner` instead of on the local machine
+///
+/// # Errors
+///
+/// Check [`PackagingError`]
+pub fn package_with_runner(
     settings: &Settings,
-    skip: &[DownloadSkip],
+    options: &PackageOptions,
+    runner: &dyn CommandRunner,
+) -> Result<(), Box<PackagingError>> {
+    let Some(report_path) = &options.report else {
+        return package_inner(settings, options, runner, false, &Recorder::default())
+            .map_err(Box::new);
+    };
+    let recorder = Recorder::new();
+    let res = package_inner(settings, options, runner, false, &recorder);
+    let outcome = match &res {
+        Ok(()) => Outcome::Succeeded,
+        Err(e) => Outcome::Failed {
+            error: e.to_string(),
+        },
+    };
+    let report_res = recorder.finish(report_path, outcome, None);
+    res?;
+    report_res.map_err(|e| Box::new(PackagingError::WriteReport(report_path.clone(), e)))
+}
+
+/// Go through the packaging steps of [`package`] without running any command or writing anything,
+/// and return what would be done. `options.report` is ignored.
+///
+/// # Errors
+///
+/// Check [`PackagingError`]
+pub fn plan_package(
+    settings: &Settings,
+    options: &PackageOptions,
+) -> Result<Plan, Box<PackagingError>> {
+    let recorder = Recorder::new();
+    package_inner(settings, options, &DryRunCommandRunner, true, &recorder)?;
+    Ok(Plan::from(recorder.take()))
+}
+
+/// Package the resources, running commands through `runner`, which is expected not to run anything on a dry run,
+/// and recording what happens with `recorder`
+fn package_inner(
+    settings: &Settings,
+    options: &PackageOptions,
+    runner: &dyn CommandRunner,
+    dry_run: bool,
+    recorder: &Recorder,
 ) -> Result<(), PackagingError> {
-    // Create .tar.gz file
-    let tar_gz = File::create(format!("{}.tar.gz", settings.name))
-        .map_err(PackagingError::ArchiveCreation)?;
-    let enc = GzEncoder::new(tar_gz, Compression::default());
-    let mut tar = tar::Builder::new(enc);
-
-    let packaging_directory = PathBuf::from(settings.name.clone());
-    if !packaging_directory.exists() {
-        create_dir_all(packaging_directory.as_path())
-            .map_err(|e| PackagingError::CreateMainDirectory(packaging_directory.clone(), e))?;
-    }
-    let packaging_directory = packaging_directory
-        .canonicalize()
-        .map_err(|e| PackagingError::GetCannonMainDirectory(packaging_directory.clone(), e))?;
+    let runner = RecordingRunner::new(runner, dry_run, recorder.clone());
+    let progress = Progress::new(options.progress.clone());
+    // Fail before any download rather than in the middle of them
+    let providers = ProviderRegistry::new(settings, &options.providers);
+    progress.phase(Phase::Check, || {
+        validation::check(settings, &providers, name_is_directory(options))
+    })?;
+    let skip: BTreeSet<&str> = options
+        .skip
+        .iter()
+        .map(|step| step.section())
+        .chain(options.skip_sections.iter().map(String::as_str))
+        .collect();
+    // Invalid recipients would leave an empty archive behind
+    let recipients = parse_recipients(&options.recipients)?;
+    let baseline = if let Some(since) = &options.since {
+        let identities = options
+            .identity
+            .as_deref()
+            .map(read_identities)
+            .transpose()?
+            .unwrap_or_default();
+        Some(Manifest::load_baseline(since, &identities)?)
+    } else {
+        None
+    };
+    let reproducible_mtime = options.reproducible.then(source_date_epoch).transpose()?;
 
-    settings.rust.package::<T>(
-        packaging_directory.as_path(),
-        &mut tar,
-        skip.contains(&DownloadSkip::Rust),
+    // Create the compressed archive file, or its volumes
+    let created_at = reproducible_mtime.unwrap_or_else(archive::now);
+    let file_name = archive_file_name(
+        &runner,
+        options
+            .name_template
+            .as_deref()
+            .unwrap_or(DEFAULT_NAME_TEMPLATE),
+        settings,
+        created_at,
     )?;
-    settings.python.package::<T>(
-        packaging_directory.as_path(),
-        &mut tar,
-        skip.contains(&DownloadSkip::Python),
-    )?;
-    settings.git_mirrors.package::<T>(
-        packaging_directory.as_path(),
-        &mut tar,
-        skip.contains(&DownloadSkip::GitClone),
-    )?;
-    settings.custom.package(&mut tar)?;
+    let archive_path = match &options.output_dir {
+        Some(output_dir) => {
+            if !dry_run && !output_dir.exists() {
+                create_dir_all(output_dir)
+                    .map_err(|e| PackagingError::CreateOutputDirectory(output_dir.clone(), e))?;
+            }
+            output_dir.join(file_name)
+        }
+        None => PathBuf::from(file_name),
+    };
+    let output = if dry_run {
+        Output::Discard { path: archive_path }
+    } else {
+        Output::new(&archive_path, options.volume_size).map_err(PackagingError::ArchiveCreation)?
+    };
+    let output = Encryption::new(output, &recipients)?;
+    let enc = Encoder::new(output, &settings.compression)?;
+    let mut tar = ArchiveBuilder::new(enc)
+        .with_progress(progress.clone())
+        .with_recorder(recorder.clone());
+    if let Some(mtime) = reproducible_mtime {
+        if !options.recipients.is_empty() {
+            recorder.warning(
+                "Encrypted archives are never byte-identical, only their content is reproducible"
+                    .to_owned(),
+            );
+        }
+        tar = tar.reproducible(mtime);
+    }
+    if let Some((baseline_manifest, baseline)) = baseline {
+        info!(
+            "Only packaging changes since the archive with manifest digest {}",
+            baseline.manifest_sha256
+        );
+        tar = tar
+            .with_baseline(baseline_manifest, baseline)
+            .map_err(PackagingError::ArchiveCreation)?;
+    }
+    if dry_run {
+        tar = tar.dry_run();
+    }
 
-    // Serialize settings at the root of the archive
+    // Serialize settings at the root of the archive, first so that installs can read them without unpacking
     #[expect(clippy::unwrap_used, reason = "should never fail")]
     {
         let temp_dir = tempfile::tempdir().unwrap();
-        let settings_file_path = temp_dir.path().join("settings.yaml");
+        let settings_file_path = temp_dir.path().join(SETTINGS_FILE);
         let settings_file = File::create_new(&settings_file_path).unwrap();
         serde_yaml::to_writer(settings_file, settings).unwrap();
-        tar.append_path_with_name(settings_file_path, "settings.yaml")
+        tar.append_path_always(settings_file_path, SETTINGS_FILE)
             .map_err(PackagingError::ArchiveInsert)?;
     }
 
-    tar.finish().map_err(PackagingError::ArchiveCreation)?;
+    let packaging_directory = if let Some(cache_dir) = &options.cache_dir {
+        cache_dir.clone()
+    } else if let Some(staging_dir) = &options.staging_dir {
+        staging_dir.clone()
+    } else {
+        PathBuf::from(settings.name.clone())
+    };
+    if dry_run && !packaging_directory.exists() {
+        info!(
+            "Would create the intermediate output directory {}",
+            packaging_directory.display()
+        );
+    } else if !packaging_directory.exists() {
+        create_dir_all(packaging_directory.as_path())
+            .map_err(|e| PackagingError::CreateMainDirectory(packaging_directory.clone(), e))?;
+    }
+    let packaging_directory = if packaging_directory.exists() {
+        packaging_directory.canonicalize()
+    } else {
+        std::path::absolute(&packaging_directory)
+    }
+    .map_err(|e| PackagingError::GetCannonMainDirectory(packaging_directory.clone(), e))?;
+
+    // Download every section, then archive them in a fixed order
+    let packaging_directory = packaging_directory.as_path();
+    let downloads = Downloads::new(packaging_directory, settings, options, dry_run)?
+        .with_recorder(recorder.clone());
+    let context = PackageContext::new(packaging_directory, &downloads, &runner, &settings.sections);
+    let mut to_download = Vec::new();
+    for provider in providers.iter() {
+        if skip.contains(provider.section()) {
+            info!("Skipping the download of {}", provider.section());
+            recorder.record_skipped_step(&format!("download {}", provider.section()));
+            continue;
+        }
+        for resource in provider.describe() {
+            debug!("Downloading {resource}");
+        }
+        to_download.push(provider);
+    }
+    // The downloads of each section take their own slots
+    let results: Vec<_> = progress.phase(Phase::Download, || {
+        downloads
+            .jobs
+            .map_nested(&to_download, |provider| provider.package(&context))
+    });
+    let errors = results.into_iter().filter_map(Result::err).collect();
+    jobs::collect_errors(errors, PackagingError::Downloads)?;
+
+    // Skipped sections are archived too, with what previous runs downloaded
+    let archive_path = progress.phase(Phase::Archive, || {
+        let total = providers.iter().count();
+        for (index, provider) in providers.iter().enumerate() {
+            progress.step(
+                Phase::Archive,
+                &format!("archive {}", provider.section()),
+                Position::new(index.saturating_add(1), total),
+                || provider.archive(&context, &mut tar),
+            )?;
+        }
+        tar.finish().map_err(PackagingError::ArchiveCreation)
+    })?;
+    if dry_run {
+        info!("Would write the archive to {}", archive_path.display());
+        if options.signing_key.is_some() {
+            info!("Would sign {}", archive_path.display());
+        }
+        return Ok(());
+    }
+    info!("Archive written to {}", archive_path.display());
+    if let Some(signing_key) = &options.signing_key {
+        signature::sign(&archive_path, signing_key)?;
+    }
+    downloads.clear_checkpoint()?;
 
     Ok(())
 }
 
 /// Unpack and install the content of the archive at `archive_path` into the current directory
 ///
+/// The installers are all run even if some of them fail, check the returned [`InstallReport`] for their outcome.
+///
 /// # Errors
 ///
-/// Check [`InstallingError`]
+/// The archive cannot be checked or unpacked, check [`InstallingError`]
 pub fn install(
     archive_path: &Path,
-    python_config_level: &PythonConfigLevel,
-    rust_config_for: Option<&PathBuf>,
-    skip: &[InstallSkip],
-) -> Result<(), Box<InstallingError>> {
-    install_inner::<LocalCommandRunner>(archive_path, python_config_level, rust_config_for, skip)
-        .map_err(Box::new)
+    options: &InstallOptions,
+) -> Result<InstallReport, Box<InstallingError>> {
+    install_with_runner(archive_path, options, &LocalCommandRunner)
+}
+
+/// Same as [`install`], running every command through `runner` instead of on the local machine
+///
+/// # Errors
+///
+/// The archive cannot be checked or unpacked, check [`InstallingError`]
+pub fn install_with_runner(
+    archive_path: &Path,
+    options: &InstallOptions,
+    runner: &dyn CommandRunner,
+) -> Result<InstallReport, Box<InstallingError>> {
+    let Some(report_path) = &options.report else {
+        return install_inner(archive_path, options, runner, &Recorder::default())
+            .map_err(Box::new);
+    };
+    let recorder = Recorder::new();
+    let res = install_inner(archive_path, options, runner, &recorder);
+    let outcome = match &res {
+        Ok(install_report) if install_report.is_success() => Outcome::Succeeded,
+        Ok(install_report) => Outcome::Failed {
+            error: format!("{} install steps failed", install_report.failures().count()),
+        },
+        Err(e) => Outcome::Failed {
+            error: e.to_string(),
+        },
+    };
+    let report_res = recorder.finish(report_path, outcome, res.as_ref().ok());
+    let install_report = res?;
+    report_res.map_err(|e| Box::new(InstallingError::WriteReport(report_path.clone(), e)))?;
+    Ok(install_report)
+}
+
+/// Go through the installation steps of [`install`] without running any command or changing any configuration,
+/// and return what would be done. `options.report` is ignored.
+///
+/// Only the settings and the manifest are read from the archive, after checking its signature as an installation
+/// would: the files it ships are laid out empty in a temporary directory, which is removed afterwards.
+/// A delta archive is checked against the baseline archive unpacked into the directory it would be unpacked into.
+/// The returned plan refers to that directory.
+///
+/// # Errors
+///
+/// The archive cannot be checked or read, check [`InstallingError`]
+pub fn plan_install(
+    archive_path: &Path,
+    options: &InstallOptions,
+) -> Result<Plan, Box<InstallingError>> {
+    let recorder = Recorder::new();
+    verify_origin(archive_path, options, &recorder)?;
+    let identities = read_options_identities(options)?;
+    let mut entries = archive::read_entries(
+        archive_path,
+        &[SETTINGS_FILE, MANIFEST_FILE, BASELINE_FILE],
+        &identities,
+    )
+    .map_err(InstallingError::ArchiveUncompress)?;
+    let settings = entries.remove(SETTINGS_FILE);
+    let unpacked_directory = unpacked_directory(archive_path, options, &recorder, || {
+        settings.as_deref().map(archive_name).transpose()
+    })?;
+    let unpacked_directory = if unpacked_directory.exists() {
+        unpacked_directory.canonicalize()
+    } else {
+        std::path::absolute(&unpacked_directory)
+    }
+    .map_err(|e| InstallingError::GetCannonMainDirectory(unpacked_directory.clone(), e))?;
+    // A delta archive can only be installed over the baseline archive unpacked before it
+    if let Some(baseline) = entries.remove(BASELINE_FILE) {
+        let baseline: Baseline = serde_json::from_slice(&baseline).map_err(|e| {
+            InstallingError::Manifest(ManifestError::Parse(PathBuf::from(BASELINE_FILE), e))
+        })?;
+        archive::check_baseline(&baseline, &unpacked_directory)?;
+    }
+    let manifest_content = entries.remove(MANIFEST_FILE).ok_or_else(|| {
+        InstallingError::Manifest(ManifestError::NotInArchive(archive_path.to_path_buf()))
+    })?;
+    let manifest: Manifest = serde_json::from_slice(&manifest_content).map_err(|e| {
+        InstallingError::Manifest(ManifestError::Parse(archive_path.to_path_buf(), e))
+    })?;
+
+    let staging = tempfile::tempdir().map_err(InstallingError::CreateStagingDirectory)?;
+    let staging_directory = staging
+        .path()
+        .canonicalize()
+        .map_err(InstallingError::CreateStagingDirectory)?;
+    manifest
+        .lay_out(&staging_directory)
+        .map_err(InstallingError::from)?;
+    if let Some(settings) = settings {
+        fs::write(staging_directory.join(SETTINGS_FILE), settings)
+            .map_err(InstallingError::CreateStagingDirectory)?;
+    }
+
+    let runner = RecordingRunner::new(&DryRunCommandRunner, true, recorder.clone());
+    let progress = Progress::new(options.progress.clone());
+    install_unpacked(
+        &manifest,
+        &staging_directory,
+        options,
+        &runner,
+        true,
+        &progress,
+        &recorder,
+    )?;
+    let mut plan = Plan::from(recorder.take());
+    plan.relocate(&staging_directory, &unpacked_directory);
+    Ok(plan)
+}
+
+/// Identities to decrypt the archive with, if any
+fn read_options_identities(
+    options: &InstallOptions,
+) -> Result<Vec<BoxedIdentity>, InstallingError> {
+    Ok(options
+        .identity
+        .as_deref()
+        .map(read_identities)
+        .transpose()?
+        .unwrap_or_default())
 }
 
-fn install_inner<T: CommandRunner>(
+/// Check the origin of the archive at `archive_path` against `options.trusted_key`, if set,
+/// before reading anything from it.
+/// The volumes of a split archive are covered by the signature of their index.
+fn verify_origin(
     archive_path: &Path,
-    python_config_level: &PythonConfigLevel,
-    rust_config_for: Option<&PathBuf>,
-    skip: &[InstallSkip],
+    options: &InstallOptions,
+    recorder: &Recorder,
 ) -> Result<(), InstallingError> {
+    if let Some(trusted_key) = &options.trusted_key {
+        let signed_path = volume::find_index(archive_path).unwrap_or(archive_path.to_path_buf());
+        signature::verify(&signed_path, trusted_key, options.allow_unsigned, recorder)?;
+    }
+    Ok(())
+}
+
+/// Directory the archive at `archive_path` is unpacked into: `options.unpack_dir` if set,
+/// or named after the settings embedded in the archive, given by `read_name` if it has any
+fn unpacked_directory<F: FnOnce() -> Result<Option<String>, InstallingError>>(
+    archive_path: &Path,
+    options: &InstallOptions,
+    recorder: &Recorder,
+    read_name: F,
+) -> Result<PathBuf, InstallingError> {
+    if let Some(unpack_dir) = &options.unpack_dir {
+        return Ok(unpack_dir.clone());
+    }
+    if let Some(name) = read_name()? {
+        return Ok(PathBuf::from(name));
+    }
+    recorder.warning(format!(
+        "No settings found in {}, naming the unpacked directory after the archive file",
+        archive_path.display()
+    ));
     // .file_prefix() isn't available until rust 1.91 and we wish to support rust 1.88 for now
     let archive_base_name = archive_path
         .file_name()
@@ -169,8 +808,24 @@ fn install_inner<T: CommandRunner>(
             .ok_or(InstallingError::InvalidArchivePath(
                 archive_path.to_path_buf(),
             ))?;
+    Ok(PathBuf::from(archive_prefix))
+}
+
+/// Unpack and install the archive, recording what happens with `recorder`
+fn install_inner(
+    archive_path: &Path,
+    options: &InstallOptions,
+    runner: &dyn CommandRunner,
+    recorder: &Recorder,
+) -> Result<InstallReport, InstallingError> {
+    let runner = RecordingRunner::new(runner, false, recorder.clone());
+    let progress = Progress::new(options.progress.clone());
+    verify_origin(archive_path, options, recorder)?;
 
-    let unpacked_directory = PathBuf::from(archive_prefix);
+    let identities = read_options_identities(options)?;
+    let unpacked_directory = unpacked_directory(archive_path, options, recorder, || {
+        read_archive_name(archive_path, &identities)
+    })?;
     if !unpacked_directory.exists() {
         create_dir_all(unpacked_directory.as_path())
             .map_err(|e| InstallingError::CreateMainDirectory(unpacked_directory.clone(), e))?;
@@ -179,59 +834,155 @@ fn install_inner<T: CommandRunner>(
         .canonicalize()
         .map_err(|e| InstallingError::GetCannonMainDirectory(unpacked_directory.clone(), e))?;
 
-    // Unpack archive .tar.gz
-    let tar_gz = File::open(archive_path).map_err(InstallingError::ArchiveUncompress)?;
-    let tar = GzDecoder::new(tar_gz);
-    let mut archive = Archive::new(tar);
-    archive
-        .unpack(unpacked_directory.as_path())
-        .map_err(InstallingError::ArchiveUncompress)?;
-    info!("Archive unpacked to {}", unpacked_directory.display());
+    let manifest = progress.phase(Phase::Unpack, || {
+        // Unpack the archive, whatever its compression format and whether it is encrypted
+        let unpacked = archive::unpack(
+            archive_path,
+            unpacked_directory.as_path(),
+            &identities,
+            options.max_unpacked_size,
+            &progress,
+        )?;
+        info!("Archive unpacked to {}", unpacked_directory.display());
+
+        // Check unpacked files against the manifest before installing anything
+        let manifest = Manifest::load(unpacked_directory.as_path())?;
+        manifest.remove_deleted(unpacked_directory.as_path())?;
+        let corrupted_files =
+            manifest.verify(unpacked_directory.as_path(), &unpacked, true, recorder);
+        if !corrupted_files.is_empty() {
+            return Err(InstallingError::IntegrityCheck(corrupted_files));
+        }
+        info!("Archive integrity checked");
+        Ok(manifest)
+    })?;
+    install_unpacked(
+        &manifest,
+        &unpacked_directory,
+        options,
+        &runner,
+        false,
+        &progress,
+        recorder,
+    )
+}
+
+/// Install the resources of the archive described by `manifest` and unpacked at `unpacked_directory`,
+/// a canonicalized path. On a `dry_run`, `runner` is expected not to run anything.
+fn install_unpacked(
+    manifest: &Manifest,
+    unpacked_directory: &Path,
+    options: &InstallOptions,
+    runner: &RecordingRunner<'_>,
+    dry_run: bool,
+    progress: &Progress,
+    recorder: &Recorder,
+) -> Result<InstallReport, InstallingError> {
+    GitMirrors::apply_bundles(
+        runner,
+        unpacked_directory,
+        &manifest.git_bundles,
+        &manifest.git_refs,
+    )?;
 
     // Get packaged settings
     let settings_path = unpacked_directory.join("settings");
     let settings = config::Config::builder()
         .add_source(config::File::with_name(&settings_path.display().to_string()).required(false))
-        .build()?;
-    let settings: Settings = settings
-        .try_deserialize()
-        .map_err(|e| InstallingError::DeserializeConfig(e.to_string()))?;
+        .build()?
+        .try_deserialize()?;
+    let settings = embedded_settings(settings)?;
     debug!("Got the following settings: {settings:#?}");
 
-    // Install resources
+    // Install resources, every step is attempted even if a previous one failed
     info!("Installing external resources");
-    let mut latest_error: Result<(), _> = Ok(());
-    let res_rs = RustSettings::install(unpacked_directory.as_path(), rust_config_for, skip);
-    if let Err(ref err) = res_rs {
-        error!("Failed to install rust deps: {err}");
-        latest_error = res_rs.map_err(InstallingError::Rust);
-    }
-    if !skip.contains(&InstallSkip::PythonConfig) {
-        let res_py =
-            PythonSettings::install::<T>(unpacked_directory.as_path(), python_config_level);
-        if let Err(ref err) = res_py {
-            error!("Failed to install python deps: {err}");
-            latest_error = res_py.map_err(InstallingError::Python);
-        }
+    let mut report = InstallReport::default();
+    // Merged with the state of previous installations into the same directory
+    let state_path = unpacked_directory.join(STATE_FILE);
+    let mut state = InstallState::load_or_default(&state_path)?;
+    // Cargo and pip are pointed either to the unpacked archive or to the resources merged from several archives
+    let resources_directory = if let Some(merge_directory) = &options.merge_into {
+        let merge_directory = merged_directory(merge_directory, dry_run)?;
+        report.record(
+            "merge crates".to_owned(),
+            merge::merge_crates(unpacked_directory, &merge_directory, dry_run, recorder),
+        );
+        report.record(
+            "merge python packages".to_owned(),
+            merge::merge_wheels(unpacked_directory, &merge_directory, dry_run, recorder),
+        );
+        merge_directory
+    } else {
+        unpacked_directory.to_path_buf()
+    };
+    let skip: BTreeSet<String> = options
+        .skip
+        .iter()
+        .map(|step| step.step_kind().to_owned())
+        .chain(options.skip_sections.iter().cloned())
+        .collect();
+    let providers = ProviderRegistry::new(&settings, &options.providers);
+    for section in settings
+        .sections
+        .keys()
+        .filter(|section| !providers.contains(section))
+    {
+        recorder.warning(format!(
+            "No provider registered for the section {section}, its resources are not installed"
+        ));
     }
-    if !skip.contains(&InstallSkip::GitPush) {
-        let res_git = settings
-            .git_mirrors
-            .install::<T>(unpacked_directory.as_path());
-        if let Err(ref err) = res_git {
-            error!("Failed to install git deps: {err}");
-            latest_error = res_git.map_err(InstallingError::Git);
+    let mut context = InstallContext::new(
+        unpacked_directory,
+        resources_directory.as_path(),
+        options,
+        dry_run,
+        &skip,
+        &mut state,
+        &mut report,
+        runner,
+        progress,
+        recorder,
+        &settings.sections,
+    );
+    progress.phase(Phase::Install, || {
+        for provider in providers.iter() {
+            if !context.skip_section(provider.section()) {
+                provider.install(&mut context);
+            }
         }
+    });
+    if !dry_run {
+        report.record("save install state".to_owned(), state.save(&state_path));
+        report.state_path = Some(state_path);
     }
-    if !skip.contains(&InstallSkip::Custom) {
-        let res = settings.custom.install::<T>(unpacked_directory.as_path());
-        if let Err(ref err) = res {
-            error!("Failed to install custom deps: {err}");
-            latest_error = res.map_err(InstallingError::Custom);
-        }
+
+    Ok(report)
+}
+
+/// Absolute path of the directory merging the resources of several archives, created unless on a dry run
+fn merged_directory(merge_directory: &Path, dry_run: bool) -> Result<PathBuf, InstallingError> {
+    if dry_run && !merge_directory.exists() {
+        return std::path::absolute(merge_directory).map_err(|e| {
+            InstallingError::GetCannonMainDirectory(merge_directory.to_path_buf(), e)
+        });
     }
+    create_dir_all(merge_directory)
+        .map_err(|e| InstallingError::CreateMainDirectory(merge_directory.to_path_buf(), e))?;
+    merge_directory
+        .canonicalize()
+        .map_err(|e| InstallingError::GetCannonMainDirectory(merge_directory.to_path_buf(), e))
+}
 
-    latest_error
+/// Undo the configuration changes of the installations recorded in the state file at `state_path`:
+/// restore the previous cargo and pip configuration and remove the tool binaries that were installed.
+///
+/// Every step is attempted even if some of them fail, check the returned [`InstallReport`] for their outcome.
+///
+/// # Errors
+///
+/// The state file cannot be read, check [`InstallingError`]
+pub fn uninstall(state_path: &Path) -> Result<InstallReport, Box<InstallingError>> {
+    state::uninstall(&LocalCommandRunner, state_path).map_err(Box::new)
 }
 
 #[cfg(test)]
@@ -0,0 +1,143 @@
+use std::{
+    fs::{self, File},
+    io,
+    path::{Path, PathBuf},
+};
+
+use tracing::{debug, info};
+use walkdir::WalkDir;
+
+use crate::{
+    CARGO_VENDOR_PATH, PIP_DOWNLOAD_DIR, archive::hash_reader, merge::errors::MergeError,
+    report::Recorder,
+};
+
+pub mod errors;
+#[cfg(test)]
+mod test;
+
+/// Checksums of a vendored crate, identifying its exact content
+const CARGO_CHECKSUM_FILE: &str = ".cargo-checksum.json";
+
+/// Add the vendored crates unpacked in `in_folder` to the ones merged into `merge_folder`.
+///
+/// Crates are vendored in versioned directories, so a crate already merged with different checksums
+/// comes from a different source: it is kept and reported as a collision.
+pub(crate) fn merge_crates(
+    in_folder: &Path,
+    merge_folder: &Path,
+    dry_run: bool,
+    recorder: &Recorder,
+) -> Result<(), MergeError> {
+    info!("Merging vendored crates into {}", merge_folder.display());
+    merge_entries(
+        &in_folder.join(CARGO_VENDOR_PATH),
+        &merge_folder.join(CARGO_VENDOR_PATH),
+        |src, dst| {
+            Ok(
+                fs::read(src.join(CARGO_CHECKSUM_FILE))?
+                    == fs::read(dst.join(CARGO_CHECKSUM_FILE))?,
+            )
+        },
+        dry_run,
+        recorder,
+    )
+}
+
+/// Add the python packages unpacked in `in_folder` to the ones merged into `merge_folder`.
+///
+/// A package file already merged with a different content is kept and reported as a collision.
+pub(crate) fn merge_wheels(
+    in_folder: &Path,
+    merge_folder: &Path,
+    dry_run: bool,
+    recorder: &Recorder,
+) -> Result<(), MergeError> {
+    info!("Merging python packages into {}", merge_folder.display());
+    merge_entries(
+        &in_folder.join(PIP_DOWNLOAD_DIR),
+        &merge_folder.join(PIP_DOWNLOAD_DIR),
+        |src, dst| Ok(hash_reader(File::open(src)?)? == hash_reader(File::open(dst)?)?),
+        dry_run,
+        recorder,
+    )
+}
+
+/// Add every entry of `src_folder` missing from `dst_folder`.
+/// Entries already there must be the same according to `same`, otherwise they are reported as collisions
+/// and recorded as warnings with `recorder`.
+fn merge_entries<F: Fn(&Path, &Path) -> io::Result<bool>>(
+    src_folder: &Path,
+    dst_folder: &Path,
+    same: F,
+    dry_run: bool,
+    recorder: &Recorder,
+) -> Result<(), MergeError> {
+    if !src_folder.is_dir() {
+        debug!("Nothing to merge from {}", src_folder.display());
+        return Ok(());
+    }
+    if !dry_run {
+        fs::create_dir_all(dst_folder)
+            .map_err(|e| MergeError::CreateDirectory(dst_folder.to_path_buf(), e))?;
+    }
+
+    let entries = fs::read_dir(src_folder)
+        .map_err(|e| MergeError::ReadDirectory(src_folder.to_path_buf(), e))?;
+    let mut collisions = Vec::new();
+    for entry in entries {
+        let entry = entry.map_err(|e| MergeError::ReadDirectory(src_folder.to_path_buf(), e))?;
+        let src = entry.path();
+        let dst = dst_folder.join(entry.file_name());
+        if dst.exists() {
+            if same(&src, &dst).map_err(|e| MergeError::Compare(src.clone(), e))? {
+                debug!("{} already merged", dst.display());
+            } else {
+                recorder.warning(format!(
+                    "{} differs from the version already merged at {}, keeping the latter",
+                    src.display(),
+                    dst.display()
+                ));
+                collisions.push(dst);
+            }
+        } else if dry_run {
+            info!("Would merge {} into {}", src.display(), dst.display());
+        } else {
+            link_all(&src, &dst)?;
+        }
+    }
+
+    if collisions.is_empty() {
+        Ok(())
+    } else {
+        Err(MergeError::Collisions(collisions))
+    }
+}
+
+/// Hard link every file of `src` (a file or a directory) to the same path under `dst`, or copy it if it cannot be linked
+fn link_all(src: &Path, dst: &Path) -> Result<(), MergeError> {
+    for entry in WalkDir::new(src) {
+        let entry = entry.map_err(|e| MergeError::ReadDirectory(src.to_path_buf(), e.into()))?;
+        let target = relocated(entry.path(), src, dst);
+        let res = if entry.file_type().is_dir() {
+            fs::create_dir_all(&target)
+        } else {
+            fs::hard_link(entry.path(), &target)
+                .or_else(|_| fs::copy(entry.path(), &target).map(|_| ()))
+        };
+        res.map_err(|e| MergeError::Link {
+            src: entry.path().to_path_buf(),
+            dst: target,
+            source: e,
+        })?;
+    }
+    Ok(())
+}
+
+/// `path` within `src`, moved to the same place within `dst`
+fn relocated(path: &Path, src: &Path, dst: &Path) -> PathBuf {
+    match path.strip_prefix(src) {
+        Ok(relative) if relative != Path::new("") => dst.join(relative),
+        _ => dst.to_path_buf(),
+    }
+}
@@ -0,0 +1,23 @@
+use std::path::PathBuf;
+
+use thiserror::Error;
+
+use crate::errors::format_paths;
+
+#[derive(Error, Debug)]
+pub enum MergeError {
+    #[error("Cannot read directory '{0}': {1}")]
+    ReadDirectory(PathBuf, #[source] std::io::Error),
+    #[error("Cannot create merged directory '{0}': {1}")]
+    CreateDirectory(PathBuf, #[source] std::io::Error),
+    #[error("Cannot merge '{src}' into '{dst}': {source}")]
+    Link {
+        src: PathBuf,
+        dst: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("Cannot compare '{0}' to its merged version: {1}")]
+    Compare(PathBuf, #[source] std::io::Error),
+    #[error("Different versions already merged, kept unchanged: {}", format_paths(.0))]
+    Collisions(Vec<PathBuf>),
+}
@@ -0,0 +1,135 @@
+use std::fs::{self, create_dir_all};
+use std::path::Path;
+
+use assertables::assert_fs_read_to_string_eq_x;
+use rstest::rstest;
+use tempfile::tempdir;
+
+use crate::merge::errors::MergeError;
+use crate::merge::{merge_crates, merge_wheels};
+use crate::report::Recorder;
+use crate::{CARGO_VENDOR_PATH, PIP_DOWNLOAD_DIR};
+
+/// Create the unpacked archive of a team, with a vendored crate and a python package
+fn unpacked_archive(root: &Path, crate_name: &str, checksum: &str, wheel: &str, content: &str) {
+    let crate_folder = root.join(CARGO_VENDOR_PATH).join(crate_name);
+    create_dir_all(crate_folder.join("src")).unwrap();
+    fs::write(crate_folder.join(".cargo-checksum.json"), checksum).unwrap();
+    fs::write(crate_folder.join("src").join("lib.rs"), checksum).unwrap();
+    create_dir_all(root.join(PIP_DOWNLOAD_DIR)).unwrap();
+    fs::write(root.join(PIP_DOWNLOAD_DIR).join(wheel), content).unwrap();
+}
+
+#[rstest]
+#[test_log::test]
+fn merge_archives() {
+    let platform = tempdir().unwrap();
+    unpacked_archive(
+        platform.path(),
+        "serde-1.0.0",
+        "serde",
+        "numpy-2.0-py3-none-any.whl",
+        "numpy",
+    );
+    let data_science = tempdir().unwrap();
+    unpacked_archive(
+        data_science.path(),
+        "serde-1.0.0",
+        "serde",
+        "pandas-2.0-py3-none-any.whl",
+        "pandas",
+    );
+    create_dir_all(
+        data_science
+            .path()
+            .join(CARGO_VENDOR_PATH)
+            .join("rand-0.9.0"),
+    )
+    .unwrap();
+    let merged = tempdir().unwrap();
+
+    for archive in [&platform, &data_science] {
+        merge_crates(archive.path(), merged.path(), false, &Recorder::default())
+            .expect("Crates should be merged");
+        merge_wheels(archive.path(), merged.path(), false, &Recorder::default())
+            .expect("Packages should be merged");
+    }
+
+    for crate_name in ["serde-1.0.0", "rand-0.9.0"] {
+        assert!(
+            merged
+                .path()
+                .join(CARGO_VENDOR_PATH)
+                .join(crate_name)
+                .is_dir(),
+            "{crate_name} should be merged"
+        );
+    }
+    assert_fs_read_to_string_eq_x!(
+        merged
+            .path()
+            .join(CARGO_VENDOR_PATH)
+            .join("serde-1.0.0")
+            .join("src")
+            .join("lib.rs"),
+        "serde".to_owned(),
+        "Crate files should be merged"
+    );
+    for wheel in ["numpy-2.0-py3-none-any.whl", "pandas-2.0-py3-none-any.whl"] {
+        assert!(
+            merged.path().join(PIP_DOWNLOAD_DIR).join(wheel).is_file(),
+            "{wheel} should be merged"
+        );
+    }
+}
+
+#[rstest]
+#[test_log::test]
+fn report_collisions() {
+    let platform = tempdir().unwrap();
+    unpacked_archive(
+        platform.path(),
+        "serde-1.0.0",
+        "from crates.io",
+        "numpy-2.0-py3-none-any.whl",
+        "numpy",
+    );
+    let embedded = tempdir().unwrap();
+    unpacked_archive(
+        embedded.path(),
+        "serde-1.0.0",
+        "from a fork",
+        "numpy-2.0-py3-none-any.whl",
+        "patched numpy",
+    );
+    let merged = tempdir().unwrap();
+    merge_crates(platform.path(), merged.path(), false, &Recorder::default())
+        .expect("Crates should be merged");
+    merge_wheels(platform.path(), merged.path(), false, &Recorder::default())
+        .expect("Packages should be merged");
+
+    let crates_res = merge_crates(embedded.path(), merged.path(), false, &Recorder::default());
+    let wheels_res = merge_wheels(embedded.path(), merged.path(), false, &Recorder::default());
+
+    let crate_collision = merged.path().join(CARGO_VENDOR_PATH).join("serde-1.0.0");
+    assert!(
+        matches!(&crates_res, Err(MergeError::Collisions(paths)) if *paths == [crate_collision]),
+        "Crate collision should be reported, got {crates_res:?}"
+    );
+    let wheel_collision = merged
+        .path()
+        .join(PIP_DOWNLOAD_DIR)
+        .join("numpy-2.0-py3-none-any.whl");
+    assert!(
+        matches!(&wheels_res, Err(MergeError::Collisions(paths)) if *paths == [wheel_collision]),
+        "Package collision should be reported, got {wheels_res:?}"
+    );
+    assert_fs_read_to_string_eq_x!(
+        merged
+            .path()
+            .join(PIP_DOWNLOAD_DIR)
+            .join("numpy-2.0-py3-none-any.whl"),
+        "numpy".to_owned(),
+        "Merged package shouldn't be overwritten"
+    );
+}
@@ -0,0 +1,104 @@
+use std::path::{Component, Path};
+
+use time::OffsetDateTime;
+
+use crate::{
+    InstallingError, SETTINGS_FILE, Settings,
+    archive::read_entry,
+    cmd::{CommandRunner, CommandSpec},
+    embedded_settings,
+    encryption::BoxedIdentity,
+    naming::errors::NamingError,
+};
+
+pub mod errors;
+#[cfg(test)]
+mod test;
+
+/// Name template of the archive when none is given
+pub(crate) const DEFAULT_NAME_TEMPLATE: &str = "{name}";
+
+/// File name of the archive following `template`, along with its extension.
+///
+/// Supported placeholders are `{name}` and `{version}` from the settings, `{date}` of the archive creation
+/// (`created_at` in seconds since the UNIX epoch) and `{git_sha}` of the commit checked out in the current directory,
+/// read even when planning a run.
+pub(crate) fn archive_file_name(
+    runner: &dyn CommandRunner,
+    template: &str,
+    settings: &Settings,
+    created_at: u64,
+) -> Result<String, NamingError> {
+    let mut name = template.replace("{name}", &settings.name);
+    if name.contains("{date}") {
+        let date = i64::try_from(created_at)
+            .ok()
+            .and_then(|created_at| OffsetDateTime::from_unix_timestamp(created_at).ok())
+            .ok_or(NamingError::Timestamp(created_at))?
+            .date();
+        name = name.replace("{date}", &date.to_string());
+    }
+    if name.contains("{version}") {
+        let version = settings
+            .version
+            .as_deref()
+            .ok_or(NamingError::MissingVersion)?;
+        name = name.replace("{version}", version);
+    }
+    if name.contains("{git_sha}") {
+        let sha = runner
+            .run(
+                &CommandSpec::new(
+                    "git",
+                    &[
+                        "rev-parse".to_owned(),
+                        "--short".to_owned(),
+                        "HEAD".to_owned(),
+                    ],
+                )
+                .read_only(),
+            )
+            .map(|output| output.stdout)
+            .map_err(NamingError::GitSha)?;
+        name = name.replace("{git_sha}", sha.trim());
+    }
+    if name.contains(['{', '}']) {
+        return Err(NamingError::UnknownPlaceholder(template.to_owned()));
+    }
+    check_name(&name)?;
+    Ok(format!(
+        "{name}.{}",
+        settings.compression.format.extension()
+    ))
+}
+
+/// Name of the archive at `path` read from its embedded settings, if it has any
+pub(crate) fn read_archive_name(
+    path: &Path,
+    identities: &[BoxedIdentity],
+) -> Result<Option<String>, InstallingError> {
+    read_entry(path, SETTINGS_FILE, identities)
+        .map_err(InstallingError::ArchiveUncompress)?
+        .as_deref()
+        .map(archive_name)
+        .transpose()
+}
+
+/// Name of the archive whose embedded settings are `content`
+pub(crate) fn archive_name(content: &[u8]) -> Result<String, InstallingError> {
+    let settings = serde_yaml::from_slice(content)
+        .map_err(|e| InstallingError::DeserializeConfig(e.to_string()))?;
+    let settings = embedded_settings(settings)?;
+    // The name becomes a directory name, it cannot point anywhere else
+    check_name(&settings.name)?;
+    Ok(settings.name)
+}
+
+/// Check that `name` can be used as a file or directory name, without reaching any other directory
+pub(crate) fn check_name(name: &str) -> Result<(), NamingError> {
+    let mut components = Path::new(name).components();
+    match (components.next(), components.next()) {
+        (Some(Component::Normal(_)), None) => Ok(()),
+        _ => Err(NamingError::InvalidName(name.to_owned())),
+    }
+}
@@ -0,0 +1,19 @@
+use thiserror::Error;
+
+use crate::cmd::CommandFailedError;
+
+#[derive(Error, Debug)]
+pub enum NamingError {
+    #[error(
+        "Unknown placeholder in the name template '{0}', expected {{name}}, {{date}}, {{version}} or {{git_sha}}"
+    )]
+    UnknownPlaceholder(String),
+    #[error("The name template uses {{version}} but the settings have no version")]
+    MissingVersion,
+    #[error("Invalid timestamp {0} for {{date}}")]
+    Timestamp(u64),
+    #[error("Cannot get the commit for {{git_sha}}: {0}")]
+    GitSha(#[source] Box<CommandFailedError>),
+    #[error("'{0}' is not a valid file name")]
+    InvalidName(String),
+}
@@ -0,0 +1,107 @@
+use std::fs;
+
+use mockall::predicate::eq;
+use rstest::rstest;
+use tempfile::tempdir;
+
+use crate::naming::{archive_file_name, errors::NamingError, read_archive_name};
+use crate::test::{archive_at, command};
+use crate::{
+    SETTINGS_FILE, Settings,
+    cmd::{CommandOutput, MockCommandRunner},
+};
+
+fn settings(version: Option<&str>) -> Settings {
+    Settings {
+        name: "offline".to_owned(),
+        version: version.map(str::to_owned),
+        ..Settings::default()
+    }
+}
+
+#[rstest]
+#[test_log::test]
+fn render_every_placeholder() {
+    let mut runner = MockCommandRunner::new();
+    runner
+        .expect_run()
+        .with(command(
+            eq("git"),
+            eq(vec![
+                "rev-parse".to_owned(),
+                "--short".to_owned(),
+                "HEAD".to_owned(),
+            ]),
+            eq(None),
+        ))
+        .times(1)
+        .returning(|_| {
+            Ok(CommandOutput {
+                stdout: "1a2b3c4\n".to_owned(),
+                ..CommandOutput::default()
+            })
+        });
+
+    // 2024-03-01T12:00:00Z
+    let name = archive_file_name(
+        &runner,
+        "{name}-{version}-{date}-{git_sha}",
+        &settings(Some("1.2.0")),
+        1_709_294_400,
+    )
+    .expect("Every placeholder should be rendered");
+
+    assert_eq!(
+        name, "offline-1.2.0-2024-03-01-1a2b3c4.tar.gz",
+        "Placeholders should be replaced and the extension appended"
+    );
+}
+
+#[rstest]
+#[case::unknown_placeholder("{name}-{branch}")]
+#[case::missing_version("{name}-{version}")]
+#[case::not_a_file_name("../{name}")]
+#[test_log::test]
+fn reject_template(#[case] template: &str) {
+    let mut runner = MockCommandRunner::new();
+    runner.expect_run().never();
+
+    let res = archive_file_name(&runner, template, &settings(None), 0);
+
+    assert!(
+        matches!(
+            res,
+            Err(NamingError::UnknownPlaceholder(_)
+                | NamingError::MissingVersion
+                | NamingError::InvalidName(_))
+        ),
+        "Template {template} should be rejected, got {res:?}"
+    );
+}
+
+#[rstest]
+#[test_log::test]
+fn read_name_from_settings() {
+    let folder = tempdir().unwrap();
+    let settings_path = folder.path().join(SETTINGS_FILE);
+    fs::write(
+        &settings_path,
+        serde_yaml::to_string(&settings(None)).unwrap(),
+    )
+    .unwrap();
+    // The file name has nothing to do with the name of the settings
+    let archive_path = folder.path().join("renamed-2024.tar.gz");
+    let mut archive = archive_at(&archive_path);
+    archive
+        .append_path_with_name(&settings_path, SETTINGS_FILE)
+        .unwrap();
+    archive.finish().unwrap();
+
+    let name = read_archive_name(&archive_path, &[]).expect("Archive should be readable");
+
+    assert_eq!(
+        name.as_deref(),
+        Some("offline"),
+        "Name should be read from the settings"
+    );
+}
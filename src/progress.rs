@@ -0,0 +1,373 @@
+use std::{
+    fmt::{self, Display},
+    io::{self, IsTerminal, Write},
+    sync::{
+        Arc, Mutex, PoisonError,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+use tracing_subscriber::{
+    EnvFilter, filter::LevelFilter, layer::SubscriberExt, util::SubscriberInitExt,
+};
+
+#[cfg(test)]
+mod test;
+
+/// Phase of a packaging or installation run, in the order they run
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Phase {
+    /// Check the settings before downloading anything
+    Check,
+    /// Download the resources of every section
+    Download,
+    /// Append the downloaded resources to the archive
+    Archive,
+    /// Check and unpack the archive
+    Unpack,
+    /// Install the unpacked resources
+    Install,
+}
+
+impl Display for Phase {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Check => "check",
+            Self::Download => "download",
+            Self::Archive => "archive",
+            Self::Unpack => "unpack",
+            Self::Install => "install",
+        })
+    }
+}
+
+/// Position of a step among the steps of the same kind, e.g., the 3rd git mirror out of 6
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Position {
+    /// Index of the step, from 1
+    pub index: usize,
+    pub total: usize,
+}
+
+impl Position {
+    /// Only step of its kind
+    pub const SINGLE: Self = Self { index: 1, total: 1 };
+
+    /// The step `index` (from 1) out of `total`
+    #[must_use]
+    pub fn new(index: usize, total: usize) -> Self {
+        Self { index, total }
+    }
+}
+
+impl Display for Position {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.index, self.total)
+    }
+}
+
+/// What a packaging or installation run is doing, sent to the [`ProgressSubscriber`] of its options
+#[derive(Clone, Debug, PartialEq)]
+pub enum ProgressEvent {
+    PhaseStarted {
+        phase: Phase,
+    },
+    PhaseFinished {
+        phase: Phase,
+        elapsed: Duration,
+    },
+    /// A step of the phase started, e.g., "git clone https://github.com/rustsec/advisory-db".
+    /// Downloads of several sections can run at the same time.
+    StepStarted {
+        phase: Phase,
+        name: String,
+        position: Position,
+    },
+    StepFinished {
+        phase: Phase,
+        name: String,
+        position: Position,
+        elapsed: Duration,
+        success: bool,
+    },
+    /// Bytes of the files appended to the archive so far
+    BytesArchived {
+        total: u64,
+    },
+    /// Bytes of the files unpacked from the archive so far
+    BytesUnpacked {
+        total: u64,
+    },
+}
+
+/// Receives the progress of a run, e.g., to display it.
+///
+/// Downloads may run on several threads at the same time, hence `Send + Sync`.
+pub trait ProgressSubscriber: Send + Sync {
+    fn on_event(&self, event: &ProgressEvent);
+}
+
+/// Sends the events of the current run to its subscriber, if any
+#[derive(Clone, Default)]
+pub(crate) struct Progress {
+    subscriber: Option<Arc<dyn ProgressSubscriber>>,
+}
+
+impl Progress {
+    pub(crate) fn new(subscriber: Option<Arc<dyn ProgressSubscriber>>) -> Self {
+        Self { subscriber }
+    }
+
+    pub(crate) fn emit(&self, event: ProgressEvent) {
+        if let Some(subscriber) = &self.subscriber {
+            subscriber.on_event(&event);
+        }
+    }
+
+    /// Run `phase`, reporting when it starts and finishes, whatever its outcome
+    pub(crate) fn phase<R, F: FnOnce() -> R>(&self, phase: Phase, run: F) -> R {
+        self.emit(ProgressEvent::PhaseStarted { phase });
+        let start = Instant::now();
+        let res = run();
+        self.emit(ProgressEvent::PhaseFinished {
+            phase,
+            elapsed: start.elapsed(),
+        });
+        res
+    }
+
+    /// Run the step `name` of `phase`, reporting when it starts and finishes
+    pub(crate) fn step<T, E, F: FnOnce() -> Result<T, E>>(
+        &self,
+        phase: Phase,
+        name: &str,
+        position: Position,
+        run: F,
+    ) -> Result<T, E> {
+        self.emit(ProgressEvent::StepStarted {
+            phase,
+            name: name.to_owned(),
+            position,
+        });
+        let start = Instant::now();
+        let res = run();
+        self.emit(ProgressEvent::StepFinished {
+            phase,
+            name: name.to_owned(),
+            position,
+            elapsed: start.elapsed(),
+            success: res.is_ok(),
+        });
+        res
+    }
+}
+
+/// Counts the bytes of the files appended to the archive or unpacked from it
+#[derive(Default)]
+pub(crate) struct ByteCounter {
+    total: AtomicU64,
+}
+
+impl ByteCounter {
+    /// Add `bytes` to the total and return it
+    pub(crate) fn add(&self, bytes: u64) -> u64 {
+        let previous = self.total.fetch_add(bytes, Ordering::Relaxed);
+        previous.saturating_add(bytes)
+    }
+}
+
+/// Displays the progress of a run on the standard error:
+/// a single line updated in place on a terminal, one line per phase and step otherwise (e.g., in CI logs).
+///
+/// Logs written through it, see [`init_logging`], are kept above the status line.
+pub struct ProgressDisplay {
+    terminal: bool,
+    state: Mutex<DisplayState>,
+}
+
+#[derive(Default)]
+struct DisplayState {
+    /// Step currently shown on the status line of a terminal
+    current: Option<String>,
+    archived: u64,
+    unpacked: u64,
+}
+
+impl Default for ProgressDisplay {
+    fn default() -> Self {
+        Self::new(io::stderr().is_terminal())
+    }
+}
+
+impl ProgressDisplay {
+    /// Display on a terminal, updating a status line in place, if `terminal` is set
+    #[must_use]
+    pub fn new(terminal: bool) -> Self {
+        Self {
+            terminal,
+            state: Mutex::new(DisplayState::default()),
+        }
+    }
+
+    /// Line to print for `event` outside of a terminal, if any
+    fn plain_line(state: &mut DisplayState, event: &ProgressEvent) -> Option<String> {
+        match event {
+            ProgressEvent::PhaseStarted { phase } => Some(format!("[{phase}] started")),
+            ProgressEvent::PhaseFinished { phase, elapsed } => {
+                let bytes = match phase {
+                    Phase::Archive => format!(", {} archived", format_size(state.archived)),
+                    Phase::Unpack => format!(", {} unpacked", format_size(state.unpacked)),
+                    _ => String::new(),
+                };
+                Some(format!(
+                    "[{phase}] finished in {}{bytes}",
+                    format_elapsed(*elapsed)
+                ))
+            }
+            ProgressEvent::StepStarted {
+                phase,
+                name,
+                position,
+            } => Some(format!("[{phase}] ({position}) {name}")),
+            ProgressEvent::StepFinished {
+                phase,
+                name,
+                position,
+                elapsed,
+                success,
+            } => Some(format!(
+                "[{phase}] ({position}) {name} {} in {}",
+                if *success { "done" } else { "failed" },
+                format_elapsed(*elapsed)
+            )),
+            ProgressEvent::BytesArchived { total } => {
+                state.archived = *total;
+                None
+            }
+            ProgressEvent::BytesUnpacked { total } => {
+                state.unpacked = *total;
+                None
+            }
+        }
+    }
+
+    /// Update the status line of a terminal for `event`, and return the lines to keep above it
+    fn terminal_update(state: &mut DisplayState, event: &ProgressEvent) -> Option<String> {
+        match event {
+            ProgressEvent::StepStarted {
+                phase,
+                name,
+                position,
+            } => {
+                state.current = Some(format!("[{phase}] ({position}) {name}"));
+                None
+            }
+            ProgressEvent::StepFinished { success: false, .. }
+            | ProgressEvent::PhaseFinished { .. } => {
+                state.current = None;
+                Self::plain_line(state, event)
+            }
+            ProgressEvent::BytesArchived { total } => {
+                state.archived = *total;
+                state.current = Some(format!("[archive] {} archived", format_size(*total)));
+                None
+            }
+            ProgressEvent::BytesUnpacked { total } => {
+                state.unpacked = *total;
+                state.current = Some(format!("[unpack] {} unpacked", format_size(*total)));
+                None
+            }
+            ProgressEvent::PhaseStarted { phase } => {
+                state.current = Some(format!("[{phase}]"));
+                None
+            }
+            ProgressEvent::StepFinished { .. } => None,
+        }
+    }
+
+    /// Write the log lines `buf` to `out`, clearing the status line of a terminal before and drawing it again after
+    fn write_log(&self, state: &DisplayState, out: &mut impl Write, buf: &[u8]) -> io::Result<()> {
+        let status = state.current.as_ref().filter(|_| self.terminal);
+        if status.is_some() {
+            write!(out, "\r\x1b[2K")?;
+        }
+        out.write_all(buf)?;
+        if let Some(current) = status {
+            write!(out, "{current}")?;
+        }
+        out.flush()
+    }
+}
+
+/// Log lines written to the standard error without garbling the status line
+impl Write for &ProgressDisplay {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        self.write_log(&state, &mut io::stderr().lock(), buf)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        io::stderr().flush()
+    }
+}
+
+/// Install the global subscriber logging the events enabled by `RUST_LOG`, info and above by default,
+/// through `display` so that they are kept above its status line
+pub fn init_logging(display: Arc<ProgressDisplay>) {
+    // Same filter as `tracing_subscriber::fmt::init`
+    let filter = EnvFilter::builder()
+        .with_default_directive(LevelFilter::INFO.into())
+        .from_env_lossy();
+    tracing_subscriber::registry()
+        .with(tracing_subscriber::fmt::layer().with_writer(display))
+        .with(filter)
+        .init();
+}
+
+impl ProgressSubscriber for ProgressDisplay {
+    fn on_event(&self, event: &ProgressEvent) {
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        let mut stderr = io::stderr().lock();
+        // The display is best effort, it never fails the run
+        if self.terminal {
+            let line = Self::terminal_update(&mut state, event);
+            // Clear the status line, print the line to keep if any, then the new status line
+            let _ = write!(stderr, "\r\x1b[2K");
+            if let Some(line) = line {
+                let _ = writeln!(stderr, "{line}");
+            }
+            if let Some(current) = &state.current {
+                let _ = write!(stderr, "{current}");
+            }
+        } else if let Some(line) = Self::plain_line(&mut state, event) {
+            let _ = writeln!(stderr, "{line}");
+        }
+        let _ = stderr.flush();
+    }
+}
+
+/// Elapsed time with a tenth of a second precision, e.g., "12.3s"
+fn format_elapsed(elapsed: Duration) -> String {
+    format!("{:.1}s", elapsed.as_secs_f64())
+}
+
+/// Size in bytes with a binary unit, e.g., "12.3 MiB"
+fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+    if bytes < 1 << 10 {
+        return format!("{bytes} B");
+    }
+    let mut unit = UNITS[0];
+    let mut divisor: u64 = 1 << 10;
+    for next in UNITS.iter().skip(1) {
+        if bytes < divisor << 10 {
+            break;
+        }
+        unit = next;
+        divisor <<= 10;
+    }
+    let tenths = (bytes % divisor).saturating_mul(10) / divisor;
+    format!("{}.{tenths} {unit}", bytes / divisor)
+}
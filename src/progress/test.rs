@@ -0,0 +1,266 @@
+use std::fs::create_dir_all;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use mockall::predicate::{eq, function};
+use rstest::rstest;
+use tempfile::tempdir;
+use url::Url;
+
+use crate::cmd::{CommandOutput, MockCommandRunner};
+use crate::progress::{DisplayState, format_size};
+use crate::test::command;
+use crate::{
+    DownloadSkip, GitMirrors, InstallOptions, InstallSkip, PackageOptions, Phase, Position,
+    ProgressDisplay, ProgressEvent, ProgressSubscriber, Settings, install_with_runner,
+    package_with_runner,
+};
+
+/// Subscriber keeping every event it receives
+#[derive(Default)]
+struct Recorder {
+    events: Mutex<Vec<ProgressEvent>>,
+}
+
+impl ProgressSubscriber for Recorder {
+    fn on_event(&self, event: &ProgressEvent) {
+        self.events.lock().unwrap().push(event.clone());
+    }
+}
+
+impl Recorder {
+    fn take(&self) -> Vec<ProgressEvent> {
+        std::mem::take(&mut *self.events.lock().unwrap())
+    }
+}
+
+/// Phases started, in order
+fn phases(events: &[ProgressEvent]) -> Vec<Phase> {
+    events
+        .iter()
+        .filter_map(|event| match event {
+            ProgressEvent::PhaseStarted { phase } => Some(*phase),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Steps finished during `phase`, with their position and outcome
+fn finished_steps(events: &[ProgressEvent], phase: Phase) -> Vec<(String, Position, bool)> {
+    events
+        .iter()
+        .filter_map(|event| match event {
+            ProgressEvent::StepFinished {
+                phase: step_phase,
+                name,
+                position,
+                success,
+                ..
+            } if *step_phase == phase => Some((name.clone(), *position, *success)),
+            _ => None,
+        })
+        .collect()
+}
+
+#[rstest]
+#[test_log::test]
+fn package_and_install_events() {
+    let folder = tempdir().unwrap();
+    let settings = Settings {
+        git_mirrors: GitMirrors::builder()
+            .mirror(
+                Url::parse("https://github.com/rustsec/advisory-db").unwrap(),
+                Url::parse("https://private.domain/global/advisory-db").unwrap(),
+            )
+            .mirror(
+                Url::parse("https://github.com/rust-lang/crates.io-index").unwrap(),
+                Url::parse("https://private.domain/global/crates.io-index").unwrap(),
+            )
+            .build(),
+        ..Settings::default()
+    };
+    let mut runner = MockCommandRunner::new();
+    runner
+        .expect_run()
+        .with(command(
+            eq("git"),
+            function(|args: &[String]| args[0] == "clone"),
+            function(|_: &Option<PathBuf>| true),
+        ))
+        .times(2)
+        .returning(|command| {
+            create_dir_all(command.cwd.as_ref().unwrap().join(&command.args[3])).unwrap();
+            Ok(CommandOutput::default())
+        });
+    runner
+        .expect_run()
+        .with(command(
+            eq("git"),
+            function(|args: &[String]| args[0] == "for-each-ref"),
+            function(|_: &Option<PathBuf>| true),
+        ))
+        .times(2)
+        .returning(|_| Ok(CommandOutput::default()));
+    runner
+        .expect_run()
+        .with(command(
+            eq("git"),
+            function(|args: &[String]| args[0] == "push"),
+            function(|_: &Option<PathBuf>| true),
+        ))
+        .times(2)
+        .returning(|_| Ok(CommandOutput::default()));
+    let recorder = Arc::new(Recorder::default());
+
+    let package_options = PackageOptions {
+        skip: vec![DownloadSkip::Rust, DownloadSkip::Python],
+        staging_dir: Some(folder.path().join("staging")),
+        output_dir: Some(folder.path().join("out")),
+        progress: Some(recorder.clone()),
+        ..PackageOptions::default()
+    };
+    package_with_runner(&settings, &package_options, &runner).expect("Packaging should work");
+    let events = recorder.take();
+
+    assert_eq!(
+        phases(&events),
+        [Phase::Check, Phase::Download, Phase::Archive],
+        "Every packaging phase should be reported, got {events:?}"
+    );
+    assert_eq!(
+        finished_steps(&events, Phase::Download),
+        [
+            (
+                "git clone https://github.com/rustsec/advisory-db".to_owned(),
+                Position::new(1, 2),
+                true
+            ),
+            (
+                "git clone https://github.com/rust-lang/crates.io-index".to_owned(),
+                Position::new(2, 2),
+                true
+            ),
+        ],
+        "Every mirror should be reported with its position"
+    );
+    let archived = events.iter().rev().find_map(|event| match event {
+        ProgressEvent::BytesArchived { total } => Some(*total),
+        _ => None,
+    });
+    assert!(
+        archived.is_some_and(|total| total > 0),
+        "Archived bytes should be reported, got {events:?}"
+    );
+
+    let install_options = InstallOptions {
+        skip: vec![
+            InstallSkip::RustConfig,
+            InstallSkip::RustTools,
+            InstallSkip::PythonConfig,
+        ],
+        unpack_dir: Some(folder.path().join("unpacked")),
+        progress: Some(recorder.clone()),
+        ..InstallOptions::default()
+    };
+    install_with_runner(
+        &folder.path().join("out").join("offline-vendoring.tar.gz"),
+        &install_options,
+        &runner,
+    )
+    .expect("Installing should work");
+    let events = recorder.take();
+
+    assert_eq!(
+        phases(&events),
+        [Phase::Unpack, Phase::Install],
+        "Every installation phase should be reported, got {events:?}"
+    );
+    assert_eq!(
+        finished_steps(&events, Phase::Install)
+            .into_iter()
+            .map(|(_, position, success)| (position, success))
+            .collect::<Vec<_>>(),
+        [(Position::new(1, 2), true), (Position::new(2, 2), true)],
+        "Skipped steps shouldn't be reported, the pushes should"
+    );
+    assert!(
+        events
+            .iter()
+            .any(|event| matches!(event, ProgressEvent::BytesUnpacked { total } if *total > 0)),
+        "Unpacked bytes should be reported"
+    );
+}
+
+#[rstest]
+#[case::phase_started(ProgressEvent::PhaseStarted { phase: Phase::Download }, Some("[download] started"))]
+#[case::step_started(
+    ProgressEvent::StepStarted {
+        phase: Phase::Download,
+        name: "git clone https://github.com/rustsec/advisory-db".to_owned(),
+        position: Position::new(3, 6),
+    },
+    Some("[download] (3/6) git clone https://github.com/rustsec/advisory-db")
+)]
+#[case::step_failed(
+    ProgressEvent::StepFinished {
+        phase: Phase::Install,
+        name: "git push https://private.domain/global/advisory-db".to_owned(),
+        position: Position::SINGLE,
+        elapsed: Duration::from_millis(1250),
+        success: false,
+    },
+    Some("[install] (1/1) git push https://private.domain/global/advisory-db failed in 1.2s")
+)]
+#[case::phase_finished(
+    ProgressEvent::PhaseFinished { phase: Phase::Archive, elapsed: Duration::from_secs(75) },
+    Some("[archive] finished in 75.0s, 3.5 MiB archived")
+)]
+#[case::bytes(ProgressEvent::BytesArchived { total: 12 }, None)]
+#[test_log::test]
+fn plain_lines(#[case] event: ProgressEvent, #[case] expected: Option<&str>) {
+    let mut state = DisplayState {
+        archived: 3_670_016,
+        ..DisplayState::default()
+    };
+
+    let line = ProgressDisplay::plain_line(&mut state, &event);
+
+    assert_eq!(
+        line.as_deref(),
+        expected,
+        "Unexpected line outside of a terminal"
+    );
+}
+
+#[rstest]
+#[case::bytes(1023, "1023 B")]
+#[case::kibibytes(1536, "1.5 KiB")]
+#[case::gibibytes(5 << 30, "5.0 GiB")]
+#[test_log::test]
+fn human_sizes(#[case] bytes: u64, #[case] expected: &str) {
+    assert_eq!(format_size(bytes), expected, "Unexpected size");
+}
+
+#[rstest]
+#[case::terminal(true, "\r\x1b[2K INFO packaging\n[download] (1/2) git clone")]
+#[case::not_terminal(false, " INFO packaging\n")]
+#[test_log::test]
+fn logs_above_status_line(#[case] terminal: bool, #[case] expected: &str) {
+    let display = ProgressDisplay::new(terminal);
+    let state = DisplayState {
+        current: Some("[download] (1/2) git clone".to_owned()),
+        ..DisplayState::default()
+    };
+    let mut out = Vec::new();
+
+    display
+        .write_log(&state, &mut out, b" INFO packaging\n")
+        .unwrap();
+
+    assert_eq!(
+        String::from_utf8(out).unwrap(),
+        expected,
+        "The status line should be cleared then drawn again below the log line on a terminal"
+    );
+}
@@ -0,0 +1,342 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    path::Path,
+    sync::Arc,
+};
+
+use crate::{
+    ArchiveBuilder, InstallOptions, InstallingError, PackagingError, Settings, SettingsError,
+    cmd::{CommandFailedError, CommandOutput, CommandRunner, CommandSpec},
+    download::Downloads,
+    progress::{Phase, Position, Progress},
+    report::{InstallReport, Recorder},
+    state::InstallState,
+};
+
+#[cfg(test)]
+mod test;
+
+/// Kind of resources downloaded into the archive and installed from it, e.g., a section of the settings.
+///
+/// Packaging downloads the resources of every provider, several providers at a time if allowed,
+/// then archives them in the order they were registered.
+/// Skipped sections, dry runs, reporting and failures are handled the same way for every provider.
+/// Downstream providers read their own section of the settings through [`PackageContext::section_settings`]
+/// and [`InstallContext::section_settings`].
+pub trait ResourceProvider: Send + Sync {
+    /// Key of the provider, e.g., "rust" or "git_mirrors", also used to skip it
+    fn section(&self) -> &str;
+
+    /// One line per resource handled, e.g., "git mirror https://github.com/rustsec/advisory-db"
+    fn describe(&self) -> Vec<String>;
+
+    /// Mistakes in the settings of the provider, found without downloading anything
+    fn verify(&self) -> Vec<SettingsError>;
+
+    /// Download the resources into [`PackageContext::directory`], running commands through the context
+    ///
+    /// # Errors
+    ///
+    /// The resources cannot be downloaded
+    fn package(&self, context: &PackageContext<'_>) -> Result<(), PackagingError>;
+
+    /// Append the downloaded resources to `archive`
+    ///
+    /// # Errors
+    ///
+    /// The resources cannot be archived
+    fn archive(
+        &self,
+        context: &PackageContext<'_>,
+        archive: &mut ArchiveBuilder,
+    ) -> Result<(), PackagingError>;
+
+    /// Install the unpacked resources, recording every step through [`InstallContext::step`]
+    fn install(&self, context: &mut InstallContext<'_>);
+}
+
+impl<P: ResourceProvider + ?Sized> ResourceProvider for Arc<P> {
+    fn section(&self) -> &str {
+        (**self).section()
+    }
+
+    fn describe(&self) -> Vec<String> {
+        (**self).describe()
+    }
+
+    fn verify(&self) -> Vec<SettingsError> {
+        (**self).verify()
+    }
+
+    fn package(&self, context: &PackageContext<'_>) -> Result<(), PackagingError> {
+        (**self).package(context)
+    }
+
+    fn archive(
+        &self,
+        context: &PackageContext<'_>,
+        archive: &mut ArchiveBuilder,
+    ) -> Result<(), PackagingError> {
+        (**self).archive(context, archive)
+    }
+
+    fn install(&self, context: &mut InstallContext<'_>) {
+        (**self).install(context);
+    }
+}
+
+/// What providers get to download and archive their resources
+pub struct PackageContext<'a> {
+    directory: &'a Path,
+    pub(crate) downloads: &'a Downloads,
+    runner: &'a dyn CommandRunner,
+    sections: &'a BTreeMap<String, serde_json::Value>,
+}
+
+impl<'a> PackageContext<'a> {
+    pub(crate) fn new(
+        directory: &'a Path,
+        downloads: &'a Downloads,
+        runner: &'a dyn CommandRunner,
+        sections: &'a BTreeMap<String, serde_json::Value>,
+    ) -> Self {
+        Self {
+            directory,
+            downloads,
+            runner,
+            sections,
+        }
+    }
+
+    /// Section `section` of the settings of a downstream provider, e.g., its own [`ResourceProvider::section`]
+    #[must_use]
+    pub fn section_settings(&self, section: &str) -> Option<&'a serde_json::Value> {
+        self.sections.get(section)
+    }
+
+    /// Absolute path of the directory the resources are downloaded into, shared by every provider
+    #[must_use]
+    pub fn directory(&self) -> &Path {
+        self.directory
+    }
+
+    /// Whether nothing should be written, commands being only recorded by the runner
+    #[must_use]
+    pub fn is_dry_run(&self) -> bool {
+        self.downloads.dry_run
+    }
+
+    /// Whether the downloads of previous runs are kept and should be updated instead of started over
+    #[must_use]
+    pub fn reuses_downloads(&self) -> bool {
+        self.downloads.reuse
+    }
+
+    /// Run the download step `name`, at `position` among the steps of its kind (e.g., the 3rd mirror out of 6),
+    /// unless the run is resumed and a previous run already finished it with the same settings and `inputs` files
+    ///
+    /// # Errors
+    ///
+    /// The step failed or the checkpoint cannot be written
+    pub fn step<F: FnOnce() -> Result<(), PackagingError>>(
+        &self,
+        name: &str,
+        inputs: &[&Path],
+        position: Position,
+        download: F,
+    ) -> Result<(), PackagingError> {
+        self.downloads.step(name, inputs, position, download)
+    }
+
+    /// Run `job` once fewer downloads than allowed by `--jobs` are running
+    pub fn run_job<R, F: FnOnce() -> R>(&self, job: F) -> R {
+        self.downloads.jobs.run(job)
+    }
+
+    /// Runner of the commands of the run, recording them in its report
+    #[must_use]
+    pub fn runner(&self) -> &'a dyn CommandRunner {
+        self.runner
+    }
+
+    /// Run the command with its environment, timeout and retry policy, or only record it on a dry run.
+    /// Its output is always empty on a dry run.
+    ///
+    /// # Errors
+    ///
+    /// The command cannot be started, fails or times out
+    pub fn run(&self, command: &CommandSpec) -> Result<CommandOutput, Box<CommandFailedError>> {
+        self.runner.run(command)
+    }
+}
+
+/// What providers get to install their resources
+pub struct InstallContext<'a> {
+    unpacked_directory: &'a Path,
+    resources_directory: &'a Path,
+    options: &'a InstallOptions,
+    dry_run: bool,
+    /// Step kinds and sections to skip
+    skip: &'a BTreeSet<String>,
+    pub(crate) state: &'a mut InstallState,
+    report: &'a mut InstallReport,
+    runner: &'a dyn CommandRunner,
+    progress: &'a Progress,
+    recorder: &'a Recorder,
+    sections: &'a BTreeMap<String, serde_json::Value>,
+}
+
+impl<'a> InstallContext<'a> {
+    #[expect(clippy::too_many_arguments, reason = "only built by the installation")]
+    pub(crate) fn new(
+        unpacked_directory: &'a Path,
+        resources_directory: &'a Path,
+        options: &'a InstallOptions,
+        dry_run: bool,
+        skip: &'a BTreeSet<String>,
+        state: &'a mut InstallState,
+        report: &'a mut InstallReport,
+        runner: &'a dyn CommandRunner,
+        progress: &'a Progress,
+        recorder: &'a Recorder,
+        sections: &'a BTreeMap<String, serde_json::Value>,
+    ) -> Self {
+        Self {
+            unpacked_directory,
+            resources_directory,
+            options,
+            dry_run,
+            skip,
+            state,
+            report,
+            runner,
+            progress,
+            recorder,
+            sections,
+        }
+    }
+
+    /// Section `section` of the settings embedded in the archive for a downstream provider,
+    /// e.g., its own [`ResourceProvider::section`]
+    #[must_use]
+    pub fn section_settings(&self, section: &str) -> Option<&'a serde_json::Value> {
+        self.sections.get(section)
+    }
+
+    /// Absolute path of the unpacked archive
+    #[must_use]
+    pub fn unpacked_directory(&self) -> &'a Path {
+        self.unpacked_directory
+    }
+
+    /// Absolute path of the crates and python packages to point cargo and pip to:
+    /// the unpacked archive, or the directory merging several archives
+    #[must_use]
+    pub fn resources_directory(&self) -> &'a Path {
+        self.resources_directory
+    }
+
+    #[must_use]
+    pub fn options(&self) -> &'a InstallOptions {
+        self.options
+    }
+
+    /// Whether nothing should be changed, commands being only recorded by the runner
+    #[must_use]
+    pub fn is_dry_run(&self) -> bool {
+        self.dry_run
+    }
+
+    /// Run the step `name` of the kind `kind` (e.g., "git push"), at `position` among the steps of its kind,
+    /// and record its outcome, or only record it as skipped if that kind of step is skipped
+    pub fn step<E: Into<InstallingError>, F: FnOnce(&mut Self) -> Result<(), E>>(
+        &mut self,
+        kind: &str,
+        name: String,
+        position: Position,
+        install: F,
+    ) {
+        if self.skip.contains(kind) {
+            self.recorder.record_skipped_step(&name);
+            self.report.skip(name);
+            return;
+        }
+        let progress = self.progress;
+        let res = progress.step(Phase::Install, &name, position, || install(self));
+        self.report.record(name, res);
+    }
+
+    pub(crate) fn skip_section(&mut self, section: &str) -> bool {
+        if !self.skip.contains(section) {
+            return false;
+        }
+        self.recorder.record_skipped_step(section);
+        self.report.skip(section.to_owned());
+        true
+    }
+
+    /// Recorder of the installation run, keeping warnings and config changes in its report
+    pub(crate) fn recorder(&self) -> &'a Recorder {
+        self.recorder
+    }
+
+    /// Runner of the commands of the run, recording them in its report
+    #[must_use]
+    pub fn runner(&self) -> &'a dyn CommandRunner {
+        self.runner
+    }
+
+    /// Run the command with its environment, timeout and retry policy, or only record it on a dry run.
+    /// Its output is always empty on a dry run.
+    ///
+    /// # Errors
+    ///
+    /// The command cannot be started, fails or times out
+    pub fn run(&self, command: &CommandSpec) -> Result<CommandOutput, Box<CommandFailedError>> {
+        self.runner.run(command)
+    }
+}
+
+/// Providers keyed by their section, in the order their resources are archived and installed
+#[derive(Default)]
+pub(crate) struct ProviderRegistry<'a> {
+    providers: Vec<&'a dyn ResourceProvider>,
+}
+
+impl<'a> ProviderRegistry<'a> {
+    /// Providers of the sections of `settings`, followed by `extra` ones (replacing the built-in one of the same section)
+    pub(crate) fn new(settings: &'a Settings, extra: &'a [Arc<dyn ResourceProvider>]) -> Self {
+        let mut registry = Self::default();
+        registry.register(&settings.rust);
+        registry.register(&settings.python);
+        registry.register(&settings.git_mirrors);
+        registry.register(&settings.custom);
+        for provider in extra {
+            registry.register(provider.as_ref());
+        }
+        registry
+    }
+
+    /// Add `provider`, in place of the one of the same section if any
+    pub(crate) fn register(&mut self, provider: &'a dyn ResourceProvider) {
+        let existing = self
+            .providers
+            .iter_mut()
+            .find(|existing| existing.section() == provider.section());
+        match existing {
+            Some(existing) => *existing = provider,
+            None => self.providers.push(provider),
+        }
+    }
+
+    pub(crate) fn iter(&self) -> impl Iterator<Item = &'a dyn ResourceProvider> {
+        self.providers.iter().copied()
+    }
+
+    /// Whether a provider is registered for `section`
+    pub(crate) fn contains(&self, section: &str) -> bool {
+        self.providers
+            .iter()
+            .any(|provider| provider.section() == section)
+    }
+}
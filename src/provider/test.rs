@@ -0,0 +1,240 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use mockall::predicate::{eq, function};
+use rstest::rstest;
+
+use crate::cmd::{CommandOutput, CommandSpec, MockCommandRunner};
+use crate::provider::ProviderRegistry;
+use crate::report::StepStatus;
+use crate::test::{InstallFixture, command};
+use crate::validation::validate;
+use crate::{
+    ArchiveBuilder, DownloadSkip, InstallContext, InstallingError, PackageContext, PackageOptions,
+    PackagingError, Position, ResourceProvider, Settings, SettingsError, plan_package,
+};
+
+/// Downstream provider of debian packages
+struct Apt {
+    section: &'static str,
+}
+
+/// Package named in the settings section of the provider, e.g., `apt: {package: wget}`, or curl
+fn package_name(section: Option<&serde_json::Value>) -> String {
+    section
+        .and_then(|section| section["package"].as_str())
+        .unwrap_or("curl")
+        .to_owned()
+}
+
+impl ResourceProvider for Apt {
+    fn section(&self) -> &str {
+        self.section
+    }
+
+    fn describe(&self) -> Vec<String> {
+        vec!["debian package curl".to_owned()]
+    }
+
+    fn verify(&self) -> Vec<SettingsError> {
+        Vec::new()
+    }
+
+    fn package(&self, context: &PackageContext<'_>) -> Result<(), PackagingError> {
+        context.step("apt download", &[], Position::SINGLE, || {
+            context
+                .run(
+                    &CommandSpec::new(
+                        "apt-get",
+                        [
+                            "download".to_owned(),
+                            package_name(context.section_settings(self.section)),
+                        ],
+                    )
+                    .cwd(context.directory().join("apt")),
+                )
+                .map(|_| ())
+                .map_err(|e| PackagingError::Provider {
+                    section: self.section.to_owned(),
+                    source: e,
+                })
+        })
+    }
+
+    fn archive(
+        &self,
+        context: &PackageContext<'_>,
+        archive: &mut ArchiveBuilder,
+    ) -> Result<(), PackagingError> {
+        archive
+            .append_dir_all("apt", context.directory().join("apt"))
+            .map_err(|e| PackagingError::Provider {
+                section: self.section.to_owned(),
+                source: Box::new(e),
+            })
+    }
+
+    fn install(&self, context: &mut InstallContext<'_>) {
+        context.step(
+            "apt config",
+            "apt config".to_owned(),
+            Position::new(1, 2),
+            |_| Ok::<(), InstallingError>(()),
+        );
+        let package = package_name(context.section_settings(self.section));
+        context.step(
+            "apt install",
+            format!("apt install {package}"),
+            Position::new(2, 2),
+            |context| {
+                context
+                    .run(
+                        &CommandSpec::new("dpkg", ["--install", "apt"])
+                            .cwd(context.unpacked_directory()),
+                    )
+                    .map(|_| ())
+                    .map_err(|e| InstallingError::Provider {
+                        section: self.section.to_owned(),
+                        source: e,
+                    })
+            },
+        );
+    }
+}
+
+#[rstest]
+#[test_log::test]
+fn register_in_place_of_builtin() {
+    let settings = Settings::default();
+    let extra: [Arc<dyn ResourceProvider>; 2] = [
+        Arc::new(Apt { section: "apt" }),
+        Arc::new(Apt {
+            section: "git_mirrors",
+        }),
+    ];
+
+    let registry = ProviderRegistry::new(&settings, &extra);
+
+    let sections: Vec<_> = registry.iter().map(ResourceProvider::section).collect();
+    assert_eq!(
+        sections,
+        ["rust", "python", "git_mirrors", "custom", "apt"],
+        "Extra providers should come after the built-in ones, or replace the one of their section"
+    );
+    assert_eq!(
+        registry
+            .iter()
+            .find(|provider| provider.section() == "git_mirrors")
+            .map(ResourceProvider::describe),
+        Some(vec!["debian package curl".to_owned()]),
+        "The built-in git mirrors should be replaced"
+    );
+}
+
+#[rstest]
+#[case::unknown_section(false, &["apt: unknown section, no provider is registered for it"])]
+#[case::downstream_provider_section(true, &[])]
+#[test_log::test]
+fn check_sections_have_a_provider(#[case] registered: bool, #[case] expected: &[&str]) {
+    let settings: Settings = serde_yaml::from_str("apt:\n    package: wget\n").unwrap();
+    let extra: Vec<Arc<dyn ResourceProvider>> = if registered {
+        vec![Arc::new(Apt { section: "apt" })]
+    } else {
+        Vec::new()
+    };
+
+    let errors: Vec<_> = validate(&settings, &ProviderRegistry::new(&settings, &extra), true)
+        .iter()
+        .map(ToString::to_string)
+        .collect();
+
+    assert_eq!(
+        errors, expected,
+        "Only the sections without any provider should be reported"
+    );
+}
+
+#[rstest]
+#[test_log::test]
+fn plan_downstream_provider() {
+    let settings = Settings {
+        name: "provider-plan".to_owned(),
+        sections: BTreeMap::from([("apt".to_owned(), serde_json::json!({"package": "wget"}))]),
+        ..Settings::default()
+    };
+    let options = PackageOptions {
+        skip: vec![DownloadSkip::Rust],
+        providers: vec![Arc::new(Apt { section: "apt" })],
+        ..PackageOptions::default()
+    };
+
+    let plan = plan_package(&settings, &options).expect("Planning should work");
+
+    // Other tests may run commands at the same time, only look for the ones of this test
+    let download = format!(
+        "apt-get download wget  (in {})",
+        std::path::absolute("provider-plan/apt").unwrap().display()
+    );
+    assert!(
+        plan.commands
+            .iter()
+            .any(|command| command.to_string() == download),
+        "The provider should run its commands through the dry-run runner, with its settings, got {:?}",
+        plan.commands
+    );
+    assert!(
+        plan.archived_directories.iter().any(|dir| dir == "apt"),
+        "The provider should archive its directory"
+    );
+}
+
+#[rstest]
+#[test_log::test]
+fn skip_steps_and_sections() {
+    let mut runner = MockCommandRunner::new();
+    let in_folder = tempfile::tempdir().unwrap();
+    runner
+        .expect_run()
+        .with(command(
+            eq("dpkg"),
+            function(|args: &[String]| args == ["--install", "apt"]),
+            eq(Some(in_folder.path().to_path_buf())),
+        ))
+        .times(1)
+        .returning(|_| Ok(CommandOutput::default()));
+    let mut fixture = InstallFixture::default();
+    fixture.skip.insert("apt config".to_owned());
+    fixture.skip.insert("custom".to_owned());
+    fixture
+        .sections
+        .insert("apt".to_owned(), serde_json::json!({"package": "wget"}));
+    let apt = Apt { section: "apt" };
+
+    let mut context = fixture.context(&runner, in_folder.path());
+    for section in ["apt", "custom"] {
+        if !context.skip_section(section) {
+            apt.install(&mut context);
+        }
+    }
+
+    let steps: Vec<_> = fixture
+        .report
+        .steps
+        .iter()
+        .map(|step| {
+            (
+                step.name.as_str(),
+                matches!(step.status, StepStatus::Skipped),
+            )
+        })
+        .collect();
+    assert_eq!(
+        steps,
+        [
+            ("apt config", true),
+            ("apt install wget", false),
+            ("custom", true)
+        ],
+        "Skipped kinds of steps and sections should be reported as skipped, the others run with their settings"
+    );
+}
@@ -1,24 +1,40 @@
 use std::{
     fs,
+    io::ErrorKind,
     path::{Path, PathBuf},
 };
 
 use clap::ValueEnum;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
-use tracing::{debug, info};
+use tracing::{debug, info, warn};
 
-use crate::{ArchiveBuilder, PIP_DOWNLOAD_DIR, cmd::CommandRunner, python::errors::PythonError};
+use crate::{
+    ArchiveBuilder, PIP_DOWNLOAD_DIR, PackagingError,
+    cmd::{CommandFailedError, CommandRunner, CommandSpec, RetryPolicy},
+    download::Downloads,
+    jobs,
+    progress::Position,
+    provider::{InstallContext, PackageContext, ResourceProvider},
+    python::errors::PythonError,
+    state::{InstallState, PipConfigState},
+    validation::{check_path_exists, errors::SettingsError},
+};
 
 pub mod errors;
 #[cfg(test)]
 mod test;
 
-#[derive(Debug, Default, Deserialize, Serialize)]
+/// Printed by `pip config get` when the key is not set
+const PIP_MISSING_KEY: &str = "No such key";
+
+#[derive(Debug, Default, Deserialize, JsonSchema, Serialize)]
+#[serde(default, deny_unknown_fields)]
 pub struct PythonSettings {
     requirement_files: Vec<PathBuf>,
 }
 
-#[derive(Default, ValueEnum, Clone)]
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize, ValueEnum)]
 pub enum PythonConfigLevel {
     /// Configure at user-level
     #[default]
@@ -27,8 +43,36 @@ pub enum PythonConfigLevel {
     Site,
 }
 
+impl PythonSettings {
+    /// Start building python settings in code, without any requirement file
+    pub fn builder() -> PythonSettingsBuilder {
+        PythonSettingsBuilder::default()
+    }
+}
+
+/// Builds [`PythonSettings`] in code instead of reading them from a settings file
+#[derive(Debug, Default)]
+#[must_use]
+pub struct PythonSettingsBuilder {
+    settings: PythonSettings,
+}
+
+impl PythonSettingsBuilder {
+    /// Download the packages of this requirement file
+    pub fn requirement_file<P: Into<PathBuf>>(mut self, requirement_file: P) -> Self {
+        self.settings
+            .requirement_files
+            .push(requirement_file.into());
+        self
+    }
+
+    pub fn build(self) -> PythonSettings {
+        self.settings
+    }
+}
+
 impl PythonConfigLevel {
-    fn pip_param(&self) -> &'static str {
+    pub(crate) fn pip_param(&self) -> &'static str {
         match self {
             Self::User => "--user",
             Self::Site => "--site",
@@ -37,58 +81,151 @@ impl PythonConfigLevel {
 }
 
 impl PythonSettings {
-    pub(crate) fn package<T: CommandRunner>(
+    /// Every requirement file that doesn't exist
+    pub(crate) fn validate(&self) -> Vec<SettingsError> {
+        self.requirement_files
+            .iter()
+            .enumerate()
+            .filter_map(|(index, requirement_file)| {
+                check_path_exists(
+                    format!("python.requirement_files[{index}]"),
+                    requirement_file,
+                )
+            })
+            .collect()
+    }
+
+    /// Download the packages of every requirement file, several at a time if `jobs` allows it
+    pub(crate) fn download(
         &self,
+        runner: &dyn CommandRunner,
         out_folder: &Path,
-        tar: &mut ArchiveBuilder,
-        skip_download: bool,
+        downloads: &Downloads,
     ) -> Result<(), PythonError> {
-        info!("Packaging pip wheel packages");
+        info!("Downloading pip wheel packages");
         if self.requirement_files.is_empty() {
             debug!("No python package");
             return Ok(());
         }
         let out_folder = out_folder.join(PIP_DOWNLOAD_DIR);
-        fs::create_dir_all(&out_folder)
-            .map_err(|e| PythonError::CreateMainDirectory(out_folder.clone(), e))?;
-
-        if !skip_download {
-            for requirement_file in &self.requirement_files {
-                let cmd = "pip";
-                T::run_cmd(
-                    cmd,
-                    &[
-                        "download".to_owned(),
-                        "-r".to_owned(),
-                        requirement_file.display().to_string(),
-                        "--dest".to_owned(),
-                        out_folder.display().to_string(),
-                    ],
-                    None,
-                )?;
+        if !downloads.dry_run {
+            fs::create_dir_all(&out_folder)
+                .map_err(|e| PythonError::CreateMainDirectory(out_folder.clone(), e))?;
+        }
+        let jobs = &downloads.jobs;
+
+        let requirement_files: Vec<_> = self.requirement_files.iter().enumerate().collect();
+        let errors = jobs
+            .map(&requirement_files, |(index, requirement_file)| {
+                let step = format!("pip download {}", requirement_file.display());
+                let position = Position::new(index.saturating_add(1), requirement_files.len());
+                downloads.step(&step, &[requirement_file], position, || {
+                    Self::download_requirements(
+                        runner,
+                        requirement_file,
+                        &out_folder,
+                        jobs.is_parallel().then_some(*index),
+                        downloads,
+                    )
+                })
+            })
+            .into_iter()
+            .filter_map(Result::err)
+            .collect();
+        jobs::collect_errors(errors, PythonError::RequirementFiles)
+    }
+
+    /// Download the packages of `requirement_file` into `out_folder`,
+    /// through a download directory of its own next to it if `parallel_index` is set, removed even on failure
+    fn download_requirements(
+        runner: &dyn CommandRunner,
+        requirement_file: &Path,
+        out_folder: &Path,
+        parallel_index: Option<usize>,
+        downloads: &Downloads,
+    ) -> Result<(), PythonError> {
+        // Concurrent downloads of the same package into one directory would overwrite each other.
+        // Their directories are outside of the archived one, in case a failed run leaves one behind.
+        let dest_folder = match parallel_index {
+            Some(index) => {
+                out_folder.with_file_name(format!(".{PIP_DOWNLOAD_DIR}-download-{index}"))
             }
+            None => out_folder.to_path_buf(),
+        };
+        let mut args = vec![
+            "download".to_owned(),
+            "-r".to_owned(),
+            requirement_file.display().to_string(),
+            "--dest".to_owned(),
+            dest_folder.display().to_string(),
+        ];
+        if downloads.reuse {
+            // Reuse the packages downloaded by previous runs instead of fetching them again
+            args.extend(["--find-links".to_owned(), out_folder.display().to_string()]);
+        }
+        let res = runner.run(&CommandSpec::new("pip", &args).retry(RetryPolicy::NETWORK));
+        if dest_folder == out_folder || downloads.dry_run {
+            return Ok(res.map(|_| ())?);
+        }
+        match res {
+            Ok(_) => move_downloads(&dest_folder, out_folder),
+            Err(e) => {
+                if let Err(remove_error) = fs::remove_dir_all(&dest_folder)
+                    && remove_error.kind() != ErrorKind::NotFound
+                {
+                    warn!(
+                        "Cannot remove the download directory {}: {remove_error}",
+                        dest_folder.display()
+                    );
+                }
+                Err(e.into())
+            }
+        }
+    }
+
+    /// Append the downloaded packages to `tar`
+    pub(crate) fn archive(
+        &self,
+        out_folder: &Path,
+        tar: &mut ArchiveBuilder,
+    ) -> Result<(), PythonError> {
+        if self.requirement_files.is_empty() {
+            return Ok(());
+        }
+        let out_folder = out_folder.join(PIP_DOWNLOAD_DIR);
+        if !tar.is_dry_run() && !out_folder.is_dir() {
+            tar.recorder()
+                .warning(format!("Nothing downloaded into {}", out_folder.display()));
+            return Ok(());
         }
         tar.append_dir_all(PIP_DOWNLOAD_DIR, &out_folder)
             .map_err(|e| PythonError::Archive {
                 src: out_folder,
                 dst: PIP_DOWNLOAD_DIR.to_owned(),
                 source: e,
-            })?;
-
-        Ok(())
+            })
     }
 
+    /// Keep the current pip configuration in `state` and configure pip to use the extracted packages
+    ///
     /// `in_folder` needs to be a canonicalized path
-    pub(crate) fn install<T: CommandRunner>(
+    pub(crate) fn install_config(
+        runner: &dyn CommandRunner,
         in_folder: &Path,
         python_config_level: &PythonConfigLevel,
+        state: &mut InstallState,
     ) -> Result<(), PythonError> {
         info!(
             "Configuring pip (user-level) to look at those wheel packages and never at the index"
         );
+        state.record_pip_config(PipConfigState {
+            level: python_config_level.clone(),
+            find_links: Self::config_value(runner, python_config_level, "global.find-links")?,
+            no_index: Self::config_value(runner, python_config_level, "global.no-index")?,
+        });
         let in_folder = in_folder.join(PIP_DOWNLOAD_DIR);
         // Tell all pip run with that user to use the extracted folder
-        T::run_cmd(
+        runner.run(&CommandSpec::new(
             "pip",
             &[
                 "config".to_owned(),
@@ -97,10 +234,9 @@ impl PythonSettings {
                 "global.find-links".to_owned(),
                 in_folder.display().to_string(),
             ],
-            None,
-        )?;
+        ))?;
         // Faster if we disable any request to pypi website
-        T::run_cmd(
+        runner.run(&CommandSpec::new(
             "pip",
             &[
                 "config".to_owned(),
@@ -109,8 +245,106 @@ impl PythonSettings {
                 "global.no-index".to_owned(),
                 "true".to_owned(),
             ],
-            None,
-        )?;
+        ))?;
         Ok(())
     }
+
+    /// Set back the pip configuration values replaced by an installation, or unset them if there were none
+    pub(crate) fn restore_config(
+        runner: &dyn CommandRunner,
+        config: &PipConfigState,
+    ) -> Result<(), PythonError> {
+        info!("Restoring pip configuration ({})", config.level.pip_param());
+        for (key, previous) in [
+            ("global.find-links", &config.find_links),
+            ("global.no-index", &config.no_index),
+        ] {
+            let mut args = vec!["config".to_owned(), config.level.pip_param().to_owned()];
+            match previous {
+                Some(previous) => args.extend(["set".to_owned(), key.to_owned(), previous.clone()]),
+                None => args.extend(["unset".to_owned(), key.to_owned()]),
+            }
+            runner.run(&CommandSpec::new("pip", &args))?;
+        }
+        Ok(())
+    }
+
+    /// Current value of a pip configuration `key` at the given level, if set
+    fn config_value(
+        runner: &dyn CommandRunner,
+        python_config_level: &PythonConfigLevel,
+        key: &str,
+    ) -> Result<Option<String>, PythonError> {
+        match runner.run(&CommandSpec::new(
+            "pip",
+            &[
+                "config".to_owned(),
+                python_config_level.pip_param().to_owned(),
+                "get".to_owned(),
+                key.to_owned(),
+            ],
+        )) {
+            Ok(output) => Ok(Some(output.stdout.trim().to_owned())),
+            // pip fails when the key is not set, any other failure (e.g., a broken pip) must not be taken for it
+            Err(e) if matches!(&*e, CommandFailedError::CommandFailed(_, _, tail) if tail.contains(PIP_MISSING_KEY)) => {
+                Ok(None)
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+impl ResourceProvider for PythonSettings {
+    fn section(&self) -> &str {
+        "python"
+    }
+
+    fn describe(&self) -> Vec<String> {
+        self.requirement_files
+            .iter()
+            .map(|requirement_file| format!("python packages of {}", requirement_file.display()))
+            .collect()
+    }
+
+    fn verify(&self) -> Vec<SettingsError> {
+        self.validate()
+    }
+
+    fn package(&self, context: &PackageContext<'_>) -> Result<(), PackagingError> {
+        Ok(self.download(context.runner(), context.directory(), context.downloads)?)
+    }
+
+    fn archive(
+        &self,
+        context: &PackageContext<'_>,
+        archive: &mut ArchiveBuilder,
+    ) -> Result<(), PackagingError> {
+        Ok(self.archive(context.directory(), archive)?)
+    }
+
+    fn install(&self, context: &mut InstallContext<'_>) {
+        context.step(
+            "python config",
+            "python config".to_owned(),
+            Position::SINGLE,
+            |context| {
+                PythonSettings::install_config(
+                    context.runner(),
+                    context.resources_directory(),
+                    &context.options().python_config_level,
+                    context.state,
+                )
+            },
+        );
+    }
+}
+
+/// Move the packages downloaded in `dest_folder` into `out_folder` and remove `dest_folder`
+fn move_downloads(dest_folder: &Path, out_folder: &Path) -> Result<(), PythonError> {
+    let move_error = |e| PythonError::MoveDownloads(dest_folder.to_path_buf(), e);
+    for entry in fs::read_dir(dest_folder).map_err(move_error)? {
+        let entry = entry.map_err(move_error)?;
+        fs::rename(entry.path(), out_folder.join(entry.file_name())).map_err(move_error)?;
+    }
+    fs::remove_dir_all(dest_folder).map_err(move_error)
 }
@@ -2,7 +2,7 @@ use std::path::PathBuf;
 
 use thiserror::Error;
 
-use crate::cmd::CommandFailedError;
+use crate::{cmd::CommandFailedError, download::errors::CheckpointError, errors::format_errors};
 
 #[derive(Error, Debug)]
 pub enum PythonError {
@@ -14,6 +14,12 @@ pub enum PythonError {
     },
     #[error(transparent)]
     CommandFailed(#[from] Box<CommandFailedError>),
+    #[error(transparent)]
+    Checkpoint(#[from] CheckpointError),
     #[error("Cannot create python sub-directory at '{0}': {1}")]
     CreateMainDirectory(PathBuf, #[source] std::io::Error),
+    #[error("Cannot move the packages downloaded in '{0}': {1}")]
+    MoveDownloads(PathBuf, #[source] std::io::Error),
+    #[error("{} requirement files failed: {}", .0.len(), format_errors(.0))]
+    RequirementFiles(Vec<PythonError>),
 }
@@ -1,12 +1,18 @@
 use std::fs::{self, File};
 use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, ExitStatus};
 
+use mockall::predicate::{eq, function};
 use rstest::rstest;
 use tempfile::tempdir;
 
 use crate::{
-    ArchiveBuilder, PIP_DOWNLOAD_DIR, cmd::LocalCommandRunner, python::PythonSettings,
-    test::archive,
+    ArchiveBuilder, PIP_DOWNLOAD_DIR, PythonConfigLevel,
+    cmd::{CommandFailedError, CommandOutput, LocalCommandRunner, MockCommandRunner},
+    download::Downloads,
+    python::PythonSettings,
+    test::{archive, command, parallel_downloads},
 };
 
 #[rstest]
@@ -21,7 +27,14 @@ requirement_files: []
 
     let out_folder = tempdir().unwrap();
     python
-        .package::<LocalCommandRunner>(out_folder.path(), &mut archive, false)
+        .download(
+            &LocalCommandRunner,
+            out_folder.path(),
+            &Downloads::default(),
+        )
+        .expect("Shouldn't fail because there is no listed resources");
+    python
+        .archive(out_folder.path(), &mut archive)
         .expect("Shouldn't fail because there is no listed resources");
     archive.finish().expect("Shouldn't fail to build archive");
 }
@@ -41,8 +54,15 @@ fn package_regular(mut archive: ArchiveBuilder) {
 
     let out_folder = tempdir().unwrap();
     python
-        .package::<LocalCommandRunner>(out_folder.path(), &mut archive, false)
-        .expect("Shouldn't fail to package python resources");
+        .download(
+            &LocalCommandRunner,
+            out_folder.path(),
+            &Downloads::default(),
+        )
+        .expect("Shouldn't fail to download python resources");
+    python
+        .archive(out_folder.path(), &mut archive)
+        .expect("Shouldn't fail to archive python resources");
     archive.finish().expect("Shouldn't fail to build archive");
 
     let download_wheels = fs::read_dir(out_folder.path().join(PIP_DOWNLOAD_DIR))
@@ -60,4 +80,151 @@ fn package_regular(mut archive: ArchiveBuilder) {
     panic!("pre-commit wasn't found in the pip download folder");
 }
 
+#[rstest]
+#[test_log::test]
+fn download_in_parallel(mut archive: ArchiveBuilder) {
+    let mut runner = MockCommandRunner::new();
+    let out_folder = tempdir().unwrap();
+    let pip_folder = out_folder.path().join(PIP_DOWNLOAD_DIR);
+    runner
+        .expect_run()
+        .with(command(
+            eq("pip"),
+            function(|args: &[String]| args[0] == "download"),
+            eq(None),
+        ))
+        .times(2)
+        .returning(|command| {
+            // Each requirement file gets its own download directory, moved afterwards
+            let dest = PathBuf::from(&command.args[4]);
+            fs::create_dir_all(&dest).unwrap();
+            let wheel = if command.args[2].ends_with("a.txt") {
+                "a-1.0-py3-none-any.whl"
+            } else {
+                "b-1.0-py3-none-any.whl"
+            };
+            fs::write(dest.join(wheel), wheel).unwrap();
+            Ok(CommandOutput::default())
+        });
+    let python: PythonSettings = serde_yaml::from_str(
+        "
+requirement_files:
+    - a.txt
+    - b.txt
+",
+    )
+    .unwrap();
+
+    python
+        .download(
+            &runner,
+            out_folder.path(),
+            &parallel_downloads(out_folder.path()),
+        )
+        .expect("Shouldn't fail to download python resources");
+    python
+        .archive(out_folder.path(), &mut archive)
+        .expect("Shouldn't fail to archive python resources");
+
+    let mut downloaded: Vec<_> = fs::read_dir(&pip_folder)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().display().to_string())
+        .collect();
+    downloaded.sort();
+    assert_eq!(
+        downloaded,
+        ["a-1.0-py3-none-any.whl", "b-1.0-py3-none-any.whl"],
+        "Every package should be moved into the pip directory"
+    );
+}
+
+#[rstest]
+#[test_log::test]
+fn failed_parallel_download_leaves_nothing_behind() {
+    let mut runner = MockCommandRunner::new();
+    let out_folder = tempdir().unwrap();
+    runner
+        .expect_run()
+        .with(command(
+            eq("pip"),
+            function(|args: &[String]| args[0] == "download"),
+            eq(None),
+        ))
+        .times(2)
+        .returning(|command| {
+            let dest = PathBuf::from(&command.args[4]);
+            fs::create_dir_all(&dest).unwrap();
+            fs::write(dest.join("partial.whl"), "partial").unwrap();
+            if command.args[2].ends_with("b.txt") {
+                return Err(Box::new(CommandFailedError::CommandFailed(
+                    Command::new("pip"),
+                    ExitStatus::default(),
+                    "connection reset".to_owned(),
+                )));
+            }
+            Ok(CommandOutput::default())
+        });
+    let python: PythonSettings = serde_yaml::from_str(
+        "
+requirement_files:
+    - a.txt
+    - b.txt
+",
+    )
+    .unwrap();
+
+    let res = python.download(
+        &runner,
+        out_folder.path(),
+        &parallel_downloads(out_folder.path()),
+    );
+
+    assert!(res.is_err(), "The failed download should be reported");
+    let left: Vec<_> = fs::read_dir(out_folder.path())
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().display().to_string())
+        .filter(|name| name.contains("download"))
+        .collect();
+    assert!(
+        left.is_empty(),
+        "No download directory should be left behind, got {left:?}"
+    );
+    assert_eq!(
+        fs::read_dir(out_folder.path().join(PIP_DOWNLOAD_DIR))
+            .unwrap()
+            .count(),
+        1,
+        "Only the packages of the successful download should be kept"
+    );
+}
+
 // No tests on install process because impossible to change user-level config location in windows
+
+#[rstest]
+#[case::missing_key("ERROR: No such key - global.find-links", true)]
+#[case::broken_pip("/usr/bin/python3: No module named pip", false)]
+#[test_log::test]
+fn config_value_failures(#[case] tail: &'static str, #[case] unset: bool) {
+    let mut runner = MockCommandRunner::new();
+    runner.expect_run().times(1).returning(move |_| {
+        Err(Box::new(CommandFailedError::CommandFailed(
+            Command::new("pip"),
+            ExitStatus::default(),
+            tail.to_owned(),
+        )))
+    });
+
+    let res = PythonSettings::config_value(&runner, &PythonConfigLevel::User, "global.find-links");
+
+    if unset {
+        assert!(
+            matches!(res, Ok(None)),
+            "A missing key should be reported as unset, got {res:?}"
+        );
+    } else {
+        assert!(
+            res.is_err(),
+            "Other failures shouldn't be taken for an unset key, got {res:?}"
+        );
+    }
+}
@@ -0,0 +1,387 @@
+use std::{
+    fmt::{self, Display},
+    fs, io, mem,
+    path::{Path, PathBuf},
+    process::ExitStatus,
+    sync::{Arc, Mutex, PoisonError},
+    time::Duration,
+};
+
+use serde::Serialize;
+use similar::TextDiff;
+use tracing::{error, info, warn};
+
+use crate::InstallingError;
+
+#[cfg(test)]
+mod test;
+
+/// What happened during a packaging or installation run, written as JSON
+#[derive(Debug, Default, Serialize)]
+pub(crate) struct RunReport {
+    pub(crate) commands: Vec<CommandRecord>,
+    pub(crate) archived_directories: Vec<ArchivedDirectory>,
+    pub(crate) skipped_steps: Vec<String>,
+    pub(crate) warnings: Vec<String>,
+    /// Outcome of every install step, empty when packaging
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub(crate) install_steps: Vec<StepSummary>,
+    /// Config files that would be changed, only filled in by dry runs
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub(crate) config_changes: Vec<ConfigChange>,
+    pub(crate) outcome: Outcome,
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct CommandRecord {
+    pub(crate) command: String,
+    pub(crate) args: Vec<String>,
+    pub(crate) cwd: Option<PathBuf>,
+    pub(crate) duration_ms: u128,
+    /// Exit code of the command, unset if it could not start or was killed by a signal
+    pub(crate) exit_code: Option<i32>,
+    pub(crate) success: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct ArchivedDirectory {
+    /// Path relative to the root of the archive
+    pub(crate) path: String,
+    /// Total size in bytes of the files in the directory
+    pub(crate) size: u64,
+}
+
+/// Change of a config file proposed by a dry run
+#[derive(Debug, Serialize)]
+pub struct ConfigChange {
+    pub path: PathBuf,
+    /// Unified diff between the current and the proposed content
+    pub diff: String,
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct StepSummary {
+    pub(crate) name: String,
+    pub(crate) status: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) error: Option<String>,
+}
+
+#[derive(Debug, Default, Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub(crate) enum Outcome {
+    #[default]
+    Succeeded,
+    Failed {
+        error: String,
+    },
+}
+
+/// Report of a run, shared by everything taking part in it: warnings and skipped steps are recorded from deep
+/// within every section, and commands by [`crate::cmd::RecordingRunner`].
+///
+/// The default recorder keeps nothing, for runs without report.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Recorder(Option<Arc<Mutex<RunReport>>>);
+
+impl Recorder {
+    /// Start filling in a new report
+    pub(crate) fn new() -> Self {
+        Self(Some(Arc::default()))
+    }
+
+    /// Write the report at `path` as JSON, with `outcome` as the final outcome
+    pub(crate) fn finish(
+        &self,
+        path: &Path,
+        outcome: Outcome,
+        install_report: Option<&InstallReport>,
+    ) -> io::Result<()> {
+        let mut report = self.take();
+        report.outcome = outcome;
+        if let Some(install_report) = install_report {
+            report.install_steps = install_report.steps.iter().map(StepSummary::from).collect();
+        }
+        let content = serde_json::to_vec_pretty(&report).map_err(io::Error::other)?;
+        fs::write(path, content)?;
+        info!("Report written to {}", path.display());
+        Ok(())
+    }
+
+    /// What was recorded so far, leaving the report empty
+    pub(crate) fn take(&self) -> RunReport {
+        self.0
+            .as_ref()
+            .map(|report| mem::take(&mut *report.lock().unwrap_or_else(PoisonError::into_inner)))
+            .unwrap_or_default()
+    }
+
+    /// Update the report, if a report was requested
+    fn record<F: FnOnce(&mut RunReport)>(&self, update: F) {
+        if let Some(report) = &self.0 {
+            update(&mut report.lock().unwrap_or_else(PoisonError::into_inner));
+        }
+    }
+
+    pub(crate) fn record_command(
+        &self,
+        command: &str,
+        args: &[String],
+        cwd: Option<PathBuf>,
+        duration: Duration,
+        status: Option<ExitStatus>,
+    ) {
+        self.record(|report| {
+            report.commands.push(CommandRecord {
+                command: command.to_owned(),
+                args: args.to_vec(),
+                cwd,
+                duration_ms: duration.as_millis(),
+                exit_code: status.and_then(|status| status.code()),
+                success: status.is_some_and(|status| status.success()),
+            });
+        });
+    }
+
+    pub(crate) fn record_archived_directory(&self, path: String, size: u64) {
+        self.record(|report| {
+            report
+                .archived_directories
+                .push(ArchivedDirectory { path, size });
+        });
+    }
+
+    pub(crate) fn record_skipped_step(&self, name: &str) {
+        self.record(|report| report.skipped_steps.push(name.to_owned()));
+    }
+
+    /// Keep the change from `old` to `new` that a dry run would make to the config file at `path`
+    pub(crate) fn record_config_change(&self, path: &Path, old: &str, new: &str) {
+        let header = path.display().to_string();
+        let diff = TextDiff::from_lines(old, new)
+            .unified_diff()
+            .header(&header, &header)
+            .to_string();
+        info!("Would change {header}:\n{diff}");
+        self.record(|report| {
+            report.config_changes.push(ConfigChange {
+                path: path.to_path_buf(),
+                diff,
+            });
+        });
+    }
+
+    /// Log a warning and keep it in the report
+    pub(crate) fn warning(&self, message: String) {
+        warn!("{message}");
+        self.record(|report| report.warnings.push(message));
+    }
+}
+
+/// Outcome of every install step, in the order they were run
+#[derive(Debug, Default)]
+pub struct InstallReport {
+    pub steps: Vec<StepReport>,
+    /// State file to give to [`crate::uninstall`] to undo the configuration changes, unset on a dry run
+    pub state_path: Option<PathBuf>,
+}
+
+#[derive(Debug)]
+pub struct StepReport {
+    /// Description of the step, e.g., "rust config" or "git push <mirror>"
+    pub name: String,
+    pub status: StepStatus,
+}
+
+#[derive(Debug)]
+pub enum StepStatus {
+    Succeeded,
+    /// Skipped on user request
+    Skipped,
+    Failed(InstallingError),
+}
+
+impl InstallReport {
+    /// Record the outcome of the step `name`
+    pub(crate) fn record<E: Into<InstallingError>>(&mut self, name: String, res: Result<(), E>) {
+        let status = match res {
+            Ok(()) => StepStatus::Succeeded,
+            Err(e) => {
+                let e = e.into();
+                error!("Step '{name}' failed: {e}");
+                StepStatus::Failed(e)
+            }
+        };
+        self.steps.push(StepReport { name, status });
+    }
+
+    /// Record that the step `name` was skipped
+    pub(crate) fn skip(&mut self, name: String) {
+        info!("Skipping step '{name}'");
+        self.steps.push(StepReport {
+            name,
+            status: StepStatus::Skipped,
+        });
+    }
+
+    /// Steps that failed, along with their error
+    pub fn failures(&self) -> impl Iterator<Item = (&str, &InstallingError)> {
+        self.steps.iter().filter_map(|step| match &step.status {
+            StepStatus::Failed(e) => Some((step.name.as_str(), e)),
+            StepStatus::Succeeded | StepStatus::Skipped => None,
+        })
+    }
+
+    /// Whether no step failed
+    pub fn is_success(&self) -> bool {
+        self.failures().next().is_none()
+    }
+}
+
+impl Display for StepStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Succeeded => write!(f, "succeeded"),
+            Self::Skipped => write!(f, "skipped"),
+            Self::Failed(e) => write!(f, "failed: {e}"),
+        }
+    }
+}
+
+impl Display for InstallReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let width = self
+            .steps
+            .iter()
+            .map(|step| step.name.len())
+            .chain(["Step".len()])
+            .max()
+            .unwrap_or_default();
+        writeln!(f, "{:width$}  Status", "Step")?;
+        for step in &self.steps {
+            writeln!(f, "{:width$}  {}", step.name, step.status)?;
+        }
+        Ok(())
+    }
+}
+
+/// What a dry run would do, in order
+#[derive(Debug, Default, Serialize)]
+pub struct Plan {
+    pub commands: Vec<PlannedCommand>,
+    /// Directories that would be archived, relative to the root of the archive
+    pub archived_directories: Vec<String>,
+    pub config_changes: Vec<ConfigChange>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PlannedCommand {
+    pub command: String,
+    pub args: Vec<String>,
+    pub cwd: Option<PathBuf>,
+}
+
+impl Plan {
+    /// Point every path under `from` to the same path under `to`,
+    /// e.g., when the archive was unpacked in a staging directory instead of its final location
+    pub(crate) fn relocate(&mut self, from: &Path, to: &Path) {
+        let (from_str, to_str) = (from.display().to_string(), to.display().to_string());
+        for command in &mut self.commands {
+            for arg in &mut command.args {
+                *arg = arg.replace(&from_str, &to_str);
+            }
+            if let Some(cwd) = &mut command.cwd
+                && let Ok(relative_cwd) = cwd.strip_prefix(from)
+            {
+                *cwd = if relative_cwd == Path::new("") {
+                    to.to_path_buf()
+                } else {
+                    to.join(relative_cwd)
+                };
+            }
+        }
+        for change in &mut self.config_changes {
+            change.diff = change.diff.replace(&from_str, &to_str);
+        }
+    }
+}
+
+impl From<RunReport> for Plan {
+    fn from(report: RunReport) -> Self {
+        Self {
+            commands: report
+                .commands
+                .into_iter()
+                .map(|command| PlannedCommand {
+                    command: command.command,
+                    args: command.args,
+                    cwd: command.cwd,
+                })
+                .collect(),
+            archived_directories: report
+                .archived_directories
+                .into_iter()
+                .map(|directory| directory.path)
+                .collect(),
+            config_changes: report.config_changes,
+        }
+    }
+}
+
+impl Display for PlannedCommand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.command)?;
+        for arg in &self.args {
+            write!(f, " {arg}")?;
+        }
+        if let Some(cwd) = &self.cwd {
+            write!(f, "  (in {})", cwd.display())?;
+        }
+        Ok(())
+    }
+}
+
+impl Display for Plan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.commands.is_empty()
+            && self.archived_directories.is_empty()
+            && self.config_changes.is_empty()
+        {
+            return writeln!(f, "Nothing to do");
+        }
+        if !self.commands.is_empty() {
+            writeln!(f, "Commands to run:")?;
+            for command in &self.commands {
+                writeln!(f, "  {command}")?;
+            }
+        }
+        if !self.archived_directories.is_empty() {
+            writeln!(f, "Directories to archive:")?;
+            for directory in &self.archived_directories {
+                writeln!(f, "  {directory}")?;
+            }
+        }
+        if !self.config_changes.is_empty() {
+            writeln!(f, "Config files to change:")?;
+            for change in &self.config_changes {
+                write!(f, "{}", change.diff)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl From<&StepReport> for StepSummary {
+    fn from(step: &StepReport) -> Self {
+        let (status, error) = match &step.status {
+            StepStatus::Succeeded => ("succeeded", None),
+            StepStatus::Skipped => ("skipped", None),
+            StepStatus::Failed(e) => ("failed", Some(e.to_string())),
+        };
+        Self {
+            name: step.name.clone(),
+            status,
+            error,
+        }
+    }
+}
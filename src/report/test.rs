@@ -0,0 +1,251 @@
+use std::fs::{self, File, create_dir_all};
+use std::path::{Path, PathBuf};
+use std::thread;
+
+use mockall::predicate::{eq, function};
+use rstest::rstest;
+use tempfile::tempdir;
+use url::Url;
+
+use crate::cmd::{
+    CommandOutput, CommandRunner, CommandSpec, LocalCommandRunner, MockCommandRunner,
+    RecordingRunner,
+};
+use crate::report::{InstallReport, Outcome, Recorder, StepStatus};
+use crate::test::{archive, command};
+use crate::{
+    ArchiveBuilder, GitMirrors, InstallingError, PackageOptions, Settings, package_with_runner,
+    plan_package,
+};
+
+#[rstest]
+#[test_log::test]
+fn report_every_step() {
+    let mut report = InstallReport::default();
+    report.record::<InstallingError>("rust config".to_owned(), Ok(()));
+    report.skip("rust tools".to_owned());
+    report.record(
+        "python config".to_owned(),
+        Err(InstallingError::DeserializeConfig("invalid".to_owned())),
+    );
+    report.record::<InstallingError>("git push".to_owned(), Ok(()));
+
+    assert!(!report.is_success(), "A failed step should fail the report");
+    assert!(
+        matches!(report.steps[1].status, StepStatus::Skipped),
+        "Skipped step should be reported"
+    );
+    assert_eq!(
+        report.to_string(),
+        "Step           Status
+rust config    succeeded
+rust tools     skipped
+python config  failed: Cannot deserialize config: invalid
+git push       succeeded
+",
+        "Every step should be listed in order"
+    );
+}
+
+#[rstest]
+#[test_log::test]
+fn write_run_report(archive: ArchiveBuilder) {
+    let recorder = Recorder::new();
+    RecordingRunner::new(&LocalCommandRunner, false, recorder.clone())
+        .run(&CommandSpec::new("cargo", ["--version"]))
+        .expect("cargo should run");
+    let in_folder = tempdir().unwrap();
+    create_dir_all(in_folder.path().join("sub")).unwrap();
+    fs::write(in_folder.path().join("sub").join("file"), "content").unwrap();
+    File::create(in_folder.path().join("empty")).unwrap();
+    let mut archive = archive.with_recorder(recorder.clone());
+    archive.append_dir_all("dir", in_folder.path()).unwrap();
+    recorder.warning("something odd".to_owned());
+    recorder.record_skipped_step("git push");
+    let mut install_report = InstallReport::default();
+    install_report.skip("git push".to_owned());
+
+    let report_path = in_folder.path().join("report.json");
+    recorder
+        .finish(
+            &report_path,
+            Outcome::Failed {
+                error: "failure".to_owned(),
+            },
+            Some(&install_report),
+        )
+        .expect("Report should be written");
+
+    let report: serde_json::Value =
+        serde_json::from_slice(&fs::read(&report_path).unwrap()).unwrap();
+    let commands = report["commands"].as_array().unwrap();
+    assert!(
+        matches!(commands.as_slice(), [command] if command["command"] == "cargo"
+            && command["args"] == serde_json::json!(["--version"])
+            && command["exit_code"] == 0
+            && command["success"] == true),
+        "Command should be reported, got {commands:?}"
+    );
+    assert_eq!(
+        report["archived_directories"],
+        serde_json::json!([{"path": "dir", "size": 7}]),
+        "Archived directory should be reported with its size"
+    );
+    assert_eq!(
+        report["warnings"],
+        serde_json::json!(["something odd"]),
+        "Warning should be reported"
+    );
+    assert_eq!(
+        report["skipped_steps"],
+        serde_json::json!(["git push"]),
+        "Skipped step should be reported"
+    );
+    assert_eq!(
+        report["install_steps"],
+        serde_json::json!([{"name": "git push", "status": "skipped"}]),
+        "Install steps should be reported"
+    );
+    assert_eq!(
+        report["outcome"],
+        serde_json::json!({"status": "failed", "error": "failure"}),
+        "Outcome should be reported"
+    );
+}
+
+#[rstest]
+#[test_log::test]
+fn plan_packaging() {
+    let custom_folder = tempdir().unwrap();
+    let settings: Settings = serde_yaml::from_str(&format!(
+        "
+name: dry-run-plan
+rust:
+    manifests: []
+    binaries: []
+    use_binstall: false
+python:
+    requirement_files: []
+git_mirrors:
+    mirrors:
+        - src: https://example.com/repo.git
+          dst: file:///srv/repo.git
+custom:
+    tasks:
+        - paths_to_package:
+            {}: extras
+          install_command: null
+          install_counts: Once
+",
+        custom_folder.path().display()
+    ))
+    .unwrap();
+
+    let plan = plan_package(&settings, &PackageOptions::default()).expect("Planning should work");
+
+    assert!(
+        !Path::new("dry-run-plan").exists() && !Path::new("dry-run-plan.tar.gz").exists(),
+        "Nothing should be written by a dry run"
+    );
+    let clone = format!(
+        "git clone --mirror https://example.com/repo.git repo.git  (in {})",
+        std::path::absolute("dry-run-plan/mirrors")
+            .unwrap()
+            .display()
+    );
+    assert_eq!(
+        plan.commands
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>(),
+        [clone],
+        "Only the clone command should be planned"
+    );
+    for directory in ["mirrors/repo.git", "extras"] {
+        assert!(
+            plan.archived_directories.iter().any(|dir| dir == directory),
+            "{directory} should be planned for archiving"
+        );
+    }
+    assert!(
+        plan.config_changes.is_empty() && !plan.to_string().contains("Config files to change"),
+        "Packaging shouldn't change any config file"
+    );
+}
+
+#[rstest]
+#[test_log::test]
+fn concurrent_runs_keep_their_own_report() {
+    let folder = tempdir().unwrap();
+    let package = |name: &str| {
+        let settings = Settings {
+            name: name.to_owned(),
+            git_mirrors: GitMirrors::builder()
+                .mirror(
+                    Url::parse(&format!("https://example.com/{name}.git")).unwrap(),
+                    Url::parse(&format!("https://private.example.com/{name}.git")).unwrap(),
+                )
+                .build(),
+            ..Settings::default()
+        };
+        let mut runner = MockCommandRunner::new();
+        runner
+            .expect_run()
+            .with(command(
+                eq("git"),
+                function(|args: &[String]| args[0] == "clone"),
+                function(|_: &Option<PathBuf>| true),
+            ))
+            .times(1)
+            .returning(|command| {
+                create_dir_all(command.cwd.as_ref().unwrap().join(&command.args[3])).unwrap();
+                Ok(CommandOutput::default())
+            });
+        runner
+            .expect_run()
+            .with(command(
+                eq("git"),
+                function(|args: &[String]| args[0] == "for-each-ref"),
+                function(|_: &Option<PathBuf>| true),
+            ))
+            .times(1)
+            .returning(|_| Ok(CommandOutput::default()));
+        let report_path = folder.path().join(format!("{name}.json"));
+        let options = PackageOptions {
+            staging_dir: Some(folder.path().join(name)),
+            output_dir: Some(folder.path().join("out")),
+            report: Some(report_path.clone()),
+            ..PackageOptions::default()
+        };
+        package_with_runner(&settings, &options, &runner).expect("Packaging should work");
+        let report: serde_json::Value =
+            serde_json::from_slice(&fs::read(&report_path).unwrap()).unwrap();
+        report["commands"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .filter(|command| command["args"][0] == "clone")
+            .map(|command| command["args"][2].clone())
+            .collect::<Vec<_>>()
+    };
+
+    let reported = thread::scope(|scope| {
+        let handles: Vec<_> = ["first", "second"]
+            .map(|name| scope.spawn(move || package(name)))
+            .into_iter()
+            .collect();
+        handles
+            .into_iter()
+            .map(|handle| handle.join().unwrap())
+            .collect::<Vec<_>>()
+    });
+
+    assert_eq!(
+        reported,
+        [
+            [serde_json::json!("https://example.com/first.git")],
+            [serde_json::json!("https://example.com/second.git")]
+        ],
+        "Each report should only list the clone of its own run"
+    );
+}
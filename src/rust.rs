@@ -1,11 +1,12 @@
 use std::{
     fs::{self, copy},
+    io::ErrorKind,
     path::{Path, PathBuf},
-    process::Command,
 };
 
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
-use toml_edit::{DocumentMut, Item, Table, value};
+use toml_edit::{DocumentMut, Item, Table, TableLike, value};
 use tracing::{debug, info};
 
 pub mod errors;
@@ -13,12 +14,19 @@ pub mod errors;
 mod test;
 
 use crate::{
-    ArchiveBuilder, CARGO_TOOLS_PATH, CARGO_VENDOR_PATH, InstallSkip,
-    cmd::{CommandFailedError, CommandRunner},
+    ArchiveBuilder, CARGO_TOOLS_PATH, CARGO_VENDOR_PATH, PackagingError,
+    cmd::{CommandRunner, CommandSpec, RetryPolicy},
+    download::Downloads,
+    progress::Position,
+    provider::{InstallContext, PackageContext, ResourceProvider},
+    report::Recorder,
     rust::errors::{CargoHomeError, RustError, RustupToolchainError},
+    state::{CargoConfigState, InstallState},
+    validation::{check_path_exists, errors::SettingsError},
 };
 
-#[derive(Debug, Default, Deserialize, Serialize)]
+#[derive(Debug, Deserialize, JsonSchema, Serialize)]
+#[serde(default, deny_unknown_fields)]
 pub struct RustSettings {
     manifests: Vec<PathBuf>,
     binaries: Vec<String>,
@@ -27,71 +35,121 @@ pub struct RustSettings {
     use_binstall: bool,
 }
 
+impl Default for RustSettings {
+    fn default() -> Self {
+        Self {
+            manifests: Vec::new(),
+            binaries: Vec::new(),
+            use_binstall: true,
+        }
+    }
+}
+
 impl RustSettings {
-    fn package_crates<T: CommandRunner>(
+    /// Start building rust settings in code, without any crate or cargo tool and with binstall
+    pub fn builder() -> RustSettingsBuilder {
+        RustSettingsBuilder::default()
+    }
+}
+
+/// Builds [`RustSettings`] in code instead of reading them from a settings file
+#[derive(Debug, Default)]
+#[must_use]
+pub struct RustSettingsBuilder {
+    settings: RustSettings,
+}
+
+impl RustSettingsBuilder {
+    /// Vendor the dependencies of the crate or workspace of this `Cargo.toml`
+    pub fn manifest<P: Into<PathBuf>>(mut self, manifest: P) -> Self {
+        self.settings.manifests.push(manifest.into());
+        self
+    }
+
+    /// Package this cargo tool, e.g., "cargo-audit"
+    pub fn binary<S: Into<String>>(mut self, binary: S) -> Self {
+        self.settings.binaries.push(binary.into());
+        self
+    }
+
+    /// Whether the cargo tools are packaged with "cargo binstall" instead of "cargo install", `true` by default
+    pub fn use_binstall(mut self, use_binstall: bool) -> Self {
+        self.settings.use_binstall = use_binstall;
+        self
+    }
+
+    pub fn build(self) -> RustSettings {
+        self.settings
+    }
+}
+
+impl RustSettings {
+    /// Every manifest that doesn't exist
+    pub(crate) fn validate(&self) -> Vec<SettingsError> {
+        self.manifests
+            .iter()
+            .enumerate()
+            .filter_map(|(index, manifest)| {
+                check_path_exists(format!("rust.manifests[{index}]"), manifest)
+            })
+            .collect()
+    }
+
+    fn download_crates(
         &self,
+        runner: &dyn CommandRunner,
         out_folder: &Path,
-        tar: &mut ArchiveBuilder,
-        skip_download: bool,
+        dry_run: bool,
     ) -> Result<(), RustError> {
-        info!("Packaging rust crates");
+        info!("Vendoring rust crates");
         if self.manifests.is_empty() {
             debug!("No crate to package");
             return Ok(());
         }
         let out_folder = out_folder.join(CARGO_VENDOR_PATH);
 
-        if skip_download {
-            fs::create_dir_all(&out_folder)
-                .map_err(|e| RustError::CreateMainDirectory(out_folder.clone(), e))?;
-        } else {
-            let cmd = "cargo";
-            let mut args = vec![
-                "vendor".to_owned(),
-                "--versioned-dirs".to_owned(),
-                "--respect-source-config".to_owned(),
-            ];
-            for manifest in &self.manifests {
-                // If the current directory is a workspace, using relative paths makes cargo fail:
-                // It checks whether any relative path is part of the workspace and only allow it if it does.
-                let manifest = manifest.canonicalize().map_err(RustError::ManifestPath)?;
-                args.push("--sync".to_owned());
-                args.push(manifest.display().to_string());
-            }
-            args.push(out_folder.display().to_string());
-            T::run_cmd(cmd, &args, None)?;
-            Self::package_std_deps::<T>(&out_folder)?;
+        let cmd = "cargo";
+        let mut args = vec![
+            "vendor".to_owned(),
+            "--versioned-dirs".to_owned(),
+            "--respect-source-config".to_owned(),
+        ];
+        for manifest in &self.manifests {
+            // If the current directory is a workspace, using relative paths makes cargo fail:
+            // It checks whether any relative path is part of the workspace and only allow it if it does.
+            let manifest = manifest.canonicalize().map_err(RustError::ManifestPath)?;
+            args.push("--sync".to_owned());
+            args.push(manifest.display().to_string());
         }
-        tar.append_dir_all(CARGO_VENDOR_PATH, &out_folder)
-            .map_err(|e| RustError::Archive {
-                src: out_folder,
-                dst: CARGO_VENDOR_PATH.to_owned(),
-                source: e,
-            })?;
-
+        args.push(out_folder.display().to_string());
+        runner.run(&CommandSpec::new(cmd, &args).retry(RetryPolicy::NETWORK))?;
+        Self::package_std_deps(runner, &out_folder, dry_run)?;
         Ok(())
     }
 
-    fn package_std_deps<T: CommandRunner>(out_folder: &Path) -> Result<(), RustupToolchainError> {
+    /// Vendor the dependencies of the rust library crates.
+    ///
+    /// On a dry run, the rust-src component might not be installed yet: its absence is not an error.
+    fn package_std_deps(
+        runner: &dyn CommandRunner,
+        out_folder: &Path,
+        dry_run: bool,
+    ) -> Result<(), RustupToolchainError> {
         // Get toolchain path from rustc
-        let mut cmd = Command::new("rustc");
-        cmd.args(&["--print".to_owned(), "sysroot".to_owned()]);
-
-        let output = cmd
-            .output()
-            .map_err(|e| Box::new(CommandFailedError::CommandStart(cmd, e)))?;
-        let toolchain_path = String::from_utf8_lossy(&output.stdout).replace(['\n', '\r'], "");
-        let toolchain_path = PathBuf::from(toolchain_path);
+        let output = runner.run(&CommandSpec::new("rustc", ["--print", "sysroot"]))?;
+        let toolchain_path = PathBuf::from(output.stdout.replace(['\n', '\r'], ""));
 
         // Get rust-src component if absent
-        T::run_cmd(
-            "rustup",
-            &[
-                "component".to_owned(),
-                "add".to_owned(),
-                "rust-src".to_owned(),
-            ],
-            None,
+        runner.run(
+            &CommandSpec::new(
+                "rustup",
+                &[
+                    "component".to_owned(),
+                    "add".to_owned(),
+                    "rust-src".to_owned(),
+                ],
+            )
+            .retry(RetryPolicy::NETWORK),
         )?;
 
         // Get to the lengthy sub-path where the rust lib sources are
@@ -101,6 +159,13 @@ impl RustSettings {
             .join("src")
             .join("rust")
             .join("library");
+        if dry_run && !toolchain_path.is_dir() {
+            info!(
+                "Would vendor the dependencies of the rust library crates in {}",
+                toolchain_path.display()
+            );
+            return Ok(());
+        }
 
         let lib_src_paths = fs::read_dir(&toolchain_path)
             .map_err(|e| RustupToolchainError::ReadToolchainDirectory(toolchain_path, e))?;
@@ -113,21 +178,23 @@ impl RustSettings {
             let src = src.path().join("Cargo.toml");
             if src.exists() {
                 info!("Vendoring {}", src.display());
-                T::run_cmd(
-                    "cargo",
-                    &[
-                        // Nightly version required because std crate use public deps
-                        // which is an unstable feature.
-                        "+nightly".to_owned(),
-                        "vendor".to_owned(),
-                        "--versioned-dirs".to_owned(),
-                        "--respect-source-config".to_owned(),
-                        "--no-delete".to_owned(),
-                        "--sync".to_owned(),
-                        src.display().to_string(),
-                        out_folder.display().to_string(),
-                    ],
-                    None,
+                runner.run(
+                    &CommandSpec::new(
+                        "cargo",
+                        &[
+                            // Nightly version required because std crate use public deps
+                            // which is an unstable feature.
+                            "+nightly".to_owned(),
+                            "vendor".to_owned(),
+                            "--versioned-dirs".to_owned(),
+                            "--respect-source-config".to_owned(),
+                            "--no-delete".to_owned(),
+                            "--sync".to_owned(),
+                            src.display().to_string(),
+                            out_folder.display().to_string(),
+                        ],
+                    )
+                    .retry(RetryPolicy::NETWORK),
                 )?;
             }
         }
@@ -135,95 +202,147 @@ impl RustSettings {
         Ok(())
     }
 
-    fn package_tools<T: CommandRunner>(
+    fn download_tools(
         &self,
+        runner: &dyn CommandRunner,
         out_folder: &Path,
-        tar: &mut ArchiveBuilder,
-        skip_download: bool,
+        dry_run: bool,
     ) -> Result<(), RustError> {
-        info!("Packaging cargo tools");
+        info!("Installing cargo tools to package");
         if self.binaries.is_empty() {
             debug!("No cargo tool to package");
             return Ok(());
         }
 
         let out_folder = out_folder.join(CARGO_TOOLS_PATH);
-        fs::create_dir_all(&out_folder)
-            .map_err(|e| RustError::CreateMainDirectory(out_folder.clone(), e))?;
-
-        if !skip_download {
-            let cmd = "cargo";
-            let mut args = if self.use_binstall {
-                vec![
-                    "binstall".to_owned(),
-                    "--disable-telemetry".to_owned(),
-                    "--no-confirm".to_owned(),
-                ]
-            } else {
-                vec!["install".to_owned()]
-            };
-            args.extend([
-                "--root".to_owned(),
-                out_folder.display().to_string(),
-                "--locked".to_owned(),
-            ]);
-            for binary in &self.binaries {
-                args.push(binary.clone());
-            }
-            T::run_cmd(cmd, &args, None)?;
+        if !dry_run {
+            fs::create_dir_all(&out_folder)
+                .map_err(|e| RustError::CreateMainDirectory(out_folder.clone(), e))?;
         }
-        tar.append_dir_all(CARGO_TOOLS_PATH, &out_folder)
-            .map_err(|e| RustError::Archive {
-                src: out_folder,
-                dst: CARGO_TOOLS_PATH.to_owned(),
-                source: e,
-            })?;
 
+        let cmd = "cargo";
+        let mut args = if self.use_binstall {
+            vec![
+                "binstall".to_owned(),
+                "--disable-telemetry".to_owned(),
+                "--no-confirm".to_owned(),
+            ]
+        } else {
+            vec!["install".to_owned()]
+        };
+        args.extend([
+            "--root".to_owned(),
+            out_folder.display().to_string(),
+            "--locked".to_owned(),
+        ]);
+        for binary in &self.binaries {
+            args.push(binary.clone());
+        }
+        runner.run(&CommandSpec::new(cmd, &args))?;
         Ok(())
     }
 
-    pub(crate) fn package<T: CommandRunner>(
+    /// Vendor the crates and install the cargo tools into `out_folder`
+    pub(crate) fn download(
+        &self,
+        runner: &dyn CommandRunner,
+        out_folder: &Path,
+        downloads: &Downloads,
+    ) -> Result<(), RustError> {
+        // Vendoring again is needed whenever a manifest or its lock file changes
+        let lock_files: Vec<PathBuf> = self
+            .manifests
+            .iter()
+            .map(PathBuf::as_path)
+            .filter_map(lock_file)
+            .collect();
+        let inputs: Vec<&Path> = self
+            .manifests
+            .iter()
+            .chain(&lock_files)
+            .map(PathBuf::as_path)
+            .collect();
+        downloads.step("rust crates", &inputs, Position::new(1, 2), || {
+            self.download_crates(runner, out_folder, downloads.dry_run)
+        })?;
+        // The tools only depend on the settings, already checked when resuming
+        downloads.step("rust tools", &[], Position::new(2, 2), || {
+            self.download_tools(runner, out_folder, downloads.dry_run)
+        })
+    }
+
+    /// Append the vendored crates and the cargo tools to `tar`
+    pub(crate) fn archive(
         &self,
         out_folder: &Path,
         tar: &mut ArchiveBuilder,
-        skip_download: bool,
     ) -> Result<(), RustError> {
-        self.package_crates::<T>(out_folder, tar, skip_download)?;
-        self.package_tools::<T>(out_folder, tar, skip_download)
+        for (is_empty, path) in [
+            (self.manifests.is_empty(), CARGO_VENDOR_PATH),
+            (self.binaries.is_empty(), CARGO_TOOLS_PATH),
+        ] {
+            if is_empty {
+                continue;
+            }
+            let src = out_folder.join(path);
+            if !tar.is_dry_run() && !src.is_dir() {
+                tar.recorder()
+                    .warning(format!("Nothing downloaded into {}", src.display()));
+                continue;
+            }
+            tar.append_dir_all(path, &src)
+                .map_err(|e| RustError::Archive {
+                    src,
+                    dst: path.to_owned(),
+                    source: e,
+                })?;
+        }
+        Ok(())
     }
 
+    /// Point cargo to the vendored crates, either in the user-level config or in the config of `rust_config_for`.
+    /// The replaced values are kept in `state`. On a dry run, the change is only recorded with `recorder`.
+    ///
     /// `in_folder` needs to be a canonicalized path
-    pub(crate) fn install(
+    pub(crate) fn install_config(
         in_folder: &Path,
         rust_config_for: Option<&PathBuf>,
-        skip: &[InstallSkip],
+        dry_run: bool,
+        state: &mut InstallState,
+        recorder: &Recorder,
     ) -> Result<(), RustError> {
-        let cargo_home = PathBuf::from(
-            std::env::var("CARGO_HOME")
-                .or(std::env::var("HOME").map(|home| format!("{home}/.cargo")))
-                .map_err(CargoHomeError::NoCargoHome)?,
-        );
-
         // Potentially restrict the cargo configuration to a given path, instead of the whole user.
         let config_folder = if let Some(rust_config_for) = rust_config_for {
             let rust_config = rust_config_for.join(".cargo");
-            fs::create_dir_all(&rust_config)
-                .map_err(|e| RustError::CreateMainDirectory(rust_config.clone(), e))?;
+            if !dry_run {
+                fs::create_dir_all(&rust_config)
+                    .map_err(|e| RustError::CreateMainDirectory(rust_config.clone(), e))?;
+            }
             rust_config
         } else {
-            cargo_home.clone()
+            cargo_home()?
         };
 
-        if !skip.contains(&InstallSkip::RustConfig) {
-            info!("Update cargo config to use vendored resources");
-            Self::update_cargo_config(
-                &config_folder.join("config.toml"),
-                &in_folder.join(CARGO_VENDOR_PATH),
-            )?;
-        }
-        if skip.contains(&InstallSkip::RustTools) {
-            return Ok(());
-        }
+        info!("Update cargo config to use vendored resources");
+        Self::update_cargo_config(
+            &config_folder.join("config.toml"),
+            &in_folder.join(CARGO_VENDOR_PATH),
+            dry_run,
+            state,
+            recorder,
+        )
+    }
+
+    /// Copy the packaged tool binaries to `${CARGO_HOME}/bin`, or only log them on a dry run.
+    /// The binaries that did not exist before are kept in `state`.
+    ///
+    /// `in_folder` needs to be a canonicalized path
+    pub(crate) fn install_tools(
+        in_folder: &Path,
+        dry_run: bool,
+        state: &mut InstallState,
+    ) -> Result<(), RustError> {
+        let cargo_home = cargo_home()?;
 
         info!("Installing cargo tools");
         let tools_in_folder = in_folder.join(CARGO_TOOLS_PATH).join("bin");
@@ -251,21 +370,91 @@ impl RustSettings {
 
             let base_name = src.file_name().display().to_string();
             let dst_path = cargo_home.join("bin").join(base_name);
+            if dry_run {
+                info!(
+                    "Would copy {} to {}",
+                    src.path().display(),
+                    dst_path.display()
+                );
+                continue;
+            }
+            let existed = dst_path.exists();
             copy(src.path(), &dst_path)
-                .map_err(|e| CargoHomeError::ImportTool(src.path(), dst_path, e))?;
+                .map_err(|e| CargoHomeError::ImportTool(src.path(), dst_path.clone(), e))?;
+            if existed {
+                info!("Replaced existing {}", dst_path.display());
+            } else {
+                state.record_tool(dst_path);
+            }
         }
         Ok(())
     }
 
+    /// Remove tool binaries previously copied by [`Self::install_tools`]
+    pub(crate) fn uninstall_tools(tools: &[PathBuf]) -> Result<(), RustError> {
+        for tool in tools {
+            info!("Removing {}", tool.display());
+            match fs::remove_file(tool) {
+                Err(e) if e.kind() == ErrorKind::NotFound => {
+                    debug!("{} already removed", tool.display());
+                }
+                res => res.map_err(|e| CargoHomeError::RemoveTool(tool.clone(), e))?,
+            }
+        }
+        Ok(())
+    }
+
+    /// Set back the cargo config values replaced by an installation, or remove them if there were none
+    pub(crate) fn restore_config(config: &CargoConfigState) -> Result<(), RustError> {
+        info!("Restoring cargo config {}", config.path.display());
+        let content = fs::read_to_string(&config.path).unwrap_or_default();
+        let mut doc = content
+            .parse::<DocumentMut>()
+            .map_err(|e| CargoHomeError::CargoConfigRead(config.path.clone(), e))?;
+        restore_value(
+            &mut doc,
+            "crates-io",
+            "replace-with",
+            config.replace_with.as_deref(),
+        );
+        restore_value(
+            &mut doc,
+            "vendored-sources",
+            "directory",
+            config.vendored_directory.as_deref(),
+        );
+        if doc
+            .get("source")
+            .and_then(Item::as_table_like)
+            .is_some_and(TableLike::is_empty)
+        {
+            doc.remove("source");
+        }
+        fs::write(&config.path, doc.to_string())
+            .map_err(|e| CargoHomeError::CargoConfigWrite(config.path.clone(), e))?;
+        Ok(())
+    }
+
     #[expect(
         clippy::indexing_slicing,
         reason = "false positive: toml_edit creates a value if the key doesn't exists"
     )]
-    fn update_cargo_config(cargo_config: &Path, vendored_path: &Path) -> Result<(), RustError> {
+    fn update_cargo_config(
+        cargo_config: &Path,
+        vendored_path: &Path,
+        dry_run: bool,
+        state: &mut InstallState,
+        recorder: &Recorder,
+    ) -> Result<(), RustError> {
         let content = fs::read_to_string(cargo_config).unwrap_or_default();
         let mut doc = content
             .parse::<DocumentMut>()
             .map_err(|e| CargoHomeError::CargoConfigRead(cargo_config.to_path_buf(), e))?;
+        state.record_cargo_config(CargoConfigState {
+            path: cargo_config.to_path_buf(),
+            replace_with: source_value(&doc, "crates-io", "replace-with"),
+            vendored_directory: source_value(&doc, "vendored-sources", "directory"),
+        });
         let source = doc["source"].or_insert(Item::Table(Table::new()));
 
         source["crates-io"]["replace-with"] = value("vendored-sources");
@@ -274,8 +463,125 @@ impl RustSettings {
             "New config for ${{CARGO_HOME}}/config.toml: {}",
             doc.to_string()
         );
+        if dry_run {
+            recorder.record_config_change(cargo_config, &content, &doc.to_string());
+            return Ok(());
+        }
         fs::write(cargo_config, doc.to_string())
             .map_err(|e| CargoHomeError::CargoConfigWrite(cargo_config.to_path_buf(), e))?;
         Ok(())
     }
 }
+
+/// Lock file of the package or of the workspace of `manifest`, if any
+fn lock_file(manifest: &Path) -> Option<PathBuf> {
+    manifest
+        .parent()?
+        .ancestors()
+        .map(|dir| dir.join("Cargo.lock"))
+        .find(|lock_file| lock_file.is_file())
+}
+
+impl ResourceProvider for RustSettings {
+    fn section(&self) -> &str {
+        "rust"
+    }
+
+    fn describe(&self) -> Vec<String> {
+        let manifests = self
+            .manifests
+            .iter()
+            .map(|manifest| format!("crates of {}", manifest.display()));
+        let binaries = self
+            .binaries
+            .iter()
+            .map(|binary| format!("cargo tool {binary}"));
+        manifests.chain(binaries).collect()
+    }
+
+    fn verify(&self) -> Vec<SettingsError> {
+        self.validate()
+    }
+
+    fn package(&self, context: &PackageContext<'_>) -> Result<(), PackagingError> {
+        context
+            .run_job(|| self.download(context.runner(), context.directory(), context.downloads))
+            .map_err(PackagingError::from)
+    }
+
+    fn archive(
+        &self,
+        context: &PackageContext<'_>,
+        archive: &mut ArchiveBuilder,
+    ) -> Result<(), PackagingError> {
+        Ok(self.archive(context.directory(), archive)?)
+    }
+
+    fn install(&self, context: &mut InstallContext<'_>) {
+        context.step(
+            "rust config",
+            "rust config".to_owned(),
+            Position::new(1, 2),
+            |context| {
+                RustSettings::install_config(
+                    context.resources_directory(),
+                    context.options().rust_config_for.as_ref(),
+                    context.is_dry_run(),
+                    context.state,
+                    context.recorder(),
+                )
+            },
+        );
+        context.step(
+            "rust tools",
+            "rust tools".to_owned(),
+            Position::new(2, 2),
+            |context| {
+                RustSettings::install_tools(
+                    context.unpacked_directory(),
+                    context.is_dry_run(),
+                    context.state,
+                )
+            },
+        );
+    }
+}
+
+/// Value of `source.<table>.<key>` in a cargo config, if set
+fn source_value(doc: &DocumentMut, table: &str, key: &str) -> Option<String> {
+    doc.get("source")
+        .and_then(|source| source.get(table))
+        .and_then(|table| table.get(key))
+        .and_then(Item::as_str)
+        .map(str::to_owned)
+}
+
+/// Set `source.<table>.<key>` back to `previous` in a cargo config, or remove it (and its table if emptied)
+#[expect(
+    clippy::indexing_slicing,
+    reason = "false positive: toml_edit creates a value if the key doesn't exists"
+)]
+fn restore_value(doc: &mut DocumentMut, table: &str, key: &str, previous: Option<&str>) {
+    if let Some(previous) = previous {
+        doc["source"][table][key] = value(previous);
+        return;
+    }
+    let Some(source) = doc.get_mut("source").and_then(Item::as_table_like_mut) else {
+        return;
+    };
+    if let Some(values) = source.get_mut(table).and_then(Item::as_table_like_mut) {
+        values.remove(key);
+        if values.is_empty() {
+            source.remove(table);
+        }
+    }
+}
+
+/// `${CARGO_HOME}`, or `${HOME}/.cargo` if unset
+fn cargo_home() -> Result<PathBuf, CargoHomeError> {
+    Ok(PathBuf::from(
+        std::env::var("CARGO_HOME")
+            .or(std::env::var("HOME").map(|home| format!("{home}/.cargo")))
+            .map_err(CargoHomeError::NoCargoHome)?,
+    ))
+}
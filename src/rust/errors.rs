@@ -3,7 +3,7 @@ use std::{env::VarError, path::PathBuf};
 use thiserror::Error;
 use toml_edit::TomlError;
 
-use crate::cmd::CommandFailedError;
+use crate::{cmd::CommandFailedError, download::errors::CheckpointError};
 
 #[derive(Error, Debug)]
 pub enum RustError {
@@ -15,6 +15,8 @@ pub enum RustError {
     },
     #[error(transparent)]
     CommandFailed(#[from] Box<CommandFailedError>),
+    #[error(transparent)]
+    Checkpoint(#[from] CheckpointError),
     #[error("Cannot create rust sub-directory at '{0}': {1}")]
     CreateMainDirectory(PathBuf, #[source] std::io::Error),
     #[error("Unable to find manifest {0}")]
@@ -33,6 +35,8 @@ pub enum CargoHomeError {
     ReadToolsDirectory(PathBuf, #[source] std::io::Error),
     #[error("Cannot copy tool from {0} to {1}: {2}")]
     ImportTool(PathBuf, PathBuf, std::io::Error),
+    #[error("Cannot remove tool {0}: {1}")]
+    RemoveTool(PathBuf, std::io::Error),
     #[error("Invalid toml in cargo config at {0}: {1}")]
     CargoConfigRead(PathBuf, TomlError),
     #[error("Cannot write updated cargo config to {0}: {1}")]
@@ -9,12 +9,19 @@ use rstest::rstest;
 use tempfile::tempdir;
 use tracing::info;
 
-use crate::test::archive;
-use crate::{ArchiveBuilder, cmd::MockCommandRunner, rust::RustSettings};
-use crate::{CARGO_TOOLS_PATH, CARGO_VENDOR_PATH};
+use crate::report::Recorder;
+use crate::state::InstallState;
+use crate::test::{archive, command};
+use crate::{
+    ArchiveBuilder,
+    cmd::{CommandOutput, MockCommandRunner},
+    download::Downloads,
+    rust::RustSettings,
+};
+use crate::{CARGO_TOOLS_PATH, CARGO_VENDOR_PATH, PackageOptions, Settings};
 
-/// Required to lock this mutex in every test
-/// because of <https://docs.rs/mockall/latest/mockall/#static-methods>
+/// Required to lock this mutex in every test changing `CARGO_HOME`,
+/// because `set_var` is only safe to call in a single-threaded environment
 ///
 /// The mutex might be poisoned if a test fails. But we don't
 /// care, because it doesn't hold any data. Whether it's poisoned or
@@ -24,13 +31,12 @@ static MTX: Mutex<()> = Mutex::new(());
 #[rstest]
 #[test_log::test]
 fn package_crates(mut archive: ArchiveBuilder) {
-    let _m = MTX.lock();
-
-    let ctx = MockCommandRunner::run_cmd_context();
+    let mut runner = MockCommandRunner::new();
     let out_folder = tempdir().unwrap();
     let crate_folder = out_folder.path().join(CARGO_VENDOR_PATH);
-    ctx.expect()
-        .with(
+    runner
+        .expect_run()
+        .with(command(
             eq("cargo"),
             function({
                 let crate_folder = crate_folder.clone();
@@ -47,18 +53,43 @@ fn package_crates(mut archive: ArchiveBuilder) {
                 }
             }),
             eq(None),
-        )
+        ))
         .times(1)
         .returning({
             let crate_folder = crate_folder.clone();
-            move |_, _, _| {
+            move |_| {
                 // cargo vendor creates the folder and it's expected by our tested code
                 fs::create_dir_all(&crate_folder).unwrap();
-                Ok(())
+                Ok(CommandOutput::default())
+            }
+        });
+    // Toolchain with the sources of the rust library crates
+    let sysroot = tempdir().unwrap();
+    let library = sysroot.path().join("lib/rustlib/src/rust/library");
+    for lib in ["core", "alloc", "std", "backtrace"] {
+        create_dir_all(library.join(lib)).unwrap();
+        File::create(library.join(lib).join("Cargo.toml")).unwrap();
+    }
+    runner
+        .expect_run()
+        .with(command(
+            eq("rustc"),
+            eq(["--print".to_owned(), "sysroot".to_owned()]),
+            eq(None),
+        ))
+        .times(1)
+        .returning({
+            let sysroot = sysroot.path().display().to_string();
+            move |_| {
+                Ok(CommandOutput {
+                    stdout: format!("{sysroot}\n"),
+                    ..CommandOutput::default()
+                })
             }
         });
-    ctx.expect()
-        .with(
+    runner
+        .expect_run()
+        .with(command(
             eq("rustup"),
             eq([
                 "component".to_owned(),
@@ -66,11 +97,12 @@ fn package_crates(mut archive: ArchiveBuilder) {
                 "rust-src".to_owned(),
             ]),
             eq(None),
-        )
+        ))
         .times(1)
-        .returning(|_, _, _| Ok(()));
-    ctx.expect()
-        .with(
+        .returning(|_| Ok(CommandOutput::default()));
+    runner
+        .expect_run()
+        .with(command(
             eq("cargo"),
             function({
                 let crate_folder = crate_folder.clone();
@@ -86,10 +118,10 @@ fn package_crates(mut archive: ArchiveBuilder) {
                 }
             }),
             eq(None),
-        )
-        // at least vendoring core, alloc & std deps
-        .times(3..)
-        .returning(|_, _, _| Ok(()));
+        ))
+        // vendoring core, alloc & std deps, but not backtrace
+        .times(3)
+        .returning(|_| Ok(CommandOutput::default()));
 
     let rust: RustSettings = serde_yaml::from_str(
         "
@@ -101,22 +133,100 @@ use_binstall: true
     )
     .unwrap();
 
-    rust.package_crates::<MockCommandRunner>(out_folder.path(), &mut archive, false)
-        .expect("Shouldn't fail to package crates");
+    rust.download(&runner, out_folder.path(), &Downloads::default())
+        .expect("Shouldn't fail to vendor crates");
+    rust.archive(out_folder.path(), &mut archive)
+        .expect("Shouldn't fail to archive crates");
     archive.finish().expect("Shouldn't fail to build archive");
 }
 
 #[rstest]
+#[case::manifest_changed("Cargo.toml")]
+#[case::lock_file_changed("Cargo.lock")]
 #[test_log::test]
-fn package_tools(mut archive: ArchiveBuilder) {
-    let _m = MTX.lock();
+fn resume_after_crates_change(#[case] changed: &str) {
+    let project = tempdir().unwrap();
+    create_dir_all(project.path().join("member")).unwrap();
+    fs::write(project.path().join("member/Cargo.toml"), "[package]").unwrap();
+    // Lock file of the workspace
+    fs::write(project.path().join("Cargo.lock"), "version = 4").unwrap();
+    let settings = Settings {
+        rust: RustSettings::builder()
+            .manifest(project.path().join("member/Cargo.toml"))
+            .build(),
+        ..Settings::default()
+    };
 
-    let ctx = MockCommandRunner::run_cmd_context();
+    let mut runner = MockCommandRunner::new();
+    runner
+        .expect_run()
+        .with(command(
+            eq("cargo"),
+            function(|args: &[String]| args[0] == "vendor"),
+            eq(None),
+        ))
+        .times(2)
+        .returning(|_| Ok(CommandOutput::default()));
+    // Toolchain without any rust library crate
+    let sysroot = tempdir().unwrap();
+    create_dir_all(sysroot.path().join("lib/rustlib/src/rust/library")).unwrap();
+    runner
+        .expect_run()
+        .with(command(
+            eq("rustc"),
+            function(|_: &[String]| true),
+            eq(None),
+        ))
+        .returning({
+            let sysroot = sysroot.path().display().to_string();
+            move |_| {
+                Ok(CommandOutput {
+                    stdout: sysroot.clone(),
+                    ..CommandOutput::default()
+                })
+            }
+        });
+    runner
+        .expect_run()
+        .with(command(
+            eq("rustup"),
+            function(|_: &[String]| true),
+            eq(None),
+        ))
+        .returning(|_| Ok(CommandOutput::default()));
+
+    let out_folder = tempdir().unwrap();
+    let options = PackageOptions {
+        resume: true,
+        ..PackageOptions::default()
+    };
+    for run in 0..2 {
+        if run == 1 {
+            let changed = if changed == "Cargo.toml" {
+                project.path().join("member").join(changed)
+            } else {
+                project.path().join(changed)
+            };
+            fs::write(changed, "changed").unwrap();
+        }
+        let downloads = Downloads::new(out_folder.path(), &settings, &options, false).unwrap();
+        settings
+            .rust
+            .download(&runner, out_folder.path(), &downloads)
+            .expect("Shouldn't fail to vendor crates");
+    }
+}
+
+#[rstest]
+#[test_log::test]
+fn package_tools(mut archive: ArchiveBuilder) {
+    let mut runner = MockCommandRunner::new();
 
     let out_folder = tempdir().unwrap();
     let binary_folder = out_folder.path().join(CARGO_TOOLS_PATH);
-    ctx.expect()
-        .with(
+    runner
+        .expect_run()
+        .with(command(
             eq("cargo"),
             function(move |args: &[String]| {
                 args.ends_with(&[
@@ -128,9 +238,9 @@ fn package_tools(mut archive: ArchiveBuilder) {
                 ])
             }),
             eq(None),
-        )
+        ))
         .times(1)
-        .returning(|_, _, _| Ok(()));
+        .returning(|_| Ok(CommandOutput::default()));
 
     let rust: RustSettings = serde_yaml::from_str(
         "
@@ -143,18 +253,18 @@ use_binstall: true
     )
     .unwrap();
 
-    rust.package_tools::<MockCommandRunner>(out_folder.path(), &mut archive, false)
-        .expect("Shouldn't fail to package tools");
+    rust.download(&runner, out_folder.path(), &Downloads::default())
+        .expect("Shouldn't fail to install tools");
+    rust.archive(out_folder.path(), &mut archive)
+        .expect("Shouldn't fail to archive tools");
     archive.finish().expect("Shouldn't fail to build archive");
 }
 
 #[rstest]
 #[test_log::test]
 fn package_empty_lists(mut archive: ArchiveBuilder) {
-    let _m = MTX.lock();
-
-    let ctx = MockCommandRunner::run_cmd_context();
-    ctx.expect().never();
+    let mut runner = MockCommandRunner::new();
+    runner.expect_run().never();
 
     let rust: RustSettings = serde_yaml::from_str(
         "
@@ -166,7 +276,9 @@ use_binstall: true
     .unwrap();
 
     let out_folder = tempdir().unwrap();
-    rust.package::<MockCommandRunner>(out_folder.path(), &mut archive, false)
+    rust.download(&runner, out_folder.path(), &Downloads::default())
+        .expect("Shouldn't fail because there is no listed resources");
+    rust.archive(out_folder.path(), &mut archive)
         .expect("Shouldn't fail because there is no listed resources");
     archive.finish().expect("Shouldn't fail to build archive");
 }
@@ -278,7 +390,17 @@ fn install(
             }
 
             // Actual tested operation
-            RustSettings::install(in_folder.path(), None, &[]).expect("Installation failed");
+            let mut state = InstallState::default();
+            RustSettings::install_config(
+                in_folder.path(),
+                None,
+                false,
+                &mut state,
+                &Recorder::default(),
+            )
+            .expect("Configuration failed");
+            RustSettings::install_tools(in_folder.path(), false, &mut state)
+                .expect("Installation failed");
 
             // Both OS have different way of quoting paths
             #[cfg(target_os = "linux")]
@@ -314,3 +436,65 @@ fn install(
         },
     );
 }
+
+#[rstest]
+#[test_log::test]
+fn install_dry_run() {
+    let _m = MTX.lock();
+    let out_folder = tempdir().unwrap();
+    temp_env::with_var(
+        "CARGO_HOME",
+        Some(out_folder.path().display().to_string()),
+        || {
+            create_dir_all(out_folder.path().join("bin")).unwrap();
+            let config_toml_path = out_folder.path().join("config.toml");
+            let initial_config_toml = "[global]\ncredential-provider = \"cargo:token\"\n";
+            fs::write(&config_toml_path, initial_config_toml).unwrap();
+            let in_folder = tempdir().unwrap();
+            create_dir_all(in_folder.path().join(CARGO_TOOLS_PATH).join("bin")).unwrap();
+            File::create(
+                in_folder
+                    .path()
+                    .join(CARGO_TOOLS_PATH)
+                    .join("bin")
+                    .join("cargo-audit"),
+            )
+            .unwrap();
+
+            let recorder = Recorder::new();
+            let mut state = InstallState::default();
+            RustSettings::install_config(in_folder.path(), None, true, &mut state, &recorder)
+                .expect("Configuration plan failed");
+            RustSettings::install_tools(in_folder.path(), true, &mut state)
+                .expect("Installation plan failed");
+            let recording = recorder.take();
+
+            assert_fs_read_to_string_eq_x!(
+                config_toml_path,
+                initial_config_toml,
+                "Config.toml shouldn't be changed by a dry run"
+            );
+            assert!(
+                !out_folder.path().join("bin").join("cargo-audit").exists(),
+                "Tools shouldn't be copied by a dry run"
+            );
+            let [change] = recording.config_changes.as_slice() else {
+                panic!("A single config change should be recorded");
+            };
+            assert_eq!(
+                change.path, config_toml_path,
+                "The change should be about the cargo config"
+            );
+            assert!(
+                change.diff.contains("+[source]")
+                    && change.diff.contains(&format!(
+                        "+vendored-sources = {{ directory = \"{}\" }}",
+                        in_folder.path().join(CARGO_VENDOR_PATH).display()
+                    ))
+                    && !change.diff.contains("-[global]"),
+                "The diff should only add the vendored sources, got {}",
+                change.diff
+            );
+        },
+    );
+}
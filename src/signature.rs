@@ -0,0 +1,97 @@
+use std::{
+    fs::{self, File},
+    io::BufReader,
+    path::{Path, PathBuf},
+};
+
+use minisign::{PError, PublicKey, SecretKey, SecretKeyBox, SignatureBox};
+use tracing::info;
+
+use crate::{InstallingError, report::Recorder, signature::errors::SignatureError};
+
+pub mod errors;
+#[cfg(test)]
+mod test;
+
+/// Suffix of the detached signature of a file, as expected by minisign
+const SIGNATURE_SUFFIX: &str = ".minisig";
+const UNTRUSTED_COMMENT: &str = "signature from cargo offline-package";
+
+/// Path of the detached signature of the file at `path`
+pub(crate) fn signature_path(path: &Path) -> PathBuf {
+    let mut signature_path = path.as_os_str().to_owned();
+    signature_path.push(SIGNATURE_SUFFIX);
+    PathBuf::from(signature_path)
+}
+
+/// Sign the file at `path` with the minisign secret key at `secret_key_path`
+/// and write the signature next to it.
+///
+/// The password of an encrypted key is prompted interactively.
+pub(crate) fn sign(path: &Path, secret_key_path: &Path) -> Result<PathBuf, SignatureError> {
+    let secret_key = read_secret_key(secret_key_path)
+        .map_err(|e| SignatureError::ReadKey(secret_key_path.to_path_buf(), e))?;
+    let file = File::open(path).map_err(|e| SignatureError::ReadArchive(path.to_path_buf(), e))?;
+    let signature = minisign::sign(
+        None,
+        &secret_key,
+        BufReader::new(file),
+        None,
+        Some(UNTRUSTED_COMMENT),
+    )
+    .map_err(|e| SignatureError::Sign(path.to_path_buf(), e))?;
+
+    let signature_path = signature_path(path);
+    fs::write(&signature_path, signature.into_string())
+        .map_err(|e| SignatureError::WriteSignature(signature_path.clone(), e))?;
+    info!("Signature written to {}", signature_path.display());
+    Ok(signature_path)
+}
+
+/// Read an unencrypted secret key, or prompt for the password of an encrypted one
+fn read_secret_key(secret_key_path: &Path) -> Result<SecretKey, PError> {
+    let secret_key_box = fs::read_to_string(secret_key_path)?;
+    SecretKeyBox::from_string(&secret_key_box)?
+        .into_unencrypted_secret_key()
+        .or_else(|_| SecretKey::from_box(SecretKeyBox::from_string(&secret_key_box)?, None))
+}
+
+/// Check the detached signature of the file at `path` against the minisign public key at `trusted_key_path`.
+///
+/// A missing signature is only accepted if `allow_unsigned` is set, with a warning recorded with `recorder`.
+pub(crate) fn verify(
+    path: &Path,
+    trusted_key_path: &Path,
+    allow_unsigned: bool,
+    recorder: &Recorder,
+) -> Result<(), InstallingError> {
+    let trusted_key = PublicKey::from_file(trusted_key_path)
+        .map_err(|e| SignatureError::ReadKey(trusted_key_path.to_path_buf(), e))?;
+
+    let signature_path = signature_path(path);
+    if !signature_path.is_file() {
+        if allow_unsigned {
+            recorder.warning(format!(
+                "No signature found at {}",
+                signature_path.display()
+            ));
+            return Ok(());
+        }
+        return Err(InstallingError::MissingSignature(signature_path));
+    }
+    let signature = SignatureBox::from_file(&signature_path)
+        .map_err(|e| InstallingError::InvalidSignature(signature_path.clone(), e))?;
+
+    let file = File::open(path).map_err(|e| SignatureError::ReadArchive(path.to_path_buf(), e))?;
+    minisign::verify(
+        &trusted_key,
+        &signature,
+        BufReader::new(file),
+        true,
+        false,
+        false,
+    )
+    .map_err(|e| InstallingError::InvalidSignature(signature_path, e))?;
+    info!("Signature of {} checked", path.display());
+    Ok(())
+}
@@ -0,0 +1,15 @@
+use std::path::PathBuf;
+
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum SignatureError {
+    #[error("Cannot read the key at '{0}': {1}")]
+    ReadKey(PathBuf, #[source] minisign::PError),
+    #[error("Cannot read '{0}' to sign or verify it: {1}")]
+    ReadArchive(PathBuf, #[source] std::io::Error),
+    #[error("Cannot sign '{0}': {1}")]
+    Sign(PathBuf, #[source] minisign::PError),
+    #[error("Cannot write the signature at '{0}': {1}")]
+    WriteSignature(PathBuf, #[source] std::io::Error),
+}
@@ -0,0 +1,117 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use minisign::KeyPair;
+use rstest::rstest;
+use tempfile::{TempDir, tempdir};
+
+use crate::report::Recorder;
+use crate::signature::{sign, signature_path, verify};
+use crate::{InstallOptions, InstallingError, plan_install};
+
+/// Write an unencrypted key pair in `folder`, return the paths of the secret and public keys
+fn write_key_pair(folder: &Path, name: &str) -> (PathBuf, PathBuf) {
+    let key_pair = KeyPair::generate_unencrypted_keypair().unwrap();
+    let secret_key_path = folder.join(format!("{name}.key"));
+    let public_key_path = folder.join(format!("{name}.pub"));
+    fs::write(
+        &secret_key_path,
+        key_pair.sk.to_box(None).unwrap().into_string(),
+    )
+    .unwrap();
+    fs::write(
+        &public_key_path,
+        key_pair.pk.to_box().unwrap().into_string(),
+    )
+    .unwrap();
+    (secret_key_path, public_key_path)
+}
+
+/// Sign a fake archive, return the folder holding everything, the archive and the public key
+fn signed_archive() -> (TempDir, PathBuf, PathBuf) {
+    let folder = tempdir().unwrap();
+    let (secret_key_path, public_key_path) = write_key_pair(folder.path(), "minisign");
+    let archive_path = folder.path().join("archive.tar.gz");
+    fs::write(&archive_path, "content").unwrap();
+    let signature = sign(&archive_path, &secret_key_path).expect("Signing should succeed");
+    assert_eq!(
+        signature,
+        signature_path(&archive_path),
+        "Signature should be next to the archive"
+    );
+    (folder, archive_path, public_key_path)
+}
+
+#[rstest]
+#[test_log::test]
+fn verify_signed_archive() {
+    let (_folder, archive_path, public_key_path) = signed_archive();
+    verify(&archive_path, &public_key_path, false, &Recorder::default())
+        .expect("Signature should be valid");
+}
+
+#[rstest]
+#[case::altered_archive(false)]
+#[case::other_key(true)]
+#[test_log::test]
+fn reject_invalid_signature(#[case] other_key: bool) {
+    let (folder, archive_path, mut public_key_path) = signed_archive();
+    if other_key {
+        (_, public_key_path) = write_key_pair(folder.path(), "other");
+    } else {
+        fs::write(&archive_path, "altered").unwrap();
+    }
+
+    let res = verify(&archive_path, &public_key_path, true, &Recorder::default());
+    assert!(
+        matches!(res, Err(InstallingError::InvalidSignature(..))),
+        "Signature should be rejected, got {res:?}"
+    );
+}
+
+#[rstest]
+#[case::rejected(false)]
+#[case::allowed(true)]
+#[test_log::test]
+fn missing_signature(#[case] allow_unsigned: bool) {
+    let (_folder, archive_path, public_key_path) = signed_archive();
+    fs::remove_file(signature_path(&archive_path)).unwrap();
+
+    let res = verify(
+        &archive_path,
+        &public_key_path,
+        allow_unsigned,
+        &Recorder::default(),
+    );
+    if allow_unsigned {
+        res.expect("Unsigned archive should be allowed");
+    } else {
+        assert!(
+            matches!(res, Err(InstallingError::MissingSignature(..))),
+            "Unsigned archive should be rejected, got {res:?}"
+        );
+    }
+}
+
+#[rstest]
+#[test_log::test]
+fn plan_checks_signature_first() {
+    let (_folder, archive_path, public_key_path) = signed_archive();
+    fs::remove_file(signature_path(&archive_path)).unwrap();
+
+    // The fake archive cannot be read, so any error other than the missing signature means it was read
+    let res = plan_install(
+        &archive_path,
+        &InstallOptions {
+            trusted_key: Some(public_key_path),
+            ..InstallOptions::default()
+        },
+    );
+    assert!(
+        matches!(
+            res.as_ref().map_err(|e| &**e),
+            Err(InstallingError::MissingSignature(..))
+        ),
+        "The signature should be checked before reading the archive, got {res:?}"
+    );
+}
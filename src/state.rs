@@ -0,0 +1,128 @@
+use std::{
+    fs,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+use crate::{
+    InstallingError, PythonConfigLevel, cmd::CommandRunner, python::PythonSettings,
+    report::InstallReport, rust::RustSettings, state::errors::StateError,
+};
+
+pub mod errors;
+#[cfg(test)]
+mod test;
+
+/// Name of the state file written in the unpacked directory
+pub(crate) const STATE_FILE: &str = "install-state.json";
+
+/// Configuration changes made by the installations into a directory, along with the values they replaced
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub(crate) struct InstallState {
+    pub(crate) cargo_configs: Vec<CargoConfigState>,
+    pub(crate) pip_configs: Vec<PipConfigState>,
+    /// Tool binaries copied into `${CARGO_HOME}/bin` that did not exist before
+    pub(crate) tools: Vec<PathBuf>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub(crate) struct CargoConfigState {
+    pub(crate) path: PathBuf,
+    /// Previous `source.crates-io.replace-with` value, if any
+    pub(crate) replace_with: Option<String>,
+    /// Previous `source.vendored-sources.directory` value, if any
+    pub(crate) vendored_directory: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub(crate) struct PipConfigState {
+    pub(crate) level: PythonConfigLevel,
+    /// Previous `global.find-links` value, if any
+    pub(crate) find_links: Option<String>,
+    /// Previous `global.no-index` value, if any
+    pub(crate) no_index: Option<String>,
+}
+
+impl InstallState {
+    pub(crate) fn load(path: &Path) -> Result<Self, StateError> {
+        let content = fs::read(path).map_err(|e| StateError::Read(path.to_path_buf(), e))?;
+        serde_json::from_slice(&content).map_err(|e| StateError::Parse(path.to_path_buf(), e))
+    }
+
+    /// Load the state at `path`, or start an empty one if no installation was made there yet
+    pub(crate) fn load_or_default(path: &Path) -> Result<Self, StateError> {
+        match Self::load(path) {
+            Err(StateError::Read(_, e)) if e.kind() == ErrorKind::NotFound => Ok(Self::default()),
+            res => res,
+        }
+    }
+
+    pub(crate) fn save(&self, path: &Path) -> Result<(), StateError> {
+        let content = serde_json::to_vec_pretty(self)
+            .map_err(|e| StateError::Parse(path.to_path_buf(), e))?;
+        fs::write(path, content).map_err(|e| StateError::Write(path.to_path_buf(), e))?;
+        info!("Install state saved to {}", path.display());
+        Ok(())
+    }
+
+    /// Keep the previous values of a cargo config, unless an earlier installation already changed it:
+    /// only the values from before the first installation are worth restoring.
+    pub(crate) fn record_cargo_config(&mut self, config: CargoConfigState) {
+        if !self
+            .cargo_configs
+            .iter()
+            .any(|recorded| recorded.path == config.path)
+        {
+            self.cargo_configs.push(config);
+        }
+    }
+
+    /// Keep the previous values of a pip config, unless an earlier installation already changed it
+    pub(crate) fn record_pip_config(&mut self, config: PipConfigState) {
+        if !self
+            .pip_configs
+            .iter()
+            .any(|recorded| recorded.level == config.level)
+        {
+            self.pip_configs.push(config);
+        }
+    }
+
+    pub(crate) fn record_tool(&mut self, path: PathBuf) {
+        if !self.tools.contains(&path) {
+            self.tools.push(path);
+        }
+    }
+}
+
+/// Restore the configurations saved in the state at `state_path` and remove the tools it lists,
+/// recording each of them as a step of the returned report
+pub(crate) fn uninstall(
+    runner: &dyn CommandRunner,
+    state_path: &Path,
+) -> Result<InstallReport, InstallingError> {
+    let state = InstallState::load(state_path)?;
+    let mut report = InstallReport::default();
+    for config in &state.cargo_configs {
+        report.record(
+            format!("restore {}", config.path.display()),
+            RustSettings::restore_config(config),
+        );
+    }
+    if !state.tools.is_empty() {
+        report.record(
+            "remove rust tools".to_owned(),
+            RustSettings::uninstall_tools(&state.tools),
+        );
+    }
+    for config in &state.pip_configs {
+        report.record(
+            format!("restore pip config ({})", config.level.pip_param()),
+            PythonSettings::restore_config(runner, config),
+        );
+    }
+    Ok(report)
+}
@@ -0,0 +1,13 @@
+use std::path::PathBuf;
+
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum StateError {
+    #[error("Cannot read the install state at '{0}': {1}")]
+    Read(PathBuf, #[source] std::io::Error),
+    #[error("Invalid install state at '{0}': {1}")]
+    Parse(PathBuf, #[source] serde_json::Error),
+    #[error("Cannot write the install state at '{0}': {1}")]
+    Write(PathBuf, #[source] std::io::Error),
+}
@@ -0,0 +1,160 @@
+use std::fs::{self, create_dir_all};
+use std::path::PathBuf;
+
+use assertables::assert_fs_read_to_string_eq_x;
+use mockall::predicate::eq;
+use rstest::rstest;
+use tempfile::tempdir;
+
+use crate::report::Recorder;
+use crate::state::{CargoConfigState, InstallState, PipConfigState, STATE_FILE, uninstall};
+use crate::test::command;
+use crate::{
+    CARGO_TOOLS_PATH, PythonConfigLevel,
+    cmd::{CommandOutput, MockCommandRunner},
+    rust::RustSettings,
+};
+
+#[rstest]
+#[case::no_previous_config(None)]
+#[case::previous_config(Some(
+    r#"[global]
+credential-provider = "cargo:token"
+
+[source]
+crates-io = { replace-with = "remote-sources" }
+vendored-sources = { directory = "lol" }
+"#
+))]
+#[test_log::test]
+fn uninstall_rust(#[case] initial_config_toml: Option<&str>) {
+    let mut runner = MockCommandRunner::new();
+    runner.expect_run().never();
+    let cargo_home = tempdir().unwrap();
+    temp_env::with_var(
+        "CARGO_HOME",
+        Some(cargo_home.path().display().to_string()),
+        || {
+            create_dir_all(cargo_home.path().join("bin")).unwrap();
+            let config_toml_path = cargo_home.path().join("config.toml");
+            if let Some(initial_config_toml) = initial_config_toml {
+                fs::write(&config_toml_path, initial_config_toml).unwrap();
+            }
+            // cargo-deny was already installed before, cargo-audit wasn't
+            fs::write(cargo_home.path().join("bin").join("cargo-deny"), "mine").unwrap();
+            let in_folder = tempdir().unwrap();
+            let tools_folder = in_folder.path().join(CARGO_TOOLS_PATH).join("bin");
+            create_dir_all(&tools_folder).unwrap();
+            for tool in ["cargo-audit", "cargo-deny"] {
+                fs::write(tools_folder.join(tool), "packaged").unwrap();
+            }
+
+            let mut state = InstallState::default();
+            RustSettings::install_config(
+                in_folder.path(),
+                None,
+                false,
+                &mut state,
+                &Recorder::default(),
+            )
+            .expect("Configuration failed");
+            RustSettings::install_tools(in_folder.path(), false, &mut state)
+                .expect("Installation failed");
+            let state_path = in_folder.path().join(STATE_FILE);
+            state.save(&state_path).expect("State should be saved");
+
+            let report = uninstall(&runner, &state_path).expect("State should be readable");
+
+            assert!(report.is_success(), "Every step should succeed: {report}");
+            assert_fs_read_to_string_eq_x!(
+                config_toml_path,
+                initial_config_toml.unwrap_or_default().to_owned(),
+                "Cargo config should be restored"
+            );
+            assert!(
+                !cargo_home.path().join("bin").join("cargo-audit").exists(),
+                "Installed tool should be removed"
+            );
+            assert!(
+                cargo_home.path().join("bin").join("cargo-deny").exists(),
+                "Tool that existed before the installation should be kept"
+            );
+        },
+    );
+}
+
+#[rstest]
+#[test_log::test]
+fn uninstall_python() {
+    let mut runner = MockCommandRunner::new();
+    runner
+        .expect_run()
+        .with(command(
+            eq("pip"),
+            eq([
+                "config".to_owned(),
+                "--user".to_owned(),
+                "set".to_owned(),
+                "global.find-links".to_owned(),
+                "/previous/wheels".to_owned(),
+            ]),
+            eq(None),
+        ))
+        .times(1)
+        .returning(|_| Ok(CommandOutput::default()));
+    runner
+        .expect_run()
+        .with(command(
+            eq("pip"),
+            eq([
+                "config".to_owned(),
+                "--user".to_owned(),
+                "unset".to_owned(),
+                "global.no-index".to_owned(),
+            ]),
+            eq(None),
+        ))
+        .times(1)
+        .returning(|_| Ok(CommandOutput::default()));
+
+    let folder = tempdir().unwrap();
+    let state_path = folder.path().join(STATE_FILE);
+    let mut state = InstallState::default();
+    state.record_pip_config(PipConfigState {
+        level: PythonConfigLevel::User,
+        find_links: Some("/previous/wheels".to_owned()),
+        no_index: None,
+    });
+    state.save(&state_path).expect("State should be saved");
+
+    let report = uninstall(&runner, &state_path).expect("State should be readable");
+    assert!(report.is_success(), "Every step should succeed: {report}");
+}
+
+#[rstest]
+#[test_log::test]
+fn keep_first_previous_values() {
+    let path = PathBuf::from("config.toml");
+    let mut state = InstallState::default();
+    for replace_with in ["remote-sources", "vendored-sources"] {
+        state.record_cargo_config(CargoConfigState {
+            path: path.clone(),
+            replace_with: Some(replace_with.to_owned()),
+            vendored_directory: None,
+        });
+    }
+    state.record_tool(PathBuf::from("cargo-audit"));
+    state.record_tool(PathBuf::from("cargo-audit"));
+
+    assert_eq!(
+        state.cargo_configs.len(),
+        1,
+        "A config should only be recorded once"
+    );
+    assert_eq!(
+        state.cargo_configs[0].replace_with.as_deref(),
+        Some("remote-sources"),
+        "Values from before the first installation should be kept"
+    );
+    assert_eq!(state.tools.len(), 1, "A tool should only be recorded once");
+}
@@ -1,13 +1,104 @@
 //! This module centralizes useful test utils
-use flate2::{Compression, write::GzEncoder};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    num::NonZeroUsize,
+    path::{Path, PathBuf},
+};
+
+use mockall::{Predicate, predicate::function};
 use rstest::fixture;
 use tempfile::tempfile;
 
-use crate::ArchiveBuilder;
+use crate::{
+    ArchiveBuilder, InstallContext, InstallOptions, InstallReport, PackageOptions, Settings,
+    cmd::{CommandRunner, CommandSpec},
+    compression::{CompressionFormat, CompressionSettings, Encoder},
+    download::Downloads,
+    encryption::Encryption,
+    progress::Progress,
+    report::Recorder,
+    state::InstallState,
+    volume::Output,
+};
 
 #[fixture]
-pub fn archive() -> ArchiveBuilder {
-    let tar_gz = tempfile().unwrap();
-    let enc = GzEncoder::new(tar_gz, Compression::default());
-    tar::Builder::new(enc)
+pub fn archive(#[default(CompressionFormat::Gzip)] format: CompressionFormat) -> ArchiveBuilder {
+    let output = Output::Single {
+        path: PathBuf::new(),
+        file: tempfile().unwrap(),
+    };
+    let settings = CompressionSettings {
+        format,
+        level: None,
+    };
+    ArchiveBuilder::new(Encoder::new(Encryption::None(output), &settings).unwrap())
+}
+
+/// Start an archive written at `path` with the default compression
+pub(crate) fn archive_at(path: &Path) -> ArchiveBuilder {
+    let output = Output::new(path, None).unwrap();
+    ArchiveBuilder::new(
+        Encoder::new(Encryption::None(output), &CompressionSettings::default()).unwrap(),
+    )
+}
+
+/// Match the commands running `program` with the arguments matching `args` in the directory matching `cwd`
+pub(crate) fn command<
+    P: Predicate<str> + Send + 'static,
+    A: Predicate<[String]> + Send + 'static,
+    C: Predicate<Option<PathBuf>> + Send + 'static,
+>(
+    program: P,
+    args: A,
+    cwd: C,
+) -> impl Predicate<CommandSpec> + Send {
+    function(move |command: &CommandSpec| {
+        program.eval(command.program.as_str())
+            && args.eval(command.args.as_slice())
+            && cwd.eval(&command.cwd)
+    })
+}
+
+/// Downloads into `directory` running two at a time
+pub(crate) fn parallel_downloads(directory: &Path) -> Downloads {
+    let options = PackageOptions {
+        jobs: NonZeroUsize::new(2),
+        ..PackageOptions::default()
+    };
+    Downloads::new(directory, &Settings::default(), &options, false).unwrap()
+}
+
+/// What an install context borrows, to install the resources of a single provider
+#[derive(Default)]
+pub(crate) struct InstallFixture {
+    pub(crate) options: InstallOptions,
+    pub(crate) skip: BTreeSet<String>,
+    pub(crate) state: InstallState,
+    pub(crate) report: InstallReport,
+    pub(crate) progress: Progress,
+    pub(crate) recorder: Recorder,
+    pub(crate) sections: BTreeMap<String, serde_json::Value>,
+}
+
+impl InstallFixture {
+    /// Context installing the archive unpacked into `directory`, running commands with `runner`
+    pub(crate) fn context<'a>(
+        &'a mut self,
+        runner: &'a dyn CommandRunner,
+        directory: &'a Path,
+    ) -> InstallContext<'a> {
+        InstallContext::new(
+            directory,
+            directory,
+            &self.options,
+            false,
+            &self.skip,
+            &mut self.state,
+            &mut self.report,
+            runner,
+            &self.progress,
+            &self.recorder,
+            &self.sections,
+        )
+    }
 }
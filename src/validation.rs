@@ -0,0 +1,62 @@
+use std::path::Path;
+
+use crate::{
+    PackagingError, Settings, naming::check_name, provider::ProviderRegistry,
+    validation::errors::SettingsError,
+};
+
+pub mod errors;
+#[cfg(test)]
+mod test;
+
+/// Every mistake found in `settings` and in the settings of its `providers`, checked without downloading anything.
+/// The name is only checked if it names the packaging directory, i.e., `name_is_directory`.
+pub(crate) fn validate(
+    settings: &Settings,
+    providers: &ProviderRegistry<'_>,
+    name_is_directory: bool,
+) -> Vec<SettingsError> {
+    let mut errors = Vec::new();
+    if name_is_directory && check_name(&settings.name).is_err() {
+        errors.push(SettingsError::Name(settings.name.clone()));
+    }
+    let compression = &settings.compression;
+    if let Some(level) = compression.level
+        && !compression.format.accepts_level(level)
+    {
+        errors.push(SettingsError::CompressionLevel(compression.format, level));
+    }
+    for provider in providers.iter() {
+        errors.extend(provider.verify());
+    }
+    errors.extend(
+        settings
+            .sections
+            .keys()
+            .filter(|section| !providers.contains(section))
+            .map(|section| SettingsError::UnknownSection(section.clone())),
+    );
+    errors
+}
+
+/// Fail with every mistake found in `settings` and in the settings of its `providers`
+pub(crate) fn check(
+    settings: &Settings,
+    providers: &ProviderRegistry<'_>,
+    name_is_directory: bool,
+) -> Result<(), PackagingError> {
+    let errors = validate(settings, providers, name_is_directory);
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(PackagingError::InvalidSettings(errors))
+    }
+}
+
+/// Error for the path of `field` if nothing exists there
+pub(crate) fn check_path_exists(field: String, path: &Path) -> Option<SettingsError> {
+    (!path.exists()).then(|| SettingsError::MissingPath {
+        field,
+        path: path.to_path_buf(),
+    })
+}
@@ -0,0 +1,25 @@
+use std::path::PathBuf;
+
+use thiserror::Error;
+use url::Url;
+
+use crate::CompressionFormat;
+
+/// Mistake found in the settings before running anything
+#[derive(Error, Debug)]
+pub enum SettingsError {
+    #[error("{field}: '{}' doesn't exist", .path.display())]
+    MissingPath { field: String, path: PathBuf },
+    #[error("{field}: cannot parse '{command}' as a shell command")]
+    UnparsableCommand { field: String, command: String },
+    #[error("git_mirrors.mirrors[{index}]: no base name in the path of '{src}' to name its clone")]
+    MirrorBaseName { index: usize, src: Url },
+    #[error("git_mirrors.mirrors: {} are all cloned into '{basename}'", .sources.iter().map(Url::as_str).collect::<Vec<_>>().join(", "))]
+    MirrorCollision { basename: String, sources: Vec<Url> },
+    #[error("name: '{0}' is not a valid directory name")]
+    Name(String),
+    #[error("compression.level: invalid level {1} for {0}")]
+    CompressionLevel(CompressionFormat, u32),
+    #[error("{0}: unknown section, no provider is registered for it")]
+    UnknownSection(String),
+}
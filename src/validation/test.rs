@@ -0,0 +1,278 @@
+use std::fs;
+
+use rstest::rstest;
+use tempfile::tempdir;
+use url::Url;
+
+use crate::compose::errors::ComposeError;
+use crate::provider::ProviderRegistry;
+use crate::validation::validate;
+use crate::{
+    CustomInstallInstallCount, CustomTask, CustomTasks, GitMirrors, PackagingError, PythonSettings,
+    RustSettings, Settings, load_settings, settings_schema,
+};
+
+#[rstest]
+#[test_log::test]
+fn report_every_mistake() {
+    let settings: Settings = serde_yaml::from_str(
+        "
+name: ../escape
+compression:
+    format: Zstd
+    level: 40
+rust:
+    manifests:
+        - ./Cargo.toml
+        - /missing/Cargo.toml
+    binaries: []
+    use_binstall: true
+python:
+    requirement_files:
+        - /missing/requirements.txt
+git_mirrors:
+    mirrors:
+        - src: https://github.com/rustsec/advisory-db
+          dst: https://private.domain/global/advisory-db
+        - src: https://mirror.domain/advisory-db
+          dst: https://private.domain/global/advisory-db-2
+        - src: https://github.com/
+          dst: https://private.domain/global/root
+custom:
+    tasks:
+        - paths_to_package:
+            /missing/vsix: vsix
+          install_command: code --install-extension 'unterminated
+          install_counts: EachFile
+",
+    )
+    .unwrap();
+
+    let errors: Vec<_> = validate(&settings, &ProviderRegistry::new(&settings, &[]), true)
+        .iter()
+        .map(ToString::to_string)
+        .collect();
+
+    assert_eq!(
+        errors,
+        [
+            "name: '../escape' is not a valid directory name",
+            "compression.level: invalid level 40 for Zstd",
+            "rust.manifests[1]: '/missing/Cargo.toml' doesn't exist",
+            "python.requirement_files[0]: '/missing/requirements.txt' doesn't exist",
+            "git_mirrors.mirrors[2]: no base name in the path of 'https://github.com/' to name its clone",
+            "git_mirrors.mirrors: https://github.com/rustsec/advisory-db, https://mirror.domain/advisory-db are all cloned into 'advisory-db'",
+            "custom.tasks[0].paths_to_package: '/missing/vsix' doesn't exist",
+            "custom.tasks[0].install_command: cannot parse 'code --install-extension 'unterminated' as a shell command",
+        ],
+        "Every mistake should be reported with its field"
+    );
+}
+
+#[rstest]
+#[test_log::test]
+fn accept_valid_settings() {
+    let custom_folder = tempdir().unwrap();
+    let settings: Settings = serde_yaml::from_str(&format!(
+        "
+rust:
+    manifests:
+        - ./Cargo.toml
+    binaries: []
+    use_binstall: true
+python:
+    requirement_files: []
+git_mirrors:
+    mirrors:
+        - src: https://github.com/rustsec/advisory-db
+          dst: https://private.domain/global/advisory-db
+custom:
+    tasks:
+        - paths_to_package:
+            {}: extras
+          install_command: code --install-extension
+          install_counts: EachFile
+",
+        custom_folder.path().display()
+    ))
+    .unwrap();
+
+    let errors = validate(&settings, &ProviderRegistry::new(&settings, &[]), true);
+
+    assert!(errors.is_empty(), "No mistake expected, got {errors:?}");
+}
+
+#[rstest]
+#[case::packaging_directory(true, 1)]
+#[case::staging_directory(false, 0)]
+#[test_log::test]
+fn check_name_of_packaging_directory(#[case] name_is_directory: bool, #[case] expected: usize) {
+    let settings = Settings {
+        name: "../escape".to_owned(),
+        ..Settings::default()
+    };
+
+    let errors = validate(
+        &settings,
+        &ProviderRegistry::new(&settings, &[]),
+        name_is_directory,
+    );
+
+    assert_eq!(
+        errors.len(),
+        expected,
+        "The name should only be checked when it names the packaging directory, got {errors:?}"
+    );
+}
+
+#[rstest]
+#[test_log::test]
+fn describe_every_section() {
+    let schema = settings_schema();
+
+    for section in [
+        "name",
+        "rust",
+        "python",
+        "git_mirrors",
+        "custom",
+        "compression",
+    ] {
+        assert!(
+            schema["properties"].get(section).is_some(),
+            "The schema should describe {section}, got {schema}"
+        );
+    }
+    assert_eq!(
+        schema["$defs"]["RustSettings"]["properties"]["use_binstall"]["type"], "boolean",
+        "Field types should be described"
+    );
+}
+
+#[rstest]
+#[test_log::test]
+fn default_missing_sections() {
+    let folder = tempdir().unwrap();
+    let path = folder.path().join("settings.yaml");
+    fs::write(
+        &path,
+        "
+python:
+    requirement_files:
+        - ./requirements.txt
+",
+    )
+    .unwrap();
+
+    let settings = load_settings(&folder.path().join("settings"), None).unwrap();
+
+    assert_eq!(settings.name, "offline-vendoring", "Name should default");
+    assert_eq!(
+        serde_json::to_value(&settings.rust).unwrap(),
+        serde_json::json!({"manifests": [], "binaries": [], "use_binstall": true}),
+        "Missing rust section should default to nothing, with binstall"
+    );
+    assert_eq!(
+        serde_json::to_value(&settings.git_mirrors).unwrap(),
+        serde_json::json!({"mirrors": []}),
+        "Missing git mirrors should default to none"
+    );
+}
+
+#[rstest]
+#[case::wrong_type(
+    "rust:\n    binaries:\n        - cargo-audit\n        - cargo-deny\n        - [cargo-nextest]\n",
+    "rust.binaries[2]"
+)]
+#[case::unknown_field(
+    "git_mirrors:\n    mirrors:\n        - src: https://github.com/rustsec/advisory-db\n          destination: https://private.domain/global/advisory-db\n",
+    "git_mirrors.mirrors[0].destination"
+)]
+#[test_log::test]
+fn report_field_path(#[case] content: &str, #[case] expected_path: &str) {
+    let folder = tempdir().unwrap();
+    let path = folder.path().join("settings.yaml");
+    fs::write(&path, content).unwrap();
+
+    let res = load_settings(&path, None).map_err(|e| *e);
+
+    assert!(
+        matches!(&res, Err(PackagingError::DeserializeSettings { path, .. }) if path == expected_path),
+        "Error should point at {expected_path}, got {res:?}"
+    );
+}
+
+#[rstest]
+#[test_log::test]
+fn reject_missing_settings_file() {
+    let folder = tempdir().unwrap();
+
+    let res = load_settings(&folder.path().join("settings"), None).map_err(|e| *e);
+
+    assert!(
+        matches!(res, Err(PackagingError::Compose(ComposeError::Read(..)))),
+        "Missing settings file should be reported, got {res:?}"
+    );
+}
+
+#[rstest]
+#[test_log::test]
+fn build_settings_in_code() {
+    let from_file: Settings = serde_yaml::from_str(
+        "
+rust:
+    manifests:
+        - ./Cargo.toml
+    binaries:
+        - cargo-audit
+    use_binstall: false
+python:
+    requirement_files:
+        - ./requirements.txt
+git_mirrors:
+    mirrors:
+        - src: https://github.com/rustsec/advisory-db
+          dst: https://private.domain/global/advisory-db
+custom:
+    tasks:
+        - paths_to_package:
+            ./extensions: vsix
+          install_command: code --install-extension
+          install_counts: EachFile
+",
+    )
+    .unwrap();
+
+    let built = Settings {
+        rust: RustSettings::builder()
+            .manifest("./Cargo.toml")
+            .binary("cargo-audit")
+            .use_binstall(false)
+            .build(),
+        python: PythonSettings::builder()
+            .requirement_file("./requirements.txt")
+            .build(),
+        git_mirrors: GitMirrors::builder()
+            .mirror(
+                Url::parse("https://github.com/rustsec/advisory-db").unwrap(),
+                Url::parse("https://private.domain/global/advisory-db").unwrap(),
+            )
+            .build(),
+        custom: CustomTasks::builder()
+            .task(
+                CustomTask::builder()
+                    .path("./extensions", "vsix")
+                    .install_command("code --install-extension")
+                    .install_counts(CustomInstallInstallCount::EachFile)
+                    .build(),
+            )
+            .build(),
+        ..Settings::default()
+    };
+
+    assert_eq!(
+        serde_json::to_value(&built).unwrap(),
+        serde_json::to_value(&from_file).unwrap(),
+        "Settings built in code should be the same as the ones read from a file"
+    );
+}
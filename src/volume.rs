@@ -0,0 +1,323 @@
+use std::{
+    fs::{self, File},
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tracing::{debug, info};
+
+use crate::{archive::hash_reader, volume::errors::VolumeError};
+
+pub mod errors;
+#[cfg(test)]
+mod test;
+
+/// Suffix of the index listing every volume of a split archive
+const INDEX_SUFFIX: &str = ".volumes.json";
+/// Suffix of the first volume of a split archive
+const FIRST_VOLUME_SUFFIX: &str = ".001";
+
+/// List of the volumes of a split archive, in order
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub(crate) struct VolumeIndex {
+    pub(crate) volumes: Vec<Volume>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub(crate) struct Volume {
+    /// File name of the volume, in the same directory as the index
+    pub(crate) file: String,
+    pub(crate) size: u64,
+    /// Hex-encoded SHA-256 digest of the volume
+    pub(crate) sha256: String,
+}
+
+/// Where the archive is written
+pub(crate) enum Output {
+    /// A single archive file
+    Single { path: PathBuf, file: File },
+    /// Fixed-size volumes
+    Volumes(VolumeWriter),
+    /// Nothing is written, the archive would be written at `path`
+    Discard { path: PathBuf },
+}
+
+impl Output {
+    /// Write the archive at `path`, or in volumes of at most `volume_size` bytes
+    /// named after `path` if it is set
+    pub(crate) fn new(path: &Path, volume_size: Option<u64>) -> io::Result<Self> {
+        Ok(match volume_size {
+            Some(volume_size) => Self::Volumes(VolumeWriter::new(path, volume_size)),
+            None => Self::Single {
+                path: path.to_path_buf(),
+                file: File::create(path)?,
+            },
+        })
+    }
+
+    /// Flush the archive and return the path of the file to hand over to the installation,
+    /// that is the archive itself or the index of its volumes
+    pub(crate) fn finish(self) -> io::Result<PathBuf> {
+        match self {
+            Self::Single { path, mut file } => {
+                file.flush()?;
+                Ok(path)
+            }
+            Self::Volumes(writer) => writer.finish(),
+            Self::Discard { path } => Ok(path),
+        }
+    }
+}
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Self::Single { file, .. } => file.write(buf),
+            Self::Volumes(writer) => writer.write(buf),
+            Self::Discard { .. } => Ok(buf.len()),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Self::Single { file, .. } => file.flush(),
+            Self::Volumes(writer) => writer.flush(),
+            Self::Discard { .. } => Ok(()),
+        }
+    }
+}
+
+/// A writer splitting its input into files of at most `volume_size` bytes
+pub(crate) struct VolumeWriter {
+    base_path: PathBuf,
+    volume_size: u64,
+    index: VolumeIndex,
+    current: Option<VolumeInProgress>,
+}
+
+/// Volume being written, along with its digest and size so far
+struct VolumeInProgress {
+    file_name: String,
+    file: File,
+    hasher: Sha256,
+    size: u64,
+}
+
+impl VolumeWriter {
+    fn new(base_path: &Path, volume_size: u64) -> Self {
+        Self {
+            base_path: base_path.to_path_buf(),
+            volume_size,
+            index: VolumeIndex::default(),
+            current: None,
+        }
+    }
+
+    /// Close the current volume if any, and record it in the index
+    fn close_volume(&mut self) -> io::Result<()> {
+        if let Some(mut volume) = self.current.take() {
+            volume.file.flush()?;
+            debug!("Volume {} completed", volume.file_name);
+            self.index.volumes.push(Volume {
+                file: volume.file_name,
+                size: volume.size,
+                sha256: hex::encode(volume.hasher.finalize()),
+            });
+        }
+        Ok(())
+    }
+
+    /// Write the index of the volumes and return its path
+    fn finish(mut self) -> io::Result<PathBuf> {
+        self.flush()?;
+        self.close_volume()?;
+        let index_path = index_path(&self.base_path);
+        let index = serde_json::to_vec_pretty(&self.index).map_err(io::Error::other)?;
+        fs::write(&index_path, index)?;
+        info!(
+            "Archive split into {} volumes, listed in {}",
+            self.index.volumes.len(),
+            index_path.display()
+        );
+        Ok(index_path)
+    }
+}
+
+impl Write for VolumeWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self
+            .current
+            .as_ref()
+            .is_some_and(|volume| volume.size >= self.volume_size)
+        {
+            self.close_volume()?;
+        }
+        let volume = match self.current.as_mut() {
+            Some(volume) => volume,
+            None => {
+                let path = volume_path(&self.base_path, self.index.volumes.len().saturating_add(1));
+                let file_name = path
+                    .file_name()
+                    .map(|file_name| file_name.to_string_lossy().to_string())
+                    .unwrap_or_default();
+                self.current.insert(VolumeInProgress {
+                    file_name,
+                    file: File::create(path)?,
+                    hasher: Sha256::new(),
+                    size: 0,
+                })
+            }
+        };
+
+        let remaining =
+            usize::try_from(self.volume_size.saturating_sub(volume.size)).unwrap_or(usize::MAX);
+        let written = volume.file.write(buf.get(..remaining).unwrap_or(buf))?;
+        volume.hasher.update(buf.get(..written).unwrap_or_default());
+        volume.size = volume.size.saturating_add(written as u64);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if let Some(volume) = self.current.as_mut() {
+            volume.file.flush()?;
+        }
+        Ok(())
+    }
+}
+
+/// Path of the volume number `number` (starting at 1) of the archive at `base_path`
+fn volume_path(base_path: &Path, number: usize) -> PathBuf {
+    let mut path = base_path.as_os_str().to_owned();
+    path.push(format!(".{number:03}"));
+    PathBuf::from(path)
+}
+
+/// Path of the index of the volumes of the archive at `base_path`
+fn index_path(base_path: &Path) -> PathBuf {
+    let mut path = base_path.as_os_str().to_owned();
+    path.push(INDEX_SUFFIX);
+    PathBuf::from(path)
+}
+
+/// Return the path of the index if `path` is either the index of a split archive or its first volume
+pub(crate) fn find_index(path: &Path) -> Option<PathBuf> {
+    let path_str = path.to_string_lossy();
+    if path_str.ends_with(INDEX_SUFFIX) {
+        return Some(path.to_path_buf());
+    }
+    path_str
+        .strip_suffix(FIRST_VOLUME_SUFFIX)
+        .map(|base_path| index_path(Path::new(base_path)))
+}
+
+/// Open the archive at `path` for reading.
+/// If `path` is the index or the first volume of a split archive, all volumes are read in sequence.
+pub(crate) fn open(path: &Path) -> io::Result<Box<dyn Read>> {
+    let Some(index_path) = find_index(path) else {
+        return Ok(Box::new(File::open(path)?));
+    };
+    let reader = VolumeReader::new(&index_path).map_err(io::Error::other)?;
+    Ok(Box::new(reader))
+}
+
+/// Open the archive at `path` for reading like [`open`], without checking the digests of its volumes.
+///
+/// Only for reading a few entries that are checked later on, hashing every volume would read the whole archive.
+pub(crate) fn open_unchecked(path: &Path) -> io::Result<Box<dyn Read>> {
+    let Some(index_path) = find_index(path) else {
+        return Ok(Box::new(File::open(path)?));
+    };
+    let reader = VolumeReader::with_sizes_checked(&index_path).map_err(io::Error::other)?;
+    Ok(Box::new(reader))
+}
+
+/// A reader going through every volume of a split archive,
+/// all of them checked against the index before the first byte is read
+pub(crate) struct VolumeReader {
+    directory: PathBuf,
+    index: VolumeIndex,
+    /// Index of the volume being read
+    position: usize,
+    current: Option<File>,
+}
+
+impl VolumeReader {
+    /// Check that every volume listed in the index exists with the expected size and digest
+    pub(crate) fn new(index_path: &Path) -> Result<Self, VolumeError> {
+        let reader = Self::with_sizes_checked(index_path)?;
+        // Entries are unpacked as they are read, and tar stops before the end of the last volume,
+        // so every volume is checked up front
+        for volume in &reader.index.volumes {
+            let volume_path = reader.directory.join(&volume.file);
+            let (_, sha256) = File::open(&volume_path)
+                .and_then(hash_reader)
+                .map_err(|e| VolumeError::ReadVolume(volume_path.clone(), e))?;
+            if sha256 != volume.sha256 {
+                return Err(VolumeError::VolumeDigest(volume_path));
+            }
+        }
+        debug!(
+            "All {} volumes found and checked",
+            reader.index.volumes.len()
+        );
+        Ok(reader)
+    }
+
+    /// Check that every volume listed in the index exists with the expected size
+    fn with_sizes_checked(index_path: &Path) -> Result<Self, VolumeError> {
+        let content = fs::read(index_path)
+            .map_err(|e| VolumeError::ReadIndex(index_path.to_path_buf(), e))?;
+        let index: VolumeIndex = serde_json::from_slice(&content)
+            .map_err(|e| VolumeError::ParseIndex(index_path.to_path_buf(), e))?;
+        let directory = index_path
+            .parent()
+            .map(Path::to_path_buf)
+            .unwrap_or_default();
+
+        // Sizes first, so that a missing or truncated volume is reported before hashing anything
+        for volume in &index.volumes {
+            let volume_path = directory.join(&volume.file);
+            let size = fs::metadata(&volume_path)
+                .map_err(|e| VolumeError::MissingVolume(volume_path.clone(), e))?
+                .len();
+            if size != volume.size {
+                return Err(VolumeError::VolumeSize {
+                    path: volume_path,
+                    expected: volume.size,
+                    actual: size,
+                });
+            }
+        }
+        Ok(Self {
+            directory,
+            index,
+            position: 0,
+            current: None,
+        })
+    }
+}
+
+impl Read for VolumeReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let Some(volume) = self.index.volumes.get(self.position) else {
+                return Ok(0);
+            };
+            let reader = match self.current.as_mut() {
+                Some(reader) => reader,
+                None => self
+                    .current
+                    .insert(File::open(self.directory.join(&volume.file))?),
+            };
+
+            let read = reader.read(buf)?;
+            if read > 0 || buf.is_empty() {
+                return Ok(read);
+            }
+            self.current = None;
+            self.position = self.position.saturating_add(1);
+        }
+    }
+}
@@ -0,0 +1,23 @@
+use std::path::PathBuf;
+
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum VolumeError {
+    #[error("Cannot read the volume index at '{0}': {1}")]
+    ReadIndex(PathBuf, #[source] std::io::Error),
+    #[error("Invalid volume index at '{0}': {1}")]
+    ParseIndex(PathBuf, #[source] serde_json::Error),
+    #[error("Volume '{0}' is missing: {1}")]
+    MissingVolume(PathBuf, #[source] std::io::Error),
+    #[error("Volume '{path}' should be {expected} bytes long but is {actual} bytes long")]
+    VolumeSize {
+        path: PathBuf,
+        expected: u64,
+        actual: u64,
+    },
+    #[error("Cannot read the volume '{0}': {1}")]
+    ReadVolume(PathBuf, #[source] std::io::Error),
+    #[error("Volume '{0}' does not match the digest in the volume index")]
+    VolumeDigest(PathBuf),
+}
@@ -0,0 +1,163 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use rstest::rstest;
+use tempfile::tempdir;
+
+use crate::InstallingError;
+use crate::archive::{ArchiveBuilder, read_entry, unpack};
+use crate::compression::{CompressionFormat, CompressionSettings, Encoder};
+use crate::encryption::Encryption;
+use crate::progress::Progress;
+use crate::volume::{Output, VolumeIndex, VolumeReader, errors::VolumeError};
+
+const VOLUME_SIZE: u64 = 1024;
+
+/// Build an uncompressed archive split in volumes of [`VOLUME_SIZE`] bytes, return the index path
+fn build_volumes(out_folder: &Path) -> PathBuf {
+    let in_folder = tempdir().unwrap();
+    write!(
+        File::create(in_folder.path().join("file")).unwrap(),
+        "{}",
+        "content ".repeat(500)
+    )
+    .unwrap();
+
+    let settings = CompressionSettings {
+        format: CompressionFormat::None,
+        level: None,
+    };
+    let output = Output::new(&out_folder.join("archive.tar"), Some(VOLUME_SIZE)).unwrap();
+    let mut archive =
+        ArchiveBuilder::new(Encoder::new(Encryption::None(output), &settings).unwrap());
+    archive.append_dir_all("", in_folder.path()).unwrap();
+    archive.finish().unwrap()
+}
+
+#[rstest]
+#[case::index("archive.tar.volumes.json")]
+#[case::first_volume("archive.tar.001")]
+#[test_log::test]
+fn unpack_volumes(#[case] file_name: &str) {
+    let out_folder = tempdir().unwrap();
+    let index_path = build_volumes(out_folder.path());
+    assert_eq!(
+        index_path,
+        out_folder.path().join("archive.tar.volumes.json"),
+        "The index should be returned"
+    );
+
+    let index: VolumeIndex = serde_json::from_slice(&fs::read(&index_path).unwrap()).unwrap();
+    assert!(
+        index.volumes.len() > 1,
+        "The archive should be split, got {index:?}"
+    );
+    for volume in &index.volumes {
+        assert!(
+            volume.size <= VOLUME_SIZE,
+            "Volume {} is too large",
+            volume.file
+        );
+    }
+
+    let unpacked = tempdir().unwrap();
+    unpack(
+        &out_folder.path().join(file_name),
+        unpacked.path(),
+        &[],
+        None,
+        &Progress::default(),
+    )
+    .expect("Volumes should unpack");
+    assert_eq!(
+        fs::read_to_string(unpacked.path().join("file")).unwrap(),
+        "content ".repeat(500),
+        "Content should survive the split"
+    );
+}
+
+#[rstest]
+#[test_log::test]
+fn missing_volume() {
+    let out_folder = tempdir().unwrap();
+    let index_path = build_volumes(out_folder.path());
+    fs::remove_file(out_folder.path().join("archive.tar.002")).unwrap();
+
+    let res = VolumeReader::new(&index_path);
+    assert!(
+        matches!(res, Err(VolumeError::MissingVolume(ref path, _)) if path.ends_with("archive.tar.002")),
+        "Missing volume should be reported before reading anything"
+    );
+}
+
+#[rstest]
+#[case::second(Some(1))]
+// The last volume holds the end of the archive, which tar never reads
+#[case::last(None)]
+#[test_log::test]
+fn corrupted_volume(#[case] position: Option<usize>) {
+    let out_folder = tempdir().unwrap();
+    let index_path = build_volumes(out_folder.path());
+    let index: VolumeIndex = serde_json::from_slice(&fs::read(&index_path).unwrap()).unwrap();
+    let volume = position
+        .map_or_else(
+            || index.volumes.last(),
+            |position| index.volumes.get(position),
+        )
+        .unwrap();
+    let volume_path = out_folder.path().join(&volume.file);
+    // Same size, different content
+    let mut file = OpenOptions::new().write(true).open(&volume_path).unwrap();
+    file.write_all(b"corrupted").unwrap();
+    drop(file);
+
+    let unpacked = tempdir().unwrap();
+    let res = unpack(
+        &index_path,
+        unpacked.path(),
+        &[],
+        None,
+        &Progress::default(),
+    );
+    assert!(
+        matches!(res, Err(InstallingError::ArchiveUncompress(ref e)) if format!("{e:?}").contains("VolumeDigest")),
+        "Corrupted volume should be detected, got {res:?}"
+    );
+    assert!(
+        fs::read_dir(unpacked.path()).unwrap().next().is_none(),
+        "Nothing should be unpacked before every volume is checked"
+    );
+    let res = VolumeReader::new(&index_path);
+    assert!(
+        matches!(res, Err(VolumeError::VolumeDigest(ref path)) if *path == volume_path),
+        "The corrupted volume should be reported"
+    );
+}
+
+#[rstest]
+#[test_log::test]
+fn read_entry_without_hashing_volumes() {
+    let out_folder = tempdir().unwrap();
+    let index_path = build_volumes(out_folder.path());
+    let index: VolumeIndex = serde_json::from_slice(&fs::read(&index_path).unwrap()).unwrap();
+    // The last volume holds the end of the archive, after the entry
+    let volume_path = out_folder.path().join(&index.volumes.last().unwrap().file);
+    let mut file = OpenOptions::new().write(true).open(&volume_path).unwrap();
+    file.write_all(b"corrupted").unwrap();
+    drop(file);
+
+    let content = read_entry(&index_path, "file", &[]).expect("Entry should be readable");
+    assert_eq!(
+        content.as_deref(),
+        Some("content ".repeat(500).as_bytes()),
+        "The entry should be read without checking the digests of the volumes"
+    );
+
+    fs::remove_file(&volume_path).unwrap();
+    let res = read_entry(&index_path, "file", &[]);
+    assert!(
+        res.is_err(),
+        "Missing volume should still be reported, got {res:?}"
+    );
+}
@@ -2,3 +2,136 @@
 
 // Note that you cannot declare a common.rs file at the parent folder
 // See https://doc.rust-lang.org/book/ch11-03-test-organization.html#submodules-in-integration-tests
+use std::{
+    fs::{self, create_dir_all},
+    io,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use offline_vendoring::{
+    CommandFailedError, CommandOutput, CommandRunner, CommandSpec, GitMirrors, InstallOptions,
+    InstallReport, InstallSkip, PackageOptions, PythonSettings, RustSettings, Settings,
+    install_with_runner, package_with_runner,
+};
+use url::Url;
+
+/// Directory of the cassette `name`, recorded by the ignored `record_*` tests
+pub fn cassette(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("cassettes")
+        .join(name)
+}
+
+/// Settings mirroring a small repository to a private server
+pub fn git_mirror_settings() -> Settings {
+    Settings {
+        git_mirrors: GitMirrors::builder()
+            .mirror(
+                Url::parse("https://git.example.com/demo.git").unwrap(),
+                Url::parse("https://private.example.com/demo.git").unwrap(),
+            )
+            .build(),
+        ..Settings::default()
+    }
+}
+
+/// Settings vendoring the dependency of a small crate (`hex`) and downloading a single wheel (`wheel`),
+/// with the crate and the requirement file written under `root`
+pub fn crate_and_wheel_settings(root: &Path) -> Settings {
+    let project = root.join("project");
+    create_dir_all(project.join("src")).unwrap();
+    fs::write(
+        project.join("Cargo.toml"),
+        "[package]\nname = \"demo\"\nversion = \"0.1.0\"\nedition = \"2021\"\n\n\
+         [dependencies]\nhex = { version = \"=0.4.3\", default-features = false }\n",
+    )
+    .unwrap();
+    fs::write(project.join("src").join("lib.rs"), "").unwrap();
+    fs::write(root.join("requirements.txt"), "wheel==0.43.0\n").unwrap();
+    Settings {
+        rust: RustSettings::builder()
+            .manifest(project.join("Cargo.toml"))
+            .build(),
+        python: PythonSettings::builder()
+            .requirement_file(root.join("requirements.txt"))
+            .build(),
+        ..Settings::default()
+    }
+}
+
+/// Runner keeping the recorded commands to `root`: it answers `rustc --print sysroot` with a toolchain under `root`,
+/// whose library is a single crate without dependencies, and runs every other command with `runner`,
+/// in the crate of [`crate_and_wheel_settings`] unless they have a working directory.
+///
+/// Vendoring the dependencies of the real library crates would make the cassette huge and tie it to the path
+/// of the toolchain, while the fake toolchain is recorded as written by `rustc` and replayed under any root.
+/// Likewise, `cargo vendor` also vendors the crate of its working directory, which would be this repository.
+pub struct IsolatedRunner<'a> {
+    pub runner: &'a dyn CommandRunner,
+    pub root: &'a Path,
+}
+
+impl CommandRunner for IsolatedRunner<'_> {
+    fn run(&self, command: &CommandSpec) -> Result<CommandOutput, Box<CommandFailedError>> {
+        if command.program != "rustc" || command.args != ["--print", "sysroot"] {
+            let mut command = command.clone();
+            command.cwd.get_or_insert_with(|| self.root.join("project"));
+            return self.runner.run(&command);
+        }
+        let sysroot = self.root.join("sysroot");
+        let library = sysroot
+            .join("lib")
+            .join("rustlib")
+            .join("src")
+            .join("rust")
+            .join("library")
+            .join("core");
+        create_dir_all(library.join("src"))
+            .and_then(|()| {
+                fs::write(
+                    library.join("Cargo.toml"),
+                    "[package]\nname = \"fake-core\"\nversion = \"0.0.0\"\nedition = \"2021\"\n",
+                )
+            })
+            .and_then(|()| fs::write(library.join("src").join("lib.rs"), ""))
+            .map_err(|e: io::Error| {
+                Box::new(CommandFailedError::CommandStart(Command::new("rustc"), e))
+            })?;
+        Ok(CommandOutput {
+            stdout: format!("{}\n", sysroot.display()),
+            stderr: String::new(),
+        })
+    }
+}
+
+/// Package `settings` then install the archive, with every directory under `root` and every command run by `runner`.
+///
+/// Nothing is configured for the current user: cargo is only configured for `root/project`,
+/// and the rust tools and the python configuration are skipped.
+pub fn package_and_install(
+    settings: &Settings,
+    root: &Path,
+    runner: &dyn CommandRunner,
+) -> InstallReport {
+    let package_options = PackageOptions {
+        staging_dir: Some(root.join("staging")),
+        output_dir: Some(root.join("out")),
+        ..PackageOptions::default()
+    };
+    package_with_runner(settings, &package_options, runner).expect("Packaging should work");
+
+    let install_options = InstallOptions {
+        rust_config_for: Some(root.join("project")),
+        skip: vec![InstallSkip::RustTools, InstallSkip::PythonConfig],
+        unpack_dir: Some(root.join("unpacked")),
+        ..InstallOptions::default()
+    };
+    install_with_runner(
+        &root.join("out").join(format!("{}.tar.gz", settings.name)),
+        &install_options,
+        runner,
+    )
+    .expect("Installing should work")
+}
@@ -1 +1,126 @@
 mod common;
+
+use std::fs;
+
+use offline_vendoring::{CassettePlayer, CassetteRecorder, LocalCommandRunner, StepStatus};
+use tempfile::tempdir;
+
+use crate::common::{
+    IsolatedRunner, cassette, crate_and_wheel_settings, git_mirror_settings, package_and_install,
+};
+
+#[test_log::test]
+fn replay_git_mirror() {
+    let root = tempdir().unwrap();
+    let player = CassettePlayer::load(&cassette("git_mirror"), root.path()).unwrap();
+
+    let report = package_and_install(&git_mirror_settings(), root.path(), &player);
+
+    let pushes: Vec<_> = report
+        .steps
+        .iter()
+        .filter(|step| step.name.starts_with("git push"))
+        .map(|step| {
+            (
+                step.name.as_str(),
+                matches!(step.status, StepStatus::Succeeded),
+            )
+        })
+        .collect();
+    assert_eq!(
+        pushes,
+        [("git push https://private.example.com/demo.git", true)],
+        "The mirror should be pushed, got {report:?}"
+    );
+    assert!(
+        root.path()
+            .join("unpacked")
+            .join("mirrors")
+            .join("demo.git")
+            .join("HEAD")
+            .is_file(),
+        "The replayed clone should be archived then unpacked"
+    );
+    assert!(
+        player.unplayed().is_empty(),
+        "Every recorded command should be replayed, missing {:?}",
+        player.unplayed()
+    );
+}
+
+/// Record the cassette of [`replay_git_mirror`] again: `cargo test --test integration_tests -- --ignored`.
+///
+/// git needs to reach the mirror and its destination, e.g., local repositories set with `url.<base>.insteadOf`.
+#[test_log::test]
+#[ignore = "runs git against the mirror and its destination"]
+fn record_git_mirror() {
+    let root = tempdir().unwrap();
+    let recorder = CassetteRecorder::new(LocalCommandRunner, root.path()).unwrap();
+
+    package_and_install(&git_mirror_settings(), root.path(), &recorder);
+
+    recorder.save(&cassette("git_mirror")).unwrap();
+}
+
+#[test_log::test]
+fn replay_crate_and_wheel() {
+    let root = tempdir().unwrap();
+    let settings = crate_and_wheel_settings(root.path());
+    let player = CassettePlayer::load(&cassette("crate_and_wheel"), root.path()).unwrap();
+
+    let report = package_and_install(&settings, root.path(), &player);
+
+    let unpacked = root.path().join("unpacked");
+    assert!(
+        unpacked
+            .join("cargo-vendor")
+            .join("hex-0.4.3")
+            .join("Cargo.toml")
+            .is_file(),
+        "The vendored crate should be archived then unpacked, got {report:?}"
+    );
+    assert!(
+        unpacked
+            .join("pip")
+            .join("wheel-0.43.0-py3-none-any.whl")
+            .is_file(),
+        "The downloaded wheel should be archived then unpacked, got {report:?}"
+    );
+    let config = fs::read_to_string(
+        root.path()
+            .join("project")
+            .join(".cargo")
+            .join("config.toml"),
+    )
+    .unwrap_or_default();
+    assert!(
+        config.contains(&unpacked.join("cargo-vendor").display().to_string()),
+        "cargo should be pointed to the vendored crates, got {config}"
+    );
+    assert!(
+        player.unplayed().is_empty(),
+        "Every recorded command should be replayed, missing {:?}",
+        player.unplayed()
+    );
+}
+
+/// Record the cassette of [`replay_crate_and_wheel`] again: `cargo test --test integration_tests -- --ignored`.
+///
+/// cargo and pip need to reach their registries, or to run offline with the crate and the wheel at hand
+/// (e.g., `CARGO_NET_OFFLINE=true`, `PIP_NO_INDEX=1` and `PIP_FIND_LINKS=<directory with the wheel>`).
+/// The commands run in the recorded crate, with a small library in place of the toolchain one (see [`IsolatedRunner`]).
+#[test_log::test]
+#[ignore = "runs cargo and pip against their registries"]
+fn record_crate_and_wheel() {
+    let root = tempdir().unwrap();
+    let settings = crate_and_wheel_settings(root.path());
+    let runner = IsolatedRunner {
+        runner: &LocalCommandRunner,
+        root: root.path(),
+    };
+    let recorder = CassetteRecorder::new(runner, root.path()).unwrap();
+
+    package_and_install(&settings, root.path(), &recorder);
+
+    recorder.save(&cassette("crate_and_wheel")).unwrap();
+}